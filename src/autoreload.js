@@ -1,4 +1,37 @@
 (function () {
+    // the version of the protocol of the websocket, which is announced
+    // by the server in its `hello` message
+    var protocol_version = 1;
+
+    // the listeners of the build lifecycle events, by event type, with
+    // `*` receiving all events
+    var listeners = {};
+    window.__TRUNK__ = {
+        version: protocol_version,
+        // subscribe to the events of the given type, returning a function
+        // to unsubscribe; a listener returning `false` prevents the default
+        // handling of the event, e.g. the reload after a successful build
+        on: (type, listener) => {
+            (listeners[type] = listeners[type] || []).push(listener);
+            return () => {
+                listeners[type] = listeners[type].filter((other) => other !== listener);
+            };
+        },
+    };
+    // call the listeners of the given event, returning whether the event
+    // should be handled by default
+    var emit = (msg) => {
+        var prevented = false;
+        (listeners[msg.type] || []).concat(listeners['*'] || []).forEach((listener) => {
+            try {
+                prevented = listener(msg) === false || prevented;
+            } catch (err) {
+                console.error('error in trunk event listener', err);
+            }
+        });
+        return !prevented;
+    };
+
     var protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
     var url = protocol + '//' + window.location.host + '/_trunk/ws';
     var poll_interval = 5000;
@@ -16,11 +49,80 @@
             poll_interval);
     };
 
+    // show the error report of a failed build in an overlay, until
+    // it is closed or the page is reloaded after a successful build
+    var overlay_id = '__trunk-error-overlay';
+    var show_error = (html) => {
+        var overlay = document.getElementById(overlay_id);
+        if (!overlay) {
+            overlay = document.createElement('div');
+            overlay.id = overlay_id;
+            Object.assign(overlay.style, {
+                position: 'fixed', inset: '0', zIndex: '2147483647', overflow: 'auto',
+                padding: '2em', background: 'rgba(24, 24, 24, 0.95)', color: '#e5e5e5',
+                font: '14px/1.5 monospace', whiteSpace: 'pre-wrap',
+            });
+            document.body.appendChild(overlay);
+        }
+        overlay.innerHTML = '<div style="color: #f14c4c; font-weight: bold; cursor: pointer;">'
+            + 'Build failed (click to close)</div>' + html;
+        overlay.firstChild.onclick = () => overlay.remove();
+    };
+
+    // swap the stylesheets changed by a rebuild in place, preserving the
+    // state of the page; the previous ones are only removed once the new
+    // ones are loaded, to avoid a flash of unstyled content
+    var swap_css = (swaps) => {
+        var links = document.querySelectorAll('link[rel="stylesheet"][href]');
+        links.forEach((link) => {
+            var swap = swaps.find((swap) => swap.old === link.getAttribute('href').split('?')[0]);
+            if (!swap) return;
+            var next = link.cloneNode();
+            // the integrity of the new stylesheet differs from the previous one
+            next.removeAttribute('integrity');
+            next.href = swap.new + (swap.new.includes('?') ? '&' : '?') + 'trunk_swap=' + Date.now();
+            next.onload = next.onerror = () => link.remove();
+            link.after(next);
+        });
+    };
+
+    // hot reload the wasm module of the app, which hands over its state
+    // from the `__trunk_hmr_dispose` hook of the previous instance to the
+    // `__trunk_hmr_accept` hook of the new one, reloading the page instead
+    // if the app does not register them
+    var hot_reload = async (module) => {
+        var dispose = window.__trunk_hmr_dispose;
+        if (typeof dispose !== 'function') return false;
+        var state = await dispose();
+        delete window.__trunk_hmr_dispose;
+        delete window.__trunk_hmr_accept;
+        var init = (await import(module.js)).default;
+        await init(module.wasm);
+        var accept = window.__trunk_hmr_accept;
+        if (typeof accept !== 'function') return false;
+        await accept(state);
+        return true;
+    };
+
     var ws = new WebSocket(url);
     ws.onmessage = (ev) => {
         const msg = JSON.parse(ev.data);
-        if (msg.reload) {
+        if (msg.type === 'hello' && msg.version !== protocol_version) {
+            console.warn('unsupported trunk autoreload protocol version', msg.version);
+        }
+        if (!emit(msg)) return;
+        if (msg.type === 'failure') {
+            show_error(msg.error);
+        } else if (msg.type !== 'success') {
+            return;
+        } else if (msg.action === 'reload' || document.getElementById(overlay_id)) {
             window.location.reload();
+        } else if (msg.action === 'hmr') {
+            hot_reload(msg.hmr)
+                .then((done) => done ? swap_css(msg.css) : window.location.reload())
+                .catch(() => window.location.reload());
+        } else if (msg.action === 'swap-css') {
+            swap_css(msg.css);
         }
     };
     ws.onclose = reload_upon_connect;
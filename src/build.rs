@@ -1,17 +1,50 @@
 //! Build system & asset pipelines.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
 use anyhow::{Context, Result};
-use futures_util::stream::StreamExt;
 use tokio::fs;
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReadDirStream;
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
 
-use crate::common::{remove_dir_all, BUILDING, ERROR, SUCCESS};
-use crate::config::{RtcBuild, STAGE_DIR};
-use crate::pipelines::HtmlPipeline;
+use crate::common::{self, remove_dir_all, BUILDING, ERROR, SUCCESS};
+use crate::config::{sibling_dist_dir, RtcBuild, PREVIOUS_SUFFIX};
+use crate::pipelines::{check_budgets, read_asset_manifest, HtmlPipeline};
+use crate::progress::{self, ProgressEvent};
+use crate::reload::Reload;
+
+/// The extensions of files which are precompressed.
+const PRECOMPRESS_EXTENSIONS: &[&str] = &[
+    "css",
+    "html",
+    "js",
+    "json",
+    "map",
+    "mjs",
+    "svg",
+    "txt",
+    "wasm",
+    "webmanifest",
+    "xml",
+];
+
+/// The result of a completed build, with the HTML error report of a failed build.
+pub type BuildDone = Result<Reload, String>;
+
+/// An event of the build lifecycle, as sent to the pages served by `trunk serve`.
+#[derive(Clone, Debug)]
+pub enum BuildEvent {
+    /// A build started.
+    Started,
+    /// A build reached the stage described by the message.
+    Progress(String),
+    /// The files of the dist dir at the given paths changed with a successful build.
+    AssetsChanged(Vec<String>),
+    /// A build completed.
+    Done(BuildDone),
+}
 
 /// A system used for building a Rust WASM app & bundling its assets.
 ///
@@ -24,6 +57,10 @@ pub struct BuildSystem {
     cfg: Arc<RtcBuild>,
     /// HTML build pipeline.
     html_pipeline: Arc<HtmlPipeline>,
+    /// HTML build pipelines of the other apps of the workspace.
+    app_pipelines: Vec<Arc<HtmlPipeline>>,
+    /// The channel to send the start & progress of builds on, if any.
+    events: Option<broadcast::Sender<BuildEvent>>,
 }
 
 impl BuildSystem {
@@ -34,30 +71,108 @@ impl BuildSystem {
     pub async fn new(
         cfg: Arc<RtcBuild>,
         ignore_chan: Option<mpsc::Sender<PathBuf>>,
+        events: Option<broadcast::Sender<BuildEvent>>,
     ) -> Result<Self> {
+        let lockfile = cfg.tools.lockfile.clone();
+        cfg.tool_context
+            .set_lockfile(lockfile.unwrap_or_else(|| "Trunk.lock".into()), cfg.frozen)
+            .await?;
+        let app_pipelines = cfg
+            .apps
+            .iter()
+            .map(|app| {
+                let app_cfg = Arc::new(cfg.for_app(app));
+                HtmlPipeline::new(app_cfg, ignore_chan.clone()).map(Arc::new)
+            })
+            .collect::<Result<_>>()?;
         let html_pipeline = Arc::new(HtmlPipeline::new(cfg.clone(), ignore_chan)?);
-        Ok(Self { cfg, html_pipeline })
+        Ok(Self {
+            cfg,
+            html_pipeline,
+            app_pipelines,
+            events,
+        })
     }
 
     /// Build the application described in the given build data.
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn build(&mut self) -> Result<()> {
+        self.run(None).await
+    }
+
+    /// Rebuild the application for the changes of the given paths, only re-running the pipelines
+    /// of the assets affected by them.
+    ///
+    /// All assets are rebuilt if the changes can not be attributed to specific assets, or with
+    /// the other apps of a workspace or transforms, whose outputs depend on all assets.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn rebuild(&mut self, changed: &[PathBuf]) -> Result<()> {
+        let plan = match self.cfg.apps.is_empty() && self.cfg.transforms.is_empty() {
+            true => self.html_pipeline.plan_rebuild(changed),
+            false => None,
+        };
+        self.run(plan).await
+    }
+
+    /// Run a build, given the plan of a rebuild as by [`HtmlPipeline::plan_rebuild`].
+    ///
+    /// The build is cancelled when the returned future is dropped, such as when the watch system
+    /// supersedes it with a build for newer changes.
+    async fn run(&mut self, plan: Option<Vec<bool>>) -> Result<()> {
         tracing::info!("{} starting build", BUILDING);
-        let res = self.do_build().await;
+        let started = Instant::now();
+        self.send_event(BuildEvent::Started);
+        progress::send(ProgressEvent::BuildStarted);
+        // The HTML pipelines run on threads of their own, so they are told to stop via a token,
+        // which is cancelled once this future completes or is dropped.
+        let cancel = CancellationToken::new();
+        let _cancel_on_drop = cancel.clone().drop_guard();
+        let res = self.do_build(plan, &cancel).await;
+        let artifacts = match &res {
+            Ok(_) => read_asset_manifest(&self.cfg.final_dist)
+                .await
+                .unwrap_or_default(),
+            Err(_) => Default::default(),
+        };
+        progress::send(ProgressEvent::BuildFinished {
+            success: res.is_ok(),
+            duration: started.elapsed(),
+            artifacts,
+        });
         match res {
             Ok(_) => {
                 tracing::info!("{} success", SUCCESS);
                 Ok(())
             }
             Err(err) => {
+                // Rebuild all assets next time, as the staging dist dir is left in an unknown state.
+                self.html_pipeline.reset();
                 tracing::error!("{} error\n{:?}", ERROR, err);
                 Err(err)
             }
         }
     }
 
+    /// Send the given event, if there is a channel for them.
+    fn send_event(&self, event: BuildEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    /// Send the progress of the build described by the given message.
+    fn progress(&self, message: impl Into<String>) {
+        let message = message.into();
+        progress::send(ProgressEvent::Stage(message.clone()));
+        self.send_event(BuildEvent::Progress(message));
+    }
+
     /// Internal business logic of `build`.
-    async fn do_build(&mut self) -> Result<()> {
+    async fn do_build(
+        &mut self,
+        plan: Option<Vec<bool>>,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
         // Ensure the output dist directories are in place.
         fs::create_dir_all(self.cfg.final_dist.as_path())
             .await
@@ -67,23 +182,68 @@ impl BuildSystem {
             .await
             .context("error preparing build environment")?;
 
+        // A rebuild starts from the files of the previous build, of which those of the affected
+        // assets are replaced. These are linked rather than copied, keeping the symlinks of
+        // copied dirs as they are.
+        if plan.is_some() {
+            common::link_dir_recursive(&self.cfg.final_dist, &self.cfg.staging_dist)
+                .await
+                .context("error linking previous build to staging dist dir")?;
+        }
+
+        // Build the other apps of the workspace first, one after another, as their cargo builds
+        // would block on each other anyway. Their outputs are then included in the asset manifest
+        // of the main app.
+        for (app, pipeline) in self.cfg.apps.iter().zip(&self.app_pipelines) {
+            tracing::info!(path = %app.path, "building app");
+            self.progress(format!("building app {}", app.path));
+            pipeline
+                .clone()
+                .spawn(None, cancel.clone())
+                .await
+                .context("error joining HTML pipeline")?
+                .with_context(|| format!("error from HTML pipeline of app {:?}", app.path))?;
+        }
+
         // Spawn the source HTML pipeline. This will spawn all other pipelines derived from
         // the source HTML, and will ultimately generate and write the final HTML.
+        self.progress("building assets");
         self.html_pipeline
             .clone()
-            .spawn()
+            .spawn(plan, cancel.clone())
             .await
             .context("error joining HTML pipeline")?
             .context("error from HTML pipeline")?;
 
+        if self.cfg.release && !self.cfg.budgets.is_empty() {
+            self.progress("checking size budgets");
+            check_budgets(&self.cfg)
+                .await
+                .context("error checking size budgets")?;
+        }
+
+        if self.cfg.release && self.cfg.precompress {
+            self.progress("precompressing assets");
+            self.precompress_staging_dist()
+                .await
+                .context("error precompressing assets")?;
+        }
+
+        if self.cfg.reproducible {
+            self.zero_staging_dist_mtimes()
+                .await
+                .context("error zeroing the mtimes of built files")?;
+        }
+
         // Move distribution from staging dist to final dist
+        self.progress("applying new distribution");
         self.finalize_dist()
             .await
             .context("error applying built distribution")?;
         Ok(())
     }
 
-    /// Creates a "staging area" (dist/.stage) for storing intermediate build results.
+    /// Creates a "staging area" (.dist.stage, next to dist) for storing intermediate build results.
     async fn prepare_staging_dist(&self) -> Result<()> {
         // Prepare staging area in which we will assemble the latest build
         let staging_dist = self.cfg.staging_dist.as_path();
@@ -95,78 +255,292 @@ impl BuildSystem {
         fs::create_dir_all(staging_dist)
             .await
             .with_context(|| "error creating build environment directory: staging dist dir")?;
+        for app in &self.cfg.apps {
+            fs::create_dir_all(staging_dist.join(&app.path))
+                .await
+                .with_context(|| {
+                    format!("error creating staging dist dir of app {:?}", app.path)
+                })?;
+        }
+        // The apps share the layout of the main app.
+        let app_dirs = std::iter::once("").chain(self.cfg.apps.iter().map(|app| app.path.as_str()));
+        for app_dir in app_dirs {
+            for dir in self.cfg.layout.dirs() {
+                let path = staging_dist.join(app_dir).join(dir);
+                fs::create_dir_all(&path)
+                    .await
+                    .with_context(|| format!("error creating staging dist dir {:?}", path))?;
+            }
+        }
 
         Ok(())
     }
 
-    /// Moves the contents of dist/.stage into dist, signifying the application
-    /// of a successful build. Also removes dist/.stage afterwards.
+    /// Write gzip (`.gz`) & brotli (`.br`) compressed copies of all text & wasm files in the
+    /// staging dist dir next to the original files.
+    async fn precompress_staging_dist(&self) -> Result<()> {
+        tracing::info!("precompressing assets");
+        let files = common::list_files_recursive(&self.cfg.staging_dist).await?;
+        let files = files.into_iter().filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| PRECOMPRESS_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                .unwrap_or(false)
+        });
+        for path in files {
+            gzip(&path).await?;
+            brotli(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the mtimes of all files in the staging dist dir to the unix epoch, so that these do not
+    /// differ between reproducible builds.
+    async fn zero_staging_dist_mtimes(&self) -> Result<()> {
+        let files = common::list_files_recursive(&self.cfg.staging_dist).await?;
+        tokio::task::spawn_blocking(move || {
+            for path in files {
+                // The files hardlinked from the previous build are already zeroed, & must not be
+                // touched until the build succeeded.
+                let modified = std::fs::metadata(&path).and_then(|meta| meta.modified());
+                if matches!(modified, Ok(time) if time == SystemTime::UNIX_EPOCH) {
+                    continue;
+                }
+                // Windows requires write access to set the times of a file, unlike unix where
+                // read-only files can be updated by their owner.
+                std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(cfg!(windows))
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::UNIX_EPOCH))
+                    .with_context(|| format!("error setting mtime of file {:?}", path))?;
+            }
+            Ok(())
+        })
+        .await
+        .context("error awaiting mtime updates")?
+    }
+
+    /// Swaps the staging dist dir into the place of the final dist dir, signifying the application
+    /// of a successful build.
     #[tracing::instrument(level = "trace", skip(self))]
     async fn finalize_dist(&self) -> Result<()> {
-        let staging_dist = self.cfg.staging_dist.clone();
         tracing::info!("applying new distribution");
+        swap_dist(&self.cfg.staging_dist, &self.cfg.final_dist).await
+    }
+}
+
+/// Swap the given staging dist dir into the place of the given final dist dir, removing the
+/// previous one, so that the final dist dir never holds the output of a partial build.
+///
+/// On Linux, both dirs are exchanged atomically. Elsewhere, or if the file system does not support
+/// this, the previous dist dir is moved aside first, so that the final dist dir is missing for the
+/// short time between both renames.
+async fn swap_dist(staging_dist: &Path, final_dist: &Path) -> Result<()> {
+    let previous_dist = sibling_dist_dir(final_dist, PREVIOUS_SUFFIX);
 
-        // Build succeeded, so delete everything in `dist`, move everything
-        // from `dist/.stage` to `dist`, and then delete `dist/.stage`.
-        self.clean_final().await?;
-        self.move_stage_to_final().await?;
-        fs::remove_dir(staging_dist)
+    // Clean up after a previous build which failed to remove its previous dist dir.
+    remove_dir_all(previous_dist.clone())
+        .await
+        .context("error cleaning previous dist dir")?;
+    if !common::path_exists(final_dist).await? {
+        return fs::rename(staging_dist, final_dist)
             .await
-            .context("error deleting staging dist dir")?;
+            .with_context(|| format!("error moving {:?} to {:?}", staging_dist, final_dist));
+    }
 
-        Ok(())
+    #[cfg(target_os = "linux")]
+    {
+        let (from, to) = (staging_dist.to_owned(), final_dist.to_owned());
+        match tokio::task::spawn_blocking(move || exchange(&from, &to))
+            .await
+            .context("error awaiting dist dir exchange")?
+        {
+            // The previous dist dir is now in the place of the staging dist dir.
+            Ok(()) => {
+                return remove_dir_all(staging_dist.to_owned())
+                    .await
+                    .context("error deleting previous dist dir");
+            }
+            Err(err) => {
+                tracing::debug!(error = %err, "error exchanging dist dirs, renaming these instead")
+            }
+        }
     }
 
-    /// Move contents of stage dir to final dist dir.
-    async fn move_stage_to_final(&self) -> Result<()> {
-        let final_dist = self.cfg.final_dist.clone();
-        let staging_dist = self.cfg.staging_dist.clone();
+    fs::rename(final_dist, &previous_dist)
+        .await
+        .with_context(|| format!("error moving {:?} to {:?}", final_dist, &previous_dist))?;
+    if let Err(err) = fs::rename(staging_dist, final_dist).await {
+        // Restore the previous build, rather than leaving no dist dir behind.
+        let _ = fs::rename(&previous_dist, final_dist).await;
+        return Err(err)
+            .with_context(|| format!("error moving {:?} to {:?}", staging_dist, final_dist));
+    }
+    remove_dir_all(previous_dist)
+        .await
+        .context("error deleting previous dist dir")
+}
 
-        let mut entries = fs::read_dir(&staging_dist)
-            .await
-            .map(ReadDirStream::new)
-            .context("error reading staging dist dir")?;
-        while let Some(entry) = entries.next().await {
-            let entry = entry.context("error reading contents of staging dist dir")?;
-            let target_path = final_dist.join(entry.file_name());
+/// Atomically exchange the given paths, both of which must exist.
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
 
-            fs::rename(entry.path(), &target_path)
-                .await
-                .with_context(|| {
-                    format!("error moving {:?} to {:?}", &entry.path(), &target_path)
-                })?;
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid NUL terminated strings, which outlive the call.
+    let res = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Write a gzip compressed copy of the given file next to it, with an additional `.gz` extension.
+async fn gzip(path: &Path) -> Result<()> {
+    let bytes = fs::read(path)
+        .await
+        .with_context(|| format!("error reading file for compression {:?}", path))?;
+    let compressed = tokio::task::spawn_blocking(move || common::gzip_bytes(&bytes))
+        .await
+        .context("error awaiting gzip compression")?
+        .with_context(|| format!("error compressing file {:?}", path))?;
+
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    common::write_file(&gz_path, compressed)
+        .await
+        .with_context(|| format!("error writing compressed file {:?}", gz_path))
+}
+
+/// Write a brotli compressed copy of the given file next to it, with an additional `.br` extension.
+async fn brotli(path: &Path) -> Result<()> {
+    let bytes = fs::read(path)
+        .await
+        .with_context(|| format!("error reading file for compression {:?}", path))?;
+    let compressed = tokio::task::spawn_blocking(move || common::brotli_bytes(&bytes))
+        .await
+        .context("error awaiting brotli compression")?
+        .with_context(|| format!("error compressing file {:?}", path))?;
+
+    let mut br_path = path.as_os_str().to_owned();
+    br_path.push(".br");
+    common::write_file(&br_path, compressed)
+        .await
+        .with_context(|| format!("error writing compressed file {:?}", br_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn swap_dist_replaces_the_final_dist_dir() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let (staging_dist, final_dist) = (tmpdir.path().join(".stage"), tmpdir.path().join("dist"));
+        for (dir, build) in [(&final_dist, "old"), (&staging_dist, "new")] {
+            fs::create_dir(dir).await?;
+            fs::write(dir.join("index.html"), build).await?;
         }
+
+        swap_dist(&staging_dist, &final_dist).await?;
+
+        assert_eq!(
+            fs::read_to_string(final_dist.join("index.html")).await?,
+            "new"
+        );
+        assert!(!common::path_exists(&staging_dist).await?);
+        let previous_dist = sibling_dist_dir(&final_dist, PREVIOUS_SUFFIX);
+        assert!(!common::path_exists(&previous_dist).await?);
         Ok(())
     }
 
-    /// Clean the contents of the final dist dir.
-    async fn clean_final(&self) -> Result<()> {
-        let final_dist = self.cfg.final_dist.clone();
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failed_rebuild_keeps_the_files_of_the_final_dist_dir() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
 
-        let mut entries = fs::read_dir(&final_dist)
-            .await
-            .map(ReadDirStream::new)
-            .context("error reading final dist dir")?;
-        while let Some(entry) = entries.next().await {
-            let entry = entry.context("error reading contents of final dist dir")?;
-            if entry.file_name() == STAGE_DIR {
-                continue;
-            }
+        use crate::config::ConfigOptsHook;
+        use crate::pipelines::PipelineStage;
 
-            let file_type = entry
-                .file_type()
-                .await
-                .context("error reading metadata of file in final dist dir")?;
-            if file_type.is_dir() {
-                remove_dir_all(entry.path())
-                    .await
-                    .context("error cleaning final dist")?;
-            } else if file_type.is_symlink() || file_type.is_file() {
-                fs::remove_file(entry.path())
-                    .await
-                    .context("error cleaning final dist")?;
+        let tmpdir = tempfile::tempdir()?;
+        let dir = fs::canonicalize(tmpdir.path()).await?;
+        let mut cfg = RtcBuild::new_test(&dir).await?;
+        cfg.filehash = false;
+        cfg.integrity = true;
+        cfg.tools.lockfile = Some(dir.join("Trunk.lock"));
+        // The post-build hook fails once the marker exists, after index.html has been written.
+        let marker = dir.join("fail");
+        cfg.hooks = vec![ConfigOptsHook {
+            stage: PipelineStage::PostBuild,
+            command: "sh".into(),
+            command_arguments: vec!["-c".into(), format!("test ! -e '{}'", marker.display())],
+            working_directory: None,
+            env: Default::default(),
+        }];
+        let final_dist = cfg.final_dist.clone();
+        let css = dir.join("app.css");
+        fs::write(
+            &cfg.target,
+            r#"<link data-trunk rel="css" href="app.css"/>"#,
+        )
+        .await?;
+        fs::write(&css, "a { color: red; }").await?;
+        let mut system = BuildSystem::new(Arc::new(cfg), None, None).await?;
+        system.build().await?;
+
+        let snapshot = || async {
+            let mut files = vec![];
+            for name in ["index.html", "app.css"] {
+                let path = final_dist.join(name);
+                let ino = fs::metadata(&path).await?.ino();
+                files.push((ino, fs::read_to_string(&path).await?));
             }
-        }
+            Result::<_>::Ok(files)
+        };
+        let before = snapshot().await?;
+        fs::write(&css, "a { color: blue; }").await?;
+        fs::write(&marker, "").await?;
+        let changed = [css];
+        assert!(system.html_pipeline.plan_rebuild(&changed).is_some());
+        assert!(system.rebuild(&changed).await.is_err());
+
+        assert_eq!(snapshot().await?, before);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rebuild_plan_includes_stylesheets_importing_a_changed_file() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let dir = fs::canonicalize(tmpdir.path()).await?;
+        let mut cfg = RtcBuild::new_test(&dir).await?;
+        cfg.tools.lockfile = Some(dir.join("Trunk.lock"));
+        let partial = dir.join("parts").join("b.css");
+        fs::create_dir(dir.join("parts")).await?;
+        fs::write(&partial, "b { color: red; }").await?;
+        fs::write(dir.join("a.css"), r#"@import "b.css";"#).await?;
+        // The partial is known as the source of the copied file.
+        fs::write(
+            &cfg.target,
+            r#"<link data-trunk rel="copy-file" href="parts/b.css"/>
+<link data-trunk rel="css" href="a.css" data-include-paths="parts"/>"#,
+        )
+        .await?;
+        let mut system = BuildSystem::new(Arc::new(cfg), None, None).await?;
+        system.build().await?;
+
+        let plan = system.html_pipeline.plan_rebuild(&[partial]);
+        assert_eq!(plan, Some(vec![true, true]));
         Ok(())
     }
 }
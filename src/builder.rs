@@ -0,0 +1,117 @@
+//! The library API of Trunk, building the assets of an HTML file without the CLI.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use tokio_util::sync::CancellationToken;
+
+use crate::build::BuildSystem;
+use crate::common::parse_public_url;
+use crate::config::{ConfigOpts, ConfigOptsBuild, Configuration};
+use crate::pipelines::{read_asset_manifest, ManifestEntry};
+
+/// A builder of the assets of an HTML file, running all pipelines of `trunk build` on it.
+///
+/// The config is layered as for the CLI, see [`Configuration`]: the options set on the builder
+/// take precedence over the overrides, the env vars & the config file, which is `Trunk.toml` (or
+/// `Trunk.json`) in the current dir unless set via [`Builder::config`].
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    /// The build options, as the CLI options of `trunk build`.
+    opts: ConfigOptsBuild,
+    /// The config below the build options.
+    configuration: Configuration,
+    /// The token cancelling the build, if any.
+    cancel: Option<CancellationToken>,
+}
+
+impl Builder {
+    /// Create a builder for the given HTML file.
+    pub fn new(target: impl Into<PathBuf>) -> Self {
+        Self {
+            opts: ConfigOptsBuild {
+                target: Some(target.into()),
+                ..Default::default()
+            },
+            configuration: Configuration::new(),
+            cancel: None,
+        }
+    }
+
+    /// Set the output dir for all final assets [default: dist].
+    pub fn dist(mut self, dist: impl Into<PathBuf>) -> Self {
+        self.opts.dist = Some(dist.into());
+        self
+    }
+
+    /// Set the path to the Trunk config file [default: Trunk.toml or Trunk.json].
+    ///
+    /// Relative paths of the config file are resolved against its dir.
+    pub fn config(mut self, config: impl Into<PathBuf>) -> Self {
+        self.configuration = self.configuration.file(config);
+        self
+    }
+
+    /// Override the key of the config with the given dotted path, such as
+    /// `build.wasm_bindgen.target`, as `--set` does on the command line.
+    pub fn set(mut self, key: impl Into<String>, value: impl Into<toml::Value>) -> Self {
+        self.configuration = self.configuration.set(key, value);
+        self
+    }
+
+    /// Set the config below the build options, replacing the config file & overrides set so far.
+    pub fn configuration(mut self, configuration: Configuration) -> Self {
+        self.configuration = configuration;
+        self
+    }
+
+    /// Set all build options, as accepted by `trunk build` on the command line.
+    ///
+    /// The HTML file & the dist dir set so far are kept, unless given by the options.
+    pub fn options(mut self, opts: ConfigOptsBuild) -> Self {
+        self.opts = ConfigOptsBuild {
+            target: opts.target.or(self.opts.target),
+            dist: opts.dist.or(self.opts.dist),
+            ..opts
+        };
+        self
+    }
+
+    /// Build in release mode [default: false].
+    pub fn release(mut self, release: bool) -> Self {
+        self.opts.release = release;
+        self
+    }
+
+    /// Set the public URL from which assets are to be served [default: /].
+    pub fn public_url(mut self, public_url: impl AsRef<str>) -> Self {
+        // Normalized as the `--public-url` option of the CLI.
+        let Ok(public_url) = parse_public_url(public_url.as_ref());
+        self.opts.public_url = Some(public_url);
+        self
+    }
+
+    /// Cancel the build once the given token is cancelled, killing any commands it runs.
+    ///
+    /// Dropping the future of [`Builder::build`] cancels the build as well.
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Run all pipelines, writing the build to the dist dir & returning its asset manifest, which
+    /// maps the logical name of every file of the build to its [`ManifestEntry`].
+    pub async fn build(self) -> Result<BTreeMap<String, ManifestEntry>> {
+        let cfg = ConfigOpts::rtc_build(self.opts, self.configuration)?;
+        let mut system = BuildSystem::new(cfg.clone(), None, None).await?;
+        match self.cancel {
+            Some(token) => tokio::select! {
+                res = system.build() => res?,
+                _ = token.cancelled() => bail!("build cancelled"),
+            },
+            None => system.build().await?,
+        }
+        read_asset_manifest(&cfg.final_dist).await
+    }
+}
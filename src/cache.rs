@@ -0,0 +1,191 @@
+//! Persistent cache of pipeline outputs.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+/// The name of the file describing a cache entry.
+const ENTRY_FILE: &str = "entry.json";
+
+/// A persistent cache of the outputs of external tools, keyed by the hash of their inputs.
+///
+/// Each entry is a dir named after its key, holding the output files & a description of the files
+/// the outputs depend on along with the hashes of their content. An entry is only used if none of
+/// these files changed. As entries are addressed by content, a cache dir can be shared by any
+/// number of projects.
+#[derive(Clone, Debug)]
+pub struct BuildCache {
+    /// The dir holding the cache entries.
+    dir: PathBuf,
+}
+
+/// The description of a cache entry.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheEntry {
+    /// The files the outputs depend on, along with the hashes of their content.
+    deps: Vec<(PathBuf, String)>,
+    /// The number of output files.
+    outputs: usize,
+}
+
+impl BuildCache {
+    /// Create a new instance, using the given dir or the default cache dir.
+    pub fn new(dir: Option<PathBuf>) -> Result<Self> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => ProjectDirs::from("dev", "trunkrs", "trunk")
+                .context("failed finding project directory")?
+                .cache_dir()
+                .join("build"),
+        };
+        Ok(Self { dir })
+    }
+
+    /// The dir holding the cache entries.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Compute the key of an entry from the given parts, such as the content of the input file,
+    /// the version of the tool & its args.
+    pub fn key<T: AsRef<[u8]>>(parts: &[T]) -> String {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            let part = part.as_ref();
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hex(&hasher.finalize())
+    }
+
+    /// Get the output files of the entry with the given key, unless it is missing or any of the
+    /// files it depends on changed.
+    pub async fn get(&self, key: &str) -> Option<Vec<Vec<u8>>> {
+        match self.try_get(key).await {
+            Ok(outputs) => outputs,
+            Err(err) => {
+                tracing::debug!(key, "error reading cache entry: {:?}", err);
+                None
+            }
+        }
+    }
+
+    async fn try_get(&self, key: &str) -> Result<Option<Vec<Vec<u8>>>> {
+        let entry_dir = self.dir.join(key);
+        let Ok(entry) = fs::read(entry_dir.join(ENTRY_FILE)).await else {
+            return Ok(None);
+        };
+        let entry: CacheEntry =
+            serde_json::from_slice(&entry).context("error parsing cache entry")?;
+        for (path, hash) in &entry.deps {
+            match fs::read(path).await {
+                Ok(content) if hex(&Sha256::digest(&content)) == *hash => {}
+                _ => return Ok(None),
+            }
+        }
+        let mut outputs = Vec::with_capacity(entry.outputs);
+        for idx in 0..entry.outputs {
+            let path = entry_dir.join(idx.to_string());
+            let output = fs::read(&path)
+                .await
+                .with_context(|| format!("error reading cached file {:?}", path))?;
+            outputs.push(output);
+        }
+        Ok(Some(outputs))
+    }
+
+    /// Store the output files for the given key, along with the files these depend on.
+    ///
+    /// Failing to store an entry is not an error of the build, so it is only logged.
+    pub async fn put(&self, key: &str, outputs: &[&[u8]], deps: &[PathBuf]) {
+        if let Err(err) = self.try_put(key, outputs, deps).await {
+            tracing::warn!(key, "error writing cache entry: {:?}", err);
+        }
+    }
+
+    async fn try_put(&self, key: &str, outputs: &[&[u8]], deps: &[PathBuf]) -> Result<()> {
+        let mut entry = CacheEntry {
+            deps: Vec::with_capacity(deps.len()),
+            outputs: outputs.len(),
+        };
+        for path in deps {
+            let content = fs::read(path)
+                .await
+                .with_context(|| format!("error reading dependency {:?}", path))?;
+            entry
+                .deps
+                .push((path.clone(), hex(&Sha256::digest(&content))));
+        }
+
+        // The entry is written to a temporary dir first, so that concurrent builds never see a
+        // partially written entry.
+        let tmp_dir = self
+            .dir
+            .join(format!("{}.tmp-{:x}", key, rand::random::<u64>()));
+        fs::create_dir_all(&tmp_dir)
+            .await
+            .with_context(|| format!("error creating cache dir {:?}", tmp_dir))?;
+        let res = write_entry(&tmp_dir, &entry, outputs).await;
+        let res = match res {
+            Ok(()) => {
+                // Replace any outdated entry.
+                let entry_dir = self.dir.join(key);
+                let _ = fs::remove_dir_all(&entry_dir).await;
+                fs::rename(&tmp_dir, &entry_dir)
+                    .await
+                    .context("error moving cache entry into place")
+            }
+            Err(err) => Err(err),
+        };
+        if res.is_err() {
+            let _ = fs::remove_dir_all(&tmp_dir).await;
+        }
+        res
+    }
+}
+
+/// Write the description & output files of an entry into the given dir.
+async fn write_entry(dir: &Path, entry: &CacheEntry, outputs: &[&[u8]]) -> Result<()> {
+    for (idx, output) in outputs.iter().enumerate() {
+        fs::write(dir.join(idx.to_string()), output)
+            .await
+            .context("error writing cached file")?;
+    }
+    let entry = serde_json::to_vec(entry).context("error serializing cache entry")?;
+    fs::write(dir.join(ENTRY_FILE), entry)
+        .await
+        .context("error writing cache entry")
+}
+
+/// Encode the given bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn get_validates_deps() -> Result<()> {
+        let tmpdir = tempfile::tempdir().context("error building tempdir for test")?;
+        let cache = BuildCache::new(Some(tmpdir.path().join("cache")))?;
+        let dep = tmpdir.path().join("dep.scss");
+        fs::write(&dep, "a { color: red; }").await?;
+
+        let key = BuildCache::key(&["sass", "1.63.6"]);
+        assert_eq!(cache.get(&key).await, None);
+        cache
+            .put(&key, &[b"a{color:red}"], std::slice::from_ref(&dep))
+            .await;
+        assert_eq!(cache.get(&key).await, Some(vec![b"a{color:red}".to_vec()]));
+
+        fs::write(&dep, "a { color: blue; }").await?;
+        assert_eq!(cache.get(&key).await, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Args;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::build::BuildSystem;
+use crate::common::{format_size, gzip_bytes};
+use crate::config::{ConfigOpts, ConfigOptsBuild, Configuration};
+use crate::pipelines::read_asset_manifest;
+
+/// Build the Rust WASM app & report the sizes of all of its assets.
+#[derive(Clone, Debug, Args)]
+#[command(name = "analyze")]
+pub struct Analyze {
+    #[command(flatten)]
+    pub build: ConfigOptsBuild,
+    /// Analyze the existing dist dir instead of building the app first, which reports no deltas
+    #[arg(long)]
+    pub no_build: bool,
+    /// Additionally write the report as JSON to the given file, e.g. for budget checks in CI
+    #[arg(long)]
+    pub json: Option<PathBuf>,
+}
+
+impl Analyze {
+    #[tracing::instrument(level = "trace", skip(self, config))]
+    pub async fn run(self, config: Configuration) -> Result<()> {
+        let cfg = ConfigOpts::rtc_build(self.build, config)?;
+        // The asset manifest of the previous build is the baseline of the deltas.
+        let previous = match self.no_build {
+            true => None,
+            false => read_asset_manifest(&cfg.final_dist).await.ok(),
+        };
+        if !self.no_build {
+            let mut system = BuildSystem::new(cfg.clone(), None, None).await?;
+            system.build().await?;
+        }
+        let manifest = read_asset_manifest(&cfg.final_dist).await.context(
+            "error reading the asset manifest of the dist dir, please ensure the app is built",
+        )?;
+
+        let mut report = Report::default();
+        for (name, entry) in manifest {
+            let path = cfg.final_dist.join(&entry.file);
+            let content = fs::read(&path)
+                .await
+                .with_context(|| format!("error reading file for analysis {:?}", &path))?;
+            let (content, gzip_size) = tokio::task::spawn_blocking(move || {
+                gzip_bytes(&content).map(|compressed| (content, compressed.len() as u64))
+            })
+            .await
+            .context("error awaiting gzip compression")?
+            .with_context(|| format!("error compressing file for analysis {:?}", &path))?;
+            // Brotli compressed copies are only available if the build precompresses its assets.
+            let mut br_path = path.as_os_str().to_owned();
+            br_path.push(".br");
+            let brotli_size = fs::metadata(&br_path).await.ok().map(|meta| meta.len());
+            let delta = previous.as_ref().map(|previous| {
+                entry.size as i64
+                    - previous
+                        .get(&name)
+                        .map(|prev| prev.size as i64)
+                        .unwrap_or(0)
+            });
+            if entry.file.ends_with(".wasm") {
+                let sections = wasm_sections(&content)
+                    .with_context(|| format!("error reading sections of wasm file {:?}", &path))?;
+                report.wasm.push(WasmReport {
+                    file: entry.file.clone(),
+                    sections,
+                });
+            }
+            report.total.size += entry.size;
+            report.total.gzip_size += gzip_size;
+            report.assets.push(AssetReport {
+                name,
+                file: entry.file,
+                size: entry.size,
+                gzip_size,
+                brotli_size,
+                delta,
+            });
+        }
+        report.total.delta = previous.map(|previous| {
+            report.total.size as i64 - previous.values().map(|prev| prev.size as i64).sum::<i64>()
+        });
+
+        report.print();
+        if let Some(path) = &self.json {
+            let content =
+                serde_json::to_string_pretty(&report).context("error serializing size report")?;
+            fs::write(path, content)
+                .await
+                .with_context(|| format!("error writing size report {:?}", path))?;
+        }
+        Ok(())
+    }
+}
+
+/// The size report of the assets of a build.
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    /// All assets, by their logical names.
+    assets: Vec<AssetReport>,
+    /// The total size of all assets.
+    total: TotalReport,
+    /// The section sizes of all wasm files.
+    wasm: Vec<WasmReport>,
+}
+
+/// The sizes of an asset.
+#[derive(Debug, Serialize)]
+struct AssetReport {
+    /// The logical name of the asset.
+    name: String,
+    /// The path of the file relative to the dist dir.
+    file: String,
+    /// The size of the file in bytes.
+    size: u64,
+    /// The size of the gzip compressed file in bytes.
+    gzip_size: u64,
+    /// The size of the brotli compressed copy of the file in bytes, if precompressed.
+    brotli_size: Option<u64>,
+    /// The change of the size since the previous build, if it was analyzed against one.
+    delta: Option<i64>,
+}
+
+/// The total size of all assets.
+#[derive(Debug, Default, Serialize)]
+struct TotalReport {
+    /// The total size in bytes.
+    size: u64,
+    /// The total gzip compressed size in bytes.
+    gzip_size: u64,
+    /// The change of the total size since the previous build, if it was analyzed against one.
+    delta: Option<i64>,
+}
+
+/// The section sizes of a wasm file.
+#[derive(Debug, Serialize)]
+struct WasmReport {
+    /// The path of the file relative to the dist dir.
+    file: String,
+    /// All sections of the file, largest first.
+    sections: Vec<WasmSection>,
+}
+
+/// A section of a wasm file.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct WasmSection {
+    /// The name of the section, custom sections being named by their own name.
+    name: String,
+    /// The size of the section in bytes, including its header.
+    size: u64,
+}
+
+impl Report {
+    /// Print the report as tables.
+    fn print(&self) {
+        println!(
+            "{:<48} {:>12} {:>12} {:>12} {:>12}",
+            "asset", "size", "gzip", "brotli", "delta"
+        );
+        for asset in &self.assets {
+            println!(
+                "{:<48} {:>12} {:>12} {:>12} {:>12}",
+                asset.name,
+                format_size(asset.size),
+                format_size(asset.gzip_size),
+                asset.brotli_size.map(format_size).unwrap_or_default(),
+                asset.delta.map(format_delta).unwrap_or_default(),
+            );
+        }
+        println!(
+            "{:<48} {:>12} {:>12} {:>12} {:>12}",
+            "total",
+            format_size(self.total.size),
+            format_size(self.total.gzip_size),
+            "",
+            self.total.delta.map(format_delta).unwrap_or_default(),
+        );
+        for wasm in &self.wasm {
+            println!();
+            println!(
+                "{:<48} {:>12}",
+                format!("sections of {}", wasm.file),
+                "size"
+            );
+            for section in &wasm.sections {
+                println!("{:<48} {:>12}", section.name, format_size(section.size));
+            }
+        }
+    }
+}
+
+/// Read the sizes of all sections of the given wasm module, largest first.
+fn wasm_sections(content: &[u8]) -> Result<Vec<WasmSection>> {
+    ensure!(
+        content.len() >= 8 && content.starts_with(b"\0asm"),
+        "invalid wasm module header"
+    );
+    let mut sections: BTreeMap<String, u64> = BTreeMap::new();
+    let mut pos = 8;
+    while pos < content.len() {
+        let start = pos;
+        let id = content[pos];
+        pos += 1;
+        let size = read_leb128(content, &mut pos)? as usize;
+        let end = pos
+            .checked_add(size)
+            .filter(|end| *end <= content.len())
+            .context("truncated wasm section")?;
+        let name = match id {
+            0 => {
+                let mut name_pos = pos;
+                let len = read_leb128(content, &mut name_pos)? as usize;
+                let name = content
+                    .get(name_pos..name_pos.saturating_add(len))
+                    .filter(|_| name_pos + len <= end)
+                    .context("truncated wasm custom section name")?;
+                format!("custom \"{}\"", String::from_utf8_lossy(name))
+            }
+            1 => "type".into(),
+            2 => "import".into(),
+            3 => "function".into(),
+            4 => "table".into(),
+            5 => "memory".into(),
+            6 => "global".into(),
+            7 => "export".into(),
+            8 => "start".into(),
+            9 => "element".into(),
+            10 => "code".into(),
+            11 => "data".into(),
+            12 => "data count".into(),
+            13 => "tag".into(),
+            _ => format!("unknown ({})", id),
+        };
+        *sections.entry(name).or_default() += (end - start) as u64;
+        pos = end;
+    }
+    let mut sections: Vec<_> = sections
+        .into_iter()
+        .map(|(name, size)| WasmSection { name, size })
+        .collect();
+    sections.sort_by_key(|section| std::cmp::Reverse(section.size));
+    Ok(sections)
+}
+
+/// Read an unsigned LEB128 encoded u32 at the given position, advancing it past the value.
+fn read_leb128(content: &[u8], pos: &mut usize) -> Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *content.get(*pos).context("truncated wasm module")?;
+        *pos += 1;
+        value |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("invalid LEB128 value in wasm module")
+}
+
+/// Format the given change of a size for humans.
+fn format_delta(delta: i64) -> String {
+    if delta < 0 {
+        format!("-{}", format_size(delta.unsigned_abs()))
+    } else {
+        format!("+{}", format_size(delta as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wasm_sections_reads_section_sizes() {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // An empty type section.
+        module.extend([1, 1, 0]);
+        // A custom section named `name` with 3 bytes of content.
+        module.extend([0, 8, 4, b'n', b'a', b'm', b'e', 1, 2, 3]);
+        let sections = wasm_sections(&module).expect("expected valid wasm module");
+        assert_eq!(
+            sections,
+            vec![
+                WasmSection {
+                    name: "custom \"name\"".into(),
+                    size: 10,
+                },
+                WasmSection {
+                    name: "type".into(),
+                    size: 3,
+                },
+            ]
+        );
+        assert!(wasm_sections(&module[..module.len() - 1]).is_err());
+        assert_eq!(format_delta(-2048), "-2.0 KiB");
+    }
+}
@@ -1,10 +1,8 @@
-use std::path::PathBuf;
-
 use anyhow::Result;
 use clap::Args;
 
 use crate::build::BuildSystem;
-use crate::config::{ConfigOpts, ConfigOptsBuild};
+use crate::config::{ConfigOpts, ConfigOptsBuild, Configuration};
 
 /// Build the Rust WASM app and all of its assets.
 #[derive(Clone, Debug, Args)]
@@ -16,9 +14,9 @@ pub struct Build {
 
 impl Build {
     #[tracing::instrument(level = "trace", skip(self, config))]
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
+    pub async fn run(self, config: Configuration) -> Result<()> {
         let cfg = ConfigOpts::rtc_build(self.build, config)?;
-        let mut system = BuildSystem::new(cfg, None).await?;
+        let mut system = BuildSystem::new(cfg, None, None).await?;
         system.build().await?;
         Ok(())
     }
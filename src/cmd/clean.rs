@@ -1,12 +1,14 @@
-use std::path::PathBuf;
 use std::process::Stdio;
 
 use anyhow::{ensure, Context, Result};
 use clap::Args;
 use tokio::process::Command;
 
-use crate::common::remove_dir_all;
-use crate::config::{ConfigOpts, ConfigOptsClean};
+use crate::cache::BuildCache;
+use crate::common::{path_exists, remove_dir_all};
+use crate::config::{
+    sibling_dist_dir, ConfigOpts, ConfigOptsClean, Configuration, PREVIOUS_SUFFIX, STAGE_SUFFIX,
+};
 use crate::tools::cache_dir;
 
 /// Clean output artifacts.
@@ -21,14 +23,63 @@ pub struct Clean {
     /// them to be downloaded by Trunk next time they are needed.
     #[arg(short, long)]
     pub tools: bool,
+    /// Optionally clean the cached tools as well as the build cache of pipeline outputs
+    #[arg(long)]
+    pub cache: bool,
+    /// Only clean the dist dir, skipping `cargo clean` even if enabled by the config
+    #[arg(long, conflicts_with_all = ["cargo", "tools", "cache"])]
+    pub dist_only: bool,
+    /// List what would be cleaned, without removing anything
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 impl Clean {
     #[tracing::instrument(level = "trace", skip(self, config))]
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
+    pub async fn run(self, config: Configuration) -> Result<()> {
+        let cache_dir_opt = match self.cache {
+            true => ConfigOpts::full(config.clone())?
+                .build
+                .and_then(|build| build.cache_dir),
+            false => None,
+        };
         let cfg = ConfigOpts::rtc_clean(self.clean, config)?;
-        let _ = remove_dir_all(cfg.dist.clone()).await;
-        if cfg.cargo {
+
+        // The dist dir, along with the dirs it is swapped with by builds.
+        let mut dirs = vec![
+            cfg.dist.clone(),
+            sibling_dist_dir(&cfg.dist, STAGE_SUFFIX),
+            sibling_dist_dir(&cfg.dist, PREVIOUS_SUFFIX),
+        ];
+        if self.tools || self.cache {
+            dirs.push(cache_dir().await.context("error getting cache dir path")?);
+        }
+        if self.cache {
+            let build_cache = BuildCache::new(cache_dir_opt)?.dir().to_owned();
+            // The build cache is part of the tools cache dir, unless configured otherwise.
+            if !dirs.iter().any(|dir| build_cache.starts_with(dir)) {
+                dirs.push(build_cache);
+            }
+        }
+        for dir in dirs {
+            if !path_exists(&dir).await? {
+                continue;
+            }
+            if self.dry_run {
+                println!("would remove {}", dir.display());
+                continue;
+            }
+            tracing::debug!(?dir, "cleaning dir");
+            remove_dir_all(dir.clone())
+                .await
+                .with_context(|| format!("error cleaning dir {:?}", dir))?;
+        }
+
+        if cfg.cargo && !self.dist_only {
+            if self.dry_run {
+                println!("would run `cargo clean`");
+                return Ok(());
+            }
             tracing::debug!("cleaning cargo dir");
             let output = Command::new("cargo")
                 .arg("clean")
@@ -42,11 +93,6 @@ impl Clean {
                 String::from_utf8_lossy(&output.stderr)
             );
         }
-        if self.tools {
-            tracing::debug!("cleaning trunk tools cache dir");
-            let path = cache_dir().await.context("error getting cache dir path")?;
-            remove_dir_all(path).await?;
-        }
         Ok(())
     }
 }
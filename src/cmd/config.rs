@@ -1,9 +1,7 @@
-use std::path::PathBuf;
-
 use anyhow::Result;
 use clap::{Args, Subcommand};
 
-use crate::config::ConfigOpts;
+use crate::config::{ConfigOpts, Configuration, CONFIG_SCHEMA};
 
 /// Trunk config controls.
 #[derive(Clone, Debug, Args)]
@@ -15,7 +13,7 @@ pub struct Config {
 
 impl Config {
     #[tracing::instrument(level = "trace", skip(self, config))]
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
+    pub async fn run(self, config: Configuration) -> Result<()> {
         // NOTE WELL: if we ever add additional subcommands, refactor this to match the pattern
         // used in main, which is much more scalable. This is faster to code, and will not force
         // incompatibility when new commands are added.
@@ -24,6 +22,7 @@ impl Config {
                 let cfg = ConfigOpts::full(config)?;
                 println!("{:#?}", cfg);
             }
+            ConfigSubcommands::Schema => print!("{}", CONFIG_SCHEMA),
         }
         Ok(())
     }
@@ -33,4 +32,6 @@ impl Config {
 enum ConfigSubcommands {
     /// Show Trunk's current config pre-CLI.
     Show,
+    /// Print the JSON schema of the config file, for editors to validate & complete it.
+    Schema,
 }
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Args;
+use tokio::fs;
+
+use crate::build::BuildSystem;
+use crate::common::{self, path_exists, remove_dir_all};
+use crate::config::{ConfigOpts, ConfigOptsBuild, ConfigOptsDeployTarget, Configuration};
+
+/// Build the app in release mode & deploy its dist dir to a target of `[deploy]`.
+#[derive(Clone, Debug, Args)]
+#[command(name = "deploy")]
+pub struct Deploy {
+    /// The name of the target of `[deploy.targets]` to deploy to [default: `deploy.default`, or
+    /// the only target]
+    #[arg(long = "to", value_name = "TARGET")]
+    pub to: Option<String>,
+    /// Deploy the existing dist dir, without building it first
+    #[arg(long)]
+    pub no_build: bool,
+    /// Print the commands of the deploy, without running them
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub build: ConfigOptsBuild,
+}
+
+impl Deploy {
+    #[tracing::instrument(level = "trace", skip(self, config))]
+    pub async fn run(self, config: Configuration) -> Result<()> {
+        let deploy = ConfigOpts::full(config.clone())?.deploy.unwrap_or_default();
+        let targets = deploy.targets.unwrap_or_default();
+        let name = match self.to.or(deploy.default) {
+            Some(name) => name,
+            None if targets.len() == 1 => targets.keys().next().cloned().unwrap_or_default(),
+            None if targets.is_empty() => {
+                bail!("no deploy targets are configured, please add one to `[deploy.targets]`")
+            }
+            None => bail!(
+                "please select one of the deploy targets via `--to` or `deploy.default`: {}",
+                names(&targets)
+            ),
+        };
+        let target = targets.get(&name).with_context(|| {
+            format!(
+                "{:?} is not a deploy target, the targets are: {}",
+                name,
+                names(&targets)
+            )
+        })?;
+
+        // Deploys are always release builds.
+        let mut build = self.build;
+        build.release = true;
+        let cfg = ConfigOpts::rtc_build(build, config)?;
+        if !self.no_build && !self.dry_run {
+            BuildSystem::new(cfg.clone(), None, None)
+                .await?
+                .build()
+                .await?;
+        }
+        let dist = cfg.final_dist.clone();
+        ensure!(
+            self.dry_run || path_exists(&dist).await?,
+            "the dist dir {:?} does not exist, please build the app first",
+            dist
+        );
+
+        tracing::info!("🚀 deploying {:?} to {:?}", dist, name);
+        match target {
+            ConfigOptsDeployTarget::GithubPages {
+                remote,
+                branch,
+                cname,
+                message,
+            } => {
+                github_pages(
+                    &dist,
+                    remote.as_deref().unwrap_or("origin"),
+                    branch.as_deref().unwrap_or("gh-pages"),
+                    cname.as_deref(),
+                    message.clone(),
+                    self.dry_run,
+                )
+                .await?
+            }
+            target => {
+                for step in steps(target, &dist)? {
+                    step.run(self.dry_run, &dist).await?;
+                }
+            }
+        }
+        if !self.dry_run {
+            tracing::info!("✅ deployed to {:?}", name);
+        }
+        Ok(())
+    }
+}
+
+/// The names of the given targets, sorted.
+fn names<T>(targets: &HashMap<String, T>) -> String {
+    let mut names: Vec<_> = targets.keys().map(String::as_str).collect();
+    names.sort();
+    names.join(", ")
+}
+
+/// A command run by a deploy.
+#[derive(Debug, PartialEq, Eq)]
+struct Step {
+    /// The program.
+    program: String,
+    /// The arguments of the program.
+    args: Vec<String>,
+}
+
+impl Step {
+    fn new(program: &str, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Run the command, or print it if this is a dry run.
+    async fn run(&self, dry_run: bool, dist: &Path) -> Result<()> {
+        if dry_run {
+            println!("{} {}", self.program, self.args.join(" "));
+            return Ok(());
+        }
+        let path = which::which(&self.program).with_context(|| {
+            format!(
+                "{} is not installed, which is needed for this deploy target",
+                self.program
+            )
+        })?;
+        let cwd = std::env::current_dir().context("error getting the current dir")?;
+        common::run_command_in_dir(
+            &self.program,
+            &path,
+            &self.args,
+            [("TRUNK_DIST_DIR", dist)],
+            &cwd,
+        )
+        .await
+    }
+}
+
+/// The commands deploying the dist dir to the given target, except for GitHub Pages.
+fn steps(target: &ConfigOptsDeployTarget, dist: &Path) -> Result<Vec<Step>> {
+    let dist_dir = dist.display().to_string();
+    Ok(match target {
+        ConfigOptsDeployTarget::GithubPages { .. } => {
+            bail!("GitHub Pages are deployed via git")
+        }
+        ConfigOptsDeployTarget::S3 {
+            bucket,
+            prefix,
+            region,
+            cloudfront_distribution,
+            args,
+        } => {
+            let prefix = prefix.as_deref().unwrap_or_default().trim_matches('/');
+            let mut sync = vec![
+                "s3".to_owned(),
+                "sync".to_owned(),
+                dist_dir,
+                format!("s3://{}/{}", bucket, prefix),
+                "--delete".to_owned(),
+            ];
+            if let Some(region) = region {
+                sync.extend(["--region".to_owned(), region.clone()]);
+            }
+            sync.extend(args.iter().flatten().cloned());
+            let mut steps = vec![Step::new("aws", sync)];
+            if let Some(distribution) = cloudfront_distribution {
+                let paths = match prefix {
+                    "" => "/*".to_owned(),
+                    prefix => format!("/{}/*", prefix),
+                };
+                steps.push(Step::new(
+                    "aws",
+                    [
+                        "cloudfront",
+                        "create-invalidation",
+                        "--distribution-id",
+                        distribution,
+                        "--paths",
+                        &paths,
+                    ],
+                ));
+            }
+            steps
+        }
+        ConfigOptsDeployTarget::Netlify {
+            site,
+            prod,
+            message,
+        } => {
+            let mut args = vec!["deploy".to_owned(), "--dir".to_owned(), dist_dir];
+            if let Some(site) = site {
+                args.extend(["--site".to_owned(), site.clone()]);
+            }
+            if *prod {
+                args.push("--prod".to_owned());
+            }
+            if let Some(message) = message {
+                args.extend(["--message".to_owned(), message.clone()]);
+            }
+            vec![Step::new("netlify", args)]
+        }
+        ConfigOptsDeployTarget::Rsync {
+            destination,
+            delete,
+            args,
+        } => {
+            let mut rsync = vec!["-az".to_owned()];
+            if *delete {
+                rsync.push("--delete".to_owned());
+            }
+            rsync.extend(args.iter().flatten().cloned());
+            // The trailing slash syncs the contents of the dist dir, rather than the dir itself.
+            rsync.extend([format!("{}/", dist_dir), destination.clone()]);
+            vec![Step::new("rsync", rsync)]
+        }
+        ConfigOptsDeployTarget::Scp { destination, args } => {
+            let mut scp = vec!["-r".to_owned()];
+            scp.extend(args.iter().flatten().cloned());
+            scp.extend([format!("{}/.", dist_dir), destination.clone()]);
+            vec![Step::new("scp", scp)]
+        }
+        ConfigOptsDeployTarget::Command { command, args } => {
+            vec![Step::new(command, args.iter().flatten().cloned())]
+        }
+    })
+}
+
+/// Force-push the dist dir as the only commit of the branch of the given remote.
+///
+/// The commit is made in a bare git dir of its own, using the dist dir as its work tree, so that
+/// neither the repo of the project nor the dist dir are changed, except for the `.nojekyll` &
+/// `CNAME` files.
+async fn github_pages(
+    dist: &Path,
+    remote: &str,
+    branch: &str,
+    cname: Option<&str>,
+    message: Option<String>,
+    dry_run: bool,
+) -> Result<()> {
+    // The URL of a named remote is read from the repo of the project.
+    let url = match remote.contains([':', '/']) {
+        true => remote.to_owned(),
+        false => git_output(&["remote", "get-url", remote])
+            .await
+            .with_context(|| format!("error reading the URL of the git remote {:?}", remote))?,
+    };
+    let message = match message {
+        Some(message) => message,
+        None => match git_output(&["rev-parse", "--short", "HEAD"]).await {
+            Ok(commit) => format!("Deploy {}", commit),
+            Err(_) => "Deploy".into(),
+        },
+    };
+    // The author of the project's repo is used, as its local config does not apply to the commit,
+    // or else a placeholder, such as in CI.
+    let author = (
+        git_output(&["config", "user.name"])
+            .await
+            .unwrap_or_else(|_| "Trunk".into()),
+        git_output(&["config", "user.email"])
+            .await
+            .unwrap_or_else(|_| "trunk@localhost".into()),
+    );
+    if !dry_run {
+        // Files & dirs starting with `_` are only served by GitHub Pages without Jekyll.
+        fs::write(dist.join(".nojekyll"), "")
+            .await
+            .context("error writing .nojekyll file")?;
+        if let Some(cname) = cname {
+            fs::write(dist.join("CNAME"), format!("{}\n", cname))
+                .await
+                .context("error writing CNAME file")?;
+        }
+    }
+
+    let git_dir = std::env::temp_dir().join(format!("trunk-deploy-{}", std::process::id()));
+    if path_exists(&git_dir).await? {
+        remove_dir_all(git_dir.clone()).await?;
+    }
+    let res = async {
+        for step in github_pages_steps(&git_dir, dist, &url, branch, &message, &author) {
+            step.run(dry_run, dist).await?;
+        }
+        Ok(())
+    }
+    .await;
+    if path_exists(&git_dir).await? {
+        remove_dir_all(git_dir.clone())
+            .await
+            .with_context(|| format!("error removing the git dir of the deploy {:?}", git_dir))?;
+    }
+    res
+}
+
+/// The git commands committing the dist dir to the branch & force-pushing it to the given URL.
+fn github_pages_steps(
+    git_dir: &Path,
+    dist: &Path,
+    url: &str,
+    branch: &str,
+    message: &str,
+    (name, email): &(String, String),
+) -> Vec<Step> {
+    let git = |args: &[&str]| {
+        let mut all = vec![
+            format!("--git-dir={}", git_dir.display()),
+            format!("--work-tree={}", dist.display()),
+        ];
+        all.extend(args.iter().map(|arg| arg.to_string()));
+        Step::new("git", all)
+    };
+    vec![
+        Step::new(
+            "git",
+            ["init", "--quiet", "--bare", &git_dir.display().to_string()],
+        ),
+        git(&["symbolic-ref", "HEAD", &format!("refs/heads/{}", branch)]),
+        git(&["add", "--all"]),
+        git(&[
+            "-c",
+            &format!("user.name={}", name),
+            "-c",
+            &format!("user.email={}", email),
+            "commit",
+            "--quiet",
+            "--message",
+            message,
+        ]),
+        git(&["push", "--quiet", "--force", url, branch]),
+    ]
+}
+
+/// The trimmed stdout of the given git command, run in the repo of the project.
+async fn git_output(args: &[&str]) -> Result<String> {
+    let output = common::command("git")
+        .args(args)
+        .output()
+        .await
+        .context("error running git, which is needed for deploys to GitHub Pages")?;
+    ensure!(
+        output.status.success(),
+        "git {} returned a bad status: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_deployed_via_their_cli() -> Result<()> {
+        let dist = Path::new("dist");
+        let s3 = ConfigOptsDeployTarget::S3 {
+            bucket: "site".into(),
+            prefix: Some("/app/".into()),
+            region: None,
+            cloudfront_distribution: Some("E123".into()),
+            args: None,
+        };
+        assert_eq!(
+            steps(&s3, dist)?,
+            [
+                Step::new("aws", ["s3", "sync", "dist", "s3://site/app", "--delete"]),
+                Step::new(
+                    "aws",
+                    [
+                        "cloudfront",
+                        "create-invalidation",
+                        "--distribution-id",
+                        "E123",
+                        "--paths",
+                        "/app/*"
+                    ]
+                ),
+            ]
+        );
+        let rsync = ConfigOptsDeployTarget::Rsync {
+            destination: "host:/var/www".into(),
+            delete: true,
+            args: None,
+        };
+        assert_eq!(
+            steps(&rsync, dist)?,
+            [Step::new(
+                "rsync",
+                ["-az", "--delete", "dist/", "host:/var/www"]
+            )]
+        );
+        let author = ("a".to_owned(), "a@b".to_owned());
+        let git_steps = github_pages_steps(
+            Path::new("/tmp/git"),
+            dist,
+            "url",
+            "gh-pages",
+            "msg",
+            &author,
+        );
+        assert_eq!(
+            git_steps.last(),
+            Some(&Step::new(
+                "git",
+                [
+                    "--git-dir=/tmp/git",
+                    "--work-tree=dist",
+                    "push",
+                    "--quiet",
+                    "--force",
+                    "url",
+                    "gh-pages"
+                ]
+            ))
+        );
+        Ok(())
+    }
+}
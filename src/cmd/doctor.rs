@@ -0,0 +1,378 @@
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{bail, Result};
+use clap::Args;
+use nipper::Document;
+
+use crate::common::{self, path_exists};
+use crate::config::{self, ConfigOpts, Configuration, RtcServe};
+use crate::tools::{self, ToolContext};
+
+/// The target of WASM builds.
+const WASM_TARGET: &str = "wasm32-unknown-unknown";
+
+/// The `rel` values of the assets of Trunk, which are only processed with a `data-trunk` attribute.
+const ASSET_RELS: [&str; 9] = [
+    "rust",
+    "css",
+    "scss",
+    "sass",
+    "tailwind-css",
+    "copy-file",
+    "copy-dir",
+    "inline",
+    "icon",
+];
+
+/// Check the environment & the project for common problems, printing how to fix them.
+#[derive(Clone, Debug, Args)]
+#[command(name = "doctor")]
+pub struct Doctor {}
+
+impl Doctor {
+    #[tracing::instrument(level = "trace", skip(self, config))]
+    pub async fn run(self, config: Configuration) -> Result<()> {
+        let mut report = Report::default();
+        check_toolchain(&mut report).await;
+        check_tools(&mut report, &config).await;
+        check_config(&mut report, &config);
+        match ConfigOpts::rtc_serve(
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            config,
+        ) {
+            Ok(cfg) => {
+                check_port(&mut report, &cfg);
+                for target in &cfg.watch.build.targets {
+                    check_html_file(&mut report, &target.path).await;
+                }
+            }
+            Err(err) => report.fail(
+                format!("the config is invalid: {:#}", err),
+                "fix the option or create the file named by the error",
+            ),
+        }
+        match report.problems {
+            0 => Ok(()),
+            problems => bail!("trunk doctor found {} problem(s)", problems),
+        }
+    }
+}
+
+/// The findings of the checks, which are printed as they are found.
+#[derive(Debug, Default)]
+struct Report {
+    /// The number of problems found.
+    problems: usize,
+    /// The number of warnings.
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&mut self, msg: impl AsRef<str>) {
+        println!("✅ {}", msg.as_ref());
+    }
+
+    fn warn(&mut self, msg: impl AsRef<str>, fix: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("⚠️  {}\n   fix: {}", msg.as_ref(), fix.as_ref());
+    }
+
+    fn fail(&mut self, msg: impl AsRef<str>, fix: impl AsRef<str>) {
+        self.problems += 1;
+        println!("❌ {}\n   fix: {}", msg.as_ref(), fix.as_ref());
+    }
+}
+
+/// The trimmed stdout of the given command, if it succeeded.
+async fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = common::command(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Check the Rust toolchain & its WASM target.
+async fn check_toolchain(report: &mut Report) {
+    match output("rustc", &["--version"]).await {
+        Some(version) => report.ok(version),
+        None => report.fail(
+            "rustc is not installed",
+            "install Rust via https://rustup.rs",
+        ),
+    }
+    match output("cargo", &["--version"]).await {
+        Some(version) => report.ok(version),
+        None => report.fail(
+            "cargo is not installed",
+            "install Rust via https://rustup.rs",
+        ),
+    }
+    match output("rustup", &["--version"]).await {
+        Some(version) => report.ok(version),
+        None => report.warn(
+            "rustup is not installed, so the toolchain of `rust-toolchain.toml` files is not used",
+            "install Rust via https://rustup.rs, or install the WASM target of the toolchain \
+             otherwise",
+        ),
+    }
+    // The target is looked up in the sysroot, so that toolchains without rustup are covered too.
+    let Some(sysroot) = output("rustc", &["--print", "sysroot"]).await else {
+        return;
+    };
+    let target_dir = Path::new(&sysroot)
+        .join("lib")
+        .join("rustlib")
+        .join(WASM_TARGET);
+    match path_exists(&target_dir).await.unwrap_or(false) {
+        true => report.ok(format!("the {} target is installed", WASM_TARGET)),
+        false => report.fail(
+            format!("the {} target is not installed", WASM_TARGET),
+            format!("rustup target add {}", WASM_TARGET),
+        ),
+    }
+}
+
+/// Check the cache of the downloaded tools & the tool lockfile.
+async fn check_tools(report: &mut Report, config: &Configuration) {
+    let cache_dir = match tools::cache_dir().await {
+        Ok(cache_dir) => cache_dir,
+        Err(err) => {
+            report.fail(
+                format!("the tool cache dir is not available: {:#}", err),
+                "ensure the cache dir of your user, such as `~/.cache`, is writable",
+            );
+            return;
+        }
+    };
+    let probe = cache_dir.join(".trunk-doctor");
+    match tokio::fs::write(&probe, b"").await {
+        Ok(()) => {
+            let _ = tokio::fs::remove_file(&probe).await;
+            report.ok(format!("the tool cache dir {:?} is writable", cache_dir));
+        }
+        Err(err) => report.fail(
+            format!(
+                "the tool cache dir {:?} is not writable: {}",
+                cache_dir, err
+            ),
+            format!("fix the permissions of {:?}", cache_dir),
+        ),
+    }
+    for (app, version, dir) in tools::cached().await.unwrap_or_default() {
+        if !path_exists(dir.join(app.path())).await.unwrap_or(false) {
+            report.fail(
+                format!(
+                    "the cached {} {} is incomplete, it has no {:?}",
+                    app.name(),
+                    version,
+                    app.path()
+                ),
+                format!("remove {:?}, so that it is downloaded again", dir),
+            );
+        }
+    }
+
+    let lockfile = ConfigOpts::full(config.clone())
+        .ok()
+        .and_then(|cfg| cfg.tools?.lockfile)
+        .unwrap_or_else(|| "Trunk.lock".into());
+    if path_exists(&lockfile).await.unwrap_or(false) {
+        match ToolContext::default()
+            .set_lockfile(lockfile.clone(), false)
+            .await
+        {
+            Ok(()) => report.ok(format!("the tool lockfile {:?} is valid", lockfile)),
+            Err(err) => report.fail(
+                format!("the tool lockfile {:?} is invalid: {:#}", lockfile, err),
+                format!("remove {:?}, so that it is written anew", lockfile),
+            ),
+        }
+    }
+}
+
+/// Check the config file for syntax errors & keys which are not options of Trunk.
+fn check_config(report: &mut Report, config: &Configuration) {
+    let path = config.config_file().or_else(|| {
+        ["Trunk.toml", "Trunk.json"]
+            .into_iter()
+            .map(PathBuf::from)
+            .find(|path| path.exists())
+    });
+    let Some(path) = path.filter(|path| path.exists()) else {
+        report.ok("no config file, the defaults of Trunk are used");
+        return;
+    };
+    let value = std::fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(
+            |contents| match path.extension().is_some_and(|ext| ext == "json") {
+                true => Ok(serde_json::from_str::<toml::Value>(&contents)?),
+                false => Ok(toml::from_str::<toml::Value>(&contents)?),
+            },
+        );
+    match value {
+        Ok(value) => {
+            for key in config::unknown_keys(&value) {
+                report.warn(
+                    format!(
+                        "`{}` of {:?} is not an option of Trunk, so it is ignored",
+                        key, path
+                    ),
+                    "check the key for typos, see https://trunkrs.dev/configuration/ for all \
+                     options",
+                );
+            }
+        }
+        Err(err) => {
+            report.fail(
+                format!("the config file {:?} can not be read: {:#}", path, err),
+                "fix the syntax of the config file",
+            );
+            return;
+        }
+    }
+    report.ok(format!("the config file {:?} can be read", path));
+}
+
+/// Check that the port of `trunk serve` is free.
+fn check_port(report: &mut Report, cfg: &RtcServe) {
+    // Free ports are selected while serving, and sockets are created anew.
+    if cfg.port == 0 || cfg.unix_socket.is_some() {
+        return;
+    }
+    let addr = SocketAddr::new(cfg.address, cfg.port);
+    match TcpListener::bind(addr) {
+        Ok(_) => report.ok(format!("{} is free for `trunk serve`", addr)),
+        Err(err) => report.warn(
+            format!("{} is not available for `trunk serve`: {}", addr, err),
+            "stop the server using the port, or serve on another one via `--port` or `port = \
+             \"auto\"` in `[serve]`",
+        ),
+    }
+}
+
+/// Check the given HTML file for assets which are not processed or missing.
+async fn check_html_file(report: &mut Report, path: &Path) {
+    match tokio::fs::read_to_string(path).await {
+        Ok(html) => {
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let before = report.problems + report.warnings;
+            check_html(report, &html, dir);
+            if report.problems + report.warnings == before {
+                report.ok(format!("the HTML file {:?} is valid", path));
+            }
+        }
+        Err(err) => report.fail(
+            format!("the HTML file {:?} can not be read: {}", path, err),
+            "set the HTML file via `target` in `[build]`, or create an `index.html` file",
+        ),
+    }
+}
+
+/// Check the given HTML of a file in the given dir.
+fn check_html(report: &mut Report, html: &str, dir: &Path) {
+    let document = Document::from(html);
+    if document.select("head").length() == 0 {
+        report.warn(
+            "the HTML file has no <head>, which the assets are injected into",
+            "add a <head> element to the HTML file",
+        );
+    }
+    let rust_apps = document
+        .select(r#"link[data-trunk][rel="rust"]:not([data-type="worker"])"#)
+        .length();
+    if rust_apps > 1 {
+        report.fail(
+            format!(
+                "the HTML file has {} Rust apps, but only one may be the main app",
+                rust_apps
+            ),
+            r#"mark all other apps as workers via data-type="worker""#,
+        );
+    }
+    if rust_apps == 0 && !dir.join("Cargo.toml").exists() {
+        report.warn(
+            format!(
+                "the HTML file has no Rust app, and there is no Cargo.toml in {:?}",
+                dir
+            ),
+            r#"add <link data-trunk rel="rust" href="path/to/Cargo.toml"/> to the HTML file"#,
+        );
+    }
+    for rel in ASSET_RELS {
+        let links = document.select(&format!(r#"link[rel="{}"]:not([data-trunk])"#, rel));
+        for link in links.nodes() {
+            let href = link.attr("href").map(|href| href.to_string());
+            // Icons are common without Trunk, only those of local files are flagged.
+            if rel == "icon" && href.as_deref().is_none_or(is_url) {
+                continue;
+            }
+            report.warn(
+                format!(
+                    r#"<link rel="{}" href={:?}> has no data-trunk attribute, so it is not processed"#,
+                    rel,
+                    href.unwrap_or_default()
+                ),
+                "add the data-trunk attribute to the link",
+            );
+        }
+    }
+    let assets = document.select("link[data-trunk][href], script[data-trunk][src]");
+    for asset in assets.nodes() {
+        let Some(href) = asset.attr("href").or_else(|| asset.attr("src")) else {
+            continue;
+        };
+        let href = href.to_string();
+        if is_url(&href) || href.contains("%TRUNK_ENV_") || dir.join(&href).exists() {
+            continue;
+        }
+        report.fail(
+            format!("the asset {:?} of the HTML file does not exist", href),
+            format!(
+                "create the file, or fix the path, which is relative to {:?}",
+                dir
+            ),
+        );
+    }
+}
+
+/// Whether the given reference is a URL, rather than a local path.
+fn is_url(href: &str) -> bool {
+    href.contains("://") || href.starts_with("//") || href.starts_with("data:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_checks_flag_missing_and_unprocessed_assets() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("Cargo.toml"), "")?;
+        std::fs::write(dir.path().join("app.css"), "")?;
+        let mut report = Report::default();
+        check_html(
+            &mut report,
+            r#"<html><head>
+                <link data-trunk rel="css" href="app.css"/>
+                <link data-trunk rel="scss" href="missing.scss"/>
+                <link rel="copy-dir" href="assets"/>
+                <link rel="icon" href="https://example.com/favicon.ico"/>
+            </head><body></body></html>"#,
+            dir.path(),
+        );
+        assert_eq!(report.problems, 1);
+        assert_eq!(report.warnings, 1);
+        Ok(())
+    }
+}
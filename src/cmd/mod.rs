@@ -1,5 +1,10 @@
+pub mod analyze;
 pub mod build;
 pub mod clean;
 pub mod config;
+pub mod deploy;
+pub mod doctor;
+pub mod new;
 pub mod serve;
+pub mod tools;
 pub mod watch;
@@ -0,0 +1,373 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Args;
+use tokio::fs;
+
+use crate::common::{self, path_exists, remove_dir_all};
+
+/// The placeholder of the package name in the files of templates.
+const PROJECT_NAME: &str = "{{project-name}}";
+
+/// Create a new Trunk project in a new dir.
+#[derive(Clone, Debug, Args)]
+#[command(name = "new")]
+pub struct New {
+    /// The dir of the project, which must not exist yet
+    pub path: PathBuf,
+    #[command(flatten)]
+    pub template: TemplateOpts,
+}
+
+/// Create a new Trunk project in an existing dir, keeping the files it already has.
+#[derive(Clone, Debug, Args)]
+#[command(name = "init")]
+pub struct Init {
+    /// The dir of the project
+    #[arg(default_value = ".")]
+    pub path: PathBuf,
+    #[command(flatten)]
+    pub template: TemplateOpts,
+}
+
+/// The template of a new project.
+#[derive(Clone, Debug, Args)]
+pub struct TemplateOpts {
+    /// The template of the app: `vanilla`, `yew`, `leptos`, `dioxus`, or the URL of a git repo to
+    /// copy the files of
+    #[arg(short, long, default_value = "vanilla")]
+    pub template: String,
+    /// The name of the package [default: the name of the dir]
+    #[arg(long)]
+    pub name: Option<String>,
+}
+
+impl New {
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        ensure!(
+            !path_exists(&self.path).await?,
+            "{:?} exists already, use `trunk init` to create a project in an existing dir",
+            self.path
+        );
+        fs::create_dir_all(&self.path)
+            .await
+            .with_context(|| format!("error creating the project dir {:?}", self.path))?;
+        let res = self.template.create(&self.path).await;
+        // The dir is removed again on errors, so that the command can be retried.
+        if res.is_err() {
+            remove_dir_all(self.path.clone())
+                .await
+                .with_context(|| format!("error removing the project dir {:?}", self.path))?;
+        }
+        res
+    }
+}
+
+impl Init {
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        ensure!(
+            fs::metadata(&self.path)
+                .await
+                .is_ok_and(|meta| meta.is_dir()),
+            "{:?} is not a dir, use `trunk new` to create a project in a new dir",
+            self.path
+        );
+        self.template.create(&self.path).await
+    }
+}
+
+impl TemplateOpts {
+    /// Create the project from the template in the given dir.
+    async fn create(self, dir: &Path) -> Result<()> {
+        let name = match self.name {
+            Some(name) => name,
+            None => default_name(dir)?,
+        };
+        ensure!(
+            is_package_name(&name),
+            "{:?} is not a valid package name, please provide one via `--name`",
+            name
+        );
+        match Template::from_name(&self.template) {
+            Some(template) => template.create(dir, &name).await?,
+            None if is_git_url(&self.template) => {
+                create_from_git(&self.template, dir, &name).await?
+            }
+            None => bail!(
+                "{:?} is neither a built-in template ({}) nor the URL of a git repo",
+                self.template,
+                Template::ALL.map(Template::name).join(", ")
+            ),
+        }
+        tracing::info!(
+            "✅ created {:?} in {:?}, run `trunk serve` in it to build & serve the app (WASM builds \
+             need the target of `rustup target add wasm32-unknown-unknown`)",
+            name,
+            dir
+        );
+        Ok(())
+    }
+}
+
+/// A built-in template.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Template {
+    Vanilla,
+    Yew,
+    Leptos,
+    Dioxus,
+}
+
+impl Template {
+    const ALL: [Self; 4] = [Self::Vanilla, Self::Yew, Self::Leptos, Self::Dioxus];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|template| template.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Vanilla => "vanilla",
+            Self::Yew => "yew",
+            Self::Leptos => "leptos",
+            Self::Dioxus => "dioxus",
+        }
+    }
+
+    /// The dependencies of the app, as the lines of the `[dependencies]` of its `Cargo.toml`.
+    fn dependencies(self) -> &'static [&'static str] {
+        match self {
+            Self::Vanilla => &[
+                r#"console_error_panic_hook = "0.1""#,
+                r#"wasm-bindgen = "0.2""#,
+                r#"web-sys = { version = "0.3", features = ["Document", "Element", "HtmlElement", "Node", "Window"] }"#,
+            ],
+            Self::Yew => &[r#"yew = { version = "0.21", features = ["csr"] }"#],
+            Self::Leptos => &[
+                r#"console_error_panic_hook = "0.1""#,
+                r#"leptos = { version = "0.6", features = ["csr"] }"#,
+            ],
+            Self::Dioxus => &[r#"dioxus = { version = "0.5", features = ["web"] }"#],
+        }
+    }
+
+    /// The `src/main.rs` file of the app.
+    fn main_rs(self) -> &'static str {
+        match self {
+            Self::Vanilla => include_str!("../templates/vanilla.rs"),
+            Self::Yew => include_str!("../templates/yew.rs"),
+            Self::Leptos => include_str!("../templates/leptos.rs"),
+            Self::Dioxus => include_str!("../templates/dioxus.rs"),
+        }
+    }
+
+    /// Write the files of the template to the given dir, keeping any files which exist already.
+    async fn create(self, dir: &Path, name: &str) -> Result<()> {
+        let cargo_toml = include_str!("../templates/Cargo.toml.in")
+            .replace("{{dependencies}}", &self.dependencies().join("\n"));
+        let files = [
+            ("Cargo.toml", cargo_toml.as_str()),
+            ("Trunk.toml", include_str!("../templates/Trunk.toml")),
+            ("index.html", include_str!("../templates/index.html")),
+            (".gitignore", include_str!("../templates/gitignore")),
+            ("src/main.rs", self.main_rs()),
+        ];
+        for (path, contents) in files {
+            write_new(&dir.join(path), &contents.replace(PROJECT_NAME, name)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy the files of the git repo at the given URL to the given dir, keeping any files which exist
+/// already, and replacing `{{project-name}}` in them with the name of the package.
+async fn create_from_git(url: &str, dir: &Path, name: &str) -> Result<()> {
+    let clone_dir = std::env::temp_dir().join(format!("trunk-template-{}", std::process::id()));
+    if path_exists(&clone_dir).await? {
+        remove_dir_all(clone_dir.clone()).await?;
+    }
+    tracing::info!("📦 cloning template {}", url);
+    let status = common::command("git")
+        .args(["clone", "--depth", "1", "--quiet", url])
+        .arg(&clone_dir)
+        .status()
+        .await
+        .context("error running git, which is needed for templates of git repos")?;
+    ensure!(status.success(), "error cloning the template {:?}", url);
+    let res = copy_template(&clone_dir, dir, name).await;
+    remove_dir_all(clone_dir.clone())
+        .await
+        .with_context(|| format!("error removing the clone of the template {:?}", clone_dir))?;
+    res
+}
+
+/// Copy the files of a template dir to the given dir, except for those of git.
+///
+/// Symlinks are recreated as symlinks, rather than followed, so that they can not copy files from
+/// outside of the template.
+async fn copy_template(from: &Path, to: &Path, name: &str) -> Result<()> {
+    let mut dirs = vec![from.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .await
+            .with_context(|| format!("error reading template dir {:?}", dir))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .with_context(|| format!("error reading template dir {:?}", dir))?
+        {
+            let path = entry.path();
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if file_type.is_symlink() {
+                link_new(&path, &to.join(path.strip_prefix(from)?)).await?;
+                continue;
+            }
+            let bytes = fs::read(&path)
+                .await
+                .with_context(|| format!("error reading template file {:?}", path))?;
+            let target = to.join(path.strip_prefix(from)?);
+            // Only text files are templated, all others are copied as they are.
+            match String::from_utf8(bytes) {
+                Ok(text) => write_new(&target, &text.replace(PROJECT_NAME, name)).await?,
+                Err(err) => write_new(&target, err.as_bytes()).await?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the file at the given path, unless it exists already.
+async fn write_new(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    if path_exists(path).await? {
+        tracing::warn!("kept the existing file {:?}", path);
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("error creating dir {:?}", parent))?;
+    }
+    fs::write(path, contents)
+        .await
+        .with_context(|| format!("error writing file {:?}", path))
+}
+
+/// Recreate the given symlink at the given path, unless it exists already.
+async fn link_new(link: &Path, path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).await.is_ok() {
+        tracing::warn!("kept the existing file {:?}", path);
+        return Ok(());
+    }
+    let target = fs::read_link(link)
+        .await
+        .with_context(|| format!("error reading template symlink {:?}", link))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("error creating dir {:?}", parent))?;
+    }
+    #[cfg(unix)]
+    return fs::symlink(&target, path)
+        .await
+        .with_context(|| format!("error creating symlink {:?}", path));
+    #[cfg(not(unix))]
+    {
+        tracing::warn!(
+            "skipped the symlink {:?} to {:?} of the template",
+            path,
+            target
+        );
+        Ok(())
+    }
+}
+
+/// The name of the package in the given dir, after the name of the dir.
+fn default_name(dir: &Path) -> Result<String> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("error getting canonical path to {:?}", dir))?;
+    let name = dir.file_name().with_context(|| {
+        format!(
+            "the dir {:?} has no name, please provide one via `--name`",
+            dir
+        )
+    })?;
+    Ok(name.to_string_lossy().replace([' ', '.'], "-"))
+}
+
+/// Whether the given name is a valid name of a cargo package.
+fn is_package_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether the given template is the URL of a git repo, rather than the name of a built-in one.
+fn is_git_url(template: &str) -> bool {
+    template.contains("://") || template.starts_with("git@") || template.ends_with(".git")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn templates_keep_existing_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("index.html"), "<html></html>").await?;
+        Template::Yew.create(dir.path(), "my-app").await?;
+
+        let cargo_toml = fs::read_to_string(dir.path().join("Cargo.toml")).await?;
+        assert!(cargo_toml.contains("name = \"my-app\""));
+        assert!(cargo_toml.contains("yew = "));
+        let main_rs = fs::read_to_string(dir.path().join("src").join("main.rs")).await?;
+        assert!(main_rs.contains("Hello from my-app!"));
+        let index_html = fs::read_to_string(dir.path().join("index.html")).await?;
+        assert_eq!(index_html, "<html></html>");
+        assert!(path_exists(dir.path().join("Trunk.toml")).await?);
+        assert!(!is_package_name("1app") && is_package_name("my_app-2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn new_removes_the_project_dir_on_errors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app");
+        let new = New {
+            path: path.clone(),
+            template: TemplateOpts {
+                template: "unknown".into(),
+                name: None,
+            },
+        };
+        assert!(new.clone().run().await.is_err());
+        assert!(!path_exists(&path).await?);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn templates_keep_symlinks() -> Result<()> {
+        let template = tempfile::tempdir()?;
+        let secret = tempfile::NamedTempFile::new()?;
+        fs::symlink(secret.path(), template.path().join("link")).await?;
+        let dir = tempfile::tempdir()?;
+        copy_template(template.path(), dir.path(), "my-app").await?;
+        let link = dir.path().join("link");
+        assert!(fs::symlink_metadata(&link).await?.is_symlink());
+        assert_eq!(fs::read_link(&link).await?, secret.path());
+        Ok(())
+    }
+}
@@ -1,10 +1,8 @@
-use std::path::PathBuf;
-
 use anyhow::{Context, Result};
 use clap::Args;
 use tokio::sync::broadcast;
 
-use crate::config::{ConfigOpts, ConfigOptsBuild, ConfigOptsServe, ConfigOptsWatch};
+use crate::config::{ConfigOpts, ConfigOptsBuild, ConfigOptsServe, ConfigOptsWatch, Configuration};
 use crate::serve::ServeSystem;
 
 /// Build, watch & serve the Rust WASM app and all of its assets.
@@ -21,7 +19,7 @@ pub struct Serve {
 
 impl Serve {
     #[tracing::instrument(level = "trace", skip(self, config))]
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
+    pub async fn run(self, config: Configuration) -> Result<()> {
         let (shutdown_tx, _) = broadcast::channel(1);
         let cfg = ConfigOpts::rtc_serve(self.build, self.watch, self.serve, config)?;
         let system = ServeSystem::new(cfg, shutdown_tx.clone()).await?;
@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+
+use crate::common::{format_size, remove_dir_all};
+use crate::config::{ConfigOpts, ConfigOptsTools, Configuration};
+use crate::tools::{self, Application, ToolContext};
+
+/// Manage the tools downloaded by Trunk.
+#[derive(Clone, Debug, Args)]
+#[command(name = "tools")]
+pub struct Tools {
+    #[command(subcommand)]
+    action: ToolsSubcommands,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum ToolsSubcommands {
+    /// List the cached tools, along with their versions & sizes.
+    List,
+    /// Download the tools used by the project, e.g. for baking them into CI images.
+    Install(Install),
+    /// Remove the cached versions of tools which are not used by the project.
+    Prune(Prune),
+}
+
+/// Download the tools used by the project.
+#[derive(Clone, Debug, Args)]
+pub struct Install {
+    /// The tools to install, such as `wasm-opt` [default: all tools pinned by the lockfile or
+    /// configured in `[tools]`]
+    pub tools: Vec<String>,
+    /// Require the versions & checksums of all tools to be pinned by the lockfile, which must
+    /// exist & is never changed
+    #[arg(long)]
+    pub frozen: bool,
+}
+
+/// Remove the cached versions of tools which are not used by the project.
+#[derive(Clone, Debug, Args)]
+pub struct Prune {
+    /// List what would be removed, without removing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Tools {
+    #[tracing::instrument(level = "trace", skip(self, config))]
+    pub async fn run(self, config: Configuration) -> Result<()> {
+        let cfg = ConfigOpts::full(config)?.tools.unwrap_or_default();
+        match self.action {
+            ToolsSubcommands::List => list().await,
+            ToolsSubcommands::Install(install) => install.run(&cfg).await,
+            ToolsSubcommands::Prune(prune) => prune.run(&cfg).await,
+        }
+    }
+}
+
+/// List the cached tools.
+async fn list() -> Result<()> {
+    let cached = tools::cached().await?;
+    if cached.is_empty() {
+        let cache_dir = tools::cache_dir().await?;
+        println!("no tools are cached in {}", cache_dir.display());
+        return Ok(());
+    }
+    let mut total = 0;
+    for (app, version, dir) in cached {
+        let size = dir_size(dir).await?;
+        total += size;
+        println!("{} {} ({})", app.name(), version, format_size(size));
+    }
+    println!("total: {}", format_size(total));
+    Ok(())
+}
+
+impl Install {
+    async fn run(self, cfg: &ConfigOptsTools) -> Result<()> {
+        let tools = ToolContext::new(false, &cfg.mirrors.clone().unwrap_or_default())?;
+        let lockfile = cfg.lockfile.clone();
+        tools
+            .set_lockfile(lockfile.unwrap_or_else(|| "Trunk.lock".into()), self.frozen)
+            .await?;
+
+        let mut apps = Vec::new();
+        for name in &self.tools {
+            let app = Application::from_name(name)
+                .with_context(|| format!("{:?} is not a tool downloaded by trunk", name))?;
+            apps.push(app);
+        }
+        if apps.is_empty() {
+            for app in Application::DOWNLOADABLE {
+                if cfg.version(app).is_some() || tools.pinned_version(app).await.is_some() {
+                    apps.push(app);
+                }
+            }
+        }
+        if apps.is_empty() {
+            tracing::info!("no tools are pinned by the lockfile or configured, nothing to install");
+        }
+        for app in apps {
+            let (path, version) = tools.get_with_version(app, cfg.version(app)).await?;
+            println!("{} {} {}", app.name(), version, path.display());
+        }
+        Ok(())
+    }
+}
+
+impl Prune {
+    async fn run(self, cfg: &ConfigOptsTools) -> Result<()> {
+        // Only the versions pinned by the lockfile are read, so it is never changed.
+        let tools = ToolContext::default();
+        let lockfile = cfg.lockfile.clone();
+        tools
+            .set_lockfile(lockfile.unwrap_or_else(|| "Trunk.lock".into()), false)
+            .await?;
+        for (app, version, dir) in crate::tools::cached().await? {
+            let pinned = tools.pinned_version(app).await;
+            let used = cfg.version(app) == Some(&version)
+                || pinned.as_deref() == Some(&version)
+                || app.default_version() == version;
+            if used {
+                continue;
+            }
+            let size = format_size(dir_size(dir.clone()).await?);
+            if self.dry_run {
+                println!("would remove {} {} ({})", app.name(), version, size);
+                continue;
+            }
+            remove_dir_all(dir.clone())
+                .await
+                .with_context(|| format!("error removing tool dir {:?}", dir))?;
+            println!("removed {} {} ({})", app.name(), version, size);
+        }
+        Ok(())
+    }
+}
+
+/// The total size of the files in the given dir.
+async fn dir_size(dir: PathBuf) -> Result<u64> {
+    fn size(path: &Path) -> std::io::Result<u64> {
+        let meta = std::fs::symlink_metadata(path)?;
+        if !meta.is_dir() {
+            return Ok(meta.len());
+        }
+        let mut total = 0;
+        for entry in std::fs::read_dir(path)? {
+            total += size(&entry?.path())?;
+        }
+        Ok(total)
+    }
+    tokio::task::spawn_blocking(move || {
+        size(&dir).with_context(|| format!("error reading the size of {:?}", dir))
+    })
+    .await
+    .context("error awaiting the size of a dir")?
+}
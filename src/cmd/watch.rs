@@ -1,10 +1,8 @@
-use std::path::PathBuf;
-
 use anyhow::{Context, Result};
 use clap::Args;
 use tokio::sync::broadcast;
 
-use crate::config::{ConfigOpts, ConfigOptsBuild, ConfigOptsWatch};
+use crate::config::{ConfigOpts, ConfigOptsBuild, ConfigOptsWatch, Configuration};
 use crate::watch::WatchSystem;
 
 /// Build & watch the Rust WASM app and all of its assets.
@@ -19,7 +17,7 @@ pub struct Watch {
 
 impl Watch {
     #[tracing::instrument(level = "trace", skip(self, config))]
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
+    pub async fn run(self, config: Configuration) -> Result<()> {
         let (shutdown_tx, _shutdown_rx) = broadcast::channel(1);
         let cfg = ConfigOpts::rtc_watch(self.build, self.watch, config)?;
         let mut system = WatchSystem::new(cfg, shutdown_tx.clone(), None).await?;
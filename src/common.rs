@@ -1,18 +1,26 @@
 //! Common functionality and types.
 
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::fs::Metadata;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use brotli::enc::BrotliEncoderParams;
 use console::Emoji;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use once_cell::sync::Lazy;
+use regex::Regex;
 use tokio::fs;
-use tokio::process::Command;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+
+use crate::progress;
 
 pub static BUILDING: Emoji<'_, '_> = Emoji("📦", "");
 pub static SUCCESS: Emoji<'_, '_> = Emoji("✅", "");
@@ -21,6 +29,12 @@ pub static SERVER: Emoji<'_, '_> = Emoji("📡", "");
 pub static LOCAL: Emoji<'_, '_> = Emoji("🏠", "");
 pub static NETWORK: Emoji<'_, '_> = Emoji("💻", "");
 
+/// Matches the `%TRUNK_ENV_<NAME>%` placeholders of environment variables.
+static ENV_PLACEHOLDER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"%TRUNK_ENV_[A-Za-z_][A-Za-z0-9_]*%")
+        .expect("error compiling env placeholder regex")
+});
+
 static CWD: Lazy<PathBuf> =
     Lazy::new(|| std::env::current_dir().expect("error getting current dir"));
 
@@ -44,6 +58,16 @@ where
         ));
     }
 
+    // Existing files are replaced, instead of being overwritten in place, see `write_file`.
+    if path_exists(&to_dir).await? {
+        let (from, to) = (from_dir.as_ref(), to_dir.as_ref());
+        for file in list_files_recursive(from).await? {
+            remove_file_if_exists(to.join(file.strip_prefix(from)?))
+                .await
+                .context("error removing copied file")?;
+        }
+    }
+
     tokio::task::spawn_blocking(move || -> Result<()> {
         let opts = fs_extra::dir::CopyOptions {
             overwrite: true,
@@ -58,6 +82,126 @@ where
     .context("error copying directory")
 }
 
+/// Recreate the tree of the given dir at the given path, hardlinking its files instead of copying
+/// them. Symlinks are recreated as symlinks to the same target, instead of being followed.
+///
+/// Files which can not be hardlinked, such as those on other file systems, are copied instead.
+pub async fn link_dir_recursive(from_dir: &Path, to_dir: &Path) -> Result<()> {
+    let mut dirs = vec![from_dir.to_owned()];
+    while let Some(current) = dirs.pop() {
+        let out = to_dir.join(current.strip_prefix(from_dir)?);
+        fs::create_dir_all(&out)
+            .await
+            .with_context(|| format!("error creating directory {:?}", &out))?;
+        let mut entries = fs::read_dir(&current)
+            .await
+            .with_context(|| format!("error reading directory {:?}", &current))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .with_context(|| format!("error reading directory {:?}", &current))?
+        {
+            let (path, file_out) = (entry.path(), out.join(entry.file_name()));
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(&path)
+                    .await
+                    .with_context(|| format!("error reading symlink {:?}", &path))?;
+                #[cfg(unix)]
+                let res = fs::symlink(&target, &file_out).await;
+                #[cfg(windows)]
+                let res = match fs::metadata(&path).await.map(|meta| meta.is_dir()) {
+                    Ok(true) => fs::symlink_dir(&target, &file_out).await,
+                    _ => fs::symlink_file(&target, &file_out).await,
+                };
+                res.with_context(|| format!("error linking {:?} to {:?}", &file_out, &target))?;
+            } else if fs::hard_link(&path, &file_out).await.is_err() {
+                fs::copy(&path, &file_out)
+                    .await
+                    .with_context(|| format!("error copying {:?} to {:?}", &path, &file_out))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy a file, reusing a previous copy of it at `prev` if the source is unchanged since, i.e. if
+/// both have the same size & the previous copy is not older than the source.
+///
+/// The previous copy is hardlinked instead of being rewritten, falling back to copying the source.
+pub async fn copy_file_incremental(from: &Path, to: &Path, prev: &Path) -> Result<()> {
+    if is_unchanged(from, prev).await && fs::hard_link(prev, to).await.is_ok() {
+        return Ok(());
+    }
+    copy_file(from, to)
+        .await
+        .with_context(|| format!("error copying {:?} to {:?}", from, to))?;
+    Ok(())
+}
+
+/// Write the given contents to a new file at the given path, replacing any file there instead of
+/// truncating it.
+///
+/// The staging dist dir of a rebuild hardlinks the files of the final dist dir, which must stay
+/// untouched until the build succeeded, so all files of the staging dist dir are written this way.
+pub async fn write_file(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    remove_file_if_exists(&path).await?;
+    fs::write(path, contents).await
+}
+
+/// Copy a file to a new file at the given path, replacing any file there, see [`write_file`].
+pub async fn copy_file(from: impl AsRef<Path>, to: impl AsRef<Path>) -> std::io::Result<u64> {
+    remove_file_if_exists(&to).await?;
+    fs::copy(from, to).await
+}
+
+/// Remove the file at the given path, if there is one, e.g. before an external tool writes to it,
+/// see [`write_file`].
+pub async fn remove_file_if_exists(path: impl AsRef<Path>) -> std::io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Check if the file at `prev` is an unchanged copy of the file at `from`.
+async fn is_unchanged(from: &Path, prev: &Path) -> bool {
+    let (Ok(from), Ok(prev)) = (fs::metadata(from).await, fs::symlink_metadata(prev).await) else {
+        return false;
+    };
+    match (from.modified(), prev.modified()) {
+        (Ok(from_modified), Ok(prev_modified)) => {
+            prev.is_file() && from.len() == prev.len() && prev_modified >= from_modified
+        }
+        _ => false,
+    }
+}
+
+/// A utility function to recursively list all files in a directory.
+pub async fn list_files_recursive(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(current) = dirs.pop() {
+        let mut entries = fs::read_dir(&current)
+            .await
+            .with_context(|| format!("error reading directory {:?}", &current))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .with_context(|| format!("error reading directory {:?}", &current))?
+        {
+            if entry.file_type().await?.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
 /// A utility function to recursively delete a directory.
 ///
 /// Use this instead of fs::remove_dir_all(...) because of Windows compatibility issues, per
@@ -127,6 +271,14 @@ pub fn strip_prefix(target: &Path) -> &Path {
     }
 }
 
+/// Create a command for the given program, which is killed once the future awaiting it is
+/// dropped, such as when the build running it is cancelled.
+pub fn command(program: impl AsRef<OsStr>) -> Command {
+    let mut command = Command::new(program);
+    command.kill_on_drop(true);
+    command
+}
+
 /// Run a global command with the given arguments and make sure it completes successfully. If it
 /// fails an error is returned.
 #[tracing::instrument(level = "trace", skip(name, path, args))]
@@ -134,19 +286,257 @@ pub async fn run_command(
     name: &str,
     path: &Path,
     args: &[impl AsRef<OsStr> + Debug],
+) -> Result<()> {
+    run_command_with_env(name, path, args, &HashMap::new()).await
+}
+
+/// Run a global command with the given arguments & additional environment variables and make
+/// sure it completes successfully. If it fails an error is returned.
+#[tracing::instrument(level = "trace", skip(name, path, args, env))]
+pub async fn run_command_with_env(
+    name: &str,
+    path: &Path,
+    args: &[impl AsRef<OsStr> + Debug],
+    env: &HashMap<String, String>,
+) -> Result<()> {
+    tracing::debug!(?args, "{name} args");
+    let child = command(path)
+        .args(args)
+        .envs(env)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("error spawning {} call", name))?;
+    wait_for_command(name, child).await
+}
+
+/// Run a global command with the given arguments & environment variables in the given dir and
+/// make sure it completes successfully. If it fails an error is returned.
+#[tracing::instrument(level = "trace", skip(name, path, args, envs))]
+pub async fn run_command_in_dir<K, V>(
+    name: &str,
+    path: &Path,
+    args: &[impl AsRef<OsStr> + Debug],
+    envs: impl IntoIterator<Item = (K, V)>,
+    dir: &Path,
+) -> Result<()>
+where
+    K: AsRef<OsStr>,
+    V: AsRef<OsStr>,
+{
+    tracing::debug!(?args, ?dir, "{name} args");
+    let child = command(path)
+        .args(args)
+        .envs(envs)
+        .current_dir(dir)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("error spawning {} call", name))?;
+    wait_for_command(name, child).await
+}
+
+/// Run a global command with the given arguments, writing the given input to its stdin, and make
+/// sure it completes successfully. If it fails an error is returned.
+#[tracing::instrument(level = "trace", skip(name, path, args, input))]
+pub async fn run_command_with_input(
+    name: &str,
+    path: &Path,
+    args: &[impl AsRef<OsStr> + Debug],
+    input: &[u8],
 ) -> Result<()> {
     tracing::debug!(?args, "{name} args");
-    let status = Command::new(path)
+    let mut child = command(path)
         .args(args)
+        .stdin(Stdio::piped())
         .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
+        .stderr(Stdio::piped())
         .spawn()
-        .with_context(|| format!("error spawning {} call", name))?
+        .with_context(|| format!("error spawning {} call", name))?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .with_context(|| format!("error opening stdin of {} call", name))?;
+    stdin
+        .write_all(input)
+        .await
+        .with_context(|| format!("error writing to stdin of {} call", name))?;
+    // Close stdin, signaling the end of the input.
+    drop(stdin);
+    wait_for_command(name, child).await
+}
+
+/// Wait for the given command to complete, passing its piped stderr output through to the
+/// terminal while capturing it for the error returned if it fails.
+async fn wait_for_command(name: &str, mut child: Child) -> Result<()> {
+    let mut stderr = child
+        .stderr
+        .take()
+        .with_context(|| format!("error opening stderr of {} call", name))?;
+    let mut output = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        let len = stderr
+            .read(&mut buf)
+            .await
+            .with_context(|| format!("error reading stderr of {} call", name))?;
+        if len == 0 {
+            break;
+        }
+        progress::write_output(&buf[..len]);
+        output.extend_from_slice(&buf[..len]);
+    }
+    let status = child
         .wait()
         .await
         .with_context(|| format!("error during {} call", name))?;
     if !status.success() {
-        bail!("{} call returned a bad status", name);
+        bail!(CommandFailed {
+            name: name.to_owned(),
+            stderr: String::from_utf8_lossy(&output).into_owned(),
+        });
     }
     Ok(())
 }
+
+/// The error of a command returning a bad status, along with the output it wrote to stderr.
+///
+/// The output is not part of the error message, as it was already passed through to the terminal.
+#[derive(Debug)]
+pub struct CommandFailed {
+    /// The name of the command.
+    pub name: String,
+    /// The output the command wrote to stderr.
+    pub stderr: String,
+}
+
+impl std::fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} call returned a bad status", self.name)
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// Replace all `%TRUNK_ENV_<NAME>%` placeholders of the given content with the value of the env
+/// var `<NAME>`, as returned by `lookup`.
+///
+/// Errors of `lookup` are returned, so that no placeholder is left unresolved.
+pub fn interpolate_env(content: &str, lookup: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let mut interpolated = String::with_capacity(content.len());
+    let mut last = 0;
+    for placeholder in ENV_PLACEHOLDER.find_iter(content) {
+        let name = placeholder
+            .as_str()
+            .trim_start_matches("%TRUNK_ENV_")
+            .trim_end_matches('%');
+        interpolated.push_str(&content[last..placeholder.start()]);
+        interpolated.push_str(&lookup(name)?);
+        last = placeholder.end();
+    }
+    interpolated.push_str(&content[last..]);
+    Ok(interpolated)
+}
+
+/// Get the value of the env var with the given name, ensuring it is allowed to be interpolated.
+pub fn allowed_env_var(name: &str, allowlist: &[String]) -> Result<String> {
+    ensure!(
+        allowlist.iter().any(|allowed| allowed == name),
+        "the env var {:?} of `%TRUNK_ENV_{}%` is not allowed to be interpolated; please add it to \
+         `build.env_allowlist`",
+        name,
+        name
+    );
+    std::env::var(name).with_context(|| {
+        format!(
+            "error reading the env var {:?} of `%TRUNK_ENV_{}%`",
+            name, name
+        )
+    })
+}
+
+/// Compress the given bytes using gzip, at its best compression level.
+pub fn gzip_bytes(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+/// Compress the given bytes using brotli, at its best compression level.
+pub fn brotli_bytes(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let params = BrotliEncoderParams {
+        quality: 11,
+        ..Default::default()
+    };
+    let mut compressed = Vec::new();
+    brotli::BrotliCompress(&mut &bytes[..], &mut compressed, &params)?;
+    Ok(compressed)
+}
+
+/// Format the given number of bytes for humans.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn link_dir_recursive_keeps_symlinks() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let from = tempfile::tempdir()?;
+        let source = tempfile::NamedTempFile::new()?;
+        fs::create_dir(from.path().join("assets")).await?;
+        fs::write(from.path().join("assets/file"), "file").await?;
+        fs::symlink(source.path(), from.path().join("assets/link")).await?;
+        let to = tempfile::tempdir()?;
+
+        link_dir_recursive(from.path(), to.path()).await?;
+
+        let link = to.path().join("assets/link");
+        assert!(fs::symlink_metadata(&link).await?.is_symlink());
+        assert_eq!(fs::read_link(&link).await?, source.path());
+        let (file, linked) = (
+            fs::metadata(from.path().join("assets/file")).await?,
+            fs::metadata(to.path().join("assets/file")).await?,
+        );
+        assert_eq!(file.ino(), linked.ino());
+        Ok(())
+    }
+
+    #[test]
+    fn interpolate_env_replaces_placeholders() {
+        let lookup = |name: &str| match name {
+            "API_URL" => Ok("https://example.com".to_string()),
+            _ => bail!("unknown env var {}", name),
+        };
+        let interpolated =
+            interpolate_env(r#"<a href="%TRUNK_ENV_API_URL%/a">%TRUNK_ENV%</a>"#, lookup)
+                .expect("expected placeholders to be interpolated");
+        assert_eq!(
+            interpolated,
+            r#"<a href="https://example.com/a">%TRUNK_ENV%</a>"#
+        );
+        assert!(interpolate_env("%TRUNK_ENV_SECRET%", lookup).is_err());
+    }
+
+    #[test]
+    fn brotli_bytes_can_be_decompressed() -> std::io::Result<()> {
+        use std::io::Read;
+
+        let text = "body { color: red; }\n".repeat(64);
+        let compressed = brotli_bytes(text.as_bytes())?;
+        assert!(compressed.len() < text.len());
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(&compressed[..], 4096).read_to_string(&mut decompressed)?;
+        assert_eq!(decompressed, text);
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! The loader of the layered config, used by the CLI & the library alike.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::ConfigOpts;
+
+/// The prefix of the env vars overriding any key of the config, with `__` separating the keys of
+/// its path, such as `TRUNK__BUILD__WASM_BINDGEN__TARGET=web`.
+const ENV_PREFIX: &str = "TRUNK__";
+
+/// The sources of Trunk's config, which are layered in the following order, where each layer takes
+/// precedence over the ones before it:
+///
+/// 1. The config file, along with the files it extends & its selected profile.
+/// 2. The `TRUNK_<SECTION>_<KEY>` env vars of the plain options, such as `TRUNK_BUILD_RELEASE`.
+/// 3. The `TRUNK__<KEY>__<KEY>` env vars, overriding any key, such as
+///    `TRUNK__BUILD__WASM_BINDGEN__TARGET`.
+/// 4. The overrides set via [`Configuration::set`], which are given as `--set <KEY>=<VALUE>` on
+///    the command line.
+/// 5. The options of the command, or of the [`Builder`](crate::Builder).
+///
+/// The values of tables & arrays replace those of the lesser layers as a whole, and flags can not
+/// be disabled by greater layers, as elsewhere in the cascade.
+#[derive(Clone, Debug, Default)]
+pub struct Configuration {
+    /// The path to the config file [default: Trunk.toml or Trunk.json].
+    file: Option<PathBuf>,
+    /// The overrides of keys, in the order they were set.
+    overrides: Vec<(String, toml::Value)>,
+}
+
+impl Configuration {
+    /// The config of the default config file, if any.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the path to the config file [default: Trunk.toml or Trunk.json].
+    pub fn file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Override the key of the given dotted path, such as `build.wasm_bindgen.target`.
+    ///
+    /// Later overrides of the same key take precedence.
+    pub fn set(mut self, key: impl Into<String>, value: impl Into<toml::Value>) -> Self {
+        self.overrides.push((key.into(), value.into()));
+        self
+    }
+
+    /// The path to the config file, if set.
+    pub(crate) fn config_file(&self) -> Option<PathBuf> {
+        self.file.clone()
+    }
+
+    /// The layer of the config overriding keys, from the `TRUNK__*` env vars & the overrides set.
+    pub(crate) fn overrides(&self) -> Result<ConfigOpts> {
+        let mut env: Vec<(String, String)> = std::env::vars()
+            .filter_map(|(name, value)| {
+                let path = name.strip_prefix(ENV_PREFIX)?;
+                Some((path.split("__").collect::<Vec<_>>().join("."), value))
+            })
+            .collect();
+        // Sorted, so that nested keys consistently replace the tables of their parents.
+        env.sort();
+        let mut table = toml::Table::new();
+        for (key, value) in env {
+            let key = key.to_lowercase();
+            insert(&mut table, &key, parse_value(&value))
+                .with_context(|| format!("error reading env var {}{}", ENV_PREFIX, key))?;
+        }
+        for (key, value) in &self.overrides {
+            insert(&mut table, key, value.clone())
+                .with_context(|| format!("error overriding config key {:?}", key))?;
+        }
+        let mut value = toml::Value::Table(table);
+        super::models::move_serve_proxies(&mut value)?;
+        value
+            .try_into()
+            .context("error reading the overrides of the config")
+    }
+}
+
+impl From<Option<PathBuf>> for Configuration {
+    fn from(file: Option<PathBuf>) -> Self {
+        Self {
+            file,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+/// Parse a `<KEY>=<VALUE>` override of the command line.
+pub fn parse_override(arg: &str) -> Result<(String, toml::Value)> {
+    let Some((key, value)) = arg.split_once('=') else {
+        bail!("overrides must be given as <KEY>=<VALUE>, such as build.release=true");
+    };
+    Ok((key.trim().to_owned(), parse_value(value.trim())))
+}
+
+/// Parse the given value as a TOML value, such as `true`, `8080` or `["a", "b"]`, or else as a
+/// string.
+fn parse_value(value: &str) -> toml::Value {
+    match toml::from_str::<toml::Table>(&format!("value = {}", value)) {
+        Ok(mut table) => table.remove("value").unwrap_or_else(|| value.into()),
+        Err(_) => value.into(),
+    }
+}
+
+/// The keys of the given dotted path of keys, as in TOML, such as
+/// `serve.headers."**".X-Frame-Options`.
+fn keys(key: &str) -> Result<Vec<String>> {
+    let mut parsed: toml::Table = toml::from_str(&format!("{} = 0", key))
+        .ok()
+        .with_context(|| format!("invalid config key {:?}", key))?;
+    let mut keys = Vec::new();
+    while let Some((key, value)) = parsed.into_iter().next() {
+        keys.push(key);
+        match value {
+            toml::Value::Table(inner) => parsed = inner,
+            _ => break,
+        }
+    }
+    Ok(keys)
+}
+
+/// Insert the value at the given dotted path of keys into the table, creating the tables on the
+/// way.
+fn insert(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<()> {
+    let mut keys = keys(key)?;
+    let Some(last) = keys.pop() else {
+        bail!("invalid config key {:?}", key);
+    };
+    let mut table = table;
+    for key in keys {
+        let entry = table
+            .entry(key)
+            .or_insert_with(|| toml::Value::Table(Default::default()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(Default::default());
+        }
+        let Some(inner) = entry.as_table_mut() else {
+            unreachable!("the entry was made a table");
+        };
+        table = inner;
+    }
+    table.insert(last, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_are_parsed_as_toml_values_or_strings() -> Result<()> {
+        let cfg = Configuration::new()
+            .set("build.release", parse_value("true"))
+            .set("build.wasm_bindgen.target", parse_value("web"))
+            .set("serve.port", parse_override("serve.port=8081")?.1)
+            .set("watch.ignore", parse_value(r#"["a", "b"]"#))
+            .overrides()?;
+        let build = cfg.build.unwrap_or_default();
+        assert!(build.release);
+        assert_eq!(
+            build
+                .wasm_bindgen
+                .and_then(|wasm_bindgen| wasm_bindgen.target),
+            Some("web".into())
+        );
+        assert_eq!(cfg.serve.and_then(|serve| serve.port), Some(8081));
+        assert_eq!(
+            cfg.watch.and_then(|watch| watch.ignore),
+            Some(vec!["a".into(), "b".into()])
+        );
+        let cfg = Configuration::new()
+            .set(r#"serve.headers."**".X-Frame-Options"#, "DENY")
+            .overrides()?;
+        let headers = cfg
+            .serve
+            .and_then(|serve| serve.headers)
+            .unwrap_or_default();
+        assert_eq!(headers["**"]["X-Frame-Options"], "DENY");
+        assert!(parse_override("build.release").is_err());
+        assert!(Configuration::new()
+            .set("build..release", true)
+            .overrides()
+            .is_err());
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::Path;
 
 use anyhow::{Context, Result};
@@ -16,10 +17,14 @@ pub struct CargoMetadata {
 }
 
 impl CargoMetadata {
-    // Create a new instance from the Cargo.toml at the given path.
-    pub async fn new(manifest: &Path) -> Result<Self> {
+    // Create a new instance from the Cargo.toml at the given path, with the environment variables
+    // of cargo builds, which may set the target dir.
+    pub async fn new(manifest: &Path, env: &HashMap<String, String>) -> Result<Self> {
         let mut cmd = MetadataCommand::new();
         cmd.manifest_path(dunce::simplified(manifest));
+        for (key, val) in env {
+            cmd.env(key, val);
+        }
         let metadata = spawn_blocking(move || cmd.exec())
             .await
             .context("error awaiting spawned cargo metadata task")?
@@ -2,8 +2,11 @@
 //!
 //! Trunk takes the typical layered configuration approach. There are 3 layers. The
 //! `Trunk.toml` config file is the base, which is then superseded by environment variables,
-//! which are finally superseded by CLI arguments and options.
+//! which are finally superseded by CLI arguments and options. The config file may also be written
+//! as JSON, as `Trunk.json`. Any key may be overridden via env vars & the CLI as well, see
+//! [`Configuration`].
 
+mod loader;
 mod manifest;
 mod models;
 #[cfg(test)]
@@ -13,12 +16,83 @@ mod rt;
 /// The default name of the directory where final build artifacts are
 /// placed after a successful build.
 pub const DIST_DIR: &str = "dist";
-/// The name of the directory used to stage build artifacts during an active build.
-pub const STAGE_DIR: &str = ".stage";
+/// The suffix of the directory used to stage build artifacts during an active build, a sibling of
+/// the dist dir which is swapped into its place after a successful build.
+pub const STAGE_SUFFIX: &str = ".stage";
+/// The suffix of the directory the previous dist dir is moved to while swapping in a new build.
+pub const PREVIOUS_SUFFIX: &str = ".previous";
 
+/// The JSON schema of the config file, for editors to validate & complete it.
+pub const CONFIG_SCHEMA: &str = include_str!("schema.json");
+
+/// The keys of the given config file value which are not described by the [`CONFIG_SCHEMA`], such
+/// as those of typos, as dotted paths.
+pub fn unknown_keys(value: &toml::Value) -> Vec<String> {
+    fn walk(
+        root: &serde_json::Value,
+        schema: &serde_json::Value,
+        value: &toml::Value,
+        path: &str,
+        unknown: &mut Vec<String>,
+    ) {
+        let schema = match schema.pointer("/allOf/0/$ref").or(schema.get("$ref")) {
+            Some(serde_json::Value::String(reference)) => {
+                match root.pointer(reference.trim_start_matches('#')) {
+                    Some(definition) => definition,
+                    None => return,
+                }
+            }
+            _ => schema,
+        };
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let key_path = match path {
+                        "" => key.clone(),
+                        _ => format!("{}.{}", path, key),
+                    };
+                    match schema
+                        .pointer(&format!("/properties/{}", key))
+                        .or(schema.get("additionalProperties"))
+                    {
+                        Some(property) => walk(root, property, value, &key_path, unknown),
+                        None => unknown.push(key_path),
+                    }
+                }
+            }
+            toml::Value::Array(values) => {
+                if let Some(items) = schema.get("items") {
+                    for value in values {
+                        walk(root, items, value, path, unknown);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let Ok(schema) = serde_json::from_str(CONFIG_SCHEMA) else {
+        return Vec::new();
+    };
+    let mut unknown = Vec::new();
+    walk(&schema, &schema, value, "", &mut unknown);
+    unknown
+}
+
+/// Whether the given watch ignore path is a glob, rather than a path.
+fn is_glob(path: &std::path::Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+pub use loader::{parse_override, Configuration};
 pub use manifest::CargoMetadata;
 pub use models::{
-    ConfigOpts, ConfigOptsBuild, ConfigOptsClean, ConfigOptsHook, ConfigOptsProxy, ConfigOptsServe,
-    ConfigOptsTools, ConfigOptsWatch,
+    ConfigOpts, ConfigOptsBuild, ConfigOptsBuildBudget, ConfigOptsBuildLayout,
+    ConfigOptsBuildPlugin, ConfigOptsBuildPrerender, ConfigOptsBuildSitemap,
+    ConfigOptsBuildTransform, ConfigOptsBuildWasmBindgen, ConfigOptsClean, ConfigOptsDeployTarget,
+    ConfigOptsHook, ConfigOptsProxy, ConfigOptsServe, ConfigOptsTools, ConfigOptsWatch,
+};
+pub use rt::{
+    sibling_dist_dir, AssetClass, Features, RtcBuild, RtcClean, RtcServe, RtcWatch, ServeAuth,
+    ServeTls,
 };
-pub use rt::{Features, RtcBuild, RtcClean, RtcServe, RtcWatch};
@@ -1,17 +1,19 @@
 use std::collections::HashMap;
-use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use axum::http::Uri;
 use clap::Args;
 use serde::{Deserialize, Deserializer};
 
-use crate::common::parse_public_url;
-use crate::config::{RtcBuild, RtcClean, RtcServe, RtcWatch};
+use crate::common::{self, parse_public_url};
+use crate::config::{Configuration, RtcBuild, RtcClean, RtcServe, RtcWatch};
+use crate::hashing::{HashAlgorithm, HashPlacement};
 use crate::pipelines::PipelineStage;
+use crate::tools::Application;
 
 /// Config options for the build system.
 #[derive(Clone, Debug, Default, Deserialize, Args)]
@@ -40,9 +42,163 @@ pub struct ConfigOptsBuild {
     /// [default: ""]
     #[arg(long)]
     pub features: Option<String>,
+    /// The wasm-opt level of Rust apps without a `data-wasm-opt` attribute [default: the default
+    /// level in release mode, off otherwise]
+    #[arg(long)]
+    pub wasm_opt_level: Option<String>,
+    /// Passes run by wasm-opt after optimizing, such as `strip-debug` or `dce` [default: None]
+    #[arg(long = "wasm-opt-pass", value_name = "pass")]
+    pub wasm_opt_passes: Option<Vec<String>>,
+    /// The wasm features enabled for wasm-opt, such as `bulk-memory` or `reference-types`
+    /// [default: None]
+    #[arg(long = "wasm-opt-feature", value_name = "feature")]
+    pub wasm_opt_features: Option<Vec<String>>,
+    /// Whether to minify JS & tailwind CSS assets without a `data-minify` attribute [default:
+    /// true in release mode]
+    #[arg(long)]
+    pub minify: Option<bool>,
     /// Whether to include hash values in the output file names [default: true]
     #[arg(long)]
     pub filehash: Option<bool>,
+    /// The algorithm used to hash file names: `seahash`, `blake3`, `sha256` or `xxh3`
+    /// [default: seahash]
+    #[arg(long)]
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// The number of hex digits file name hashes are truncated to [default: the full hash]
+    #[arg(long)]
+    pub hash_length: Option<usize>,
+    /// Where hashes are placed: in the file name (`name`, e.g. `app-<hash>.js`) or as query of
+    /// the references to the file (`query`, e.g. `app.js?v=<hash>`) [default: name]
+    #[arg(long)]
+    pub hash_placement: Option<HashPlacement>,
+    /// Write gzip & brotli compressed copies of text and wasm assets in release mode
+    /// [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub precompress: bool,
+    /// Add subresource integrity attributes to injected scripts and stylesheets
+    /// [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub integrity: bool,
+    /// The maximum number of external tools, such as sass or wasm-opt, run concurrently by the
+    /// asset pipelines [default: the number of available CPUs]
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+    /// Whether to cache the outputs of sass & wasm-opt across builds
+    /// [default: true]
+    #[arg(long)]
+    pub cache: Option<bool>,
+    /// The dir of the build cache, which can be shared by any number of projects [default: a
+    /// platform dependent cache dir]
+    #[arg(long)]
+    pub cache_dir: Option<PathBuf>,
+    /// The target dir of cargo builds, e.g. to share it across projects in CI [default: the
+    /// target dir of the cargo workspace]
+    #[arg(long)]
+    pub cargo_target_dir: Option<PathBuf>,
+    /// A wrapper of rustc for cargo builds, such as `sccache` [default: None]
+    #[arg(long)]
+    pub rustc_wrapper: Option<String>,
+    /// Environment variables set for cargo builds only, added to those of `env` [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub cargo_env: Option<HashMap<String, String>>,
+    /// Build reproducibly, so that two builds of the same sources are byte-identical: assets are
+    /// finalized in a stable order, tool versions are pinned, no nonce is generated & the mtimes
+    /// of all files in the dist dir are zeroed [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub reproducible: bool,
+    /// Require the versions & checksums of all downloaded tools to be pinned by the lockfile,
+    /// which must exist & is never changed [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub frozen: bool,
+    /// Never download any tools or crates, failing the build if a required tool is neither
+    /// installed on the system nor cached [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub offline: bool,
+    /// The profile of the Trunk config file to apply, overriding the config of the file itself
+    /// [default: None]
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Additional HTML files to build along with `target`, each finalized like the target itself
+    /// while sharing the outputs of identical assets [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub targets: Option<Vec<ConfigOptsBuildTarget>>,
+    /// Other apps of the workspace to build along with the main app, each into its own subdir of
+    /// the dist dir [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub apps: Option<Vec<ConfigOptsBuildApp>>,
+    /// Environment variables set for cargo builds & hooks [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub env: Option<HashMap<String, String>>,
+    /// The environment variables which may be interpolated into the source HTML files & the
+    /// Trunk config file via `%TRUNK_ENV_<NAME>%` placeholders [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub env_allowlist: Option<Vec<String>>,
+    /// Size budgets of the built files, which fail release builds when exceeded [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub budgets: Option<Vec<ConfigOptsBuildBudget>>,
+    /// Commands transforming the built files matching a glob, such as appending license banners
+    /// [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub transforms: Option<Vec<ConfigOptsBuildTransform>>,
+    /// Plugins building the `<link data-trunk>` assets of other `rel` values via external
+    /// commands [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub plugins: Option<Vec<ConfigOptsBuildPlugin>>,
+    /// The subdirs of the dist dir the outputs of the different asset classes are written to
+    /// [default: all outputs are written to the dist dir itself]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub layout: Option<ConfigOptsBuildLayout>,
+    /// The defaults of the wasm-bindgen options of Rust apps, such as the target of their JS glue
+    /// [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub wasm_bindgen: Option<ConfigOptsBuildWasmBindgen>,
+    /// The routes of the app prerendered via a headless browser in release builds [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub prerender: Option<ConfigOptsBuildPrerender>,
+    /// The `sitemap.xml` & `robots.txt` files of the routes of the app [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub sitemap: Option<ConfigOptsBuildSitemap>,
     /// Optional pattern for the app loader script [default: None]
     ///
     /// Patterns should include the sequences `{base}`, `{wasm}`, and `{js}` in order to
@@ -88,36 +244,204 @@ pub struct ConfigOptsBuild {
     pub pattern_params: Option<HashMap<String, String>>,
 }
 
+/// Config options for an additional HTML file to build.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigOptsBuildTarget {
+    /// The HTML file to build.
+    pub target: PathBuf,
+    /// The path of the output HTML file, relative to the dist dir [default: the file name of
+    /// `target`]
+    pub output: Option<String>,
+}
+
+/// Config options for the layout of the dist dir.
+///
+/// Each value is a subdir of the dist dir, the outputs of asset classes without a subdir are
+/// written to the dist dir itself.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ConfigOptsBuildLayout {
+    /// The subdir of JS & TypeScript assets.
+    pub js: Option<String>,
+    /// The subdir of CSS, sass, less & tailwind assets, along with their source maps.
+    pub css: Option<String>,
+    /// The subdir of the outputs of Rust apps: the wasm, its JS glue & snippets.
+    pub wasm: Option<String>,
+    /// The subdir of all other assets, such as copied files, images, fonts & icons.
+    pub assets: Option<String>,
+}
+
+/// Config options for the wasm-bindgen calls of Rust apps, the defaults of the attributes of their
+/// `<link data-trunk rel="rust">` elements.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ConfigOptsBuildWasmBindgen {
+    /// The target of the JS glue of main apps: `web`, `no-modules` or `bundler` [default: web]
+    pub target: Option<String>,
+    /// Use weak references for JS objects owned by Rust [default: false]
+    #[serde(default)]
+    pub weak_refs: bool,
+    /// Enable reference types [default: false]
+    #[serde(default)]
+    pub reference_types: bool,
+    /// Keep the debug info, even in release mode [default: false]
+    #[serde(default)]
+    pub keep_debug: bool,
+    /// Include the debug checks of wasm-bindgen in the JS glue [default: false]
+    #[serde(default)]
+    pub debug: bool,
+}
+
+/// Config options for prerendering the routes of the app via a headless browser, writing their HTML
+/// to the dist dir in release builds.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ConfigOptsBuildPrerender {
+    /// The routes to prerender, relative to the public URL, such as `/` or `/about`.
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// The Chrome or Chromium executable rendering the routes [default: the first of `chromium`,
+    /// `chromium-browser`, `google-chrome`, `google-chrome-stable` & `chrome` in `PATH`]
+    pub browser: Option<PathBuf>,
+    /// Additional arguments of the browser, such as `--no-sandbox` [default: None]
+    pub args: Option<Vec<String>>,
+    /// The time the app is given to render each route, as the virtual time of the browser
+    /// [default: 5s]
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub wait: Option<Duration>,
+}
+
+/// Config options for writing the `sitemap.xml` & `robots.txt` files of the routes of the app to
+/// the dist dir.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ConfigOptsBuildSitemap {
+    /// The absolute URL of the site, such as `https://example.com`, which the path of the public
+    /// URL & the routes are appended to [default: the public URL, if absolute]
+    pub base_url: Option<String>,
+    /// The routes listed by the sitemap, relative to the public URL [default: the prerendered
+    /// routes, or else those of all HTML files of the build]
+    pub routes: Option<Vec<String>>,
+    /// The template of the `robots.txt` file, relative to the config file [default: allowing all
+    /// crawlers & listing the sitemap, unless the build has a `robots.txt` file already]
+    pub robots: Option<PathBuf>,
+}
+
+/// Config options for a size budget of the built files.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigOptsBuildBudget {
+    /// The glob of the files the budget applies to, matched against their path relative to the
+    /// dist dir if it includes a `/`, otherwise against their file name.
+    pub files: String,
+    /// The maximum size, e.g. `900KB`, `150KiB`, `1.5MB` or `1024` (bytes).
+    pub max_size: String,
+    /// Whether to check the gzip compressed size of the files rather than their size [default:
+    /// false]
+    #[serde(default)]
+    pub gzip: bool,
+    /// Whether to check the total size of all matching files rather than the size of each
+    /// [default: false]
+    #[serde(default)]
+    pub total: bool,
+}
+
+/// Config options for a command transforming the built files.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigOptsBuildTransform {
+    /// The glob of the files the command transforms, matched against their path relative to the
+    /// dist dir if it includes a `/`, otherwise against their file name.
+    pub files: String,
+    /// The command, reading the content of a file from stdin & writing the transformed content
+    /// to stdout.
+    pub command: String,
+    /// Any arguments to pass to the command.
+    #[serde(default)]
+    pub command_arguments: Vec<String>,
+}
+
+/// Config options for a plugin, building the assets of a `rel` value via an external command.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigOptsBuildPlugin {
+    /// The `rel` value of the `<link data-trunk>` assets the plugin builds.
+    pub rel: String,
+    /// The command, reading the asset as JSON from stdin & writing its outputs as JSON to stdout.
+    pub command: String,
+    /// Any arguments to pass to the command.
+    #[serde(default)]
+    pub command_arguments: Vec<String>,
+}
+
+/// Config options for another app of the workspace to build.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigOptsBuildApp {
+    /// The index HTML file of the app.
+    pub target: PathBuf,
+    /// The subdir of the dist dir the app is built into, which is also the subpath of the public
+    /// URL the app is served from.
+    pub path: String,
+}
+
 /// Config options for the watch system.
 #[derive(Clone, Debug, Default, Deserialize, Args)]
 pub struct ConfigOptsWatch {
     /// Watch specific file(s) or folder(s) [default: build target parent folder]
     #[arg(short, long, value_name = "path")]
+    #[serde(alias = "paths")]
     pub watch: Option<Vec<PathBuf>>,
-    /// Paths to ignore [default: []]
+    /// Paths or globs, e.g. `**/*.md`, to ignore [default: []]
     #[arg(short, long, value_name = "path")]
     pub ignore: Option<Vec<PathBuf>>,
+    /// Poll the filesystem for changes at the given interval, for filesystems without change
+    /// notifications such as Docker volumes, NFS or WSL2 mounts [default: false, interval: 1s]
+    #[arg(
+        long = "watch-poll",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1s",
+        value_parser = humantime::parse_duration,
+        value_name = "INTERVAL"
+    )]
+    #[serde(default, deserialize_with = "deserialize_poll")]
+    pub poll: Option<Duration>,
+    /// The time to wait for further changes before triggering a build, such as `200ms` [default:
+    /// 25ms]
+    #[arg(long = "watch-debounce", value_parser = humantime::parse_duration, value_name = "DURATION")]
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub debounce: Option<Duration>,
+    /// Rebuild all assets on each change, rather than only re-running the pipelines of the
+    /// assets affected by it [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub full_rebuilds: bool,
 }
 
 /// Config options for the serve system.
 #[derive(Clone, Debug, Default, Deserialize, Args)]
 pub struct ConfigOptsServe {
-    /// The address to serve on [default: 127.0.0.1]
-    #[arg(long)]
-    pub address: Option<IpAddr>,
-    /// The port to serve on [default: 8080]
+    /// The IP address to serve on, or the path of a Unix domain socket prefixed with `unix:`
+    /// [default: 127.0.0.1]
     #[arg(long)]
+    pub address: Option<String>,
+    /// The port to serve on, with `0` or `auto` selecting a free port [default: 8080]
+    #[arg(long, value_parser = parse_port)]
+    #[serde(default, deserialize_with = "deserialize_port")]
     pub port: Option<u16>,
-    /// Open a browser tab once the initial build is complete [default: false]
+    /// Open a browser tab at the given path, or at the public URL, once a build succeeded
+    /// [default: false]
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        value_name = "PATH"
+    )]
+    #[serde(default, deserialize_with = "deserialize_open")]
+    pub open: Option<String>,
+    /// The browser to open the page with, instead of the default browser [default: None]
     #[arg(long)]
-    #[serde(default)]
-    pub open: bool,
+    pub open_browser: Option<String>,
     /// A URL to which requests will be proxied [default: None]
     #[arg(long = "proxy-backend")]
     #[serde(default, deserialize_with = "deserialize_uri")]
     pub proxy_backend: Option<Uri>,
-    /// The URI on which to accept requests which are to be rewritten and proxied to backend
-    /// [default: None]
+    /// The URI on which to accept requests which are to be rewritten and proxied to backend, or
+    /// a regex path rewrite of the form `regex -> replacement` [default: None]
     #[arg(long = "proxy-rewrite")]
     #[serde(default)]
     pub proxy_rewrite: Option<String>,
@@ -133,6 +457,65 @@ pub struct ConfigOptsServe {
     #[arg(long = "no-autoreload")]
     #[serde(default)]
     pub no_autoreload: bool,
+    /// Hot reload the wasm module of the web app via the `__trunk_hmr_dispose` &
+    /// `__trunk_hmr_accept` hooks of the app, instead of reloading the page (experimental)
+    /// [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub hmr: bool,
+    /// The PEM encoded TLS certificate to serve HTTPS with, requires `--tls-key` [default: None]
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// The PEM encoded private key of the TLS certificate, requires `--tls-cert` [default: None]
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Serve HTTPS with a generated self-signed certificate [default: false]
+    #[arg(long, conflicts_with = "tls_cert")]
+    #[serde(default)]
+    pub tls_self_signed: bool,
+    /// Additionally serve HTTP/3 on the UDP port of the same number, requires TLS [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub http3: bool,
+    /// Compress static files on the fly with gzip or brotli, as accepted by the client [default:
+    /// false]
+    #[arg(long)]
+    #[serde(default)]
+    pub compress: bool,
+    /// Require either basic auth with the given `user:password`, or the given access token
+    /// [default: None]
+    #[arg(long = "serve-auth", value_name = "USER:PASSWORD|TOKEN")]
+    pub auth: Option<String>,
+    /// Headers to set on the responses for the paths matching each glob [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub headers: Option<HashMap<String, HashMap<String, String>>>,
+    /// Globs of the paths which fall back to the index HTML file when not found [default: **]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub fallback: Option<Vec<String>>,
+    /// Globs of the paths which never fall back to the index HTML file [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[arg(skip)]
+    #[serde(default)]
+    pub fallback_exclude: Option<Vec<String>>,
+    /// The page served with a 404 status for paths not falling back to the index HTML file,
+    /// relative to the dist dir, e.g. `404.html` [default: None]
+    #[arg(long)]
+    pub not_found: Option<String>,
+    /// Log each request along with its status, latency & whether it was proxied [default: false]
+    #[arg(long)]
+    #[serde(default)]
+    pub log_requests: bool,
+    /// Record all requests & write them as a HAR file to the given path at shutdown [default:
+    /// None]
+    #[arg(long, value_name = "PATH")]
+    pub har: Option<PathBuf>,
 }
 
 /// Config options for the serve system.
@@ -156,8 +539,44 @@ pub struct ConfigOptsTools {
     pub wasm_bindgen: Option<String>,
     /// Version of `wasm-opt` to use.
     pub wasm_opt: Option<String>,
-    /// Version of `tailwindcss-cli` to use.
+    /// Version of `tailwindcss-cli` to use, either a v3 or v4 release.
     pub tailwindcss: Option<String>,
+    /// Version of `oxipng` to use.
+    pub oxipng: Option<String>,
+    /// Version of `cwebp` to use.
+    pub cwebp: Option<String>,
+    /// Version of `esbuild` to use.
+    pub esbuild: Option<String>,
+    /// Version of `less` to use, whose `lessc` runs on the node installed on the system.
+    pub lessc: Option<String>,
+    /// Version of `postcss` to use, unless `postcss-cli` is installed on the system.
+    pub postcss: Option<String>,
+    /// The lockfile pinning the versions & checksums of downloaded tools [default:
+    /// Trunk.lock, next to the config file]
+    pub lockfile: Option<PathBuf>,
+    /// The base URLs of mirrors of the releases of tools, by the name of the tool [default: None]
+    ///
+    /// These values can only be provided via config file.
+    #[serde(default)]
+    pub mirrors: Option<HashMap<String, String>>,
+}
+
+impl ConfigOptsTools {
+    /// The configured version of the given tool, if any.
+    pub fn version(&self, app: Application) -> Option<&str> {
+        match app {
+            Application::Sass => self.sass.as_deref(),
+            Application::WasmBindgen => self.wasm_bindgen.as_deref(),
+            Application::WasmOpt => self.wasm_opt.as_deref(),
+            Application::TailwindCss => self.tailwindcss.as_deref(),
+            Application::Oxipng => self.oxipng.as_deref(),
+            Application::Cwebp => self.cwebp.as_deref(),
+            Application::Esbuild => self.esbuild.as_deref(),
+            Application::Lessc => self.lessc.as_deref(),
+            Application::Postcss => self.postcss.as_deref(),
+            _ => None,
+        }
+    }
 }
 
 /// Config options for building proxies.
@@ -167,6 +586,8 @@ pub struct ConfigOptsTools {
 /// vars.
 #[derive(Clone, Debug, Deserialize)]
 pub struct ConfigOptsProxy {
+    /// An optional name of the proxy, used in its logs & errors.
+    pub name: Option<String>,
     /// The URL of the backend to which requests are to be proxied.
     #[serde(deserialize_with = "deserialize_uri")]
     pub backend: Uri,
@@ -174,7 +595,8 @@ pub struct ConfigOptsProxy {
     /// defaults to the URI of the backend.
     ///
     /// When a value is specified, requests received on this URI will have this URI segment
-    /// replaced with the URI of the `backend`.
+    /// replaced with the URI of the `backend`. A value of the form `regex -> replacement`, such as
+    /// `^/api/v1 -> /v1`, instead rewrites the matching part of the request paths.
     pub rewrite: Option<String>,
     /// Configure the proxy for handling WebSockets.
     #[serde(default)]
@@ -182,6 +604,31 @@ pub struct ConfigOptsProxy {
     /// Configure the proxy to accept insecure certificates.
     #[serde(default)]
     pub insecure: bool,
+    /// The time to wait for a connection to the backend, such as `5s`.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub connect_timeout: Option<Duration>,
+    /// The time to wait for the backend to respond to a request, such as `30s`. Only used by
+    /// HTTP proxies.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub read_timeout: Option<Duration>,
+    /// The number of times to retry requests which failed to connect to the backend.
+    #[serde(default)]
+    pub retries: u32,
+    /// The time to wait before retrying a request, such as `200ms` [default: 500ms]
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub retry_delay: Option<Duration>,
+    /// Headers to set on the requests to the backend, replacing any of the same name.
+    #[serde(default)]
+    pub request_headers: HashMap<String, String>,
+    /// Headers to remove from the requests to the backend.
+    #[serde(default)]
+    pub remove_request_headers: Vec<String>,
+    /// Headers to set on the responses of the backend, replacing any of the same name.
+    #[serde(default)]
+    pub response_headers: HashMap<String, String>,
+    /// Headers to remove from the responses of the backend.
+    #[serde(default)]
+    pub remove_response_headers: Vec<String>,
 }
 
 /// Config options for build system hooks.
@@ -195,6 +642,81 @@ pub struct ConfigOptsHook {
     /// Any arguments to pass to the command.
     #[serde(default)]
     pub command_arguments: Vec<String>,
+    /// The dir to run the command in, relative to the config file [default: the current dir]
+    pub working_directory: Option<PathBuf>,
+    /// Environment variables set for this hook, added to those of `[build].env`.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// Config options of a named profile, overriding the config of the Trunk config file when
+/// selected with `--profile`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ConfigOptsProfile {
+    /// Build in release mode.
+    pub release: Option<bool>,
+    /// The public URL from which assets are to be served.
+    pub public_url: Option<String>,
+    /// The wasm-opt level of Rust apps without a `data-wasm-opt` attribute.
+    pub wasm_opt_level: Option<String>,
+    /// The passes run by wasm-opt after optimizing.
+    pub wasm_opt_passes: Option<Vec<String>>,
+    /// The wasm features enabled for wasm-opt.
+    pub wasm_opt_features: Option<Vec<String>>,
+    /// Whether to minify JS & tailwind CSS assets without a `data-minify` attribute.
+    pub minify: Option<bool>,
+    /// Environment variables set for cargo builds & hooks, added to those of `[build].env`.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// The target dir of cargo builds.
+    pub cargo_target_dir: Option<PathBuf>,
+    /// A wrapper of rustc for cargo builds, such as `sccache`.
+    pub rustc_wrapper: Option<String>,
+    /// Environment variables set for cargo builds only, added to those of `[build].cargo_env`.
+    #[serde(default)]
+    pub cargo_env: HashMap<String, String>,
+    /// The proxies to use instead of those of the config file.
+    pub proxy: Option<Vec<ConfigOptsProxy>>,
+}
+
+/// Move the proxies of `[[serve.proxy]]` to those of `[[proxy]]` in the given config value, as
+/// they are handled along with them.
+pub(super) fn move_serve_proxies(cfg_value: &mut toml::Value) -> Result<()> {
+    let serve_proxies = cfg_value
+        .get_mut("serve")
+        .and_then(toml::Value::as_table_mut)
+        .and_then(|serve| serve.remove("proxy"));
+    if let (Some(proxies), Some(cfg_table)) = (serve_proxies, cfg_value.as_table_mut()) {
+        let all = cfg_table
+            .entry("proxy")
+            .or_insert_with(|| toml::Value::Array(Vec::new()));
+        match (all, proxies) {
+            (toml::Value::Array(all), toml::Value::Array(proxies)) => all.extend(proxies),
+            _ => bail!("`proxy` & `serve.proxy` of the config must be arrays of tables"),
+        }
+    }
+    Ok(())
+}
+
+/// Interpolate the allowed env vars into all string values of the given TOML value.
+fn interpolate_toml_env(value: &mut toml::Value, allowlist: &[String]) -> Result<()> {
+    match value {
+        toml::Value::String(val) => {
+            *val = common::interpolate_env(val, |name| common::allowed_env_var(name, allowlist))?;
+        }
+        toml::Value::Array(values) => {
+            for val in values {
+                interpolate_toml_env(val, allowlist)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, val) in table.iter_mut() {
+                interpolate_toml_env(val, allowlist)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
 }
 
 /// Deserialize a Uri from a string.
@@ -209,6 +731,170 @@ where
         .map_err(|err| serde::de::Error::custom(err.to_string()))
 }
 
+/// Deserialize the page to open from either a boolean, opening the public URL if `true`, or a path.
+fn deserialize_open<'de, D>(data: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Open {
+        Bool(bool),
+        Path(String),
+    }
+    Ok(match Open::deserialize(data)? {
+        Open::Bool(true) => Some(String::new()),
+        Open::Bool(false) => None,
+        // Env vars are always strings.
+        Open::Path(path) if path == "true" => Some(String::new()),
+        Open::Path(path) if path == "false" => None,
+        Open::Path(path) => Some(path),
+    })
+}
+
+/// Parse the port to serve on, with `auto` selecting a free port like `0`.
+fn parse_port(val: &str) -> std::result::Result<u16, std::num::ParseIntError> {
+    match val {
+        "auto" => Ok(0),
+        val => val.parse(),
+    }
+}
+
+/// Deserialize the port to serve on from either a number or a string, which may be `auto`.
+fn deserialize_port<'de, D>(data: D) -> std::result::Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Port {
+        Number(u16),
+        // Env vars are always strings.
+        String(String),
+    }
+    match Port::deserialize(data)? {
+        Port::Number(port) => Ok(Some(port)),
+        Port::String(val) => parse_port(&val)
+            .map(Some)
+            .map_err(|err| serde::de::Error::custom(format!("invalid port {:?}: {}", val, err))),
+    }
+}
+
+/// The polling interval of the watcher if polling is enabled without an interval.
+const DEFAULT_WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Deserialize the polling interval of the watcher, from either a bool enabling polling at the
+/// default interval, or a human readable interval.
+fn deserialize_poll<'de, D>(data: D) -> std::result::Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Poll {
+        Bool(bool),
+        // Env vars are always strings.
+        String(String),
+    }
+    match Poll::deserialize(data)? {
+        Poll::Bool(true) => Ok(Some(DEFAULT_WATCH_POLL_INTERVAL)),
+        Poll::Bool(false) => Ok(None),
+        Poll::String(val) => match val.as_str() {
+            "true" => Ok(Some(DEFAULT_WATCH_POLL_INTERVAL)),
+            "false" => Ok(None),
+            _ => humantime::parse_duration(&val).map(Some).map_err(|err| {
+                serde::de::Error::custom(format!("invalid duration {:?}: {}", val, err))
+            }),
+        },
+    }
+}
+
+/// Deserialize a Duration from a human readable string, such as `1m 30s`.
+fn deserialize_duration<'de, D>(data: D) -> std::result::Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let val = String::deserialize(data)?;
+    humantime::parse_duration(&val)
+        .map(Some)
+        .map_err(|err| serde::de::Error::custom(format!("invalid duration {:?}: {}", val, err)))
+}
+
+/// Config options for deploying the dist dir via `trunk deploy`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ConfigOptsDeploy {
+    /// The target deployed to, unless one is given to `trunk deploy` [default: the only target]
+    pub default: Option<String>,
+    /// The targets of deploys, by their name.
+    pub targets: Option<HashMap<String, ConfigOptsDeployTarget>>,
+}
+
+/// A target of deploys, selected via its `kind`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ConfigOptsDeployTarget {
+    /// Force-push the dist dir as the only commit of a branch of a git remote, served by GitHub
+    /// Pages.
+    GithubPages {
+        /// The name or URL of the git remote [default: origin]
+        remote: Option<String>,
+        /// The branch served by GitHub Pages [default: gh-pages]
+        branch: Option<String>,
+        /// The custom domain of the site, written to a `CNAME` file.
+        cname: Option<String>,
+        /// The message of the commit [default: Deploy <commit of the project>]
+        message: Option<String>,
+    },
+    /// Sync the dist dir to an S3 bucket via the `aws` CLI, optionally invalidating the cache of a
+    /// CloudFront distribution.
+    S3 {
+        /// The bucket.
+        bucket: String,
+        /// The prefix of the keys of the files [default: None]
+        prefix: Option<String>,
+        /// The region of the bucket [default: the region of the `aws` CLI]
+        region: Option<String>,
+        /// The ID of the CloudFront distribution whose cache is invalidated.
+        cloudfront_distribution: Option<String>,
+        /// Additional arguments of `aws s3 sync`, such as `--cache-control`.
+        args: Option<Vec<String>>,
+    },
+    /// Deploy the dist dir to Netlify via the `netlify` CLI.
+    Netlify {
+        /// The ID or name of the site [default: the site linked via `netlify link`]
+        site: Option<String>,
+        /// Deploy to production, rather than to a draft URL [default: false]
+        #[serde(default)]
+        prod: bool,
+        /// The message of the deploy.
+        message: Option<String>,
+    },
+    /// Sync the dist dir to a destination via `rsync`.
+    Rsync {
+        /// The destination, such as `user@host:/var/www/app`.
+        destination: String,
+        /// Delete the files of the destination which are not in the dist dir [default: false]
+        #[serde(default)]
+        delete: bool,
+        /// Additional arguments of `rsync`.
+        args: Option<Vec<String>>,
+    },
+    /// Copy the dist dir to a destination via `scp`.
+    Scp {
+        /// The destination, such as `user@host:/var/www/app`.
+        destination: String,
+        /// Additional arguments of `scp`.
+        args: Option<Vec<String>>,
+    },
+    /// Run a custom command, given the dist dir via the `TRUNK_DIST_DIR` env var.
+    Command {
+        /// The command.
+        command: String,
+        /// The arguments of the command.
+        args: Option<Vec<String>>,
+    },
+}
+
 /// A model of all potential configuration options for the Trunk CLI system.
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct ConfigOpts {
@@ -217,14 +903,19 @@ pub struct ConfigOpts {
     pub serve: Option<ConfigOptsServe>,
     pub clean: Option<ConfigOptsClean>,
     pub tools: Option<ConfigOptsTools>,
+    pub deploy: Option<ConfigOptsDeploy>,
     pub proxy: Option<Vec<ConfigOptsProxy>>,
     pub hooks: Option<Vec<ConfigOptsHook>>,
+    pub profile: Option<HashMap<String, ConfigOptsProfile>>,
 }
 
 impl ConfigOpts {
     /// Extract the runtime config for the build system based on all config layers.
-    pub fn rtc_build(cli_build: ConfigOptsBuild, config: Option<PathBuf>) -> Result<Arc<RtcBuild>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+    pub fn rtc_build(
+        cli_build: ConfigOptsBuild,
+        config: impl Into<Configuration>,
+    ) -> Result<Arc<RtcBuild>> {
+        let base_layer = Self::file_and_env_layers(config.into(), cli_build.profile.clone())?;
         let build_layer = Self::cli_opts_layer_build(cli_build, base_layer);
         let build_opts = build_layer.build.unwrap_or_default();
         let tools_opts = build_layer.tools.unwrap_or_default();
@@ -238,9 +929,9 @@ impl ConfigOpts {
     pub fn rtc_watch(
         cli_build: ConfigOptsBuild,
         cli_watch: ConfigOptsWatch,
-        config: Option<PathBuf>,
+        config: impl Into<Configuration>,
     ) -> Result<Arc<RtcWatch>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+        let base_layer = Self::file_and_env_layers(config.into(), cli_build.profile.clone())?;
         let build_layer = Self::cli_opts_layer_build(cli_build, base_layer);
         let watch_layer = Self::cli_opts_layer_watch(cli_watch, build_layer);
         let build_opts = watch_layer.build.unwrap_or_default();
@@ -257,9 +948,9 @@ impl ConfigOpts {
         cli_build: ConfigOptsBuild,
         cli_watch: ConfigOptsWatch,
         cli_serve: ConfigOptsServe,
-        config: Option<PathBuf>,
+        config: impl Into<Configuration>,
     ) -> Result<Arc<RtcServe>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+        let base_layer = Self::file_and_env_layers(config.into(), cli_build.profile.clone())?;
         let build_layer = Self::cli_opts_layer_build(cli_build, base_layer);
         let watch_layer = Self::cli_opts_layer_watch(cli_watch, build_layer);
         let serve_layer = Self::cli_opts_layer_serve(cli_serve, watch_layer);
@@ -279,16 +970,19 @@ impl ConfigOpts {
     }
 
     /// Extract the runtime config for the clean system based on all config layers.
-    pub fn rtc_clean(cli_clean: ConfigOptsClean, config: Option<PathBuf>) -> Result<Arc<RtcClean>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+    pub fn rtc_clean(
+        cli_clean: ConfigOptsClean,
+        config: impl Into<Configuration>,
+    ) -> Result<Arc<RtcClean>> {
+        let base_layer = Self::file_and_env_layers(config.into(), None)?;
         let clean_layer = Self::cli_opts_layer_clean(cli_clean, base_layer);
         let clean_opts = clean_layer.clean.unwrap_or_default();
         Ok(Arc::new(RtcClean::new(clean_opts)))
     }
 
-    /// Return the full configuration based on config file & environment variables.
-    pub fn full(config: Option<PathBuf>) -> Result<Self> {
-        Self::file_and_env_layers(config)
+    /// Return the full configuration based on config file, environment variables & overrides.
+    pub fn full(config: impl Into<Configuration>) -> Result<Self> {
+        Self::file_and_env_layers(config.into(), None)
     }
 
     fn cli_opts_layer_build(cli: ConfigOptsBuild, cfg_base: Self) -> Self {
@@ -300,7 +994,37 @@ impl ConfigOpts {
             no_default_features: cli.no_default_features,
             all_features: cli.all_features,
             features: cli.features,
+            wasm_opt_level: cli.wasm_opt_level,
+            wasm_opt_passes: cli.wasm_opt_passes,
+            wasm_opt_features: cli.wasm_opt_features,
+            minify: cli.minify,
             filehash: cli.filehash,
+            hash_algorithm: cli.hash_algorithm,
+            hash_length: cli.hash_length,
+            hash_placement: cli.hash_placement,
+            precompress: cli.precompress,
+            integrity: cli.integrity,
+            jobs: cli.jobs,
+            cache: cli.cache,
+            cache_dir: cli.cache_dir,
+            cargo_target_dir: cli.cargo_target_dir,
+            rustc_wrapper: cli.rustc_wrapper,
+            cargo_env: cli.cargo_env,
+            reproducible: cli.reproducible,
+            frozen: cli.frozen,
+            offline: cli.offline,
+            profile: cli.profile,
+            targets: cli.targets,
+            apps: cli.apps,
+            env: cli.env,
+            env_allowlist: cli.env_allowlist,
+            budgets: cli.budgets,
+            transforms: cli.transforms,
+            plugins: cli.plugins,
+            layout: cli.layout,
+            wasm_bindgen: cli.wasm_bindgen,
+            prerender: cli.prerender,
+            sitemap: cli.sitemap,
             inject_scripts: cli.inject_scripts,
             pattern_script: cli.pattern_script,
             pattern_preload: cli.pattern_preload,
@@ -312,8 +1036,10 @@ impl ConfigOpts {
             serve: None,
             clean: None,
             tools: None,
+            deploy: None,
             proxy: None,
             hooks: None,
+            profile: None,
         };
         Self::merge(cfg_base, cfg_build)
     }
@@ -322,6 +1048,9 @@ impl ConfigOpts {
         let opts = ConfigOptsWatch {
             watch: cli.watch,
             ignore: cli.ignore,
+            poll: cli.poll,
+            debounce: cli.debounce,
+            full_rebuilds: cli.full_rebuilds,
         };
         let cfg = ConfigOpts {
             build: None,
@@ -329,8 +1058,10 @@ impl ConfigOpts {
             serve: None,
             clean: None,
             tools: None,
+            deploy: None,
             proxy: None,
             hooks: None,
+            profile: None,
         };
         Self::merge(cfg_base, cfg)
     }
@@ -340,11 +1071,25 @@ impl ConfigOpts {
             address: cli.address,
             port: cli.port,
             open: cli.open,
+            open_browser: cli.open_browser,
             proxy_backend: cli.proxy_backend,
             proxy_rewrite: cli.proxy_rewrite,
             proxy_insecure: cli.proxy_insecure,
             proxy_ws: cli.proxy_ws,
             no_autoreload: cli.no_autoreload,
+            hmr: cli.hmr,
+            tls_cert: cli.tls_cert,
+            tls_key: cli.tls_key,
+            tls_self_signed: cli.tls_self_signed,
+            http3: cli.http3,
+            compress: cli.compress,
+            auth: cli.auth,
+            headers: None,
+            fallback: None,
+            fallback_exclude: None,
+            not_found: cli.not_found,
+            log_requests: cli.log_requests,
+            har: cli.har,
         };
         let cfg = ConfigOpts {
             build: None,
@@ -352,8 +1097,10 @@ impl ConfigOpts {
             serve: Some(opts),
             clean: None,
             tools: None,
+            deploy: None,
             proxy: None,
             hooks: None,
+            profile: None,
         };
         Self::merge(cfg_base, cfg)
     }
@@ -369,25 +1116,89 @@ impl ConfigOpts {
             serve: None,
             clean: Some(opts),
             tools: None,
+            deploy: None,
             proxy: None,
             hooks: None,
+            profile: None,
         };
         Self::merge(cfg_base, cfg)
     }
 
-    fn file_and_env_layers(path: Option<PathBuf>) -> Result<Self> {
-        let toml_cfg = Self::from_file(path)?;
+    fn file_and_env_layers(config: Configuration, profile: Option<String>) -> Result<Self> {
+        let mut toml_cfg = Self::from_file(config.config_file())?;
         let env_cfg = Self::from_env().context("error reading trunk env var config")?;
-        let cfg = Self::merge(toml_cfg, env_cfg);
+        let override_cfg = config.overrides()?;
+        // A profile given on the CLI takes precedence over one given via overrides, env var or
+        // config file.
+        let profile = profile
+            .or_else(|| override_cfg.build.as_ref()?.profile.clone())
+            .or_else(|| env_cfg.build.as_ref()?.profile.clone())
+            .or_else(|| toml_cfg.build.as_ref()?.profile.clone());
+        if let Some(profile) = profile {
+            toml_cfg = toml_cfg.apply_profile(&profile)?;
+        }
+        let cfg = Self::merge(Self::merge(toml_cfg, env_cfg), override_cfg);
         Ok(cfg)
     }
 
+    /// Apply the profile of the config file with the given name, overriding the config of the
+    /// file itself.
+    ///
+    /// The profile is applied to the config file layer only, env vars & CLI args still take
+    /// precedence.
+    fn apply_profile(mut self, name: &str) -> Result<Self> {
+        let profile = self
+            .profile
+            .as_ref()
+            .and_then(|profiles| profiles.get(name))
+            .cloned()
+            .with_context(|| {
+                format!(
+                    "profile {:?} not found; please ensure it is declared as [profile.{}] in the \
+                     Trunk config file",
+                    name, name
+                )
+            })?;
+        let build = self.build.get_or_insert_with(Default::default);
+        if let Some(release) = profile.release {
+            build.release = release;
+        }
+        build.public_url = profile.public_url.or(build.public_url.take());
+        build.wasm_opt_level = profile.wasm_opt_level.or(build.wasm_opt_level.take());
+        build.wasm_opt_passes = profile.wasm_opt_passes.or(build.wasm_opt_passes.take());
+        build.wasm_opt_features = profile.wasm_opt_features.or(build.wasm_opt_features.take());
+        build.minify = profile.minify.or(build.minify);
+        if !profile.env.is_empty() {
+            build
+                .env
+                .get_or_insert_with(Default::default)
+                .extend(profile.env);
+        }
+        build.cargo_target_dir = profile.cargo_target_dir.or(build.cargo_target_dir.take());
+        build.rustc_wrapper = profile.rustc_wrapper.or(build.rustc_wrapper.take());
+        if !profile.cargo_env.is_empty() {
+            build
+                .cargo_env
+                .get_or_insert_with(Default::default)
+                .extend(profile.cargo_env);
+        }
+        self.proxy = profile.proxy.or(self.proxy.take());
+        Ok(self)
+    }
+
     /// Read runtime config from a `Trunk.toml` file at the target path.
     ///
     /// NOTE WELL: any paths specified in a Trunk.toml file must be interpreted as being relative
     /// to the file itself.
     fn from_file(path: Option<PathBuf>) -> Result<Self> {
-        let mut trunk_toml_path = path.unwrap_or_else(|| "Trunk.toml".into());
+        // Without a path, either a `Trunk.toml` or a `Trunk.json` file is used.
+        let mut trunk_toml_path = path.unwrap_or_else(|| {
+            ["Trunk.toml", "Trunk.json"]
+                .into_iter()
+                .map(PathBuf::from)
+                .find(|path| path.exists())
+                .unwrap_or_else(|| "Trunk.toml".into())
+        });
         if !trunk_toml_path.exists() {
             return Ok(Default::default());
         }
@@ -399,10 +1210,41 @@ impl ConfigOpts {
                 )
             })?;
         }
+        Self::read_file(trunk_toml_path, &mut Vec::new())
+    }
+
+    /// Read the config file at the given canonical path, along with the config files it extends.
+    ///
+    /// `extended` holds the files extended by the files already read, to detect cycles.
+    fn read_file(trunk_toml_path: PathBuf, extended: &mut Vec<PathBuf>) -> Result<Self> {
         let cfg_bytes =
             std::fs::read_to_string(&trunk_toml_path).context("error reading config file")?;
-        let mut cfg: Self = toml::from_str(&cfg_bytes)
-            .context("error reading config file contents as TOML data")?;
+        // JSON config files are read into the same model as TOML files.
+        let mut cfg_value: toml::Value =
+            match trunk_toml_path.extension().is_some_and(|ext| ext == "json") {
+                true => serde_json::from_str(&cfg_bytes)
+                    .context("error reading config file contents as JSON data")?,
+                false => toml::from_str(&cfg_bytes)
+                    .context("error reading config file contents as TOML data")?,
+            };
+        // Only the env vars allowed by the file itself may be interpolated into its values.
+        let env_allowlist: Vec<String> = cfg_value
+            .get("build")
+            .and_then(|build| build.get("env_allowlist"))
+            .and_then(toml::Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| name.as_str().map(String::from))
+            .collect();
+        interpolate_toml_env(&mut cfg_value, &env_allowlist)
+            .context("error interpolating env vars into config file")?;
+        let extends = cfg_value
+            .as_table_mut()
+            .and_then(|cfg_table| cfg_table.remove("extends"));
+        move_serve_proxies(&mut cfg_value)?;
+        let mut cfg: Self = cfg_value
+            .try_into()
+            .context("error reading config file contents")?;
         if let Some(parent) = trunk_toml_path.parent() {
             if let Some(build) = cfg.build.as_mut() {
                 if let Some(target) = build.target.as_mut() {
@@ -416,11 +1258,64 @@ impl ConfigOpts {
                             })?;
                     }
                 }
+                for target in build.targets.iter_mut().flatten() {
+                    if !target.target.is_absolute() {
+                        target.target = std::fs::canonicalize(parent.join(&target.target))
+                            .with_context(|| {
+                                format!(
+                                    "error taking canonical path to [[build.targets]] target {:?} \
+                                     in {:?}",
+                                    target.target, trunk_toml_path
+                                )
+                            })?;
+                    }
+                }
+                for app in build.apps.iter_mut().flatten() {
+                    if !app.target.is_absolute() {
+                        app.target =
+                            std::fs::canonicalize(parent.join(&app.target)).with_context(|| {
+                                format!(
+                                    "error taking canonical path to [[build.apps]] target {:?} in \
+                                     {:?}",
+                                    app.target, trunk_toml_path
+                                )
+                            })?;
+                    }
+                }
                 if let Some(dist) = build.dist.as_mut() {
                     if !dist.is_absolute() {
                         *dist = parent.join(&dist);
                     }
                 }
+                if let Some(cache_dir) = build.cache_dir.as_mut() {
+                    if !cache_dir.is_absolute() {
+                        *cache_dir = parent.join(&cache_dir);
+                    }
+                }
+                if let Some(target_dir) = build.cargo_target_dir.as_mut() {
+                    if !target_dir.is_absolute() {
+                        *target_dir = parent.join(&target_dir);
+                    }
+                }
+                // Plugin commands with a path are relative to the file, others are looked up in
+                // PATH.
+                for plugin in build.plugins.iter_mut().flatten() {
+                    if plugin.command.contains('/') && !Path::new(&plugin.command).is_absolute() {
+                        let command = plugin.command.trim_start_matches("./");
+                        plugin.command = parent.join(command).display().to_string();
+                    }
+                }
+                if let Some(robots) = build.sitemap.as_mut().and_then(|s| s.robots.as_mut()) {
+                    if !robots.is_absolute() {
+                        *robots = parent.join(&robots);
+                    }
+                }
+            }
+            // The lockfile is kept next to the config file, unless configured otherwise.
+            let tools = cfg.tools.get_or_insert_with(Default::default);
+            let lockfile = tools.lockfile.get_or_insert_with(|| "Trunk.lock".into());
+            if !lockfile.is_absolute() {
+                *lockfile = parent.join(&lockfile);
             }
             if let Some(watch) = cfg.watch.as_mut() {
                 if let Some(watch_paths) = watch.watch.as_mut() {
@@ -438,7 +1333,14 @@ impl ConfigOpts {
                 }
                 if let Some(ignore_paths) = watch.ignore.as_mut() {
                     for path in ignore_paths.iter_mut() {
-                        if !path.is_absolute() {
+                        // Globs are relative to the file as well, but may not match any path yet.
+                        if super::is_glob(path) {
+                            if !path.is_absolute() {
+                                *path =
+                                    PathBuf::from(glob::Pattern::escape(&parent.to_string_lossy()))
+                                        .join(&path);
+                            }
+                        } else if !path.is_absolute() {
                             *path =
                                 std::fs::canonicalize(parent.join(&path)).with_context(|| {
                                     format!(
@@ -451,6 +1353,38 @@ impl ConfigOpts {
                     }
                 }
             }
+            if let Some(serve) = cfg.serve.as_mut() {
+                for path in [
+                    serve.tls_cert.as_mut(),
+                    serve.tls_key.as_mut(),
+                    serve.har.as_mut(),
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    if !path.is_absolute() {
+                        *path = parent.join(&path);
+                    }
+                }
+            }
+            for hook in cfg.hooks.iter_mut().flatten() {
+                if let Some(dir) = hook.working_directory.as_mut() {
+                    if !dir.is_absolute() {
+                        *dir = parent.join(&dir);
+                    }
+                }
+            }
+            for profile in cfg
+                .profile
+                .iter_mut()
+                .flat_map(|profiles| profiles.values_mut())
+            {
+                if let Some(target_dir) = profile.cargo_target_dir.as_mut() {
+                    if !target_dir.is_absolute() {
+                        *target_dir = parent.join(&target_dir);
+                    }
+                }
+            }
             if let Some(clean) = cfg.clean.as_mut() {
                 if let Some(dist) = clean.dist.as_mut() {
                     if !dist.is_absolute() {
@@ -459,7 +1393,45 @@ impl ConfigOpts {
                 }
             }
         }
-        Ok(cfg)
+
+        // The config of the file takes precedence over the config of the file it extends.
+        let base_path = match (extends, trunk_toml_path.parent()) {
+            (None | Some(toml::Value::Boolean(false)), _) => return Ok(cfg),
+            (Some(toml::Value::String(base)), Some(parent)) => {
+                std::fs::canonicalize(parent.join(&base)).with_context(|| {
+                    format!(
+                        "error taking canonical path to extended config file {:?} in {:?}",
+                        base, trunk_toml_path
+                    )
+                })?
+            }
+            // The closest config file of the parent dirs, such as the one of the workspace.
+            (Some(toml::Value::Boolean(true)), Some(parent)) => parent
+                .ancestors()
+                .skip(1)
+                .flat_map(|dir| [dir.join("Trunk.toml"), dir.join("Trunk.json")])
+                .find(|path| path.exists())
+                .with_context(|| {
+                    format!(
+                        "no config file to extend found in the parent dirs of {:?}",
+                        trunk_toml_path
+                    )
+                })?,
+            _ => bail!(
+                "`extends` of {:?} must be the path of a config file or `true`",
+                trunk_toml_path
+            ),
+        };
+        extended.push(trunk_toml_path.clone());
+        if extended.contains(&base_path) {
+            bail!(
+                "the config file {:?} extends itself via {:?}",
+                base_path,
+                trunk_toml_path
+            );
+        }
+        let base = Self::read_file(base_path, extended)?;
+        Ok(Self::merge(base, cfg))
     }
 
     fn from_env() -> Result<Self> {
@@ -469,8 +1441,10 @@ impl ConfigOpts {
             serve: Some(envy::prefixed("TRUNK_SERVE_").from_env()?),
             clean: Some(envy::prefixed("TRUNK_CLEAN_").from_env()?),
             tools: Some(envy::prefixed("TRUNK_TOOLS_").from_env()?),
+            deploy: None,
             proxy: None,
             hooks: None,
+            profile: None,
         })
     }
 
@@ -483,11 +1457,56 @@ impl ConfigOpts {
                 g.target = g.target.or(l.target);
                 g.dist = g.dist.or(l.dist);
                 g.public_url = g.public_url.or(l.public_url);
+                g.wasm_opt_level = g.wasm_opt_level.or(l.wasm_opt_level);
+                g.wasm_opt_passes = g.wasm_opt_passes.or(l.wasm_opt_passes);
+                g.wasm_opt_features = g.wasm_opt_features.or(l.wasm_opt_features);
+                g.minify = g.minify.or(l.minify);
                 g.filehash = g.filehash.or(l.filehash);
+                g.hash_algorithm = g.hash_algorithm.or(l.hash_algorithm);
+                g.hash_length = g.hash_length.or(l.hash_length);
+                g.hash_placement = g.hash_placement.or(l.hash_placement);
+                g.jobs = g.jobs.or(l.jobs);
+                g.cache = g.cache.or(l.cache);
+                g.cache_dir = g.cache_dir.or(l.cache_dir);
+                g.cargo_target_dir = g.cargo_target_dir.or(l.cargo_target_dir);
+                g.rustc_wrapper = g.rustc_wrapper.or(l.rustc_wrapper);
+                g.cargo_env = g.cargo_env.or(l.cargo_env);
+                g.targets = g.targets.or(l.targets);
+                g.apps = g.apps.or(l.apps);
+                g.profile = g.profile.or(l.profile);
+                g.env = g.env.or(l.env);
+                g.env_allowlist = g.env_allowlist.or(l.env_allowlist);
+                g.budgets = g.budgets.or(l.budgets);
+                g.transforms = g.transforms.or(l.transforms);
+                g.plugins = g.plugins.or(l.plugins);
+                g.layout = g.layout.or(l.layout);
+                g.wasm_bindgen = g.wasm_bindgen.or(l.wasm_bindgen);
+                g.prerender = g.prerender.or(l.prerender);
+                g.sitemap = g.sitemap.or(l.sitemap);
                 // NOTE: this can not be disabled in the cascade.
                 if l.release {
                     g.release = true;
                 }
+                // NOTE: this can not be disabled in the cascade.
+                if l.precompress {
+                    g.precompress = true;
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.integrity {
+                    g.integrity = true;
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.reproducible {
+                    g.reproducible = true;
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.frozen {
+                    g.frozen = true;
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.offline {
+                    g.offline = true;
+                }
                 g.inject_scripts = g.inject_scripts.or(l.inject_scripts);
                 g.pattern_preload = g.pattern_preload.or(l.pattern_preload);
                 g.pattern_script = g.pattern_script.or(l.pattern_script);
@@ -501,6 +1520,12 @@ impl ConfigOpts {
             (Some(l), Some(mut g)) => {
                 g.watch = g.watch.or(l.watch);
                 g.ignore = g.ignore.or(l.ignore);
+                g.poll = g.poll.or(l.poll);
+                g.debounce = g.debounce.or(l.debounce);
+                // NOTE: this can not be disabled in the cascade.
+                if l.full_rebuilds {
+                    g.full_rebuilds = true;
+                }
                 Some(g)
             }
         };
@@ -518,9 +1543,38 @@ impl ConfigOpts {
                     g.no_autoreload = true;
                 }
                 // NOTE: this can not be disabled in the cascade.
-                if l.open {
-                    g.open = true;
+                if l.hmr {
+                    g.hmr = true;
+                }
+                g.open = g.open.or(l.open);
+                g.open_browser = g.open_browser.or(l.open_browser);
+                // The certificate & its key are only taken from the same layer.
+                if g.tls_cert.is_none() && g.tls_key.is_none() {
+                    g.tls_cert = l.tls_cert;
+                    g.tls_key = l.tls_key;
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.tls_self_signed {
+                    g.tls_self_signed = true;
                 }
+                // NOTE: this can not be disabled in the cascade.
+                if l.http3 {
+                    g.http3 = true;
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.compress {
+                    g.compress = true;
+                }
+                g.auth = g.auth.or(l.auth);
+                g.headers = g.headers.or(l.headers);
+                g.fallback = g.fallback.or(l.fallback);
+                g.fallback_exclude = g.fallback_exclude.or(l.fallback_exclude);
+                g.not_found = g.not_found.or(l.not_found);
+                // NOTE: this can not be disabled in the cascade.
+                if l.log_requests {
+                    g.log_requests = true;
+                }
+                g.har = g.har.or(l.har);
                 Some(g)
             }
         };
@@ -531,6 +1585,14 @@ impl ConfigOpts {
                 g.sass = g.sass.or(l.sass);
                 g.wasm_bindgen = g.wasm_bindgen.or(l.wasm_bindgen);
                 g.wasm_opt = g.wasm_opt.or(l.wasm_opt);
+                g.tailwindcss = g.tailwindcss.or(l.tailwindcss);
+                g.oxipng = g.oxipng.or(l.oxipng);
+                g.cwebp = g.cwebp.or(l.cwebp);
+                g.esbuild = g.esbuild.or(l.esbuild);
+                g.lessc = g.lessc.or(l.lessc);
+                g.postcss = g.postcss.or(l.postcss);
+                g.lockfile = g.lockfile.or(l.lockfile);
+                g.mirrors = g.mirrors.or(l.mirrors);
                 Some(g)
             }
         };
@@ -546,6 +1608,15 @@ impl ConfigOpts {
                 Some(g)
             }
         };
+        greater.deploy = match (lesser.deploy.take(), greater.deploy.take()) {
+            (None, None) => None,
+            (Some(val), None) | (None, Some(val)) => Some(val),
+            (Some(l), Some(mut g)) => {
+                g.default = g.default.or(l.default);
+                g.targets = g.targets.or(l.targets);
+                Some(g)
+            }
+        };
         greater.proxy = match (lesser.proxy.take(), greater.proxy.take()) {
             (None, None) => None,
             (Some(val), None) | (None, Some(val)) => Some(val),
@@ -556,6 +1627,15 @@ impl ConfigOpts {
             (Some(val), None) | (None, Some(val)) => Some(val),
             (Some(_), Some(g)) => Some(g), // No meshing/merging. Only take the greater value.
         };
+        // Profiles are merged by name, so that extending config files may add profiles.
+        greater.profile = match (lesser.profile.take(), greater.profile.take()) {
+            (None, None) => None,
+            (Some(val), None) | (None, Some(val)) => Some(val),
+            (Some(mut l), Some(g)) => {
+                l.extend(g);
+                Some(l)
+            }
+        };
         greater
     }
 }
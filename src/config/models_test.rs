@@ -1,4 +1,8 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use crate::config::models::*;
+use crate::pipelines::PipelineStage;
 
 #[cfg(not(target_family = "windows"))]
 #[test]
@@ -41,3 +45,453 @@ fn err_bad_trunk_toml_watch_ignore() {
     );
     assert_eq!(err.to_string(), expected_err);
 }
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_toml_watch() -> anyhow::Result<()> {
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let data = cwd.join("tests").join("data");
+    let path = data.join("watch.toml");
+    let cfg = ConfigOpts::rtc_watch(Default::default(), Default::default(), Some(path))?;
+
+    assert_eq!(cfg.paths, [cwd.join("examples").join("yew").join("src")]);
+    let globs: Vec<_> = cfg.ignored_globs.iter().map(|glob| glob.as_str()).collect();
+    assert_eq!(
+        globs,
+        [
+            format!("{}/**/*.md", data.display()),
+            format!("{}/tests/**", data.display())
+        ]
+    );
+    assert_eq!(cfg.poll, Some(Duration::from_millis(500)));
+    assert_eq!(cfg.debounce, Duration::from_millis(100));
+    assert!(cfg.full_rebuilds);
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn err_bad_trunk_toml_build_targets() {
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd
+        .join("tests")
+        .join("data")
+        .join("bad-build-targets.toml");
+    let err =
+        ConfigOpts::rtc_build(Default::default(), Some(path)).expect_err("expected config to err");
+    let expected_err = format!(
+        r#"error taking canonical path to [[build.targets]] target "fake.html" in "{}/tests/data/bad-build-targets.toml""#,
+        cwd.to_string_lossy(),
+    );
+    assert_eq!(err.to_string(), expected_err);
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_toml_profile() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("profiles.toml");
+    let cli = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        profile: Some("staging".into()),
+        ..Default::default()
+    };
+
+    let cfg = ConfigOpts::rtc_build(cli, Some(path))?;
+
+    assert_eq!(cfg.public_url, "/staging/");
+    assert_eq!(cfg.wasm_opt_level.as_deref(), Some("z"));
+    assert_eq!(cfg.wasm_opt_passes, ["strip-debug"]);
+    assert!(cfg.minify && !cfg.release);
+    assert_eq!(cfg.env["API_URL"], "https://staging.example.com");
+    assert_eq!(cfg.env["LOG_LEVEL"], "debug");
+    assert!(!cfg.env.contains_key("CARGO_INCREMENTAL"));
+    assert_eq!(cfg.cargo_env["API_URL"], "https://staging.example.com");
+    assert_eq!(cfg.cargo_env["CARGO_INCREMENTAL"], "0");
+    assert_eq!(cfg.cargo_env["RUSTC_WRAPPER"], "sccache");
+    let target_dir = cwd
+        .join("tests")
+        .join("data")
+        .join("target")
+        .join("staging");
+    assert_eq!(
+        cfg.cargo_env["CARGO_TARGET_DIR"],
+        target_dir.display().to_string()
+    );
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_toml_hooks() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let data = cwd.join("tests").join("data");
+    let cli = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    };
+
+    let cfg = ConfigOpts::rtc_build(cli, Some(data.join("hooks.toml")))?;
+
+    let (generate, ls) = (&cfg.hooks[0], &cfg.hooks[1]);
+    assert_eq!(generate.stage, PipelineStage::PreBuild);
+    assert_eq!(generate.working_directory, Some(data.join("proto")));
+    assert_eq!(generate.env["BUF_CACHE_DIR"], ".cache/buf");
+    assert_eq!(ls.stage, PipelineStage::PostBuild);
+    assert!(ls.working_directory.is_none() && ls.env.is_empty());
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_toml_plugins() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let data = cwd.join("tests").join("data");
+    let cli = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    };
+
+    let cfg = ConfigOpts::rtc_build(cli, Some(data.join("plugins.toml")))?;
+
+    let (mdbook, image_cdn) = (&cfg.plugins[0], &cfg.plugins[1]);
+    assert_eq!(mdbook.rel, "mdbook");
+    assert_eq!(
+        mdbook.command,
+        data.join("plugins")
+            .join("trunk-mdbook")
+            .display()
+            .to_string()
+    );
+    assert_eq!(mdbook.command_arguments, ["--quiet"]);
+    assert_eq!(image_cdn.command, "trunk-image-cdn");
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn err_trunk_toml_unknown_profile() {
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("profiles.toml");
+    let cli = ConfigOptsBuild {
+        profile: Some("production".into()),
+        ..Default::default()
+    };
+    let err = ConfigOpts::rtc_build(cli, Some(path)).expect_err("expected config to err");
+    assert_eq!(
+        err.to_string(),
+        r#"profile "production" not found; please ensure it is declared as [profile.production] in the Trunk config file"#
+    );
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn err_trunk_toml_env_not_allowed() {
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd
+        .join("tests")
+        .join("data")
+        .join("bad-env-interpolation.toml");
+    let err =
+        ConfigOpts::rtc_build(Default::default(), Some(path)).expect_err("expected config to err");
+    assert_eq!(
+        err.to_string(),
+        "error interpolating env vars into config file"
+    );
+    assert_eq!(
+        err.root_cause().to_string(),
+        r#"the env var "HOME" of `%TRUNK_ENV_HOME%` is not allowed to be interpolated; please add it to `build.env_allowlist`"#
+    );
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_toml_serve_proxies() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("proxies.toml");
+    let cli = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    };
+
+    let cfg = ConfigOpts::rtc_serve(cli, Default::default(), Default::default(), Some(path))?;
+
+    assert_eq!(cfg.proxies.len(), 2);
+    assert_eq!(cfg.proxies[0].path(), "/api/");
+    let users = &cfg.proxies[1];
+    assert_eq!(users.name.as_deref(), Some("users"));
+    assert_eq!(users.path(), "/users/");
+    assert_eq!(users.connect_timeout, Some(Duration::from_secs(2)));
+    assert_eq!(users.read_timeout, Some(Duration::from_secs(90)));
+    assert_eq!(users.retries, 3);
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_toml_serve_headers_and_fallback() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("serve-headers.toml");
+    let cli = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    };
+
+    let cfg = ConfigOpts::rtc_serve(
+        cli,
+        Default::default(),
+        Default::default(),
+        Some(path.clone()),
+    )?;
+
+    let globs: Vec<_> = cfg.headers.iter().map(|h| h.glob.as_str()).collect();
+    assert_eq!(globs, ["**", "assets/**"]);
+    assert_eq!(
+        cfg.headers[0].headers["cross-origin-embedder-policy"],
+        "require-corp"
+    );
+    assert_eq!(cfg.headers[1].headers["cache-control"], "max-age=3600");
+    assert_eq!(cfg.fallback, [glob::Pattern::new("**")?]);
+    assert_eq!(cfg.fallback_exclude, [glob::Pattern::new("api/**")?]);
+    assert_eq!(cfg.not_found.as_deref(), Some("404.html"));
+    assert!(cfg.log_requests);
+    // The HAR file is relative to the config file.
+    assert_eq!(cfg.har, Some(path.with_file_name("requests.har")));
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_serve_auth() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("proxies.toml");
+    for (auth, expected) in [
+        (
+            "me:pass:word",
+            crate::config::ServeAuth::Basic {
+                user: "me".into(),
+                password: "pass:word".into(),
+            },
+        ),
+        ("secret", crate::config::ServeAuth::Token("secret".into())),
+    ] {
+        let cli_build = ConfigOptsBuild {
+            dist: Some(tmpdir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let cli_serve = ConfigOptsServe {
+            auth: Some(auth.into()),
+            ..Default::default()
+        };
+        let cfg =
+            ConfigOpts::rtc_serve(cli_build, Default::default(), cli_serve, Some(path.clone()))?;
+        assert_eq!(cfg.auth, Some(expected));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_serve_open() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("proxies.toml");
+    for (open, expected) in [
+        (None, None),
+        (Some(""), Some("/")),
+        (Some("some/route"), Some("/some/route")),
+    ] {
+        let cli_build = ConfigOptsBuild {
+            dist: Some(tmpdir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let cli_serve = ConfigOptsServe {
+            open: open.map(Into::into),
+            ..Default::default()
+        };
+        let cfg =
+            ConfigOpts::rtc_serve(cli_build, Default::default(), cli_serve, Some(path.clone()))?;
+        assert_eq!(cfg.open.as_deref(), expected);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_serve_unix_socket() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("proxies.toml");
+    let rtc_serve = |address: &str| {
+        let cli_build = ConfigOptsBuild {
+            dist: Some(tmpdir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let cli_serve = ConfigOptsServe {
+            address: Some(address.into()),
+            ..Default::default()
+        };
+        ConfigOpts::rtc_serve(cli_build, Default::default(), cli_serve, Some(path.clone()))
+    };
+    let cfg = rtc_serve("unix:/tmp/trunk.sock")?;
+    assert_eq!(cfg.unix_socket, Some(PathBuf::from("/tmp/trunk.sock")));
+    let cfg = rtc_serve("0.0.0.0")?;
+    assert_eq!(cfg.unix_socket, None);
+    assert!(cfg.address.is_unspecified());
+    assert!(rtc_serve("localhost:8080").is_err());
+
+    // TLS is not served on sockets, rather than ignoring either of them.
+    let cli_serve = ConfigOptsServe {
+        address: Some("unix:/tmp/trunk.sock".into()),
+        tls_self_signed: true,
+        ..Default::default()
+    };
+    let err = ConfigOpts::rtc_serve(
+        Default::default(),
+        Default::default(),
+        cli_serve,
+        Some(path.clone()),
+    )
+    .expect_err("expected TLS on a Unix domain socket to be rejected");
+    assert!(err.to_string().contains("Unix domain socket"));
+    Ok(())
+}
+
+#[test]
+fn ok_serve_port_auto() -> anyhow::Result<()> {
+    use clap::Parser;
+    let serve = |args: &[&str]| crate::Trunk::try_parse_from(args).map(|trunk| trunk.action);
+    for port in ["auto", "0"] {
+        match serve(&["trunk", "serve", "--port", port])? {
+            crate::TrunkSubcommands::Serve(serve) => assert_eq!(serve.serve.port, Some(0)),
+            _ => panic!("expected the serve subcommand"),
+        }
+    }
+    assert!(serve(&["trunk", "serve", "--port", "random"]).is_err());
+    let opts: ConfigOptsServe = toml::from_str(r#"port = "auto""#)?;
+    assert_eq!(opts.port, Some(0));
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn err_trunk_toml_tls_cert_without_key() {
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("bad-serve-tls.toml");
+    let err = ConfigOpts::rtc_serve(
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Some(path),
+    )
+    .expect_err("expected config to err");
+    assert_eq!(
+        err.to_string(),
+        "both `serve.tls_cert` & `serve.tls_key` are required to serve HTTPS"
+    );
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_json_matches_trunk_toml() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let data = cwd.join("tests").join("data");
+    let cli = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let serve = |file: &str| {
+        let path = data.join(file);
+        ConfigOpts::rtc_serve(
+            cli.clone(),
+            Default::default(),
+            Default::default(),
+            Some(path),
+        )
+    };
+    let (toml, json) = (serve("serve-headers.toml")?, serve("serve-headers.json")?);
+
+    assert_eq!(json.headers.len(), toml.headers.len());
+    assert_eq!(json.fallback_exclude, toml.fallback_exclude);
+    assert_eq!(json.not_found, toml.not_found);
+    assert_eq!(json.har, Some(data.join("requests.har")));
+    Ok(())
+}
+
+#[test]
+fn config_schema_describes_example_trunk_toml() -> anyhow::Result<()> {
+    let example: toml::Value = toml::from_str(&std::fs::read_to_string("Trunk.toml")?)?;
+    assert_eq!(crate::config::unknown_keys(&example), Vec::<String>::new());
+    let typo: toml::Value =
+        toml::from_str("[serve]\nprot = 8080\n[[proxy]]\nbackend = \"/\"\nws_ = true")?;
+    assert_eq!(
+        crate::config::unknown_keys(&typo),
+        ["proxy.ws_", "serve.prot"]
+    );
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_trunk_toml_extends() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let data = cwd.join("tests").join("data");
+    let cli = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let cfg = ConfigOpts::rtc_serve(
+        cli,
+        Default::default(),
+        Default::default(),
+        Some(data.join("extends.toml")),
+    )?;
+
+    assert_eq!(cfg.not_found.as_deref(), Some("missing.html"));
+    assert_eq!(cfg.fallback_exclude, [glob::Pattern::new("api/**")?]);
+    assert!(cfg.log_requests);
+    // Paths are relative to the file declaring them.
+    assert_eq!(cfg.har, Some(data.join("requests.har")));
+
+    let err = ConfigOpts::rtc_build(Default::default(), Some(data.join("bad-extends.toml")))
+        .expect_err("expected config to err");
+    assert!(err.to_string().contains("extends itself"));
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[test]
+fn ok_config_overrides_take_precedence_over_file() -> anyhow::Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let cwd = std::env::current_dir().expect("error getting cwd");
+    let path = cwd.join("tests").join("data").join("serve-headers.toml");
+    let config = crate::config::Configuration::new()
+        .file(path)
+        .set("serve.not_found", "missing.html")
+        .set("serve.port", 8081)
+        .set("serve.address", "0.0.0.0");
+    let cli_build = ConfigOptsBuild {
+        dist: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let cli_serve = ConfigOptsServe {
+        port: Some(9000),
+        ..Default::default()
+    };
+    let cfg = ConfigOpts::rtc_serve(cli_build, Default::default(), cli_serve, config)?;
+
+    assert_eq!(cfg.not_found.as_deref(), Some("missing.html"));
+    assert_eq!(cfg.address.to_string(), "0.0.0.0");
+    // The options of the command take precedence over the overrides.
+    assert_eq!(cfg.port, 9000);
+    assert!(cfg.log_requests);
+    Ok(())
+}
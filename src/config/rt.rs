@@ -1,15 +1,25 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{anyhow, ensure, Context, Result};
-use axum::http::Uri;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use glob::Pattern;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
+use crate::cache::BuildCache;
 use crate::config::{
-    ConfigOptsBuild, ConfigOptsClean, ConfigOptsHook, ConfigOptsProxy, ConfigOptsServe,
-    ConfigOptsTools, ConfigOptsWatch,
+    ConfigOptsBuild, ConfigOptsBuildLayout, ConfigOptsBuildPlugin, ConfigOptsBuildWasmBindgen,
+    ConfigOptsClean, ConfigOptsHook, ConfigOptsProxy, ConfigOptsServe, ConfigOptsTools,
+    ConfigOptsWatch,
 };
+use crate::hashing::FileHash;
+use crate::pipelines::{Budget, Prerender, Sitemap, Transform};
+use crate::proxy::Proxy;
+use crate::serve::ServeHeaders;
+use crate::tools::{Application, ToolContext};
 
 /// Config options for the cargo build command
 #[derive(Clone, Debug)]
@@ -32,21 +42,79 @@ pub struct RtcBuild {
     pub target: PathBuf,
     /// The parent directory of the target index HTML file.
     pub target_parent: PathBuf,
+    /// All HTML files to build, starting with `target`, which is written to `index.html`.
+    pub targets: Vec<RtcBuildTarget>,
+    /// The other apps of the workspace, which are built before the main app.
+    pub apps: Vec<RtcBuildApp>,
     /// Build in release mode.
     pub release: bool,
     /// The public URL from which assets are to be served.
     pub public_url: String,
+    /// The wasm-opt level of Rust apps without a `data-wasm-opt` attribute, if configured.
+    pub wasm_opt_level: Option<String>,
+    /// The passes run by wasm-opt after optimizing, such as `strip-debug`.
+    pub wasm_opt_passes: Vec<String>,
+    /// The wasm features enabled for wasm-opt, such as `bulk-memory`.
+    pub wasm_opt_features: Vec<String>,
+    /// If `true`, JS & tailwind CSS assets without a `data-minify` attribute are minified.
+    pub minify: bool,
+    /// Environment variables set for cargo builds & hooks.
+    pub env: HashMap<String, String>,
+    /// Environment variables set for cargo builds, i.e. those of `env`, `cargo_env` and the
+    /// `CARGO_TARGET_DIR`, `RUSTC_WRAPPER` & `CARGO_NET_OFFLINE` of the config.
+    pub cargo_env: HashMap<String, String>,
+    /// The environment variables which may be interpolated into the source HTML files.
+    pub env_allowlist: Vec<String>,
+    /// The size budgets of the built files, checked in release mode.
+    pub budgets: Vec<Budget>,
+    /// The commands transforming the built files.
+    pub transforms: Vec<Transform>,
+    /// The plugins building the assets of other `rel` values.
+    pub plugins: Vec<ConfigOptsBuildPlugin>,
+    /// The subdirs of the dist dir the outputs of the different asset classes are written to.
+    pub layout: OutputLayout,
+    /// The defaults of the wasm-bindgen options of Rust apps.
+    pub wasm_bindgen: ConfigOptsBuildWasmBindgen,
+    /// The routes prerendered in release builds, if any.
+    pub prerender: Option<Prerender>,
+    /// The sitemap & robots.txt file written to the dist dir, if any.
+    pub sitemap: Option<Sitemap>,
     /// If `true`, then files being processed should be hashed and the hash should be
     /// appeneded to the file's name.
     pub filehash: bool,
+    /// The strategy used to hash file names, if `filehash` is enabled.
+    pub hash: FileHash,
+    /// If `true`, gzip & brotli compressed copies of text and wasm assets are written in release
+    /// mode.
+    pub precompress: bool,
+    /// If `true`, subresource integrity attributes are added to injected scripts and
+    /// stylesheets.
+    pub integrity: bool,
+    /// Limits the number of external tools run concurrently by the asset pipelines.
+    pub jobs: Arc<Semaphore>,
+    /// Whether the build requires cross-origin isolation, as a Rust app uses wasm threads. This is
+    /// set by the builds, for `trunk serve` to send the headers enabling it.
+    pub cross_origin_isolated: Arc<AtomicBool>,
+    /// The cache of the outputs of external tools, if enabled.
+    pub cache: Option<BuildCache>,
+    /// If `true`, the build is reproducible: assets are finalized in document order, tool versions
+    /// are pinned, no nonce is generated & the mtimes of all files in the dist dir are zeroed.
+    pub reproducible: bool,
+    /// If `true`, the lockfile must pin the versions & checksums of all downloaded tools & is never
+    /// changed.
+    pub frozen: bool,
     /// The directory where final build artifacts are placed after a successful build.
     pub final_dist: PathBuf,
-    /// The directory used to stage build artifacts during an active build.
+    /// The directory used to stage build artifacts during an active build, a sibling of the final
+    /// dist dir which is swapped into its place after a successful build.
     pub staging_dist: PathBuf,
     /// The configuration of the features passed to cargo.
     pub cargo_features: Features,
     /// Configuration for automatic application download.
     pub tools: ConfigOptsTools,
+    /// The state of the tools used by the build, shared with the builds of the other apps of the
+    /// workspace.
+    pub tool_context: Arc<ToolContext>,
     /// Build process hooks.
     pub hooks: Vec<ConfigOptsHook>,
     /// A bool indicating if the output HTML should have the WebSocket autoloader injected.
@@ -65,6 +133,110 @@ pub struct RtcBuild {
     pub pattern_params: Option<HashMap<String, String>>,
 }
 
+/// An HTML file built by the build system.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtcBuildTarget {
+    /// The canonical path to the source HTML file.
+    pub path: PathBuf,
+    /// The path of the output HTML file, relative to the dist dir.
+    pub output: String,
+}
+
+/// A class of assets, whose outputs are written to a common subdir of the dist dir.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetClass {
+    /// JS & TypeScript assets.
+    Js,
+    /// CSS, sass, less & tailwind assets.
+    Css,
+    /// The outputs of Rust apps.
+    Wasm,
+    /// All other assets, such as copied files, images, fonts & icons.
+    Other,
+}
+
+/// The subdirs of the dist dir the outputs of the different asset classes are written to,
+/// without leading or trailing slashes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputLayout {
+    pub js: Option<String>,
+    pub css: Option<String>,
+    pub wasm: Option<String>,
+    pub other: Option<String>,
+}
+
+impl OutputLayout {
+    /// Create a new instance, ensuring all subdirs are relative paths inside the dist dir.
+    fn new(opts: ConfigOptsBuildLayout) -> Result<Self> {
+        let subdir = |dir: Option<String>, class: &str| -> Result<Option<String>> {
+            let Some(dir) = dir else {
+                return Ok(None);
+            };
+            let path = dir.trim_matches('/').to_string();
+            ensure!(
+                is_relative_subdir(&path),
+                "the `build.layout.{}` dir {:?} must be a relative path inside the dist dir",
+                class,
+                dir
+            );
+            Ok(Some(path))
+        };
+        Ok(Self {
+            js: subdir(opts.js, "js")?,
+            css: subdir(opts.css, "css")?,
+            wasm: subdir(opts.wasm, "wasm")?,
+            other: subdir(opts.assets, "assets")?,
+        })
+    }
+
+    /// The subdir of the given asset class, if any.
+    pub fn dir(&self, class: AssetClass) -> Option<&str> {
+        match class {
+            AssetClass::Js => self.js.as_deref(),
+            AssetClass::Css => self.css.as_deref(),
+            AssetClass::Wasm => self.wasm.as_deref(),
+            AssetClass::Other => self.other.as_deref(),
+        }
+    }
+
+    /// All configured subdirs.
+    pub fn dirs(&self) -> impl Iterator<Item = &str> {
+        [&self.js, &self.css, &self.wasm, &self.other]
+            .into_iter()
+            .filter_map(|dir| dir.as_deref())
+    }
+}
+
+/// Check whether the given path, separated by `/`, is a non-empty relative path which does not
+/// leave the dir it is relative to.
+fn is_relative_subdir(path: &str) -> bool {
+    !path.is_empty()
+        && path
+            .split('/')
+            .all(|segment| !matches!(segment, "" | "." | ".."))
+}
+
+/// The hidden sibling dir `.<name><suffix>` of the given dist dir.
+///
+/// Being in the same parent dir, it is on the same file system as the dist dir, so that the two
+/// can be swapped by renaming them.
+pub fn sibling_dist_dir(dist: &Path, suffix: &str) -> PathBuf {
+    let name = dist
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| super::DIST_DIR.into());
+    dist.with_file_name(format!(".{name}{suffix}"))
+}
+
+/// Another app of the workspace built by the build system.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtcBuildApp {
+    /// The canonical path to the index HTML file of the app.
+    pub target: PathBuf,
+    /// The subdir of the dist dir the app is built into, without leading or trailing slashes.
+    pub path: String,
+}
+
 impl RtcBuild {
     /// Construct a new instance.
     pub(super) fn new(
@@ -103,7 +275,12 @@ impl RtcBuild {
         let final_dist = final_dist
             .canonicalize()
             .context("error taking canonical path to dist dir")?;
-        let staging_dist = final_dist.join(super::STAGE_DIR);
+        ensure!(
+            final_dist.parent().is_some(),
+            "the dist dir {:?} must not be the root dir",
+            &final_dist
+        );
+        let staging_dist = sibling_dist_dir(&final_dist, super::STAGE_SUFFIX);
 
         // Highlander-rule: There can be only one (prohibits contradicting arguments):
         ensure!(
@@ -111,6 +288,102 @@ impl RtcBuild {
             "Cannot combine --all-features with --no-default-features and/or --features"
         );
 
+        let jobs = match opts.jobs {
+            Some(jobs) => jobs,
+            None => std::thread::available_parallelism()
+                .context("error determining the available parallelism, please set `jobs`")?
+                .get(),
+        };
+        ensure!(jobs > 0, "the number of jobs must be greater than 0");
+        let cache = match opts.cache.unwrap_or(true) {
+            true => Some(BuildCache::new(opts.cache_dir)?),
+            false => None,
+        };
+
+        let hash = FileHash::new(
+            opts.hash_algorithm.unwrap_or_default(),
+            opts.hash_length,
+            opts.hash_placement.unwrap_or_default(),
+        )?;
+
+        let mut targets = vec![RtcBuildTarget {
+            path: target.clone(),
+            output: "index.html".into(),
+        }];
+        for extra in opts.targets.unwrap_or_default() {
+            let path = extra.target.canonicalize().with_context(|| {
+                format!(
+                    "error getting canonical path to source HTML file {:?}",
+                    &extra.target
+                )
+            })?;
+            let output = match extra.output {
+                Some(output) => output,
+                None => path
+                    .file_name()
+                    .context("source HTML file has no file name")?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            ensure!(
+                !targets.iter().any(|target| target.output == output),
+                "the output {:?} of the HTML file {:?} is already used by another target, please set a distinct `output` for it",
+                output,
+                path
+            );
+            targets.push(RtcBuildTarget { path, output });
+        }
+
+        let mut apps: Vec<RtcBuildApp> = vec![];
+        for app in opts.apps.unwrap_or_default() {
+            let target = app.target.canonicalize().with_context(|| {
+                format!(
+                    "error getting canonical path to source HTML file {:?}",
+                    &app.target
+                )
+            })?;
+            let path = app.path.trim_matches('/').to_string();
+            ensure!(
+                is_relative_subdir(&path),
+                "the path {:?} of the app {:?} must be a relative path inside the dist dir",
+                app.path,
+                target
+            );
+            ensure!(
+                !apps.iter().any(|other| other.path == path),
+                "the path {:?} of the app {:?} is already used by another app",
+                path,
+                target
+            );
+            apps.push(RtcBuildApp { target, path });
+        }
+
+        let budgets = opts
+            .budgets
+            .unwrap_or_default()
+            .iter()
+            .map(Budget::new)
+            .collect::<Result<_>>()?;
+        let transforms = opts
+            .transforms
+            .unwrap_or_default()
+            .iter()
+            .map(Transform::new)
+            .collect::<Result<_>>()?;
+
+        let prerender = opts
+            .prerender
+            .as_ref()
+            .filter(|prerender| !prerender.routes.is_empty())
+            .map(Prerender::new)
+            .transpose()?;
+        let public_url = opts.public_url.unwrap_or_else(|| "/".into());
+        let sitemap = opts
+            .sitemap
+            .as_ref()
+            .map(|sitemap| Sitemap::new(sitemap, &public_url))
+            .transpose()?;
+
         let cargo_features = if opts.all_features {
             Features::All
         } else {
@@ -120,15 +393,56 @@ impl RtcBuild {
             }
         };
 
+        let env = opts.env.unwrap_or_default();
+        let mut cargo_env = env.clone();
+        cargo_env.extend(opts.cargo_env.unwrap_or_default());
+        if let Some(target_dir) = opts.cargo_target_dir {
+            cargo_env.insert("CARGO_TARGET_DIR".into(), target_dir.display().to_string());
+        }
+        if let Some(wrapper) = opts.rustc_wrapper {
+            cargo_env.insert("RUSTC_WRAPPER".into(), wrapper);
+        }
+        if opts.offline {
+            cargo_env.insert("CARGO_NET_OFFLINE".into(), "true".into());
+        }
+
         Ok(Self {
             target,
             target_parent,
+            targets,
+            apps,
             release: opts.release,
-            public_url: opts.public_url.unwrap_or_else(|| "/".into()),
+            public_url,
+            wasm_opt_level: opts.wasm_opt_level,
+            wasm_opt_passes: opts.wasm_opt_passes.unwrap_or_default(),
+            wasm_opt_features: opts.wasm_opt_features.unwrap_or_default(),
+            minify: opts.minify.unwrap_or(opts.release),
+            env,
+            cargo_env,
+            env_allowlist: opts.env_allowlist.unwrap_or_default(),
+            budgets,
+            transforms,
+            plugins: opts.plugins.unwrap_or_default(),
+            layout: OutputLayout::new(opts.layout.unwrap_or_default())?,
+            wasm_bindgen: opts.wasm_bindgen.unwrap_or_default(),
+            prerender,
+            sitemap,
             filehash: opts.filehash.unwrap_or(true),
+            hash,
+            precompress: opts.precompress,
+            integrity: opts.integrity,
+            jobs: Arc::new(Semaphore::new(jobs)),
+            cross_origin_isolated: Default::default(),
+            cache,
+            reproducible: opts.reproducible,
+            frozen: opts.frozen,
             staging_dist,
             final_dist,
             cargo_features,
+            tool_context: Arc::new(ToolContext::new(
+                opts.offline,
+                tools.mirrors.as_ref().unwrap_or(&HashMap::new()),
+            )?),
             tools,
             hooks,
             inject_autoloader,
@@ -145,16 +459,45 @@ impl RtcBuild {
         let target = tmpdir.join("index.html");
         let target_parent = tmpdir.to_path_buf();
         let final_dist = tmpdir.join("dist");
-        let staging_dist = final_dist.join(".stage");
-        tokio::fs::create_dir_all(&staging_dist)
-            .await
-            .context("error creating dist & staging dir for test")?;
+        let staging_dist = tmpdir.join(".dist.stage");
+        for dir in [&final_dist, &staging_dist] {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .context("error creating dist & staging dir for test")?;
+        }
         Ok(Self {
+            targets: vec![RtcBuildTarget {
+                path: target.clone(),
+                output: "index.html".into(),
+            }],
+            apps: Vec::new(),
             target,
             target_parent,
             release: false,
             public_url: "/".into(),
+            wasm_opt_level: None,
+            wasm_opt_passes: Vec::new(),
+            wasm_opt_features: Vec::new(),
+            minify: false,
+            env: HashMap::new(),
+            cargo_env: HashMap::new(),
+            env_allowlist: Vec::new(),
+            budgets: Vec::new(),
+            transforms: Vec::new(),
+            plugins: Vec::new(),
+            layout: OutputLayout::default(),
+            wasm_bindgen: Default::default(),
+            prerender: None,
+            sitemap: None,
             filehash: true,
+            hash: FileHash::default(),
+            precompress: false,
+            integrity: false,
+            jobs: Arc::new(Semaphore::new(1)),
+            cross_origin_isolated: Default::default(),
+            cache: None,
+            reproducible: false,
+            frozen: false,
             final_dist,
             staging_dist,
             cargo_features: Features::All,
@@ -163,7 +506,15 @@ impl RtcBuild {
                 wasm_bindgen: None,
                 wasm_opt: None,
                 tailwindcss: None,
+                oxipng: None,
+                cwebp: None,
+                esbuild: None,
+                lessc: None,
+                postcss: None,
+                lockfile: None,
+                mirrors: None,
             },
+            tool_context: Default::default(),
             hooks: Vec::new(),
             inject_autoloader: true,
             inject_scripts: true,
@@ -172,8 +523,81 @@ impl RtcBuild {
             pattern_params: None,
         })
     }
+
+    /// Wait for one of the job slots of the build, which is held until the permit is dropped.
+    ///
+    /// This is used to limit the number of external tools, such as sass or wasm-opt, running
+    /// concurrently, while still running the pipelines of independent assets in parallel.
+    pub async fn acquire_job(&self) -> Result<SemaphorePermit<'_>> {
+        self.jobs
+            .acquire()
+            .await
+            .context("error acquiring a job slot of the build")
+    }
+
+    /// The strategy used to hash file names, unless hashing is disabled.
+    pub fn file_hash(&self) -> Option<&FileHash> {
+        self.filehash.then_some(&self.hash)
+    }
+
+    /// The runtime config of the given app of the workspace, which is built into its subdir of the
+    /// dist dir & served from the matching subpath of the public URL.
+    pub fn for_app(&self, app: &RtcBuildApp) -> Self {
+        let target_parent = app
+            .target
+            .parent()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| self.target_parent.clone());
+        Self {
+            target: app.target.clone(),
+            target_parent,
+            targets: vec![RtcBuildTarget {
+                path: app.target.clone(),
+                output: "index.html".into(),
+            }],
+            public_url: format!("{}/{}/", self.public_url.trim_end_matches('/'), app.path),
+            final_dist: self.final_dist.join(&app.path),
+            staging_dist: self.staging_dist.join(&app.path),
+            // Hooks only run once per build, along with the main app, while apps can not be
+            // nested.
+            hooks: Vec::new(),
+            apps: Vec::new(),
+            // The routes are prerendered along with the main app.
+            prerender: None,
+            sitemap: None,
+            ..self.clone()
+        }
+    }
+
+    /// The dir of the staging dist dir the outputs of the given asset class are written to.
+    pub fn output_dir(&self, class: AssetClass) -> PathBuf {
+        match self.layout.dir(class) {
+            Some(dir) => self.staging_dist.join(dir),
+            None => self.staging_dist.clone(),
+        }
+    }
+
+    /// The path relative to the dist dir of the output file of the given asset class with the
+    /// given name, as written to [`RtcBuild::output_dir`].
+    pub fn output_file(&self, class: AssetClass, name: &str) -> String {
+        match self.layout.dir(class) {
+            Some(dir) => format!("{dir}/{name}"),
+            None => name.to_string(),
+        }
+    }
+
+    /// The version of the given tool to use, if any is required.
+    ///
+    /// Reproducible builds pin the versions of all tools which are not configured to their
+    /// default, rather than using any system installed version.
+    pub fn tool_version<'a>(&self, app: Application, version: Option<&'a str>) -> Option<&'a str> {
+        version.or_else(|| self.reproducible.then(|| app.default_version()))
+    }
 }
 
+/// The time to wait for further changes before triggering a build by default.
+const DEFAULT_WATCH_DEBOUNCE: Duration = Duration::from_millis(25);
+
 /// Runtime config for the watch system.
 #[derive(Clone, Debug)]
 pub struct RtcWatch {
@@ -183,6 +607,14 @@ pub struct RtcWatch {
     pub paths: Vec<PathBuf>,
     /// Paths to ignore.
     pub ignored_paths: Vec<PathBuf>,
+    /// Globs of the absolute paths to ignore.
+    pub ignored_globs: Vec<Pattern>,
+    /// The interval to poll the filesystem for changes at, instead of using change notifications.
+    pub poll: Option<Duration>,
+    /// The time to wait for further changes before triggering a build.
+    pub debounce: Duration,
+    /// Whether to rebuild all assets on each change, rather than only the affected ones.
+    pub full_rebuilds: bool,
 }
 
 impl RtcWatch {
@@ -203,33 +635,70 @@ impl RtcWatch {
                 .map_err(|_| anyhow!("invalid watch path provided: {:?}", path))?;
             paths.push(canon_path);
         }
-        // If no watch paths were provided, then we default to the target HTML's parent dir.
+        // If no watch paths were provided, then we default to the parent dirs of the target HTML
+        // files.
         if paths.is_empty() {
             paths.push(build.target_parent.clone());
+            let targets = build.targets.iter().map(|target| &target.path);
+            for target in targets.chain(build.apps.iter().map(|app| &app.target)) {
+                let dir = target.parent().map(PathBuf::from);
+                if let Some(dir) = dir.filter(|dir| !paths.iter().any(|path| dir.starts_with(path)))
+                {
+                    paths.push(dir);
+                }
+            }
         }
 
-        // Take the canonical path of each of the specified ignore targets.
-        let mut ignored_paths = match opts.ignore {
-            None => vec![],
-            Some(paths) => {
-                paths
-                    .into_iter()
-                    .try_fold(vec![], |mut acc, path| -> Result<Vec<PathBuf>> {
-                        let canon_path = path
-                            .canonicalize()
-                            .map_err(|_| anyhow!("invalid ignore path provided: {:?}", path))?;
-                        acc.push(canon_path);
-                        Ok(acc)
-                    })?
+        let debounce = opts.debounce.unwrap_or(DEFAULT_WATCH_DEBOUNCE);
+        ensure!(
+            !debounce.is_zero(),
+            "`watch.debounce` must be greater than zero"
+        );
+        ensure!(
+            !opts.poll.is_some_and(|poll| poll.is_zero()),
+            "`watch.poll` must be greater than zero"
+        );
+
+        // Take the canonical path of each of the specified ignore targets, and make the globs
+        // absolute.
+        let mut ignored_paths = vec![];
+        let mut ignored_globs = vec![];
+        for path in opts.ignore.unwrap_or_default() {
+            if super::is_glob(&path) {
+                let glob = match path.is_absolute() {
+                    true => path,
+                    false => {
+                        let cwd = std::env::current_dir()
+                            .and_then(|cwd| cwd.canonicalize())
+                            .context("error getting the current dir for ignore globs")?;
+                        PathBuf::from(Pattern::escape(&cwd.to_string_lossy())).join(path)
+                    }
+                };
+                let glob = glob.to_string_lossy();
+                ignored_globs.push(
+                    Pattern::new(&glob)
+                        .with_context(|| format!("invalid ignore glob provided: {:?}", glob))?,
+                );
+            } else {
+                let canon_path = path
+                    .canonicalize()
+                    .map_err(|_| anyhow!("invalid ignore path provided: {:?}", path))?;
+                ignored_paths.push(canon_path);
             }
-        };
-        // Ensure the final dist dir is always ignored.
+        }
+        // Ensure the final dist dir, as well as the dirs it is swapped with, are always ignored.
         ignored_paths.push(build.final_dist.clone());
+        ignored_paths.push(build.staging_dist.clone());
+        ignored_paths.push(sibling_dist_dir(&build.final_dist, super::PREVIOUS_SUFFIX));
 
         Ok(Self {
             build,
             paths,
             ignored_paths,
+            ignored_globs,
+            poll: opts.poll,
+            debounce,
+            full_rebuilds: opts.full_rebuilds,
         })
     }
 }
@@ -241,22 +710,77 @@ pub struct RtcServe {
     pub watch: Arc<RtcWatch>,
     /// The IP address to serve on.
     pub address: IpAddr,
-    /// The port to serve on.
+    /// The path of the Unix domain socket to serve on instead of the IP address & port, if any.
+    pub unix_socket: Option<PathBuf>,
+    /// The port to serve on, with `0` selecting a free port.
     pub port: u16,
-    /// Open a browser tab once the initial build is complete.
-    pub open: bool,
-    /// A URL to which requests will be proxied.
-    pub proxy_backend: Option<Uri>,
-    /// The URI on which to accept requests which are to be rewritten and proxied to backend.
-    pub proxy_rewrite: Option<String>,
-    /// Configure the proxy for handling WebSockets.
-    pub proxy_ws: bool,
-    /// Configure the proxy to accept insecure connections.
-    pub proxy_insecure: bool,
+    /// The path of the page to open a browser tab at once a build succeeded, if any.
+    pub open: Option<String>,
+    /// The browser to open the page with, instead of the default browser.
+    pub open_browser: Option<String>,
     /// Any proxies configured to run along with the server.
-    pub proxies: Option<Vec<ConfigOptsProxy>>,
+    ///
+    /// A proxy declared via CLI takes precedence over those of the config file.
+    pub proxies: Vec<Proxy>,
     /// Whether to disable auto-reload of the web page when a build completes.
     pub no_autoreload: bool,
+    /// Whether to hot reload the wasm module of the web page instead of reloading it.
+    pub hmr: bool,
+    /// The TLS certificate to serve HTTPS with, if enabled.
+    pub tls: Option<ServeTls>,
+    /// Whether to additionally serve HTTP/3.
+    pub http3: bool,
+    /// Whether to compress static files on the fly.
+    pub compress: bool,
+    /// The credentials required to access the server, if any.
+    pub auth: Option<ServeAuth>,
+    /// The headers to set on the responses for the paths matching their globs.
+    pub headers: Vec<ServeHeaders>,
+    /// The globs of the paths which fall back to the index HTML file when not found.
+    pub fallback: Vec<Pattern>,
+    /// The globs of the paths which never fall back to the index HTML file.
+    pub fallback_exclude: Vec<Pattern>,
+    /// The page served with a 404 status for paths not falling back, relative to the dist dir.
+    pub not_found: Option<String>,
+    /// Whether to log each request.
+    pub log_requests: bool,
+    /// The path to write the HAR file of all requests to at shutdown, if any.
+    pub har: Option<PathBuf>,
+}
+
+/// The TLS certificate of the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServeTls {
+    /// A PEM encoded certificate & private key, read from the given files.
+    Files { cert: PathBuf, key: PathBuf },
+    /// A generated self-signed certificate.
+    SelfSigned,
+}
+
+/// The credentials required to access the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServeAuth {
+    /// Basic auth with the given user & password.
+    Basic { user: String, password: String },
+    /// An access token, sent as a bearer token, a cookie or a query parameter.
+    Token(String),
+}
+
+impl ServeAuth {
+    /// Parse the given `user:password` or access token.
+    fn parse(auth: &str) -> Result<Self> {
+        ensure!(
+            !auth.is_empty(),
+            "`serve.auth` must not be empty; please set a `user:password` or an access token"
+        );
+        Ok(match auth.split_once(':') {
+            Some((user, password)) => Self::Basic {
+                user: user.to_owned(),
+                password: password.to_owned(),
+            },
+            None => Self::Token(auth.to_owned()),
+        })
+    }
 }
 
 impl RtcServe {
@@ -275,17 +799,107 @@ impl RtcServe {
             hooks,
             !opts.no_autoreload,
         )?);
+        let tls = match (opts.tls_cert, opts.tls_key, opts.tls_self_signed) {
+            (None, None, false) => None,
+            (None, None, true) => Some(ServeTls::SelfSigned),
+            (Some(cert), Some(key), false) => Some(ServeTls::Files { cert, key }),
+            (Some(_), Some(_), true) => bail!(
+                "`serve.tls_self_signed` can not be combined with `serve.tls_cert` & `serve.tls_key`"
+            ),
+            _ => bail!("both `serve.tls_cert` & `serve.tls_key` are required to serve HTTPS"),
+        };
+        // The address is either an IP address, or the path of a Unix domain socket.
+        let (address, unix_socket) = match opts.address.as_deref() {
+            None => (IpAddr::V4(Ipv4Addr::LOCALHOST), None),
+            Some(address) => match address.strip_prefix("unix:") {
+                Some(path) => (IpAddr::V4(Ipv4Addr::LOCALHOST), Some(PathBuf::from(path))),
+                None => {
+                    let address = address
+                        .parse()
+                        .with_context(|| format!("invalid `serve.address` {:?}", address))?;
+                    (address, None)
+                }
+            },
+        };
+        ensure!(
+            unix_socket.is_none() || tls.is_none(),
+            "TLS is not supported when serving on a Unix domain socket via `serve.address`"
+        );
+        ensure!(
+            !opts.http3 || tls.is_some(),
+            "`serve.http3` requires TLS; please set `serve.tls_cert` & `serve.tls_key` or `serve.tls_self_signed`"
+        );
+        let proxies = match opts.proxy_backend {
+            Some(backend) => vec![ConfigOptsProxy {
+                name: None,
+                backend,
+                rewrite: opts.proxy_rewrite,
+                ws: opts.proxy_ws,
+                insecure: opts.proxy_insecure,
+                connect_timeout: None,
+                read_timeout: None,
+                retries: 0,
+                retry_delay: None,
+                request_headers: HashMap::new(),
+                remove_request_headers: Vec::new(),
+                response_headers: HashMap::new(),
+                remove_response_headers: Vec::new(),
+            }],
+            None => proxies.unwrap_or_default(),
+        };
+        let proxies = proxies.iter().map(Proxy::new).collect::<Result<_>>()?;
+        // An empty path opens the public URL.
+        let open = opts.open.map(|path| match path.is_empty() {
+            true => watch.build.public_url.clone(),
+            false => format!("/{}", path.trim_start_matches('/')),
+        });
+        let parse_globs = |globs: Vec<String>, key: &str| {
+            globs
+                .iter()
+                .map(|glob| {
+                    Pattern::new(glob)
+                        .with_context(|| format!("invalid glob `{}` of `serve.{}`", glob, key))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let fallback = parse_globs(
+            opts.fallback.unwrap_or_else(|| vec!["**".into()]),
+            "fallback",
+        )?;
+        let fallback_exclude = parse_globs(
+            opts.fallback_exclude.unwrap_or_default(),
+            "fallback_exclude",
+        )?;
+        let mut headers = opts
+            .headers
+            .unwrap_or_default()
+            .iter()
+            .map(|(glob, headers)| ServeHeaders::new(glob, headers))
+            .collect::<Result<Vec<_>>>()?;
+        // The headers of longer, more specific globs are applied last, overriding the others.
+        headers.sort_by(|a, b| {
+            (a.glob.as_str().len(), a.glob.as_str()).cmp(&(b.glob.as_str().len(), b.glob.as_str()))
+        });
         Ok(Self {
             watch,
-            address: opts.address.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            address,
+            unix_socket,
             port: opts.port.unwrap_or(8080),
-            open: opts.open,
-            proxy_backend: opts.proxy_backend,
-            proxy_rewrite: opts.proxy_rewrite,
-            proxy_insecure: opts.proxy_insecure,
-            proxy_ws: opts.proxy_ws,
+            open,
+            open_browser: opts.open_browser,
             proxies,
             no_autoreload: opts.no_autoreload,
+            hmr: opts.hmr,
+            tls,
+            http3: opts.http3,
+            compress: opts.compress,
+            auth: opts.auth.as_deref().map(ServeAuth::parse).transpose()?,
+            headers,
+            fallback,
+            fallback_exclude,
+            not_found: opts.not_found,
+            log_requests: opts.log_requests,
+            har: opts.har,
         })
     }
 }
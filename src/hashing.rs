@@ -0,0 +1,155 @@
+//! Hashing of output file names.
+
+use std::str::FromStr;
+
+use anyhow::{bail, ensure, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// The algorithm used to hash the content of output files.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    /// SeaHash, a fast 64-bit hash (default).
+    #[default]
+    Seahash,
+    /// BLAKE3, 256-bit.
+    Blake3,
+    /// SHA-256.
+    Sha256,
+    /// XXH3, a fast 64-bit hash.
+    Xxh3,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seahash" => Ok(Self::Seahash),
+            "blake3" => Ok(Self::Blake3),
+            "sha256" => Ok(Self::Sha256),
+            "xxh3" => Ok(Self::Xxh3),
+            _ => bail!(
+                "unknown hash algorithm `{}`; please ensure the value is one of `seahash`, `blake3`, `sha256` or `xxh3`",
+                s
+            ),
+        }
+    }
+}
+
+/// Where the hash is placed in references to output files.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashPlacement {
+    /// The hash is part of the file name, e.g. `app-<hash>.js` (default).
+    #[default]
+    Name,
+    /// The file keeps its name, references to it have the hash as query, e.g. `app.js?v=<hash>`.
+    Query,
+}
+
+impl FromStr for HashPlacement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(Self::Name),
+            "query" => Ok(Self::Query),
+            _ => bail!(
+                "unknown hash placement `{}`; please ensure the value is one of `name` or `query`",
+                s
+            ),
+        }
+    }
+}
+
+/// The strategy used to hash the names of output files.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileHash {
+    /// The hash algorithm.
+    pub algorithm: HashAlgorithm,
+    /// The number of hex digits the hash is truncated to, if any.
+    pub length: Option<usize>,
+    /// Where the hash is placed.
+    pub placement: HashPlacement,
+}
+
+impl FileHash {
+    /// Create a new instance, ensuring the hash is not truncated to nothing.
+    pub fn new(
+        algorithm: HashAlgorithm,
+        length: Option<usize>,
+        placement: HashPlacement,
+    ) -> Result<Self> {
+        ensure!(
+            length != Some(0),
+            "the hash length must be at least 1, to disable hashing set `filehash` to `false`"
+        );
+        Ok(Self {
+            algorithm,
+            length,
+            placement,
+        })
+    }
+
+    /// Compute the hash of the given content, as lowercase hex.
+    pub fn digest(&self, content: &[u8]) -> String {
+        let mut hash = match self.algorithm {
+            HashAlgorithm::Seahash => format!("{:x}", seahash::hash(content)),
+            HashAlgorithm::Blake3 => blake3::hash(content).to_hex().to_string(),
+            HashAlgorithm::Sha256 => Sha256::digest(content)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            HashAlgorithm::Xxh3 => format!("{:016x}", xxhash_rust::xxh3::xxh3_64(content)),
+        };
+        if let Some(length) = self.length {
+            hash.truncate(length);
+        }
+        hash
+    }
+
+    /// Build the name of the file `{stem}{suffix}`, hashed with the given hash.
+    ///
+    /// With `HashPlacement::Query`, the result is a reference with the hash as query, the file
+    /// itself being named `{stem}{suffix}`, see [`file_path`].
+    pub fn name_with_hash(&self, stem: &str, suffix: &str, hash: &str) -> String {
+        match self.placement {
+            HashPlacement::Name => format!("{stem}-{hash}{suffix}"),
+            HashPlacement::Query => format!("{stem}{suffix}?v={hash}"),
+        }
+    }
+
+    /// Build the hashed name of the file `{stem}{suffix}` with the given content.
+    pub fn name(&self, stem: &str, suffix: &str, content: &[u8]) -> String {
+        self.name_with_hash(stem, suffix, &self.digest(content))
+    }
+}
+
+/// The path of the file referenced by the given hashed name, without any hash query.
+pub fn file_path(name: &str) -> &str {
+    match name.split_once('?') {
+        Some((path, _)) => path,
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_places_truncated_hash() {
+        let hash = FileHash::new(HashAlgorithm::Sha256, Some(8), HashPlacement::Name)
+            .expect("expected valid file hash");
+        assert_eq!(hash.name("app", ".js", b"abc"), "app-ba7816bf.js");
+
+        let hash = FileHash::new(HashAlgorithm::Seahash, None, HashPlacement::Query)
+            .expect("expected valid file hash");
+        let name = hash.name("app", "_bg.wasm", b"abc");
+        assert_eq!(name, format!("app_bg.wasm?v={:x}", seahash::hash(b"abc")));
+        assert_eq!(file_path(&name), "app_bg.wasm");
+        assert!(FileHash::new(HashAlgorithm::Xxh3, Some(0), HashPlacement::Name).is_err());
+    }
+}
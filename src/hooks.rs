@@ -1,16 +1,17 @@
+use std::ffi::OsString;
 use std::process::Stdio;
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use futures_util::stream::{FuturesUnordered, StreamExt};
-use tokio::process::Command;
-use tokio::task::JoinHandle;
 
+use crate::common;
 use crate::config::RtcBuild;
-use crate::pipelines::PipelineStage;
+use crate::pipelines::{self, PipelineHandle, PipelineStage};
 
-/// A `FuturesUnordered` containing a `JoinHandle` for each hook-running task.
-pub type HookHandles = FuturesUnordered<JoinHandle<Result<()>>>;
+/// A `FuturesUnordered` containing a `PipelineHandle` for each hook-running task, which aborts
+/// the hook when dropped.
+pub type HookHandles = FuturesUnordered<PipelineHandle<Result<()>>>;
 
 /// Spawns tokio tasks for all hooks configured for the given `HookStage`.
 pub fn spawn_hooks(cfg: Arc<RtcBuild>, stage: PipelineStage) -> HookHandles {
@@ -19,23 +20,23 @@ pub fn spawn_hooks(cfg: Arc<RtcBuild>, stage: PipelineStage) -> HookHandles {
         .iter()
         .filter(|hook_cfg| hook_cfg.stage == stage)
         .map(|hook_cfg| {
-            let mut command = Command::new(&hook_cfg.command);
+            let mut command = common::command(&hook_cfg.command);
             command
                 .args(&hook_cfg.command_arguments)
                 .stdout(Stdio::inherit())
                 .stderr(Stdio::inherit())
-                .env("TRUNK_PROFILE", if cfg.release { "release" } else { "debug" })
-                .env("TRUNK_HTML_FILE", &cfg.target)
-                .env("TRUNK_SOURCE_DIR", &cfg.target_parent)
-                .env("TRUNK_STAGING_DIR", &cfg.staging_dist)
-                .env("TRUNK_DIST_DIR", &cfg.final_dist)
-                .env("TRUNK_PUBLIC_URL", &cfg.public_url);
+                .envs(&cfg.env)
+                .envs(&hook_cfg.env)
+                .envs(trunk_env(&cfg));
+            if let Some(dir) = &hook_cfg.working_directory {
+                command.current_dir(dir);
+            }
 
             tracing::info!(command_arguments = ?hook_cfg.command_arguments, "spawned hook {}", hook_cfg.command);
 
             let command_name = hook_cfg.command.clone();
             tracing::info!(?stage, command = %command_name, "spawning hook");
-            tokio::spawn(async move {
+            pipelines::spawn(async move {
                 let status = command
                     .spawn()
                     .with_context(|| format!("error spawning hook call for {}", command_name))?
@@ -54,6 +55,22 @@ pub fn spawn_hooks(cfg: Arc<RtcBuild>, stage: PipelineStage) -> HookHandles {
     futures
 }
 
+/// The `TRUNK_*` environment variables describing the build, as provided to hooks & the commands
+/// of hook assets.
+pub fn trunk_env(cfg: &RtcBuild) -> Vec<(&'static str, OsString)> {
+    vec![
+        (
+            "TRUNK_PROFILE",
+            if cfg.release { "release" } else { "debug" }.into(),
+        ),
+        ("TRUNK_HTML_FILE", cfg.target.clone().into()),
+        ("TRUNK_SOURCE_DIR", cfg.target_parent.clone().into()),
+        ("TRUNK_STAGING_DIR", cfg.staging_dist.clone().into()),
+        ("TRUNK_DIST_DIR", cfg.final_dist.clone().into()),
+        ("TRUNK_PUBLIC_URL", cfg.public_url.clone().into()),
+    ]
+}
+
 /// Waits for all of the given hooks to finish.
 pub async fn wait_hooks(mut futures: HookHandles) -> Result<()> {
     while let Some(result) = futures.next().await {
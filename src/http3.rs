@@ -0,0 +1,122 @@
+//! HTTP/3 serving of the dev server.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::http::{Request, Response};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use bytes::{Buf, Bytes, BytesMut};
+use h3::server::RequestStream;
+use http_body::Body as _;
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+
+/// The ALPN protocol of HTTP/3.
+const ALPN_H3: &[u8] = b"h3";
+
+/// Spawn an HTTP/3 server on the UDP port of the given address, serving the given router until
+/// the shutdown future completes.
+///
+/// The TLS config of the HTTPS server is reused, with HTTP/3 as its only ALPN protocol.
+pub fn spawn(
+    addr: SocketAddr,
+    tls: &RustlsConfig,
+    router: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<JoinHandle<()>> {
+    let mut crypto = (*tls.get_inner()).clone();
+    crypto.alpn_protocols = vec![ALPN_H3.to_vec()];
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    let endpoint = quinn::Endpoint::server(config, addr)
+        .with_context(|| format!("error binding HTTP/3 server to {}", addr))?;
+
+    Ok(tokio::spawn(async move {
+        tokio::pin!(shutdown);
+        loop {
+            let connecting = tokio::select! {
+                _ = &mut shutdown => break,
+                connecting = endpoint.accept() => match connecting {
+                    Some(connecting) => connecting,
+                    None => break,
+                },
+            };
+            let router = router.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle_connection(connecting, router).await {
+                    tracing::debug!(error = ?err, "error from HTTP/3 connection");
+                }
+            });
+        }
+        endpoint.close(0u32.into(), b"server shutdown");
+        endpoint.wait_idle().await;
+    }))
+}
+
+/// Serve all requests of the given QUIC connection.
+async fn handle_connection(connecting: quinn::Connecting, router: Router) -> Result<()> {
+    let conn = connecting
+        .await
+        .context("error establishing QUIC connection")?;
+    let mut conn = h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(conn))
+        .await
+        .context("error establishing HTTP/3 connection")?;
+    while let Some((req, stream)) = conn
+        .accept()
+        .await
+        .context("error accepting HTTP/3 request")?
+    {
+        let router = router.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_request(req, stream, router).await {
+                tracing::debug!(error = ?err, "error from HTTP/3 request");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Serve the given request with the router, streaming the response body.
+async fn handle_request<S>(
+    req: Request<()>,
+    mut stream: RequestStream<S, Bytes>,
+    router: Router,
+) -> Result<()>
+where
+    S: h3::quic::BidiStream<Bytes>,
+{
+    let mut body = BytesMut::new();
+    while let Some(mut chunk) = stream
+        .recv_data()
+        .await
+        .context("error reading HTTP/3 request body")?
+    {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+    let (parts, ()) = req.into_parts();
+    let req = Request::from_parts(parts, Body::from(body.freeze()));
+
+    let res = match router.oneshot(req).await {
+        Ok(res) => res,
+        Err(err) => match err {},
+    };
+    let (parts, mut body) = res.into_parts();
+    stream
+        .send_response(Response::from_parts(parts, ()))
+        .await
+        .context("error sending HTTP/3 response")?;
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.context("error reading response body")?;
+        stream
+            .send_data(chunk)
+            .await
+            .context("error sending HTTP/3 response body")?;
+    }
+    stream
+        .finish()
+        .await
+        .context("error finishing HTTP/3 response")
+}
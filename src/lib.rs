@@ -0,0 +1,157 @@
+//! Trunk's asset processing as a library, for embedding the pipelines of `trunk build` into other
+//! build tools, such as cargo xtasks, custom dev servers or rules of other build systems.
+//!
+//! A [`Builder`] builds the assets of an HTML file into a dist dir & returns the [asset
+//! manifest](https://trunkrs.dev/assets/#asset-manifest) of the build:
+//!
+//! ```no_run
+//! # async fn build() -> anyhow::Result<()> {
+//! let manifest = trunk_pipelines::Builder::new("index.html")
+//!     .dist("target/dist")
+//!     .release(true)
+//!     .build()
+//!     .await?;
+//! for (name, entry) in &manifest {
+//!     println!("{} -> {}", name, entry.file);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Progress is logged via [`tracing`](https://docs.rs/tracing) under the `trunk` target, so a
+//! subscriber has to be installed to see it.
+
+#![deny(clippy::unwrap_used)]
+
+mod build;
+mod builder;
+mod cache;
+mod cmd;
+mod common;
+mod config;
+mod hashing;
+mod hooks;
+mod http3;
+mod overlay;
+mod pipelines;
+pub mod progress;
+mod proxy;
+mod reload;
+mod request_log;
+mod serve;
+mod tools;
+mod watch;
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use config::parse_override;
+
+pub use builder::Builder;
+pub use config::{ConfigOptsBuild, Configuration};
+pub use pipelines::ManifestEntry;
+pub use tokio_util::sync::CancellationToken;
+
+/// Build, bundle & ship your Rust WASM application to the web.
+#[derive(Parser)]
+#[command(about, author, version, name = "trunk")]
+pub struct Trunk {
+    #[command(subcommand)]
+    action: TrunkSubcommands,
+    /// Path to the Trunk config file, as TOML or, with a `.json` extension, as JSON [default:
+    /// Trunk.toml or Trunk.json]
+    #[arg(long, env = "TRUNK_CONFIG", global(true))]
+    pub config: Option<PathBuf>,
+    /// Override a key of the config, such as `build.wasm_bindgen.target=web`, with the value
+    /// read as TOML, or else as a string
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override, global(true))]
+    pub overrides: Vec<(String, toml::Value)>,
+    /// Enable verbose logging.
+    #[arg(short, long, global(true))]
+    pub verbose: bool,
+    /// Be more quiet, conflicts with --verbose
+    #[arg(short, long, global(true), conflicts_with("verbose"))]
+    pub quiet: bool,
+    /// The format of the messages printed to stdout
+    #[arg(
+        long,
+        value_enum,
+        env = "TRUNK_MESSAGE_FORMAT",
+        global(true),
+        default_value_t = MessageFormat::Human
+    )]
+    pub message_format: MessageFormat,
+}
+
+/// The format of the messages printed to stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    /// Human readable logs, along with a status line in terminals.
+    Human,
+    /// Newline-delimited JSON messages for tools such as editors, with the logs on stderr.
+    Json,
+}
+
+impl Trunk {
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn run(self) -> Result<()> {
+        let config = self
+            .overrides
+            .into_iter()
+            .fold(Configuration::from(self.config), |config, (key, value)| {
+                config.set(key, value)
+            });
+        match self.action {
+            TrunkSubcommands::Build(inner) => inner.run(config).await,
+            TrunkSubcommands::Analyze(inner) => inner.run(config).await,
+            TrunkSubcommands::Clean(inner) => inner.run(config).await,
+            TrunkSubcommands::Serve(inner) => inner.run(config).await,
+            TrunkSubcommands::Watch(inner) => inner.run(config).await,
+            TrunkSubcommands::Config(inner) => inner.run(config).await,
+            TrunkSubcommands::Tools(inner) => inner.run(config).await,
+            TrunkSubcommands::Doctor(inner) => inner.run(config).await,
+            TrunkSubcommands::Deploy(inner) => inner.run(config).await,
+            TrunkSubcommands::New(inner) => inner.run().await,
+            TrunkSubcommands::Init(inner) => inner.run().await,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum TrunkSubcommands {
+    /// Build the Rust WASM app and all of its assets.
+    Build(cmd::build::Build),
+    /// Build the Rust WASM app & report the sizes of all of its assets.
+    Analyze(cmd::analyze::Analyze),
+    /// Build & watch the Rust WASM app and all of its assets.
+    Watch(cmd::watch::Watch),
+    /// Build, watch & serve the Rust WASM app and all of its assets.
+    Serve(cmd::serve::Serve),
+    /// Clean output artifacts.
+    Clean(cmd::clean::Clean),
+    /// Trunk config controls.
+    Config(cmd::config::Config),
+    /// Manage the tools downloaded by Trunk.
+    Tools(cmd::tools::Tools),
+    /// Check the environment & the project for common problems.
+    Doctor(cmd::doctor::Doctor),
+    /// Build the Rust WASM app in release mode & deploy it to a target of `[deploy]`.
+    Deploy(cmd::deploy::Deploy),
+    /// Create a new Trunk project in a new dir.
+    New(cmd::new::New),
+    /// Create a new Trunk project in an existing dir.
+    Init(cmd::new::Init),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Trunk;
+
+    #[test]
+    fn verify_cli() {
+        use clap::CommandFactory;
+        Trunk::command().debug_assert();
+    }
+}
@@ -0,0 +1,45 @@
+// Load the wasm of a Rust app via the JS glue of wasm-bindgen, streaming it into its instantiation.
+//
+// The progress of the download is dispatched on `window` as `trunk:wasm-loading-progress` events,
+// and reported to the callbacks of the initializer of the app, the default export of its
+// `data-initializer` module, if any.
+async function __trunkLoad(init, url, size, initializer) {
+    const { onStart, onProgress, onComplete, onSuccess, onFailure } =
+        (await initializer?.()) ?? {};
+    onStart?.();
+    try {
+        const response = await fetch(url);
+        if (!response.ok) {
+            throw new Error(`error fetching ${url}: ${response.status} ${response.statusText}`);
+        }
+        // The size of the build is used as total, as the content length of compressed responses
+        // is the compressed size, while the body is read decompressed.
+        let current = 0;
+        const reader = response.body.getReader();
+        const body = new ReadableStream({
+            async pull(controller) {
+                const { done, value } = await reader.read();
+                if (done) {
+                    controller.close();
+                    return;
+                }
+                current += value.byteLength;
+                const progress = { current, total: Math.max(size, current) };
+                window.dispatchEvent(
+                    new CustomEvent("trunk:wasm-loading-progress", { detail: progress }),
+                );
+                onProgress?.(progress);
+                controller.enqueue(value);
+            },
+        });
+        // Streamed into the instantiation, which requires the content type of the response.
+        const wasm = await init(new Response(body, { headers: response.headers }));
+        onSuccess?.(wasm);
+        return wasm;
+    } catch (error) {
+        onFailure?.(error);
+        throw error;
+    } finally {
+        onComplete?.();
+    }
+}
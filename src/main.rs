@@ -1,21 +1,13 @@
 #![deny(clippy::unwrap_used)]
 
-mod build;
-mod cmd;
-mod common;
-mod config;
-mod hooks;
-mod pipelines;
-mod proxy;
-mod serve;
-mod tools;
-mod watch;
-
-use std::path::PathBuf;
+mod message_format;
+mod status_line;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::Parser;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::prelude::*;
+use trunk_pipelines::{MessageFormat, Trunk};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -26,6 +18,12 @@ async fn main() -> Result<()> {
         eprintln!("error enabling ANSI support: {:?}", err);
     }
 
+    let json = cli.message_format == MessageFormat::Json;
+    // With JSON messages on stdout, the logs are moved to stderr.
+    let writer = match json {
+        true => BoxMakeWriter::new(std::io::stderr),
+        false => BoxMakeWriter::new(status_line::Writer),
+    };
     tracing_subscriber::registry()
         // Filter spans based on the RUST_LOG env var.
         .with(eval_logging(&cli))
@@ -34,13 +32,27 @@ async fn main() -> Result<()> {
             tracing_subscriber::fmt::layer()
                 .with_target(false)
                 .with_level(true)
+                .with_writer(writer)
                 .compact(),
         )
+        .with(json.then_some(message_format::DiagnosticLayer))
         // Install this registry as the global tracing registry.
         .try_init()
         .context("error initializing logging")?;
+    if !json {
+        if !cli.quiet {
+            status_line::spawn();
+        }
+        return cli.run().await;
+    }
 
-    cli.run().await
+    let messages = message_format::Messages::spawn();
+    let res = cli.run().await;
+    messages.finish().await;
+    if let Err(err) = &res {
+        message_format::print_error(err);
+    }
+    res
 }
 
 fn eval_logging(cli: &Trunk) -> tracing_subscriber::EnvFilter {
@@ -51,58 +63,3 @@ fn eval_logging(cli: &Trunk) -> tracing_subscriber::EnvFilter {
     };
     tracing_subscriber::EnvFilter::new(directives)
 }
-
-/// Build, bundle & ship your Rust WASM application to the web.
-#[derive(Parser)]
-#[command(about, author, version, name = "trunk")]
-struct Trunk {
-    #[command(subcommand)]
-    action: TrunkSubcommands,
-    /// Path to the Trunk config file [default: Trunk.toml]
-    #[arg(long, env = "TRUNK_CONFIG", global(true))]
-    pub config: Option<PathBuf>,
-    /// Enable verbose logging.
-    #[arg(short, long, global(true))]
-    pub verbose: bool,
-    /// Be more quiet, conflicts with --verbose
-    #[arg(short, long, global(true), conflicts_with("verbose"))]
-    pub quiet: bool,
-}
-
-impl Trunk {
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn run(self) -> Result<()> {
-        match self.action {
-            TrunkSubcommands::Build(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Clean(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Serve(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Watch(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Config(inner) => inner.run(self.config).await,
-        }
-    }
-}
-
-#[derive(Subcommand)]
-enum TrunkSubcommands {
-    /// Build the Rust WASM app and all of its assets.
-    Build(cmd::build::Build),
-    /// Build & watch the Rust WASM app and all of its assets.
-    Watch(cmd::watch::Watch),
-    /// Build, watch & serve the Rust WASM app and all of its assets.
-    Serve(cmd::serve::Serve),
-    /// Clean output artifacts.
-    Clean(cmd::clean::Clean),
-    /// Trunk config controls.
-    Config(cmd::config::Config),
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::Trunk;
-
-    #[test]
-    fn verify_cli() {
-        use clap::CommandFactory;
-        Trunk::command().debug_assert();
-    }
-}
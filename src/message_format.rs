@@ -0,0 +1,230 @@
+//! The JSON message format of the CLI, printing newline-delimited JSON messages to stdout.
+//!
+//! Every message is an object with a `reason` field, like the messages of
+//! `cargo --message-format json`:
+//!
+//! - `build-started` when a build starts.
+//! - `artifact` for every file written by a successful build, with the `name`, `file`, `size` &
+//!   `integrity` of its entry in the asset manifest.
+//! - `build-finished` when a build finished, with its `success` & its `duration_ms`.
+//! - `diagnostic` for every warning & error logged, with its `level`, `message` & any other
+//!   `fields` of the log event, and for every diagnostic of the compiler, with its `rendered` text
+//!   & its `file`, `line` & `column` as fields.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+use trunk_pipelines::progress::{self, ProgressEvent};
+use trunk_pipelines::ManifestEntry;
+
+/// Whether an error was printed as a diagnostic.
+static ERRORED: AtomicBool = AtomicBool::new(false);
+
+/// A JSON message.
+#[derive(Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum Message<'a> {
+    BuildStarted,
+    Artifact {
+        name: &'a str,
+        #[serde(flatten)]
+        entry: &'a ManifestEntry,
+    },
+    BuildFinished {
+        success: bool,
+        duration_ms: u128,
+    },
+    Diagnostic {
+        level: &'a str,
+        message: &'a str,
+        fields: Map<String, Value>,
+    },
+}
+
+/// Print the given message as a line of JSON.
+fn print(message: &Message) {
+    let mut out = io::stdout().lock();
+    // Errors writing to stdout are ignored, as there is nowhere else to report them.
+    if serde_json::to_writer(&mut out, message).is_ok() {
+        let _ = writeln!(out);
+        let _ = out.flush();
+    }
+}
+
+/// Print the diagnostic of the given error, which ended the process, unless an error was printed
+/// already, such as the one of a failed build.
+pub fn print_error(err: &anyhow::Error) {
+    if ERRORED.load(Ordering::Relaxed) {
+        return;
+    }
+    print(&Message::Diagnostic {
+        level: "error",
+        message: &format!("{:#}", err),
+        fields: Map::new(),
+    });
+}
+
+/// The task printing the messages of all builds.
+pub struct Messages {
+    handle: JoinHandle<()>,
+    stop: CancellationToken,
+}
+
+impl Messages {
+    /// Print the messages of all builds, until [`Messages::finish`] is called.
+    pub fn spawn() -> Self {
+        let stop = CancellationToken::new();
+        // Subscribed right away, so that no events of the first build are missed.
+        let mut events = progress::subscribe();
+        let handle = tokio::spawn({
+            let stop = stop.clone();
+            async move {
+                loop {
+                    tokio::select! {
+                        event = events.recv() => match event {
+                            Ok(event) => print_event(event),
+                            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        },
+                        _ = stop.cancelled() => {
+                            // All events of the finished builds are sent by now.
+                            while let Ok(event) = events.try_recv() {
+                                print_event(event);
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Self { handle, stop }
+    }
+
+    /// Print the messages still pending & stop.
+    pub async fn finish(self) {
+        self.stop.cancel();
+        let _ = self.handle.await;
+    }
+}
+
+/// Print the messages of the given progress event, if any.
+fn print_event(event: ProgressEvent) {
+    match event {
+        ProgressEvent::BuildStarted => print(&Message::BuildStarted),
+        ProgressEvent::Diagnostic {
+            level,
+            message,
+            rendered,
+            file,
+            line,
+            column,
+        } => {
+            let mut fields = Map::new();
+            fields.insert("rendered".into(), rendered.into());
+            fields.insert("file".into(), file.into());
+            fields.insert("line".into(), line.into());
+            fields.insert("column".into(), column.into());
+            print(&Message::Diagnostic {
+                level: &level,
+                message: &message,
+                fields,
+            });
+        }
+        ProgressEvent::BuildFinished {
+            success,
+            duration,
+            artifacts,
+        } => {
+            for (name, entry) in &artifacts {
+                print(&Message::Artifact { name, entry });
+            }
+            print(&Message::BuildFinished {
+                success,
+                duration_ms: duration.as_millis(),
+            });
+        }
+        _ => {}
+    }
+}
+
+/// A layer printing the warnings & errors logged as diagnostics.
+pub struct DiagnosticLayer;
+
+impl<S: Subscriber> Layer<S> for DiagnosticLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level > Level::WARN {
+            return;
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let level = match level {
+            Level::ERROR => {
+                ERRORED.store(true, Ordering::Relaxed);
+                "error"
+            }
+            _ => "warning",
+        };
+        print(&Message::Diagnostic {
+            level,
+            // Trimmed, as the emojis of the logs leave blanks outside of terminals.
+            message: fields.message.trim(),
+            fields: fields.other,
+        });
+    }
+}
+
+/// The fields of a log event.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    other: Map<String, Value>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_owned(),
+            name => {
+                self.other.insert(name.to_owned(), value.into());
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifacts_are_flattened_into_their_message() -> anyhow::Result<()> {
+        let entry = ManifestEntry {
+            file: "app-0123456789abcdef.js".into(),
+            size: 42,
+            integrity: "sha384-abc".into(),
+        };
+        let message = Message::Artifact {
+            name: "app.js",
+            entry: &entry,
+        };
+        assert_eq!(
+            serde_json::to_string(&message)?,
+            r#"{"reason":"artifact","name":"app.js","file":"app-0123456789abcdef.js","size":42,"integrity":"sha384-abc"}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! The in-browser error overlay of failed builds during `trunk serve`.
+
+use std::fmt::Write;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::common::CommandFailed;
+
+/// Matches ANSI control sequences, capturing their parameters & final byte.
+static ANSI_SEQUENCE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\x1b\[([0-9;]*)([@-~])").expect("valid ANSI sequence regex"));
+
+/// The CSS colors of the 16 basic ANSI colors, the bright variants last.
+const PALETTE: [&str; 16] = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+];
+
+/// Render the error of a failed build as the HTML content of the error overlay.
+///
+/// The stderr output of any failed command, e.g. the errors of rustc, sass or tailwind, is shown
+/// first with its ANSI colors, followed by the error chain as logged to the terminal.
+pub fn report(err: &anyhow::Error) -> String {
+    let mut html = String::new();
+    for output in err
+        .chain()
+        .filter_map(|err| err.downcast_ref::<CommandFailed>())
+        .map(|err| err.stderr.trim_end())
+        .filter(|output| !output.is_empty())
+    {
+        let _ = write!(html, "<pre>{}</pre>", ansi_to_html(output));
+    }
+    let _ = write!(html, "<pre>{}</pre>", ansi_to_html(&format!("{:?}", err)));
+    html
+}
+
+/// Convert the given text with ANSI escape sequences to HTML, with the colors & text styles of
+/// SGR sequences as inline styles. All other control sequences are removed.
+fn ansi_to_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut style = Style::default();
+    let mut last = 0;
+    for seq in ANSI_SEQUENCE.captures_iter(text) {
+        let (Some(whole), Some(params), Some(kind)) = (seq.get(0), seq.get(1), seq.get(2)) else {
+            continue;
+        };
+        style.write_span(&mut html, &text[last..whole.start()]);
+        last = whole.end();
+        if kind.as_str() == "m" {
+            style.apply(params.as_str());
+        }
+    }
+    style.write_span(&mut html, &text[last..]);
+    html
+}
+
+/// The current text style of ANSI formatted text.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Style {
+    /// The foreground color.
+    fg: Option<String>,
+    /// The background color.
+    bg: Option<String>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Style {
+    /// Apply the given parameters of an SGR sequence.
+    fn apply(&mut self, params: &str) {
+        let mut params = params.split(';').map(|param| param.parse().unwrap_or(0));
+        // An empty sequence resets all styles.
+        if params.clone().count() == 0 {
+            *self = Self::default();
+        }
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(PALETTE[param as usize - 30].into()),
+                90..=97 => self.fg = Some(PALETTE[param as usize - 82].into()),
+                40..=47 => self.bg = Some(PALETTE[param as usize - 40].into()),
+                100..=107 => self.bg = Some(PALETTE[param as usize - 92].into()),
+                38 => self.fg = extended_color(&mut params),
+                48 => self.bg = extended_color(&mut params),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Write the given text to the HTML, escaped & wrapped in a span of this style if needed.
+    fn write_span(&self, html: &mut String, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let mut css = String::new();
+        if let Some(fg) = &self.fg {
+            let _ = write!(css, "color:{};", fg);
+        }
+        if let Some(bg) = &self.bg {
+            let _ = write!(css, "background-color:{};", bg);
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.7;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        if self.underline {
+            css.push_str("text-decoration:underline;");
+        }
+        if css.is_empty() {
+            escape_html(html, text);
+        } else {
+            let _ = write!(html, r#"<span style="{}">"#, css);
+            escape_html(html, text);
+            html.push_str("</span>");
+        }
+    }
+}
+
+/// Parse the color of an extended color SGR parameter, either of the 256 color palette
+/// (`5;<n>`) or an RGB color (`2;<r>;<g>;<b>`).
+fn extended_color(params: &mut impl Iterator<Item = u32>) -> Option<String> {
+    match params.next()? {
+        5 => {
+            let n = params.next()?;
+            Some(match n {
+                0..=15 => PALETTE[n as usize].into(),
+                16..=231 => {
+                    let level = |c: u32| if c == 0 { 0 } else { c * 40 + 55 };
+                    let n = n - 16;
+                    format!(
+                        "rgb({},{},{})",
+                        level(n / 36),
+                        level(n / 6 % 6),
+                        level(n % 6)
+                    )
+                }
+                _ => {
+                    let gray = (n.min(255) - 232) * 10 + 8;
+                    format!("rgb({},{},{})", gray, gray, gray)
+                }
+            })
+        }
+        2 => {
+            let (r, g, b) = (params.next()?, params.next()?, params.next()?);
+            Some(format!("rgb({},{},{})", r, g, b))
+        }
+        _ => None,
+    }
+}
+
+/// Append the given text to the HTML, escaping all special characters.
+fn escape_html(html: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi_to_html_converts_colors() {
+        let html = ansi_to_html("\x1b[1m\x1b[38;5;9merror\x1b[0m: <a> & \x1b[32mok\x1b[39m\x1b[K");
+        assert_eq!(
+            html,
+            concat!(
+                r#"<span style="color:#f14c4c;font-weight:bold;">error</span>"#,
+                ": &lt;a&gt; &amp; ",
+                r#"<span style="color:#0dbc79;">ok</span>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn report_includes_command_output() {
+        let err = anyhow::Error::new(CommandFailed {
+            name: "sass".into(),
+            stderr: "\x1b[31mError\x1b[0m: expected \"{\".\n".into(),
+        })
+        .context("error from SASS pipeline");
+        let html = report(&err);
+        assert!(html.starts_with(
+            r#"<pre><span style="color:#cd3131;">Error</span>: expected &quot;{&quot;.</pre>"#
+        ));
+        assert!(html.contains("sass call returned a bad status"));
+    }
+}
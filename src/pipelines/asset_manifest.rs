@@ -0,0 +1,146 @@
+//! Asset manifest emission.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::copy_dir::url_path;
+use crate::common;
+use crate::config::RtcBuild;
+
+/// The dir of the asset manifest, relative to the dist dir.
+pub const MANIFEST_DIR: &str = ".trunk";
+/// The file name of the asset manifest.
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Matches the hash of a hashed file name, along with the suffix following it.
+static HASH_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(.+)-[0-9a-f]{1,16}((?:_bg|_loader|_thread)?(?:\.[^-]*)?)$")
+        .expect("error compiling file hash regex")
+});
+
+/// An entry of the asset manifest.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    /// The path of the file relative to the dist dir.
+    pub file: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The subresource integrity digest of the file.
+    pub integrity: String,
+}
+
+/// Strip the hash from the name of the given hashed file, a path relative to the dist dir.
+///
+/// `None` is returned if the file name does not include a hash.
+pub fn unhashed_name(file: &str) -> Option<String> {
+    let (dir, name) = match file.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, file),
+    };
+    let caps = HASH_REGEX.captures(name)?;
+    let name = format!("{}{}", &caps[1], &caps[2]);
+    Some(match dir {
+        Some(dir) => format!("{dir}/{name}"),
+        None => name,
+    })
+}
+
+/// Write the asset manifest to `.trunk/manifest.json` in the staging dist dir, mapping the logical
+/// name of every file in the dist dir to its final path, size & integrity digest.
+///
+/// `logical_names` maps the paths of hashed files to their logical names, all other files are
+/// listed under their own path. The entries of the manifests of the other apps of the workspace,
+/// which are built beforehand, are merged in with their logical names & files prefixed by the
+/// subdir of the app.
+pub async fn write_asset_manifest(
+    cfg: &RtcBuild,
+    logical_names: &HashMap<String, String>,
+) -> Result<()> {
+    let mut manifest = BTreeMap::new();
+    for path in common::list_files_recursive(&cfg.staging_dist).await? {
+        let rel_path = path.strip_prefix(&cfg.staging_dist).unwrap_or(&path);
+        if rel_path.starts_with(MANIFEST_DIR)
+            || cfg.apps.iter().any(|app| rel_path.starts_with(&app.path))
+        {
+            continue;
+        }
+        let file = url_path(rel_path);
+        let content = fs::read(&path)
+            .await
+            .with_context(|| format!("error reading file for asset manifest {:?}", &path))?;
+        let name = logical_names
+            .get(&file)
+            .cloned()
+            .unwrap_or_else(|| file.clone());
+        manifest.insert(
+            name,
+            ManifestEntry {
+                file,
+                size: content.len() as u64,
+                integrity: super::integrity_digest(&content),
+            },
+        );
+    }
+
+    for app in &cfg.apps {
+        let entries = read_asset_manifest(&cfg.staging_dist.join(&app.path))
+            .await
+            .with_context(|| format!("error reading asset manifest of app {:?}", &app.path))?;
+        for (name, mut entry) in entries {
+            entry.file = format!("{}/{}", app.path, entry.file);
+            manifest.insert(format!("{}/{}", app.path, name), entry);
+        }
+    }
+
+    let dir = cfg.staging_dist.join(MANIFEST_DIR);
+    fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("error creating asset manifest dir {:?}", &dir))?;
+    let content =
+        serde_json::to_string_pretty(&manifest).context("error serializing asset manifest")?;
+    common::write_file(dir.join(MANIFEST_FILE), content)
+        .await
+        .context("error writing asset manifest")
+}
+
+/// Read the asset manifest of the given dist dir, mapping logical names to their entries.
+pub async fn read_asset_manifest(dist: &Path) -> Result<BTreeMap<String, ManifestEntry>> {
+    let path = dist.join(MANIFEST_DIR).join(MANIFEST_FILE);
+    let content = fs::read(&path)
+        .await
+        .with_context(|| format!("error reading asset manifest {:?}", &path))?;
+    serde_json::from_slice(&content)
+        .with_context(|| format!("error parsing asset manifest {:?}", &path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unhashed_name_strips_hash() {
+        assert_eq!(
+            unhashed_name("index-1f2e3d.css").as_deref(),
+            Some("index.css")
+        );
+        assert_eq!(
+            unhashed_name("index-1f2e3d.css.map").as_deref(),
+            Some("index.css.map")
+        );
+        assert_eq!(
+            unhashed_name("my-app-1f2e3d_bg.wasm").as_deref(),
+            Some("my-app_bg.wasm")
+        );
+        assert_eq!(
+            unhashed_name("img/lib.min-1f2e3d.js").as_deref(),
+            Some("img/lib.min.js")
+        );
+        assert_eq!(unhashed_name("index.css"), None);
+    }
+}
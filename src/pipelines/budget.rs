@@ -0,0 +1,143 @@
+//! Size budgets of the built files.
+
+use anyhow::{bail, Context, Result};
+use glob::Pattern;
+use tokio::fs;
+
+use super::asset_manifest::read_asset_manifest;
+use crate::common::{format_size, gzip_bytes};
+use crate::config::{ConfigOptsBuildBudget, RtcBuild};
+
+/// A size budget of the built files.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    /// The glob of the files the budget applies to.
+    files: Pattern,
+    /// The maximum size in bytes.
+    max_size: u64,
+    /// The maximum size as configured, for error messages.
+    max_size_str: String,
+    /// Whether the gzip compressed size of the files is checked.
+    gzip: bool,
+    /// Whether the total size of all matching files is checked.
+    total: bool,
+}
+
+impl Budget {
+    /// Create a new instance, parsing the glob & maximum size of the given config.
+    pub fn new(opts: &ConfigOptsBuildBudget) -> Result<Self> {
+        let files = Pattern::new(&opts.files)
+            .with_context(|| format!("invalid glob `{}` of size budget", opts.files))?;
+        let max_size = parse_size(&opts.max_size)
+            .with_context(|| format!("invalid max size of size budget for `{}`", opts.files))?;
+        Ok(Self {
+            files,
+            max_size,
+            max_size_str: opts.max_size.clone(),
+            gzip: opts.gzip,
+            total: opts.total,
+        })
+    }
+
+    /// Describe the exceeded budget, for the given file or the total of all matching files.
+    fn describe_excess(&self, file: Option<&str>, size: u64) -> String {
+        let gzip = if self.gzip { " (gzip)" } else { "" };
+        let size_str = format_size(size);
+        let excess = format_size(size - self.max_size);
+        match file {
+            Some(file) => format!(
+                "{file}{gzip}: {size_str} exceeds the budget of {} for `{}` by {excess}",
+                self.max_size_str,
+                self.files.as_str(),
+            ),
+            None => format!(
+                "total of `{}`{gzip}: {size_str} exceeds the budget of {} by {excess}",
+                self.files.as_str(),
+                self.max_size_str,
+            ),
+        }
+    }
+}
+
+/// Check the files listed in the asset manifest of the staging dist dir against all size budgets,
+/// returning an error describing every exceeded budget.
+pub async fn check_budgets(cfg: &RtcBuild) -> Result<()> {
+    let manifest = read_asset_manifest(&cfg.staging_dist).await?;
+    let mut excesses = Vec::new();
+    for budget in &cfg.budgets {
+        let mut total = 0;
+        for entry in manifest
+            .values()
+            .filter(|entry| super::glob_matches(&budget.files, &entry.file))
+        {
+            let size = match budget.gzip {
+                true => {
+                    let path = cfg.staging_dist.join(&entry.file);
+                    let content = fs::read(&path).await.with_context(|| {
+                        format!("error reading file for size budget {:?}", path)
+                    })?;
+                    let compressed = tokio::task::spawn_blocking(move || gzip_bytes(&content))
+                        .await
+                        .context("error awaiting gzip compression")?
+                        .with_context(|| format!("error compressing file {:?}", path))?;
+                    compressed.len() as u64
+                }
+                false => entry.size,
+            };
+            if !budget.total && size > budget.max_size {
+                excesses.push(budget.describe_excess(Some(&entry.file), size));
+            }
+            total += size;
+        }
+        if budget.total && total > budget.max_size {
+            excesses.push(budget.describe_excess(None, total));
+        }
+    }
+    if !excesses.is_empty() {
+        bail!(
+            "{} size budget(s) exceeded:\n  {}",
+            excesses.len(),
+            excesses.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Parse a size, such as `900KB`, `150 KiB`, `1.5MB` or `1024` (bytes).
+fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (value, unit) = size.split_at(split);
+    let value: f64 = value
+        .parse()
+        .with_context(|| format!("invalid size `{}`", size))?;
+    let factor = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "kib" => 1024.0,
+        "mb" => 1e6,
+        "mib" => 1024.0 * 1024.0,
+        _ => bail!(
+            "unknown unit of size `{}`; please ensure it is one of `B`, `KB`, `KiB`, `MB` or `MiB`",
+            size
+        ),
+    };
+    Ok((value * factor) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_handles_units() {
+        assert_eq!(parse_size("1024").expect("valid size"), 1024);
+        assert_eq!(parse_size("900KB").expect("valid size"), 900_000);
+        assert_eq!(parse_size("150 KiB").expect("valid size"), 153_600);
+        assert_eq!(parse_size("1.5MB").expect("valid size"), 1_500_000);
+        assert!(parse_size("1.5 GB").is_err());
+        assert!(parse_size("KB").is_err());
+    }
+}
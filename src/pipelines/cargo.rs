@@ -0,0 +1,202 @@
+//! Cargo builds, reading the JSON messages of cargo.
+
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{bail, Context, Result};
+use cargo_metadata::diagnostic::{Diagnostic, DiagnosticLevel};
+use cargo_metadata::{Artifact, Message};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+use crate::common::{self, CommandFailed};
+use crate::progress::{self, ProgressEvent};
+
+/// Run `cargo build` with the given arguments & environment variables, returning the artifacts of
+/// all built targets.
+///
+/// The diagnostics of the compiler are rendered as by cargo itself, but only once per build, even
+/// if reported for several targets. Each one is also sent as a [`ProgressEvent::Diagnostic`]. If
+/// the build fails, the rendered diagnostics are part of the error, for the error overlay.
+///
+/// If rustc is wrapped by sccache, its cache hits & misses during the build are logged.
+#[tracing::instrument(level = "trace", skip(args, env))]
+pub async fn build(args: &[&str], env: &HashMap<String, String>) -> Result<Vec<Artifact>> {
+    let sccache = env
+        .get("RUSTC_WRAPPER")
+        .cloned()
+        .or_else(|| std::env::var("RUSTC_WRAPPER").ok())
+        .filter(|wrapper| {
+            Path::new(wrapper)
+                .file_stem()
+                .is_some_and(|stem| stem == "sccache")
+        });
+    let stats_before = match &sccache {
+        Some(sccache) => sccache_stats(sccache).await,
+        None => None,
+    };
+
+    // Diagnostics are colored like cargo's own output, which is kept colored on terminals.
+    let message_format = match std::io::stderr().is_terminal() {
+        true => "--message-format=json-diagnostic-rendered-ansi",
+        false => "--message-format=json",
+    };
+    tracing::debug!(?args, "cargo args");
+    let mut child = common::command("cargo")
+        .args(args)
+        .arg(message_format)
+        .envs(env)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("error spawning cargo build")?;
+    let (Some(stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        bail!("error opening the output of cargo build");
+    };
+    // The progress of cargo is passed through, while the messages are read.
+    let stderr = tokio::spawn(async move {
+        let mut output = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(len @ 1..) = stderr.read(&mut buf).await {
+            progress::write_output(&buf[..len]);
+            output.extend_from_slice(&buf[..len]);
+        }
+        output
+    });
+
+    let mut artifacts = Vec::new();
+    let mut rendered = HashSet::new();
+    let mut diagnostics = String::new();
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .context("error reading the messages of cargo build")?
+    {
+        match serde_json::from_str(&line) {
+            Ok(Message::CompilerArtifact(artifact)) => artifacts.push(artifact),
+            Ok(Message::CompilerMessage(msg)) => {
+                let Some(text) = msg.message.rendered.clone() else {
+                    continue;
+                };
+                if !rendered.insert(text.clone()) {
+                    continue;
+                }
+                progress::write_output(text.as_bytes());
+                diagnostics.push_str(&text);
+                progress::send(diagnostic_event(&msg.message));
+            }
+            Ok(_) => {}
+            // Other output, such as of build scripts, is passed through.
+            Err(_) => progress::write_output(format!("{line}\n").as_bytes()),
+        }
+    }
+
+    let stderr = stderr
+        .await
+        .context("error reading the output of cargo build")?;
+    let status = child.wait().await.context("error during cargo build")?;
+    if let (Some(sccache), Some((hits_before, misses_before))) = (&sccache, stats_before) {
+        if let Some((hits, misses)) = sccache_stats(sccache).await {
+            tracing::info!(
+                "sccache: {} cache hits, {} cache misses",
+                hits.saturating_sub(hits_before),
+                misses.saturating_sub(misses_before)
+            );
+        }
+    }
+    if !status.success() {
+        bail!(CommandFailed {
+            name: "cargo".into(),
+            stderr: diagnostics + &String::from_utf8_lossy(&stderr),
+        });
+    }
+    Ok(artifacts)
+}
+
+/// The total cache hits & misses of the sccache server, if it can be queried.
+///
+/// The server is shared by all builds of the machine, so concurrent builds affect the stats.
+async fn sccache_stats(sccache: &str) -> Option<(u64, u64)> {
+    let output = common::command(sccache)
+        .args(["--show-stats", "--stats-format=json"])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        tracing::debug!("error querying sccache stats");
+        return None;
+    }
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    let total = |kind: &str| {
+        stats
+            .pointer(&format!("/stats/{kind}/counts"))
+            .and_then(|counts| counts.as_object())
+            .map(|counts| counts.values().filter_map(|count| count.as_u64()).sum())
+            .unwrap_or(0)
+    };
+    Some((total("cache_hits"), total("cache_misses")))
+}
+
+/// The progress event of the given compiler diagnostic.
+fn diagnostic_event(diagnostic: &Diagnostic) -> ProgressEvent {
+    let level = match diagnostic.level {
+        DiagnosticLevel::Error | DiagnosticLevel::Ice => "error",
+        DiagnosticLevel::Warning => "warning",
+        _ => "note",
+    };
+    let span = diagnostic.spans.iter().find(|span| span.is_primary);
+    ProgressEvent::Diagnostic {
+        level: level.into(),
+        message: diagnostic.message.clone(),
+        rendered: diagnostic.rendered.clone().unwrap_or_default(),
+        file: span.map(|span| span.file_name.clone()),
+        line: span.map(|span| span.line_start),
+        column: span.map(|span| span.column_start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostic_event_locates_primary_span() -> Result<()> {
+        let diagnostic: Diagnostic = serde_json::from_value(serde_json::json!({
+            "message": "unused variable: `x`",
+            "code": null,
+            "level": "warning",
+            "spans": [{
+                "file_name": "src/main.rs",
+                "byte_start": 20,
+                "byte_end": 21,
+                "line_start": 2,
+                "line_end": 2,
+                "column_start": 9,
+                "column_end": 10,
+                "is_primary": true,
+                "text": [],
+                "label": null,
+                "suggested_replacement": null,
+                "suggestion_applicability": null,
+                "expansion": null
+            }],
+            "children": [],
+            "rendered": "warning: unused variable: `x`\n"
+        }))?;
+        assert_eq!(
+            diagnostic_event(&diagnostic),
+            ProgressEvent::Diagnostic {
+                level: "warning".into(),
+                message: "unused variable: `x`".into(),
+                rendered: "warning: unused variable: `x`\n".into(),
+                file: Some("src/main.rs".into()),
+                line: Some(2),
+                column: Some(9),
+            }
+        );
+        Ok(())
+    }
+}
@@ -1,16 +1,28 @@
 //! Copy-dir asset pipeline.
 
-use std::path::{Component, PathBuf};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
-use nipper::Document;
+use anyhow::{bail, Context, Result};
+use glob::Pattern;
+use nipper::{Document, Selection};
 use tokio::fs;
-use tokio::task::JoinHandle;
 
-use super::{Attrs, TrunkAssetPipelineOutput, ATTR_HREF};
-use crate::common::copy_dir_recursive;
+use super::{Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HASH, ATTR_HREF};
+use crate::common::{self, copy_dir_recursive};
 use crate::config::RtcBuild;
+use crate::hashing::{self, FileHash};
+
+/// The attribute used to only copy the files matching any of the given globs.
+const ATTR_INCLUDE: &str = "data-include";
+/// The attribute used to skip the files matching any of the given globs.
+const ATTR_EXCLUDE: &str = "data-exclude";
+/// The attribute used to set how the files are placed in the dist dir.
+const ATTR_STRATEGY: &str = "data-strategy";
+/// The attributes of HTML elements which are checked for references to hashed files.
+const REFERENCE_ATTRS: &[&str] = &["href", "src", "poster", "srcset", "style"];
 
 /// A CopyDir asset pipeline.
 pub struct CopyDir {
@@ -19,9 +31,68 @@ pub struct CopyDir {
     /// Runtime build config.
     cfg: Arc<RtcBuild>,
     /// The path to the dir being copied.
-    path: PathBuf,
+    pub(super) path: PathBuf,
     /// Optional target path inside the dist dir.
     target_path: Option<PathBuf>,
+    /// The globs of the files to copy, all files are copied if empty.
+    include: Vec<Pattern>,
+    /// The globs of the files to skip.
+    exclude: Vec<Pattern>,
+    /// The strategy used to hash the names of the copied files, if these should be hashed.
+    hash: Option<FileHash>,
+    /// How the files are placed in the dist dir.
+    strategy: CopyStrategy,
+}
+
+/// How the files of a copied dir are placed in the dist dir.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum CopyStrategy {
+    /// The files are copied (default).
+    Copy,
+    /// The files are symlinked to their source.
+    Symlink,
+    /// The files are hardlinked to their source.
+    Hardlink,
+}
+
+impl FromStr for CopyStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "copy" => Ok(Self::Copy),
+            "symlink" => Ok(Self::Symlink),
+            "hardlink" => Ok(Self::Hardlink),
+            s => bail!(
+                r#"unknown `{}="{}"` value for <link data-trunk rel="copy-dir" .../> element; please ensure the value is empty, `copy`, `symlink` or `hardlink`"#,
+                ATTR_STRATEGY,
+                s
+            ),
+        }
+    }
+}
+
+impl CopyStrategy {
+    /// Place the file at `from` at `to`, according to this strategy.
+    ///
+    /// If `prev` is given, an unchanged copy of the file at that path is reused instead of copying
+    /// the file again. A file of the previous build at `to`, as linked by rebuilds, is replaced
+    /// instead of writing through to its source.
+    pub(super) async fn apply(self, from: &Path, to: &Path, prev: Option<&Path>) -> Result<()> {
+        common::remove_file_if_exists(to)
+            .await
+            .with_context(|| format!("error removing file {:?}", to))?;
+        match (self, prev) {
+            (Self::Copy, Some(prev)) => return common::copy_file_incremental(from, to, prev).await,
+            (Self::Copy, None) => fs::copy(from, to).await.map(|_| ()),
+            (Self::Hardlink, _) => fs::hard_link(from, to).await,
+            #[cfg(unix)]
+            (Self::Symlink, _) => fs::symlink(from, to).await,
+            #[cfg(windows)]
+            (Self::Symlink, _) => fs::symlink_file(from, to).await,
+        }
+        .with_context(|| format!("error copying {:?} to {:?}", from, to))
+    }
 }
 
 impl CopyDir {
@@ -42,23 +113,35 @@ impl CopyDir {
         if !path.is_absolute() {
             path = html_dir.join(path);
         }
-        let target_path = attrs
-            .get("data-target-path")
-            .map(|val| val.parse())
-            .transpose()?;
+        let target_path = super::target_path(&attrs)?;
+        let include = parse_globs(&attrs, ATTR_INCLUDE)?;
+        let exclude = parse_globs(&attrs, ATTR_EXCLUDE)?;
+        let hash = attrs
+            .contains_key(ATTR_HASH)
+            .then(|| super::file_hash(&cfg, &attrs))
+            .flatten();
+        let strategy = attrs
+            .get(ATTR_STRATEGY)
+            .map(|val| CopyStrategy::from_str(val))
+            .transpose()?
+            .unwrap_or(CopyStrategy::Copy);
 
         Ok(Self {
             id,
             cfg,
             path,
             target_path,
+            include,
+            exclude,
+            hash,
+            strategy,
         })
     }
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
@@ -75,31 +158,276 @@ impl CopyDir {
         })?;
 
         let dir_out = if let Some(path) = self.target_path {
-            if path.is_absolute() || path.components().any(|c| matches!(c, Component::ParentDir)) {
-                anyhow::bail!(
-                    "Invalid data-target-path '{}'. Must be a relative path without '..'.",
-                    path.display()
-                );
-            }
             let dir_out = self.cfg.staging_dist.join(&path);
             tokio::fs::create_dir_all(&dir_out).await?;
             dir_out
         } else {
             self.cfg.staging_dist.join(dir_name)
         };
-        copy_dir_recursive(canonical_path, dir_out).await?;
+        // Release builds are always copied, so that the dist dir can be deployed as is.
+        let strategy = if self.cfg.release {
+            CopyStrategy::Copy
+        } else {
+            self.strategy
+        };
+        let mut hashed = HashMap::new();
+        if self.cfg.release
+            && self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.hash.is_none()
+        {
+            copy_dir_recursive(canonical_path, dir_out).await?;
+        } else {
+            // Other builds reuse the unchanged files of the previous build, still in the final
+            // dist dir, instead of rewriting the whole dir.
+            let prev_dir = match self.cfg.release {
+                true => None,
+                false => Some(
+                    self.cfg
+                        .final_dist
+                        .join(dir_out.strip_prefix(&self.cfg.staging_dist)?),
+                ),
+            };
+            for file in common::list_files_recursive(&canonical_path).await? {
+                let rel_file = file.strip_prefix(&canonical_path)?;
+                if !is_copied(rel_file, &self.include, &self.exclude) {
+                    continue;
+                }
+                let mut file_out = dir_out.join(rel_file);
+                if let Some(parent) = file_out.parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("error creating directory {:?}", parent))?;
+                }
+                let Some(hash) = &self.hash else {
+                    let prev = prev_dir.as_ref().map(|dir| dir.join(rel_file));
+                    strategy.apply(&file, &file_out, prev.as_deref()).await?;
+                    continue;
+                };
+
+                let bytes = fs::read(&file)
+                    .await
+                    .with_context(|| format!("error reading file for hashing {:?}", &file))?;
+                let dist_path = url_path(file_out.strip_prefix(&self.cfg.staging_dist)?);
+                let hashed_name = super::hashed_file_name(hash, rel_file, &bytes);
+                let prev = prev_dir.as_ref().map(|dir| {
+                    dir.join(rel_file)
+                        .with_file_name(hashing::file_path(&hashed_name))
+                });
+                file_out.set_file_name(hashing::file_path(&hashed_name));
+                strategy.apply(&file, &file_out, prev.as_deref()).await?;
+                hashed.insert(dist_path, hashed_name);
+            }
+        }
 
         tracing::info!(path = ?rel_path, "finished copying directory");
-        Ok(TrunkAssetPipelineOutput::CopyDir(CopyDirOutput(self.id)))
+        Ok(TrunkAssetPipelineOutput::CopyDir(CopyDirOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            hashed,
+        }))
     }
 }
 
+/// Parse the value of a glob attribute, a comma separated list of globs.
+fn parse_globs(attrs: &Attrs, attr: &str) -> Result<Vec<Pattern>> {
+    let Some(val) = attrs.get(attr) else {
+        return Ok(vec![]);
+    };
+    val.split(',')
+        .map(str::trim)
+        .filter(|glob| !glob.is_empty())
+        .map(|glob| {
+            Pattern::new(glob).with_context(|| {
+                format!(
+                    r#"invalid glob `{}` in `{}` attr for <link data-trunk rel="copy-dir" .../> element"#,
+                    glob, attr
+                )
+            })
+        })
+        .collect()
+}
+
+/// Check if the file at the given path, relative to the copied dir, should be copied.
+///
+/// Globs containing a `/` are matched against the relative path, all others against the file
+/// name only.
+fn is_copied(rel_path: &Path, include: &[Pattern], exclude: &[Pattern]) -> bool {
+    let path = url_path(rel_path);
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let matches = |glob: &Pattern| {
+        if glob.as_str().contains('/') {
+            glob.matches(&path)
+        } else {
+            glob.matches(name)
+        }
+    };
+    (include.is_empty() || include.iter().any(matches)) && !exclude.iter().any(matches)
+}
+
+/// Join the components of a relative path using `/`, as used in URLs.
+pub(crate) fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Rewrite a reference to a hashed file, returning `None` if it does not reference one.
+///
+/// `hashed` maps the paths of the hashed files, relative to the dist dir, to their hashed names.
+/// Relative references are resolved against `base_dir`, the dir of the referencing file relative
+/// to the dist dir.
+fn rewrite_reference(
+    reference: &str,
+    base_dir: &str,
+    public_url: &str,
+    hashed: &HashMap<String, String>,
+) -> Option<String> {
+    let (path, suffix) = reference.split_at(reference.find(['?', '#']).unwrap_or(reference.len()));
+    let dist_path = if let Some(rel) = path.strip_prefix(public_url) {
+        rel.to_string()
+    } else if let Some(rel) = path.strip_prefix('/').filter(|rel| !rel.starts_with('/')) {
+        rel.to_string()
+    } else if path.is_empty() || path.contains(':') {
+        return None;
+    } else {
+        let mut segments = vec![];
+        for segment in base_dir.split('/').chain(path.split('/')) {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment),
+            }
+        }
+        segments.join("/")
+    };
+    let hashed_name = hashed.get(&dist_path)?;
+    let dir = &path[..path.rfind('/').map(|idx| idx + 1).unwrap_or_default()];
+    Some(format!("{}{}{}", dir, hashed_name, suffix))
+}
+
+/// Rewrite all `url(...)` references to hashed files in the given CSS.
+fn rewrite_css(
+    css: &str,
+    base_dir: &str,
+    public_url: &str,
+    hashed: &HashMap<String, String>,
+) -> String {
+    super::css::URL_REGEX
+        .replace_all(css, |caps: &regex::Captures| {
+            caps.get(1)
+                .or(caps.get(2))
+                .or(caps.get(3))
+                .and_then(|url| rewrite_reference(url.as_str(), base_dir, public_url, hashed))
+                .map(|url| format!(r#"url("{}")"#, url))
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
 /// The output of a CopyDir build pipeline.
-pub struct CopyDirOutput(usize);
+#[derive(Clone)]
+pub struct CopyDirOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The paths of the hashed files, relative to the dist dir, mapped to their hashed names.
+    pub hashed: HashMap<String, String>,
+}
 
 impl CopyDirOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
-        dom.select(&super::trunk_id_selector(self.0)).remove();
+        dom.select(&super::trunk_id_selector(self.id)).remove();
+        rewrite_html(dom, &self.cfg.public_url, &self.hashed);
         Ok(())
     }
 }
+
+/// Rewrite the references to hashed files in all CSS files of the staging dist dir.
+///
+/// `hashed` maps the paths of the hashed files, relative to the dist dir, to their hashed names.
+/// This must only run once per build, while the references in each document are rewritten when
+/// the output is finalized.
+pub(super) async fn rewrite_hashed_css(
+    cfg: &RtcBuild,
+    hashed: &HashMap<String, String>,
+) -> Result<()> {
+    if hashed.is_empty() {
+        return Ok(());
+    }
+
+    for path in common::list_files_recursive(&cfg.staging_dist).await? {
+        if path.extension().map(|ext| ext == "css") != Some(true) {
+            continue;
+        }
+        let css = fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("error reading file {:?} to string", &path))?;
+        let base_dir = path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&cfg.staging_dist).ok())
+            .map(url_path)
+            .unwrap_or_default();
+        let rewritten = rewrite_css(&css, &base_dir, &cfg.public_url, hashed);
+        if rewritten != css {
+            // Linked files are replaced, instead of writing through to their source.
+            common::write_file(&path, rewritten)
+                .await
+                .with_context(|| format!("error writing file {:?}", &path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the references to hashed files in the attributes & inline styles of the document.
+pub(super) fn rewrite_html(dom: &mut Document, public_url: &str, hashed: &HashMap<String, String>) {
+    let selector = REFERENCE_ATTRS
+        .iter()
+        .map(|attr| format!("[{}]", attr))
+        .collect::<Vec<_>>()
+        .join(", ");
+    for node in dom.select(&selector).nodes() {
+        for attr in REFERENCE_ATTRS {
+            let Some(val) = node.attr(attr) else {
+                continue;
+            };
+            let rewritten = match *attr {
+                "style" => rewrite_css(&val, "", public_url, hashed),
+                "srcset" => val
+                    .split(',')
+                    .map(|candidate| {
+                        let candidate = candidate.trim();
+                        let (url, descriptor) = candidate.split_at(
+                            candidate
+                                .find(char::is_whitespace)
+                                .unwrap_or(candidate.len()),
+                        );
+                        match rewrite_reference(url, "", public_url, hashed) {
+                            Some(url) => format!("{}{}", url, descriptor),
+                            None => candidate.to_string(),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                _ => match rewrite_reference(&val, "", public_url, hashed) {
+                    Some(url) => url,
+                    None => continue,
+                },
+            };
+            if rewritten != *val {
+                node.set_attr(attr, &rewritten);
+            }
+        }
+    }
+    for node in dom.select("style").nodes() {
+        let css = node.text();
+        let rewritten = rewrite_css(&css, "", public_url, hashed);
+        if rewritten != *css {
+            Selection::from(node.clone()).set_html(rewritten);
+        }
+    }
+}
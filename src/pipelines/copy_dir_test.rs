@@ -6,7 +6,7 @@ use anyhow::{Context, Result};
 
 use crate::config::RtcBuild;
 use crate::pipelines::copy_dir::*;
-use crate::pipelines::ATTR_HREF;
+use crate::pipelines::{TrunkAssetPipelineOutput, ATTR_HREF};
 
 /// A fixture for setting up basic test config.
 async fn setup_test_config() -> Result<(tempfile::TempDir, Arc<RtcBuild>, PathBuf)> {
@@ -140,3 +140,228 @@ async fn ok_run_target_path_copy() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn ok_run_filtered_copy() -> Result<()> {
+    // Assemble.
+    let (tmpdir, cfg, asset_dir) = setup_test_config().await?;
+    let copy_location_dir = cfg.staging_dist.join("test_dir");
+    tokio::fs::create_dir(asset_dir.join("img"))
+        .await
+        .context("error creating test sub dir")?;
+    for file in ["img/logo.png", "img/logo.psd", "img/.DS_Store"] {
+        tokio::fs::write(asset_dir.join(file), b"abc123")
+            .await
+            .context("error writing test file contents")?;
+    }
+    let mut attrs = HashMap::new();
+    attrs.insert(ATTR_HREF.into(), "test_dir".into());
+    attrs.insert("data-include".into(), "img/*".into());
+    attrs.insert("data-exclude".into(), "*.psd, .DS_Store".into());
+    let cmd = CopyDir::new(cfg, Arc::new(tmpdir.into_path()), attrs, 0)
+        .await
+        .context("error constructing CopyDir pipeline")?;
+
+    // Action.
+    let _out = cmd
+        .spawn()
+        .await
+        .context("unexpected task join error from pipeline")?
+        .context("unexpected pipeline error")?;
+
+    // Assert.
+    anyhow::ensure!(
+        copy_location_dir.join("img/logo.png").is_file(),
+        "expected included file to be copied"
+    );
+    for file in ["test_file", "img/logo.psd", "img/.DS_Store"] {
+        anyhow::ensure!(
+            !copy_location_dir.join(file).exists(),
+            "expected '{}' not to be copied",
+            file
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ok_run_hashed_copy() -> Result<()> {
+    // Assemble.
+    let (tmpdir, cfg, _) = setup_test_config().await?;
+    let copy_location_dir = cfg.staging_dist.join("test_dir");
+    let hashed_name = format!("test_file-{:x}", seahash::hash(b"abc123"));
+    let css_file = cfg.staging_dist.join("style.css");
+    tokio::fs::write(&css_file, "a { background: url(test_dir/test_file); }")
+        .await
+        .context("error writing test css file")?;
+    let mut attrs = HashMap::new();
+    attrs.insert(ATTR_HREF.into(), "test_dir".into());
+    attrs.insert("data-hash".into(), "".into());
+    let cmd = CopyDir::new(cfg, Arc::new(tmpdir.into_path()), attrs, 0)
+        .await
+        .context("error constructing CopyDir pipeline")?;
+    let mut dom = nipper::Document::from(
+        r#"<html><body><img src="/test_dir/test_file?v=1"><img srcset="./test_dir/test_file 2x, other 1x"></body></html>"#,
+    );
+
+    // Action.
+    let out = cmd
+        .spawn()
+        .await
+        .context("unexpected task join error from pipeline")?
+        .context("unexpected pipeline error")?;
+    let TrunkAssetPipelineOutput::CopyDir(out) = out else {
+        anyhow::bail!("unexpected pipeline output, expected CopyDir output");
+    };
+    rewrite_hashed_css(&out.cfg, &out.hashed).await?;
+    out.finalize(&mut dom).await?;
+
+    // Assert.
+    anyhow::ensure!(
+        copy_location_dir.join(&hashed_name).is_file()
+            && !copy_location_dir.join("test_file").exists(),
+        "expected copied file to be hashed"
+    );
+    let html = dom.html().to_string();
+    anyhow::ensure!(
+        html.contains(&format!(r#"src="/test_dir/{}?v=1""#, hashed_name))
+            && html.contains(&format!(
+                r#"srcset="./test_dir/{} 2x, other 1x""#,
+                hashed_name
+            )),
+        "unexpected references in html: {}",
+        html
+    );
+    let css = tokio::fs::read_to_string(&css_file)
+        .await
+        .context("error reading test css file")?;
+    anyhow::ensure!(
+        css == format!(r#"a {{ background: url("test_dir/{}"); }}"#, hashed_name),
+        "unexpected references in css: {}",
+        css
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn ok_run_symlink_copy() -> Result<()> {
+    // Assemble.
+    let (tmpdir, cfg, asset_dir) = setup_test_config().await?;
+    let copy_location = cfg.staging_dist.join("test_dir/test_file");
+    let mut attrs = HashMap::new();
+    attrs.insert(ATTR_HREF.into(), "test_dir".into());
+    attrs.insert("data-strategy".into(), "symlink".into());
+    let cmd = CopyDir::new(cfg, Arc::new(tmpdir.into_path()), attrs, 0)
+        .await
+        .context("error constructing CopyDir pipeline")?;
+
+    // Action.
+    let _out = cmd
+        .spawn()
+        .await
+        .context("unexpected task join error from pipeline")?
+        .context("unexpected pipeline error")?;
+
+    // Assert.
+    let link = tokio::fs::read_link(&copy_location)
+        .await
+        .context("error reading symlink")?;
+    let orig = tokio::fs::canonicalize(asset_dir.join("test_file"))
+        .await
+        .context("error taking canonical path of original file")?;
+    anyhow::ensure!(
+        link == orig,
+        "unexpected symlink target, expected '{}' == '{}'",
+        link.display(),
+        orig.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn ok_run_symlink_copy_after_rebuild() -> Result<()> {
+    // Assemble.
+    let (tmpdir, cfg, asset_dir) = setup_test_config().await?;
+    let mut attrs = HashMap::new();
+    attrs.insert(ATTR_HREF.into(), "test_dir".into());
+    attrs.insert("data-strategy".into(), "symlink".into());
+    let cmd = CopyDir::new(cfg.clone(), Arc::new(tmpdir.path().into()), attrs, 0)
+        .await
+        .context("error constructing CopyDir pipeline")?;
+    // The symlink of a previous build, linked into the staging dist dir by a selective rebuild.
+    let prev_dir = cfg.final_dist.join("test_dir");
+    tokio::fs::create_dir(&prev_dir)
+        .await
+        .context("error creating previous dist dir")?;
+    let orig = tokio::fs::canonicalize(asset_dir.join("test_file"))
+        .await
+        .context("error taking canonical path of original file")?;
+    tokio::fs::symlink(&orig, prev_dir.join("test_file"))
+        .await
+        .context("error creating previous symlink")?;
+    crate::common::link_dir_recursive(&cfg.final_dist, &cfg.staging_dist).await?;
+
+    // Action.
+    let _out = cmd
+        .spawn()
+        .await
+        .context("unexpected task join error from pipeline")?
+        .context("unexpected pipeline error")?;
+
+    // Assert.
+    let link = tokio::fs::read_link(cfg.staging_dist.join("test_dir/test_file"))
+        .await
+        .context("error reading symlink")?;
+    anyhow::ensure!(
+        link == orig,
+        "unexpected symlink target, expected '{}' == '{}'",
+        link.display(),
+        orig.display()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ok_run_reuses_unchanged_files() -> Result<()> {
+    // Assemble.
+    let (tmpdir, cfg, _) = setup_test_config().await?;
+    let copy_location = cfg.staging_dist.join("test_dir/test_file");
+    // A previous copy with the same size, which is newer than the source file.
+    let prev_dir = cfg.final_dist.join("test_dir");
+    tokio::fs::create_dir(&prev_dir)
+        .await
+        .context("error creating previous dist dir")?;
+    tokio::fs::write(prev_dir.join("test_file"), b"xyz789")
+        .await
+        .context("error writing previous copy of test file")?;
+    let mut attrs = HashMap::new();
+    attrs.insert(ATTR_HREF.into(), "test_dir".into());
+    let cmd = CopyDir::new(cfg, Arc::new(tmpdir.into_path()), attrs, 0)
+        .await
+        .context("error constructing CopyDir pipeline")?;
+
+    // Action.
+    let _out = cmd
+        .spawn()
+        .await
+        .context("unexpected task join error from pipeline")?
+        .context("unexpected pipeline error")?;
+
+    // Assert.
+    let copied = tokio::fs::read_to_string(&copy_location)
+        .await
+        .context("error reading copied file")?;
+    anyhow::ensure!(
+        copied == "xyz789",
+        "expected previous copy to be reused, got '{}'",
+        copied
+    );
+
+    Ok(())
+}
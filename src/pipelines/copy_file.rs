@@ -1,14 +1,20 @@
 //! Copy-file asset pipeline.
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use nipper::Document;
-use tokio::task::JoinHandle;
+use tokio::fs;
 
+use super::copy_dir::rewrite_html;
+use crate::common;
 use crate::config::RtcBuild;
-use crate::pipelines::{AssetFile, Attrs, TrunkAssetPipelineOutput, ATTR_HREF};
+use crate::hashing::{self, FileHash};
+use crate::pipelines::{
+    AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HASH, ATTR_HREF,
+};
 
 /// A CopyFile asset pipeline.
 pub struct CopyFile {
@@ -17,7 +23,11 @@ pub struct CopyFile {
     /// Runtime build config.
     cfg: Arc<RtcBuild>,
     /// The asset file being processed.
-    asset: AssetFile,
+    pub(super) asset: AssetFile,
+    /// Optional target path inside the dist dir, a dir if it ends with a `/`.
+    target_path: Option<(PathBuf, bool)>,
+    /// The strategy used to hash the name of the copied file, if it should be hashed.
+    hash: Option<FileHash>,
 }
 
 impl CopyFile {
@@ -36,13 +46,30 @@ impl CopyFile {
         let mut path = PathBuf::new();
         path.extend(href_attr.split('/'));
         let asset = AssetFile::new(&html_dir, path).await?;
-        Ok(Self { id, cfg, asset })
+        let target_path = super::target_path(&attrs)?.map(|path| {
+            let is_dir = attrs
+                .get(super::ATTR_TARGET_PATH)
+                .map(|val| val.ends_with('/'))
+                .unwrap_or(false);
+            (path, is_dir)
+        });
+        let hash = attrs
+            .contains_key(ATTR_HASH)
+            .then(|| super::file_hash(&cfg, &attrs))
+            .flatten();
+        Ok(Self {
+            id,
+            cfg,
+            asset,
+            target_path,
+            hash,
+        })
     }
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
@@ -50,18 +77,73 @@ impl CopyFile {
     async fn run(self) -> Result<TrunkAssetPipelineOutput> {
         let rel_path = crate::common::strip_prefix(&self.asset.path);
         tracing::info!(path = ?rel_path, "copying file");
-        let _ = self.asset.copy(&self.cfg.staging_dist, false).await?;
+        let mut hashed = HashMap::new();
+        match &self.target_path {
+            None => {
+                let file_name = self
+                    .asset
+                    .copy(&self.cfg.staging_dist, self.hash.as_ref())
+                    .await?;
+                if self.hash.is_some() {
+                    hashed.insert(
+                        self.asset.file_name.to_string_lossy().into_owned(),
+                        file_name,
+                    );
+                }
+            }
+            Some((target_path, is_dir)) => {
+                let mut rel_file = target_path.clone();
+                if *is_dir {
+                    rel_file.push(&self.asset.file_name);
+                }
+                let bytes = fs::read(&self.asset.path).await.with_context(|| {
+                    format!("error reading file for copying {:?}", &self.asset.path)
+                })?;
+                let mut file_path = self.cfg.staging_dist.join(&rel_file);
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("error creating directory {:?}", parent))?;
+                }
+                if let Some(hash) = &self.hash {
+                    let hashed_name = super::hashed_file_name(hash, &rel_file, &bytes);
+                    file_path.set_file_name(hashing::file_path(&hashed_name));
+                    hashed.insert(super::copy_dir::url_path(&rel_file), hashed_name);
+                }
+                common::write_file(&file_path, bytes)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "error copying file {:?} to {:?}",
+                            &self.asset.path, &file_path
+                        )
+                    })?;
+            }
+        }
         tracing::info!(path = ?rel_path, "finished copying file");
-        Ok(TrunkAssetPipelineOutput::CopyFile(CopyFileOutput(self.id)))
+        Ok(TrunkAssetPipelineOutput::CopyFile(CopyFileOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            hashed,
+        }))
     }
 }
 
 /// The output of a CopyFile build pipeline.
-pub struct CopyFileOutput(usize);
+#[derive(Clone)]
+pub struct CopyFileOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The path of the copied file relative to the dist dir, mapped to its hashed name if hashed.
+    pub hashed: HashMap<String, String>,
+}
 
 impl CopyFileOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
-        dom.select(&super::trunk_id_selector(self.0)).remove();
+        dom.select(&super::trunk_id_selector(self.id)).remove();
+        rewrite_html(dom, &self.cfg.public_url, &self.hashed);
         Ok(())
     }
 }
@@ -90,3 +90,57 @@ async fn ok_run_basic_copy() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn ok_run_target_path_hashed_copy() -> Result<()> {
+    // Assemble.
+    let (tmpdir, cfg, _) = setup_test_config().await?;
+    let copy_location = cfg.staging_dist.join(format!(
+        "config/renamed-{:x}.json",
+        seahash::hash(b"abc123")
+    ));
+    let mut attrs = HashMap::new();
+    attrs.insert(ATTR_HREF.into(), "test_file".into());
+    attrs.insert("data-target-path".into(), "config/renamed.json".into());
+    attrs.insert("data-hash".into(), "".into());
+    let cmd = CopyFile::new(cfg, Arc::new(tmpdir.into_path()), attrs, 0)
+        .await
+        .context("error constructing CopyFile pipeline")?;
+
+    // Action.
+    let _out = cmd
+        .spawn()
+        .await
+        .context("unexpected task join error from pipeline")?
+        .context("unexpected pipeline error")?;
+
+    // Assert.
+    anyhow::ensure!(
+        copy_location.is_file(),
+        "expected '{}' to be a file",
+        copy_location.display(),
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn err_new_target_path_outside_dist() -> Result<()> {
+    // Assemble.
+    let (tmpdir, cfg, _) = setup_test_config().await?;
+    let mut attrs = HashMap::new();
+    attrs.insert(ATTR_HREF.into(), "test_file".into());
+    attrs.insert("data-target-path".into(), "../test_file".into());
+
+    // Action.
+    let res = CopyFile::new(cfg, Arc::new(tmpdir.into_path()), attrs, 0).await;
+
+    // Assert.
+    anyhow::ensure!(
+        res.is_err(),
+        "unexpected success while constructing CopyFile pipeline, expected error on invalid \
+         `data-target-path` attr"
+    );
+
+    Ok(())
+}
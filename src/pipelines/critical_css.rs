@@ -0,0 +1,208 @@
+//! Critical CSS extraction.
+
+use nipper::{Document, Matcher};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Matches pseudo-classes & pseudo-elements, including their arguments.
+static PSEUDO_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"::?[a-zA-Z-]+(?:\((?:[^()]|\([^()]*\))*\))?")
+        .expect("error compiling css pseudo selector regex")
+});
+
+/// Extract the rules of the given CSS which apply to the elements of the document.
+///
+/// This is an approximation of the rules needed for the first render: a style rule is critical if
+/// any of its selectors, stripped of all pseudo-classes & pseudo-elements, matches an element of
+/// the document. Conditional group rules (`@media`, `@supports`, ...) are kept with their critical
+/// rules only, while all other at-rules (e.g. `@font-face` or `@keyframes`) are left to the full
+/// stylesheet.
+pub fn extract_critical(css: &str, dom: &Document) -> String {
+    let mut critical = String::new();
+    for (prelude, block) in Rules::new(css) {
+        let Some(block) = block else {
+            continue;
+        };
+        if let Some(at_rule) = prelude.strip_prefix('@') {
+            let name = at_rule
+                .split(|c: char| c.is_whitespace() || c == '(')
+                .next()
+                .unwrap_or_default();
+            if matches!(name, "media" | "supports" | "layer" | "container") {
+                let nested = extract_critical(block, dom);
+                if !nested.is_empty() {
+                    critical.push_str(&format!("{}{{{}}}", prelude, nested));
+                }
+            }
+        } else if is_critical(prelude, dom) {
+            critical.push_str(&format!("{}{{{}}}", prelude, block));
+        }
+    }
+    critical
+}
+
+/// Reference the stylesheet at `href`, inlining the rules of its CSS which apply to the document
+/// & loading the full stylesheet without blocking the first render.
+///
+/// The `attrs` are added to the stylesheet links.
+pub fn deferred_stylesheet_html(css: &str, href: &str, attrs: &str, dom: &Document) -> String {
+    format!(
+        r#"<style type="text/css">{critical}</style><link rel="stylesheet" href="{href}" media="print" onload="this.media='all'"{attrs}/><noscript><link rel="stylesheet" href="{href}"{attrs}/></noscript>"#,
+        critical = extract_critical(css, dom),
+    )
+}
+
+/// Check if any of the selectors of a style rule matches an element of the document.
+fn is_critical(selectors: &str, dom: &Document) -> bool {
+    split_top_level(selectors).into_iter().any(|selector| {
+        let selector = PSEUDO_REGEX.replace_all(selector, "");
+        let selector = selector.trim().trim_end_matches(['>', '+', '~']).trim();
+        if selector.is_empty() {
+            return true;
+        }
+        let matcher = Matcher::new(selector).ok();
+        match matcher {
+            Some(matcher) => dom.select_matcher(&matcher).exists(),
+            // Rules with unsupported selectors are kept, better safe than sorry.
+            None => true,
+        }
+    })
+}
+
+/// Split a selector list at the commas which are not nested in parentheses or brackets.
+fn split_top_level(selectors: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut depth, mut start) = (0usize, 0);
+    for (idx, c) in selectors.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&selectors[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&selectors[start..]);
+    parts
+}
+
+/// An iterator over the top-level rules of a stylesheet, yielding the prelude of each rule and
+/// its block, if any. Statements such as `@import` have no block.
+struct Rules<'a> {
+    css: &'a str,
+    pos: usize,
+}
+
+impl<'a> Rules<'a> {
+    fn new(css: &'a str) -> Self {
+        Self { css, pos: 0 }
+    }
+
+    /// Find the end of the current block, `start` being the position right after its `{`.
+    fn block_end(&self, start: usize) -> usize {
+        let bytes = self.css.as_bytes();
+        let (mut depth, mut idx) = (1usize, start);
+        while idx < bytes.len() {
+            match bytes[idx] {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return idx;
+                    }
+                }
+                quote @ (b'"' | b'\'') => idx = skip_string(bytes, idx, quote),
+                b'/' if bytes.get(idx + 1) == Some(&b'*') => idx = skip_comment(bytes, idx),
+                _ => {}
+            }
+            idx += 1;
+        }
+        bytes.len()
+    }
+}
+
+impl<'a> Iterator for Rules<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.css.as_bytes();
+        let mut start = self.pos;
+        let mut idx = self.pos;
+        while idx < bytes.len() {
+            match bytes[idx] {
+                b'{' => {
+                    let end = self.block_end(idx + 1);
+                    self.pos = (end + 1).min(bytes.len());
+                    let prelude = self.css[start..idx].trim();
+                    return Some((prelude, Some(&self.css[idx + 1..end])));
+                }
+                b';' => {
+                    self.pos = idx + 1;
+                    return Some((self.css[start..idx].trim(), None));
+                }
+                quote @ (b'"' | b'\'') => idx = skip_string(bytes, idx, quote),
+                b'/' if bytes.get(idx + 1) == Some(&b'*') => {
+                    let comment_start = idx;
+                    idx = skip_comment(bytes, idx);
+                    // Comments in front of a rule are not part of its prelude.
+                    if self.css[start..comment_start].trim().is_empty() {
+                        start = idx + 1;
+                    }
+                }
+                b'}' => start = idx + 1,
+                _ => {}
+            }
+            idx += 1;
+        }
+        self.pos = bytes.len();
+        None
+    }
+}
+
+/// Return the position of the closing quote of the string starting at `start`.
+fn skip_string(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut idx = start + 1;
+    while idx < bytes.len() && bytes[idx] != quote {
+        if bytes[idx] == b'\\' {
+            idx += 1;
+        }
+        idx += 1;
+    }
+    idx
+}
+
+/// Return the position of the closing `/` of the comment starting at `start`.
+fn skip_comment(bytes: &[u8], start: usize) -> usize {
+    let mut idx = start + 2;
+    while idx + 1 < bytes.len() && !(bytes[idx] == b'*' && bytes[idx + 1] == b'/') {
+        idx += 1;
+    }
+    idx + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_critical_keeps_matching_rules() {
+        let dom = Document::from(r#"<html><body><h1 class="title">Hi</h1></body></html>"#);
+        let css = "/* header */ h1.title:hover, .nav { color: red; } .footer { color: blue; } \
+                   @media (min-width: 40em) { .title::after { content: \"}\"; } p { margin: 0; } } \
+                   @font-face { font-family: x; }";
+        assert_eq!(
+            extract_critical(css, &dom),
+            "h1.title:hover, .nav{ color: red; }@media (min-width: 40em){.title::after{ content: \"}\"; }}"
+        );
+    }
+
+    #[test]
+    fn split_top_level_ignores_nested_commas() {
+        assert_eq!(
+            split_top_level("a:is(.b, .c), d[e=\",\"]"),
+            vec!["a:is(.b, .c)", " d[e=\",\"]"]
+        );
+    }
+}
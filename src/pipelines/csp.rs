@@ -0,0 +1,187 @@
+//! Content-Security-Policy finalization.
+
+use std::str::FromStr;
+
+use anyhow::{bail, ensure, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use nipper::Document;
+use sha2::{Digest, Sha256};
+
+/// The attribute marking the `<meta http-equiv="Content-Security-Policy">` placeholder.
+const CSP_MARKER_ATTR: &str = "data-trunk-csp";
+/// The selector of all inline scripts.
+const INLINE_SCRIPTS: &str = "script:not([src])";
+/// The selector of all inline styles.
+const INLINE_STYLES: &str = "style";
+
+/// How inline scripts & styles are allowed by the generated policy.
+#[derive(Debug, PartialEq, Eq)]
+enum CspMode {
+    /// The SHA-256 hash of each inline script & style is added to the policy (default).
+    Hash,
+    /// A random nonce is generated per build, set on all scripts & styles and added to the policy.
+    Nonce,
+}
+
+impl FromStr for CspMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "hash" => Ok(Self::Hash),
+            "nonce" => Ok(Self::Nonce),
+            s => bail!(
+                r#"unknown `{}="{}"` value for <meta http-equiv="Content-Security-Policy" .../> element; please ensure the value is empty, `hash` or `nonce`"#,
+                CSP_MARKER_ATTR,
+                s
+            ),
+        }
+    }
+}
+
+/// Write the sources needed for all inline scripts & styles of the document into the content of
+/// the `<meta data-trunk-csp http-equiv="Content-Security-Policy" content="..."/>` placeholder.
+///
+/// This has to run once the document is otherwise final, as any later change to an inline script
+/// or style would invalidate the policy. Reproducible builds do not support nonces, as these
+/// differ between builds.
+pub fn finalize_csp(dom: &mut Document, reproducible: bool) -> Result<()> {
+    let mut meta = dom.select(&format!(
+        r#"html head meta[{}][http-equiv="Content-Security-Policy"]"#,
+        CSP_MARKER_ATTR
+    ));
+    if !meta.exists() {
+        return Ok(());
+    }
+    let mode = CspMode::from_str(&meta.attr_or(CSP_MARKER_ATTR, ""))?;
+    ensure!(
+        !(reproducible && mode == CspMode::Nonce),
+        r#"`{}="nonce"` can not be used in reproducible builds, as the nonce differs between builds; please use `{}="hash"` instead"#,
+        CSP_MARKER_ATTR,
+        CSP_MARKER_ATTR
+    );
+    let policy = meta.attr_or("content", "").to_string();
+
+    let (script_sources, style_sources) = match mode {
+        CspMode::Hash => (
+            inline_hashes(dom, INLINE_SCRIPTS),
+            inline_hashes(dom, INLINE_STYLES),
+        ),
+        CspMode::Nonce => {
+            let nonce = BASE64.encode(rand::random::<[u8; 16]>());
+            let mut elements = dom.select("script, style");
+            elements.set_attr("nonce", &nonce);
+            let source = format!("'nonce-{nonce}'");
+            (vec![source.clone()], vec![source])
+        }
+    };
+    let policy = add_sources(&policy, "script-src", &script_sources);
+    let policy = add_sources(&policy, "style-src", &style_sources);
+
+    meta.remove_attr(CSP_MARKER_ATTR);
+    meta.set_attr("content", &policy);
+    Ok(())
+}
+
+/// Compute the CSP hash sources of the content of all elements matching the given selector.
+fn inline_hashes(dom: &Document, selector: &str) -> Vec<String> {
+    let mut hashes = vec![];
+    for node in dom.select(selector).nodes() {
+        let hash = format!("'sha256-{}'", BASE64.encode(Sha256::digest(&*node.text())));
+        if !hashes.contains(&hash) {
+            hashes.push(hash);
+        }
+    }
+    hashes
+}
+
+/// Add the given sources to a directive of the policy.
+///
+/// If the policy lacks the directive, it is added. As it then no longer falls back to
+/// `default-src`, the sources of `default-src` are copied over.
+fn add_sources(policy: &str, directive: &str, sources: &[String]) -> String {
+    if sources.is_empty() {
+        return policy.to_string();
+    }
+
+    let mut directives = policy
+        .split(';')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let name_of = |d: &str| {
+        d.split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+    let sources = sources.join(" ");
+    match directives.iter_mut().find(|d| name_of(d) == directive) {
+        Some(existing) => {
+            existing.push(' ');
+            existing.push_str(&sources);
+        }
+        None => {
+            let defaults = directives
+                .iter()
+                .find(|d| name_of(d) == "default-src")
+                .map(|d| d["default-src".len()..].trim().to_string())
+                .filter(|d| !d.is_empty());
+            directives.push(match defaults {
+                Some(defaults) => format!("{directive} {defaults} {sources}"),
+                None => format!("{directive} {sources}"),
+            });
+        }
+    }
+    directives.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_sources_extends_existing_directive() {
+        let policy = add_sources(
+            "default-src 'self'; script-src 'self'",
+            "script-src",
+            &["'sha256-abc'".into()],
+        );
+        assert_eq!(policy, "default-src 'self'; script-src 'self' 'sha256-abc'");
+    }
+
+    #[test]
+    fn add_sources_copies_default_src() {
+        let policy = add_sources("default-src 'self';", "style-src", &["'nonce-abc'".into()]);
+        assert_eq!(policy, "default-src 'self'; style-src 'self' 'nonce-abc'");
+        assert_eq!(add_sources("", "style-src", &[]), "");
+    }
+
+    #[test]
+    fn finalize_csp_hashes_inline_scripts() {
+        let mut dom = Document::from(
+            r#"<html><head><meta data-trunk-csp http-equiv="Content-Security-Policy" content="default-src 'self'"></head><body><script>alert(1)</script><script src="app.js"></script></body></html>"#,
+        );
+        finalize_csp(&mut dom, false).expect("expected policy to be generated");
+        let meta = dom.select("meta");
+        assert!(meta.attr(CSP_MARKER_ATTR).is_none());
+        assert_eq!(
+            meta.attr("content")
+                .expect("expected content attr")
+                .to_string(),
+            format!(
+                "default-src 'self'; script-src 'self' 'sha256-{}'",
+                BASE64.encode(Sha256::digest("alert(1)"))
+            )
+        );
+    }
+
+    #[test]
+    fn finalize_csp_rejects_nonce_in_reproducible_builds() {
+        let html = r#"<html><head><meta data-trunk-csp="nonce" http-equiv="Content-Security-Policy" content="default-src 'self'"></head><body></body></html>"#;
+        assert!(finalize_csp(&mut Document::from(html), true).is_err());
+        assert!(finalize_csp(&mut Document::from(html), false).is_ok());
+    }
+}
@@ -1,14 +1,43 @@
 //! CSS asset pipeline.
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
+use futures_util::future::{BoxFuture, FutureExt};
 use nipper::Document;
-use tokio::task::JoinHandle;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tokio::fs;
 
-use super::{AssetFile, Attrs, TrunkAssetPipelineOutput, ATTR_HREF};
-use crate::config::RtcBuild;
+use super::{
+    critical_css, AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_CRITICAL,
+    ATTR_HREF,
+};
+use crate::common::{self, path_exists};
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
+use crate::tools::Application;
+
+/// The attribute used to enable processing the CSS with PostCSS.
+const ATTR_POSTCSS: &str = "data-postcss";
+/// The attribute used to configure additional paths `@import`s are resolved against.
+const ATTR_INCLUDE_PATHS: &str = "data-include-paths";
+
+/// Matches `url(...)` references, capturing the double quoted, single quoted or unquoted URL.
+pub(super) static URL_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^)"'\s]*))\s*\)"#)
+        .expect("error compiling css url regex")
+});
+/// Matches `@import` statements, capturing the URL (in any of its five possible notations) and
+/// the trailing media query list.
+static IMPORT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"@import\s*(?:url\(\s*(?:"([^"]*)"|'([^']*)'|([^)"'\s]*))\s*\)|"([^"]*)"|'([^']*)')([^;]*);"#,
+    )
+    .expect("error compiling css import regex")
+});
 
 /// A CSS asset pipeline.
 pub struct Css {
@@ -17,7 +46,17 @@ pub struct Css {
     /// Runtime build config.
     cfg: Arc<RtcBuild>,
     /// The asset file being processed.
-    asset: AssetFile,
+    pub(super) asset: AssetFile,
+    /// If the CSS should be processed with PostCSS, optionally with the path to the PostCSS config.
+    postcss: Option<Option<PathBuf>>,
+    /// Additional paths `@import`s are resolved against, after the directory of the importing file.
+    pub(super) include_paths: Vec<PathBuf>,
+    /// If subresource integrity attributes should be added to the stylesheet link.
+    use_integrity: bool,
+    /// If the critical rules should be inlined & the stylesheet deferred.
+    use_critical: bool,
+    /// The strategy used to hash the output file name, unless hashing is disabled.
+    hash: Option<FileHash>,
 }
 
 impl Css {
@@ -36,34 +75,317 @@ impl Css {
         let mut path = PathBuf::new();
         path.extend(href_attr.split('/'));
         let asset = AssetFile::new(&html_dir, path).await?;
-        Ok(Self { id, cfg, asset })
+        let postcss = attrs.get(ATTR_POSTCSS).map(|config| {
+            // An empty value means that PostCSS looks up its config on its own.
+            (!config.is_empty()).then(|| {
+                let mut path = PathBuf::new();
+                path.extend(config.split('/'));
+                html_dir.join(path)
+            })
+        });
+        let include_paths = attrs
+            .get(ATTR_INCLUDE_PATHS)
+            .map(|paths| {
+                paths
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .map(|path| {
+                        let mut include_path = PathBuf::new();
+                        include_path.extend(path.split('/'));
+                        html_dir.join(include_path)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let use_integrity = super::use_integrity(&cfg, &attrs)?;
+        // Critical CSS is only extracted for release builds.
+        let use_critical = attrs.contains_key(ATTR_CRITICAL) && cfg.release;
+        let hash = super::file_hash(&cfg, &attrs);
+        Ok(Self {
+            id,
+            cfg,
+            asset,
+            postcss,
+            include_paths,
+            use_integrity,
+            use_critical,
+            hash,
+        })
     }
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
     #[tracing::instrument(level = "trace", skip(self))]
     async fn run(self) -> Result<TrunkAssetPipelineOutput> {
         let rel_path = crate::common::strip_prefix(&self.asset.path);
+        let css = match &self.postcss {
+            Some(config) => self.run_postcss(config.as_deref()).await?,
+            None => self.asset.read_to_string().await?,
+        };
+
         tracing::info!(path = ?rel_path, "copying & hashing css");
-        let file = self
-            .asset
-            .copy(&self.cfg.staging_dist, self.cfg.filehash)
-            .await?;
+        let mut stack = vec![self.asset.path.clone()];
+        let css = flatten_imports(
+            &self.cfg,
+            &self.include_paths,
+            &self.asset.path,
+            css,
+            &mut stack,
+        )
+        .await?;
+
+        // Hash the contents to generate a file name, and then write the contents to the dist dir.
+        let file_stem = self.asset.file_stem.to_string_lossy();
+        let file = match &self.hash {
+            Some(hash) => hash.name(&file_stem, ".css", css.as_bytes()),
+            None => format!("{}.css", file_stem),
+        };
+        let file = self.cfg.output_file(AssetClass::Css, &file);
+        let file_path = self.cfg.staging_dist.join(hashing::file_path(&file));
+        let critical = self.use_critical.then(|| css.clone());
+        common::write_file(&file_path, css)
+            .await
+            .context("error writing CSS pipeline output")?;
         tracing::info!(path = ?rel_path, "finished copying & hashing css");
+
+        let integrity = if self.use_integrity {
+            Some(super::file_integrity(&file_path).await?)
+        } else {
+            None
+        };
         Ok(TrunkAssetPipelineOutput::Css(CssOutput {
             cfg: self.cfg.clone(),
             id: self.id,
             file,
+            integrity,
+            critical,
         }))
     }
+
+    /// Process the CSS file with PostCSS, returning the resulting CSS.
+    async fn run_postcss(&self, config: Option<&Path>) -> Result<String> {
+        let version = self.cfg.tools.postcss.as_deref();
+        let postcss = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Postcss,
+                self.cfg.tool_version(Application::Postcss, version),
+            )
+            .await?;
+
+        let path_str = dunce::simplified(&self.asset.path).display().to_string();
+        // Stylesheets of the same name may be processed at the same time, so each is written to a
+        // temp file of its own, which is removed once dropped.
+        let output = tempfile::Builder::new()
+            .prefix(".postcss-")
+            .suffix(".css")
+            .tempfile_in(&self.cfg.staging_dist)
+            .context("error creating postcss output file")?
+            .into_temp_path();
+        let file_path = dunce::simplified(&output).display().to_string();
+        let config_str = config.map(|config| dunce::simplified(config).display().to_string());
+        let mut args = vec![path_str.as_str(), "--output", &file_path, "--no-map"];
+        if let Some(config_str) = &config_str {
+            args.extend(["--config", config_str]);
+        }
+
+        let rel_path = crate::common::strip_prefix(&self.asset.path);
+        tracing::info!(path = ?rel_path, "processing css with postcss");
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Postcss.name(), &postcss, &args).await?;
+
+        let css = fs::read_to_string(&output).await?;
+        output
+            .close()
+            .context("error removing postcss output file")?;
+
+        tracing::info!(path = ?rel_path, "finished processing css with postcss");
+        Ok(css)
+    }
+}
+
+/// Copy the local files referenced via `url(...)` in the given CSS to the staging dir, hashed if
+/// enabled, and rewrite the references to point to the copies.
+///
+/// Relative URLs are resolved against `base_dir`. Absolute, remote & data URLs are left as they
+/// are, as are references to files which do not exist.
+pub async fn rewrite_urls(cfg: &RtcBuild, base_dir: &Path, css: &str) -> Result<String> {
+    let mut copied: HashMap<&str, String> = HashMap::new();
+    let mut output = String::with_capacity(css.len());
+    let mut last = 0;
+    for caps in URL_REGEX.captures_iter(css) {
+        let (Some(whole), Some(url)) = (caps.get(0), caps.get(1).or(caps.get(2)).or(caps.get(3)))
+        else {
+            continue;
+        };
+        let Some((path, suffix)) = local_path(url.as_str()) else {
+            continue;
+        };
+        let file = match copied.get(path) {
+            Some(file) => file.clone(),
+            None => {
+                let mut rel_path = PathBuf::new();
+                rel_path.extend(path.split('/'));
+                let asset = match AssetFile::new(base_dir, rel_path).await {
+                    Ok(asset) => asset,
+                    Err(err) => {
+                        tracing::warn!(
+                            "not rewriting css reference `url({})`: {:#}",
+                            url.as_str(),
+                            err
+                        );
+                        continue;
+                    }
+                };
+                let file = asset
+                    .copy_output(cfg, AssetClass::Other, cfg.file_hash())
+                    .await?;
+                copied.insert(path, file.clone());
+                file
+            }
+        };
+        output.push_str(&css[last..whole.start()]);
+        output.push_str(&format!(r#"url("{}{}{}")"#, cfg.public_url, file, suffix));
+        last = whole.end();
+    }
+    output.push_str(&css[last..]);
+    Ok(output)
+}
+
+/// Inline the files imported via `@import` into the CSS of the file at `path`, recursively.
+///
+/// Imports with media queries are wrapped into an `@media` rule. Imports which can not be
+/// resolved, as well as imports with a cascade layer or feature query, are left as they are. The
+/// `url(...)` references of every file are rewritten relative to that file.
+///
+/// The `stack` holds the files currently being flattened, in order to detect circular imports.
+fn flatten_imports<'a>(
+    cfg: &'a RtcBuild,
+    include_paths: &'a [PathBuf],
+    path: &'a Path,
+    css: String,
+    stack: &'a mut Vec<PathBuf>,
+) -> BoxFuture<'a, Result<String>> {
+    async move {
+        let dir = path.parent().unwrap_or(path);
+        let mut output = String::with_capacity(css.len());
+        let mut last = 0;
+        for caps in IMPORT_REGEX.captures_iter(&css) {
+            let Some(whole) = caps.get(0) else {
+                continue;
+            };
+            let url = (1..=5)
+                .find_map(|idx| caps.get(idx))
+                .map(|url| url.as_str())
+                .unwrap_or_default();
+            let media = caps
+                .get(6)
+                .map(|media| media.as_str().trim())
+                .unwrap_or_default();
+            let Some(import_path) = resolve_import(dir, include_paths, url, media).await? else {
+                continue;
+            };
+            ensure!(
+                !stack.contains(&import_path),
+                "circular css @import of {:?} in {:?}",
+                &import_path,
+                path
+            );
+
+            let imported = fs::read_to_string(&import_path)
+                .await
+                .with_context(|| format!("error reading imported css {:?}", &import_path))?;
+            stack.push(import_path.clone());
+            let imported = flatten_imports(
+                cfg,
+                include_paths,
+                &import_path,
+                strip_charset(imported),
+                stack,
+            )
+            .await?;
+            stack.pop();
+
+            output.push_str(&rewrite_urls(cfg, dir, &css[last..whole.start()]).await?);
+            if media.is_empty() {
+                output.push_str(&imported);
+            } else {
+                output.push_str(&format!("@media {} {{\n{}\n}}", media, imported));
+            }
+            last = whole.end();
+        }
+        output.push_str(&rewrite_urls(cfg, dir, &css[last..]).await?);
+        Ok(output)
+    }
+    .boxed()
+}
+
+/// Resolve the file imported by an `@import` statement with the given URL & media query list,
+/// relative to `dir` or any of the include paths.
+///
+/// `None` is returned if the import should be left as it is.
+async fn resolve_import(
+    dir: &Path,
+    include_paths: &[PathBuf],
+    url: &str,
+    media: &str,
+) -> Result<Option<PathBuf>> {
+    // Cascade layers & feature queries can't be expressed by wrapping the content in `@media`.
+    if media.starts_with("layer") || media.starts_with("supports(") {
+        return Ok(None);
+    }
+    let Some((path, _)) = local_path(url) else {
+        return Ok(None);
+    };
+    let mut rel_path = PathBuf::new();
+    rel_path.extend(path.split('/'));
+    for dir in std::iter::once(dir).chain(include_paths.iter().map(PathBuf::as_path)) {
+        let import_path = dir.join(&rel_path);
+        if path_exists(&import_path).await? {
+            let import_path = fs::canonicalize(&import_path)
+                .await
+                .with_context(|| format!("error getting canonical path for {:?}", &import_path))?;
+            return Ok(Some(import_path));
+        }
+    }
+    tracing::warn!(
+        "could not resolve css `@import` of {:?}, leaving it as is",
+        url
+    );
+    Ok(None)
+}
+
+/// Remove a leading `@charset` rule, which is only valid at the very start of a stylesheet.
+fn strip_charset(css: String) -> String {
+    match css.trim_start_matches('\u{feff}').strip_prefix("@charset") {
+        Some(rest) => rest
+            .split_once(';')
+            .map(|(_, rest)| rest.to_string())
+            .unwrap_or_default(),
+        None => css,
+    }
+}
+
+/// Split a URL referenced from CSS into the path of a local file & its query/fragment suffix.
+///
+/// `None` is returned for URLs which do not reference a local file relative to the stylesheet.
+fn local_path(url: &str) -> Option<(&str, &str)> {
+    if url.is_empty() || url.starts_with('#') || url.starts_with('/') || url.contains(':') {
+        return None;
+    }
+    let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    (!path.is_empty()).then_some((path, suffix))
 }
 
 /// The output of a CSS build pipeline.
+#[derive(Clone)]
 pub struct CssOutput {
     /// The runtime build config.
     pub cfg: Arc<RtcBuild>,
@@ -71,16 +393,114 @@ pub struct CssOutput {
     pub id: usize,
     /// Name the finalized output file.
     pub file: String,
+    /// The subresource integrity digest of the output file, if enabled.
+    pub integrity: Option<String>,
+    /// The CSS to extract the critical rules from, if enabled.
+    pub critical: Option<String>,
 }
 
 impl CssOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let href = format!("{}{}", &self.cfg.public_url, self.file);
+        let integrity = super::integrity_attrs(self.integrity.as_deref());
+        let html = match &self.critical {
+            Some(css) => critical_css::deferred_stylesheet_html(css, &href, &integrity, dom),
+            None => format!(r#"<link rel="stylesheet" href="{href}"{integrity}/>"#),
+        };
         dom.select(&super::trunk_id_selector(self.id))
-            .replace_with_html(format!(
-                r#"<link rel="stylesheet" href="{base}{file}"/>"#,
-                base = &self.cfg.public_url,
-                file = self.file
-            ));
+            .replace_with_html(html);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_path_splits_suffix() {
+        assert_eq!(local_path("fonts/a.woff2"), Some(("fonts/a.woff2", "")));
+        assert_eq!(local_path("../a.svg#icon"), Some(("../a.svg", "#icon")));
+        assert_eq!(local_path("a.eot?#iefix"), Some(("a.eot", "?#iefix")));
+    }
+
+    #[test]
+    fn local_path_skips_non_local_urls() {
+        for url in [
+            "",
+            "#id",
+            "/abs.png",
+            "//cdn.example.com/a.png",
+            "https://example.com/a.png",
+            "data:image/png;base64,AA==",
+        ] {
+            assert_eq!(local_path(url), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn import_regex_captures_url_and_media() {
+        let css = r#"@import url("a.css"); @import 'b.css' screen and (min-width: 40em);"#;
+        let imports = IMPORT_REGEX
+            .captures_iter(css)
+            .map(|caps| {
+                let url = (1..=5)
+                    .find_map(|idx| caps.get(idx))
+                    .map(|url| url.as_str());
+                (url, caps.get(6).map(|media| media.as_str().trim()))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            imports,
+            vec![
+                (Some("a.css"), Some("")),
+                (Some("b.css"), Some("screen and (min-width: 40em)"))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn flatten_imports_inlines_nested_files() -> Result<()> {
+        let tmpdir = tempfile::tempdir().context("error building tempdir for test")?;
+        let mut cfg = RtcBuild::new_test(tmpdir.path()).await?;
+        cfg.filehash = false;
+        let dir = fs::canonicalize(tmpdir.path()).await?;
+        fs::create_dir_all(dir.join("parts")).await?;
+        fs::write(
+            dir.join("parts/a.css"),
+            r#"@import "b.css" print;a{background:url(bg.png)}"#,
+        )
+        .await?;
+        fs::write(dir.join("parts/b.css"), "b{}").await?;
+        fs::write(dir.join("parts/bg.png"), "png").await?;
+
+        let path = dir.join("main.css");
+        let css = r#"@import url("parts/a.css");main{}"#.to_string();
+        let mut stack = vec![path.clone()];
+        let css = flatten_imports(&cfg, &[], &path, css, &mut stack).await?;
+
+        assert_eq!(
+            css,
+            "@media print {\nb{}\n}a{background:url(\"/bg.png\")}main{}"
+        );
+        assert!(path_exists(cfg.staging_dist.join("bg.png")).await?);
+        Ok(())
+    }
+
+    #[test]
+    fn strip_charset_removes_leading_rule() {
+        assert_eq!(strip_charset(r#"@charset "utf-8";a{}"#.into()), "a{}");
+        assert_eq!(strip_charset("a{}".into()), "a{}");
+    }
+
+    #[test]
+    fn url_regex_matches_quoted_and_unquoted() {
+        let css = r#"a { background: url( "a.png" ) } b { src: url('b.woff2') url(c.svg) }"#;
+        let urls = URL_REGEX
+            .captures_iter(css)
+            .filter_map(|caps| caps.get(1).or(caps.get(2)).or(caps.get(3)))
+            .map(|url| url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(urls, vec!["a.png", "b.woff2", "c.svg"]);
+    }
+}
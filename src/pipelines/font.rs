@@ -0,0 +1,217 @@
+//! Font asset pipeline.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use nipper::Document;
+use tokio::fs;
+
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF};
+use crate::common;
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
+use crate::tools::Application;
+
+/// The attribute used to subset the font to the given unicode range.
+const ATTR_UNICODE_RANGE: &str = "data-unicode-range";
+/// The attribute used to declare a `@font-face` rule for the font with the given family name.
+const ATTR_FONT_FAMILY: &str = "data-font-family";
+
+/// A font asset pipeline.
+pub struct Font {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The asset file being processed.
+    pub(super) asset: AssetFile,
+    /// The MIME type of the font.
+    mime: &'static str,
+    /// The format of the font, as used by `@font-face` rules.
+    format: &'static str,
+    /// An optional unicode range (such as `U+0000-00FF,U+20AC`) to subset the font to.
+    unicode_range: Option<String>,
+    /// An optional font family name to declare a `@font-face` rule for.
+    font_family: Option<String>,
+    /// The strategy used to hash the output file name, unless hashing is disabled.
+    hash: Option<FileHash>,
+}
+
+impl Font {
+    pub const TYPE_FONT: &'static str = "font";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        // Build the path to the target asset.
+        let href_attr = attrs.get(ATTR_HREF).context(
+            r#"required attr `href` missing for <link data-trunk rel="font" .../> element"#,
+        )?;
+        let mut path = PathBuf::new();
+        path.extend(href_attr.split('/'));
+        let asset = AssetFile::new(&html_dir, path).await?;
+        let (mime, format) = match asset.ext.as_deref().map(str::to_lowercase).as_deref() {
+            Some("woff2") => ("font/woff2", "woff2"),
+            Some("woff") => ("font/woff", "woff"),
+            Some("ttf") => ("font/ttf", "truetype"),
+            Some("otf") => ("font/otf", "opentype"),
+            _ => bail!(
+                r#"unsupported font file {:?} for <link data-trunk rel="font" .../> element; please ensure the file is a woff2, woff, ttf or otf font"#,
+                &asset.path
+            ),
+        };
+        let unicode_range = attrs
+            .get(ATTR_UNICODE_RANGE)
+            .filter(|range| !range.is_empty())
+            .cloned();
+        let font_family = attrs.get(ATTR_FONT_FAMILY).cloned();
+        let hash = super::file_hash(&cfg, &attrs);
+        Ok(Self {
+            id,
+            cfg,
+            asset,
+            mime,
+            format,
+            unicode_range,
+            font_family,
+            hash,
+        })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let rel_path = crate::common::strip_prefix(&self.asset.path);
+        let file = match &self.unicode_range {
+            Some(unicode_range) => {
+                tracing::info!(path = ?rel_path, "subsetting & hashing font");
+                let file = self.subset(unicode_range).await?;
+                tracing::info!(path = ?rel_path, "finished subsetting & hashing font");
+                self.cfg.output_file(AssetClass::Other, &file)
+            }
+            None => {
+                tracing::info!(path = ?rel_path, "copying & hashing font");
+                let file = self
+                    .asset
+                    .copy_output(&self.cfg, AssetClass::Other, self.hash.as_ref())
+                    .await?;
+                tracing::info!(path = ?rel_path, "finished copying & hashing font");
+                file
+            }
+        };
+        Ok(TrunkAssetPipelineOutput::Font(FontOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            mime: self.mime,
+            format: self.format,
+            unicode_range: self.unicode_range,
+            font_family: self.font_family,
+            file,
+        }))
+    }
+
+    /// Subset the font to the given unicode range using `pyftsubset`, writing the result to the
+    /// assets dir of the staging dist dir.
+    ///
+    /// The base file name of the output, hashed if enabled, is returned.
+    async fn subset(&self, unicode_range: &str) -> Result<String> {
+        let pyftsubset = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Pyftsubset,
+                self.cfg.tool_version(Application::Pyftsubset, None),
+            )
+            .await?;
+
+        let path_str = dunce::simplified(&self.asset.path).display().to_string();
+        let file_stem = self.asset.file_stem.to_string_lossy();
+        let ext = self.asset.ext.as_deref().unwrap_or_default();
+        let file_name = format!("{}.{}", file_stem, ext);
+        let out_dir = self.cfg.output_dir(AssetClass::Other);
+        let file_path = out_dir.join(&file_name);
+        let mut args = vec![
+            path_str,
+            format!("--unicodes={}", unicode_range),
+            format!("--output-file={}", dunce::simplified(&file_path).display()),
+        ];
+        // Keep the compression of web fonts.
+        if matches!(self.mime, "font/woff2" | "font/woff") {
+            args.push(format!("--flavor={}", ext.to_lowercase()));
+        }
+        common::remove_file_if_exists(&file_path).await?;
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Pyftsubset.name(), &pyftsubset, &args).await?;
+
+        let Some(hash) = &self.hash else {
+            return Ok(file_name);
+        };
+
+        // Hash the subset font & move it to its final location.
+        let bytes = fs::read(&file_path)
+            .await
+            .with_context(|| format!("error reading subset font {:?}", &file_path))?;
+        let hashed_file_name = hash.name(&file_stem, &format!(".{}", ext), &bytes);
+        let hashed_file_path = out_dir.join(hashing::file_path(&hashed_file_name));
+        fs::rename(&file_path, &hashed_file_path)
+            .await
+            .with_context(|| format!("error moving {:?} to {:?}", &file_path, &hashed_file_path))?;
+        Ok(hashed_file_name)
+    }
+}
+
+/// The output of a font build pipeline.
+#[derive(Clone)]
+pub struct FontOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The MIME type of the font.
+    pub mime: &'static str,
+    /// The format of the font, as used by `@font-face` rules.
+    pub format: &'static str,
+    /// The unicode range the font was subset to, if any.
+    pub unicode_range: Option<String>,
+    /// The font family name to declare a `@font-face` rule for, if any.
+    pub font_family: Option<String>,
+    /// Name of the finalized output file.
+    pub file: String,
+}
+
+impl FontOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let base = &self.cfg.public_url;
+        let mut html = format!(
+            r#"<link rel="preload" as="font" type="{mime}" href="{base}{file}" crossorigin/>"#,
+            mime = self.mime,
+            file = self.file
+        );
+        // Declare the font, as its hashed file name can't be referenced from other stylesheets.
+        if let Some(font_family) = &self.font_family {
+            let unicode_range = self
+                .unicode_range
+                .as_ref()
+                .map(|range| format!("unicode-range:{range};"))
+                .unwrap_or_default();
+            html.push_str(&format!(
+                r#"<style type="text/css">@font-face{{font-family:"{font_family}";src:url("{base}{file}") format("{format}");font-display:swap;{unicode_range}}}</style>"#,
+                file = self.file,
+                format = self.format,
+            ));
+        }
+        dom.select(&super::trunk_id_selector(self.id))
+            .replace_with_html(html);
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! Hook asset pipeline, running a custom command to generate assets.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{ensure, Context, Result};
+use nipper::Document;
+use tokio::sync::mpsc;
+
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput};
+use crate::common;
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::FileHash;
+use crate::hooks::trunk_env;
+
+/// The attribute of the command to run, along with its arguments separated by whitespace.
+const ATTR_COMMAND: &str = "data-command";
+/// The attribute of the comma-separated files written by the command.
+const ATTR_OUTPUT: &str = "data-output";
+
+/// A hook asset pipeline.
+pub struct Hook {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The dir of the source HTML file, which the command runs in.
+    html_dir: Arc<PathBuf>,
+    /// The command to run.
+    command: PathBuf,
+    /// Any arguments to pass to the command.
+    args: Vec<String>,
+    /// The files written by the command, relative to the dir of the source HTML file.
+    outputs: Vec<String>,
+    /// An optional channel to be used to communicate the outputs to the watcher to be ignored.
+    ignore_chan: Option<mpsc::Sender<PathBuf>>,
+    /// The strategy used to hash the output file names, unless hashing is disabled.
+    hash: Option<FileHash>,
+}
+
+impl Hook {
+    pub const TYPE_HOOK: &'static str = "hook";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        ignore_chan: Option<mpsc::Sender<PathBuf>>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        let mut command = attrs
+            .get(ATTR_COMMAND)
+            .context(r#"required attr `data-command` missing for <link data-trunk rel="hook" .../> element"#)?
+            .split_whitespace()
+            .map(String::from);
+        let program = command
+            .next()
+            .context(r#"empty `data-command` of <link data-trunk rel="hook" .../> element"#)?;
+        // Commands with a path are relative to the source HTML file, others are looked up in PATH.
+        let command_path = match program.contains('/') {
+            true => html_dir.join(local_path(&program)),
+            false => PathBuf::from(program),
+        };
+        let outputs: Vec<_> = attrs
+            .get(ATTR_OUTPUT)
+            .context(r#"required attr `data-output` missing for <link data-trunk rel="hook" .../> element"#)?
+            .split(',')
+            .map(str::trim)
+            .filter(|output| !output.is_empty())
+            .map(String::from)
+            .collect();
+        ensure!(
+            !outputs.is_empty(),
+            r#"empty `data-output` of <link data-trunk rel="hook" .../> element"#
+        );
+        let hash = super::file_hash(&cfg, &attrs);
+        Ok(Self {
+            id,
+            cfg,
+            html_dir,
+            command: command_path,
+            args: command.collect(),
+            outputs,
+            ignore_chan,
+            hash,
+        })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let rel_path = common::strip_prefix(&self.command);
+        tracing::info!(command = ?rel_path, "running hook asset");
+
+        // The outputs are written next to the sources, so these must not trigger new builds.
+        if let Some(chan) = &self.ignore_chan {
+            for output in &self.outputs {
+                let _ = chan.try_send(self.html_dir.join(local_path(output)));
+            }
+        }
+
+        let env = self
+            .cfg
+            .env
+            .iter()
+            .map(|(key, val)| (key.into(), val.into()))
+            .chain(
+                trunk_env(&self.cfg)
+                    .into_iter()
+                    .map(|(key, val)| (key.into(), val)),
+            )
+            .collect::<Vec<(OsString, OsString)>>();
+        let name = self.command.display().to_string();
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command_in_dir(&name, &self.command, &self.args, env, &self.html_dir).await?;
+
+        let files =
+            copy_outputs(&self.cfg, &self.html_dir, &self.outputs, self.hash.as_ref()).await?;
+        tracing::info!(command = ?rel_path, "finished running hook asset");
+        Ok(TrunkAssetPipelineOutput::Hook(HookOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            files,
+            html: None,
+        }))
+    }
+}
+
+/// Copy the given output files of a command, relative to `dir`, to the dirs of their asset
+/// classes in the staging dist dir.
+pub(super) async fn copy_outputs(
+    cfg: &RtcBuild,
+    dir: &Path,
+    outputs: &[String],
+    hash: Option<&FileHash>,
+) -> Result<Vec<HookFile>> {
+    let mut files = Vec::with_capacity(outputs.len());
+    for name in outputs {
+        let asset = AssetFile::new(dir, local_path(name))
+            .await
+            .with_context(|| format!("error reading output {:?} of command", name))?;
+        let kind = HookFileKind::of(&asset);
+        let file = asset.copy_output(cfg, kind.class(), hash).await?;
+        files.push(HookFile {
+            name: name.clone(),
+            file,
+            kind,
+        });
+    }
+    Ok(files)
+}
+
+/// The given path of an attr, with `/` separated segments, as a relative FS path.
+pub(super) fn local_path(path: &str) -> PathBuf {
+    let mut local = PathBuf::new();
+    local.extend(path.split('/').filter(|segment| *segment != "."));
+    local
+}
+
+/// The kind of an output file of a hook asset, determining the tag it is referenced by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookFileKind {
+    /// A stylesheet, referenced by a `<link rel="stylesheet">`.
+    Css,
+    /// A script, referenced by a `<script>`.
+    Js,
+    /// A JS module, referenced by a `<script type="module">`.
+    JsModule,
+    /// Any other file, which is only copied.
+    Other,
+}
+
+impl HookFileKind {
+    /// The kind of the given file, by its extension.
+    pub(super) fn of(asset: &AssetFile) -> Self {
+        match asset.ext.as_deref() {
+            Some("css") => Self::Css,
+            Some("js") => Self::Js,
+            Some("mjs") => Self::JsModule,
+            _ => Self::Other,
+        }
+    }
+
+    /// The asset class of the files of this kind.
+    fn class(self) -> AssetClass {
+        match self {
+            Self::Css => AssetClass::Css,
+            Self::Js | Self::JsModule => AssetClass::Js,
+            Self::Other => AssetClass::Other,
+        }
+    }
+}
+
+/// An output file of a hook asset.
+#[derive(Clone, Debug)]
+pub struct HookFile {
+    /// The path of the file as declared, relative to the dir it was written to.
+    pub name: String,
+    /// The path of the file relative to the dist dir.
+    pub file: String,
+    /// The kind of the file.
+    pub kind: HookFileKind,
+}
+
+/// The output of a hook build pipeline.
+#[derive(Clone)]
+pub struct HookOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The output files, in the order they were declared.
+    pub files: Vec<HookFile>,
+    /// The HTML replacing the element, with `{{name}}` placeholders for the URLs of the output
+    /// files, instead of the tags referencing the stylesheets & scripts among them.
+    pub html: Option<String>,
+}
+
+impl HookOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let base = &self.cfg.public_url;
+        if let Some(html) = &self.html {
+            let html = self.files.iter().fold(html.clone(), |html, file| {
+                html.replace(
+                    &format!("{{{{{}}}}}", file.name),
+                    &format!("{base}{}", file.file),
+                )
+            });
+            dom.select(&super::trunk_id_selector(self.id))
+                .replace_with_html(html);
+            return Ok(());
+        }
+        let html = self
+            .files
+            .iter()
+            .filter_map(|HookFile { file, kind, .. }| match kind {
+                HookFileKind::Css => {
+                    Some(format!(r#"<link rel="stylesheet" href="{base}{file}"/>"#))
+                }
+                HookFileKind::Js => Some(format!(r#"<script src="{base}{file}"></script>"#)),
+                HookFileKind::JsModule => Some(format!(
+                    r#"<script type="module" src="{base}{file}"></script>"#
+                )),
+                HookFileKind::Other => None,
+            })
+            .collect::<String>();
+        dom.select(&super::trunk_id_selector(self.id))
+            .replace_with_html(html);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_path_skips_current_dir_segments() {
+        assert_eq!(
+            local_path("./scripts/gen.sh"),
+            PathBuf::from("scripts").join("gen.sh")
+        );
+        assert_eq!(
+            local_path("gen/out.css"),
+            PathBuf::from("gen").join("out.css")
+        );
+    }
+}
@@ -1,205 +1,492 @@
 //! Source HTML pipelines.
 
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use anyhow::{ensure, Context, Result};
-use futures_util::stream::{FuturesUnordered, StreamExt};
+use anyhow::{bail, ensure, Context, Result};
+use futures_util::future::FutureExt;
+use futures_util::stream::{BoxStream, FuturesOrdered, FuturesUnordered, StreamExt};
 use nipper::Document;
 use tokio::fs;
 use tokio::runtime::Handle;
 use tokio::sync::mpsc;
-use tokio::task::JoinHandle;
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
 
+use crate::common;
 use crate::config::RtcBuild;
 use crate::hooks::{spawn_hooks, wait_hooks};
 use crate::pipelines::rust::RustApp;
+use crate::pipelines::{asset_manifest, csp, prerender, sitemap, transform};
 use crate::pipelines::{
-    Attrs, PipelineStage, TrunkAsset, TrunkAssetPipelineOutput, TrunkAssetReference, TRUNK_ID,
+    AssetSources, Attrs, PipelineRegistry, PipelineStage, TrunkAsset, TrunkAssetPipelineOutput,
+    TrunkAssetReference, TRUNK_ID,
 };
+use crate::progress::{self, ProgressEvent};
 
 const PUBLIC_URL_MARKER_ATTR: &str = "data-trunk-public-url";
 const RELOAD_SCRIPT: &str = include_str!("../autoreload.js");
 
-type AssetPipelineHandles = FuturesUnordered<JoinHandle<Result<TrunkAssetPipelineOutput>>>;
+/// The handles of the spawned asset pipelines, along with the index of each asset & the indices
+/// of the documents using it.
+type AssetPipelineHandles = BoxStream<
+    'static,
+    (
+        usize,
+        Vec<usize>,
+        Result<Result<TrunkAssetPipelineOutput>, JoinError>,
+    ),
+>;
+
+/// The sources & outputs of the assets of a build, by asset index.
+type AssetOutputs = Vec<(AssetSources, TrunkAssetPipelineOutput)>;
 
 /// An HTML assets build pipeline.
 ///
 /// This build pipeline is responsible for processing the source HTML of the application, as well
 /// as spawning child pipelines for any assets found in the source HTML.
+///
+/// All HTML files of the build are processed by the same pipeline, so that identical assets of
+/// different documents are only built once.
 pub struct HtmlPipeline {
     /// Runtime config.
     cfg: Arc<RtcBuild>,
-    /// The path to the source HTML document from which the output `index.html` will be built.
-    target_html_path: PathBuf,
-    /// The parent directory of `target_html_path`.
-    target_html_dir: Arc<PathBuf>,
+    /// The source HTML documents to build, starting with the one from which the output
+    /// `index.html` will be built.
+    targets: Vec<HtmlTarget>,
     /// An optional channel to be used to communicate ignore paths to the watcher.
     ignore_chan: Option<mpsc::Sender<PathBuf>>,
+    /// The pipelines of the `<link data-trunk>` assets, by their `rel` values.
+    registry: PipelineRegistry,
+    /// The sources & outputs of the assets of the previous build, if it succeeded, which are
+    /// reused by rebuilds for the assets not affected by the changes.
+    previous: Mutex<Option<AssetOutputs>>,
+}
+
+/// A source HTML document of an HTML pipeline.
+struct HtmlTarget {
+    /// The path to the source HTML document.
+    path: PathBuf,
+    /// The parent directory of `path`.
+    dir: Arc<PathBuf>,
+    /// The path of the output HTML file, relative to the dist dir.
+    output: String,
 }
 
 impl HtmlPipeline {
     /// Create a new instance.
     pub fn new(cfg: Arc<RtcBuild>, ignore_chan: Option<mpsc::Sender<PathBuf>>) -> Result<Self> {
-        let target_html_path = cfg
-            .target
-            .canonicalize()
-            .context("failed to get canonical path of target HTML file")?;
-        let target_html_dir = Arc::new(
-            target_html_path
-                .parent()
-                .context("failed to determine parent dir of target HTML file")?
-                .to_owned(),
-        );
+        let targets = cfg
+            .targets
+            .iter()
+            .map(|target| {
+                let path = target
+                    .path
+                    .canonicalize()
+                    .context("failed to get canonical path of target HTML file")?;
+                let dir = Arc::new(
+                    path.parent()
+                        .context("failed to determine parent dir of target HTML file")?
+                        .to_owned(),
+                );
+                Ok(HtmlTarget {
+                    path,
+                    dir,
+                    output: target.output.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
 
         Ok(Self {
+            registry: PipelineRegistry::new(&cfg.plugins),
             cfg,
-            target_html_path,
-            target_html_dir,
+            targets,
             ignore_chan,
+            previous: Mutex::new(None),
         })
     }
 
+    /// Plan a rebuild for the changes of the given paths, returning whether each asset of the
+    /// previous build is affected by them.
+    ///
+    /// `None` is returned if all assets need to be rebuilt, i.e. without a previous build or if
+    /// any path is not a known source of an asset, such as the source HTML or config files.
+    pub fn plan_rebuild(&self, changed: &[PathBuf]) -> Option<Vec<bool>> {
+        let previous = self.previous.lock().unwrap_or_else(|err| err.into_inner());
+        let previous = previous.as_ref()?;
+        let known = changed.iter().all(|path| {
+            !self.targets.iter().any(|target| &target.path == path)
+                && previous.iter().any(|(sources, _)| sources.contains(path))
+        });
+        if !known {
+            return None;
+        }
+        Some(
+            previous
+                .iter()
+                .map(|(sources, _)| changed.iter().any(|path| sources.affected_by(path)))
+                .collect(),
+        )
+    }
+
+    /// Forget the outputs of the previous build, so that the next build rebuilds all assets.
+    pub fn reset(&self) {
+        *self.previous.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    }
+
     /// Spawn a new pipeline.
-    #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self: Arc<Self>) -> JoinHandle<Result<()>> {
+    ///
+    /// Given the plan of a rebuild, the staging dist dir must hold the files of the previous
+    /// build, of which those of the assets not affected by the changes are reused.
+    ///
+    /// Once the given token is cancelled, the pipeline stops & aborts all asset pipelines.
+    #[tracing::instrument(level = "trace", skip(self, cancel))]
+    pub fn spawn(
+        self: Arc<Self>,
+        plan: Option<Vec<bool>>,
+        cancel: CancellationToken,
+    ) -> JoinHandle<Result<()>> {
         // NOTE WELL: this is a pattern to spawn a blocking thread, and then execute a !Send
         // future on the current thread. This is needed because nipper's internals are !Send.
-        tokio::task::spawn_blocking(move || Handle::current().block_on(self.run()))
+        tokio::task::spawn_blocking(move || {
+            Handle::current().block_on(async move {
+                tokio::select! {
+                    res = self.run(plan) => res,
+                    _ = cancel.cancelled() => bail!("build cancelled"),
+                }
+            })
+        })
     }
 
     /// Run this pipeline.
     #[tracing::instrument(level = "trace", skip(self))]
-    async fn run(self: Arc<Self>) -> Result<()> {
+    async fn run(self: Arc<Self>, plan: Option<Vec<bool>>) -> Result<()> {
+        // The previous outputs are only kept once this build succeeded.
+        let previous = self
+            .previous
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take();
+
         tracing::info!("spawning asset pipelines");
 
         // Spawn and wait on pre-build hooks.
         wait_hooks(spawn_hooks(self.cfg.clone(), PipelineStage::PreBuild)).await?;
 
-        // Open the source HTML file for processing.
-        let raw_html = fs::read_to_string(&self.target_html_path).await?;
-        let mut target_html = Document::from(&raw_html);
-
-        // Iterator over all `link[data-trunk]` elements, assigning IDs & building pipelines.
-        let mut assets = vec![];
-        let links = target_html.select(r#"link[data-trunk], script[data-trunk]"#);
-        for (id, link) in links.nodes().iter().enumerate() {
-            // Set the node's Trunk ID
-            link.set_attr(TRUNK_ID, &id.to_string());
-            let asset_ref = match link.node_name().as_deref() {
-                Some("link") => {
-                    // Accumulate all attrs. The main reason we collect this as
-                    // raw data instead of passing around the link itself is so that we are not
-                    // constrained by `!Send` types.
-                    let attrs = link
-                        .attrs()
-                        .into_iter()
-                        .fold(Attrs::new(), |mut acc, attr| {
-                            acc.insert(
-                                attr.name.local.as_ref().to_string(),
-                                attr.value.to_string(),
-                            );
-                            acc
-                        });
+        // The assets of all documents, along with the indices of the documents using them.
+        let mut assets: Vec<(TrunkAsset, Vec<usize>)> = vec![];
+        // The descriptions of the assets for the progress reports, by asset index.
+        let mut labels = vec![];
+        // The Trunk IDs & indices of the assets, by their source dir, element name & attrs.
+        // Identical elements of different documents share the Trunk ID of the first one, so that
+        // the asset is only built once.
+        let mut asset_keys: HashMap<_, (usize, usize)> = HashMap::new();
+        // The indices of the default Rust app assets, by the dir of their Cargo project.
+        let mut default_apps: HashMap<_, usize> = HashMap::new();
+        // The `id` attrs of the elements, by their Trunk ID.
+        let mut element_ids = HashMap::new();
+        let mut next_id = 0;
+        let mut docs = Vec::with_capacity(self.targets.len());
+        for (doc, target) in self.targets.iter().enumerate() {
+            // Open the source HTML file for processing.
+            let raw_html = fs::read_to_string(&target.path)
+                .await
+                .with_context(|| format!("error reading source HTML file {:?}", &target.path))?;
+            // Env vars set by the config are interpolated as is, any others need to be allowed.
+            let raw_html =
+                common::interpolate_env(&raw_html, |name| match self.cfg.env.get(name) {
+                    Some(val) => Ok(val.clone()),
+                    None => common::allowed_env_var(name, &self.cfg.env_allowlist),
+                })
+                .with_context(|| {
+                    format!(
+                        "error interpolating env vars into source HTML file {:?}",
+                        &target.path
+                    )
+                })?;
+            let target_html = Document::from(&raw_html);
 
-                    Some(TrunkAssetReference::Link(attrs))
+            // Iterator over all `link[data-trunk]` elements, assigning IDs & building pipelines.
+            let links =
+                target_html.select(r#"link[data-trunk], script[data-trunk], img[data-trunk]"#);
+            for link in links.nodes().iter() {
+                // Accumulate all attrs. The main reason we collect this as raw data instead of
+                // passing around the link itself is so that we are not constrained by `!Send`
+                // types.
+                let mut attrs = link
+                    .attrs()
+                    .into_iter()
+                    .fold(Attrs::new(), |mut acc, attr| {
+                        acc.insert(attr.name.local.as_ref().to_string(), attr.value.to_string());
+                        acc
+                    });
+                let node_name = link.node_name().map(|name| name.to_string());
+                let label = asset_label(node_name.as_deref(), &attrs);
+                let key = (
+                    target.dir.clone(),
+                    node_name.clone(),
+                    attrs.clone().into_iter().collect::<BTreeMap<_, _>>(),
+                );
+                if let Some(&(id, idx)) = asset_keys.get(&key) {
+                    // Set the node's Trunk ID to the one of the identical asset.
+                    link.set_attr(TRUNK_ID, &id.to_string());
+                    let asset_docs = &mut assets[idx].1;
+                    if !asset_docs.contains(&doc) {
+                        asset_docs.push(doc);
+                    }
+                    continue;
                 }
-                Some("script") => {
-                    let attrs = link
-                        .attrs()
-                        .into_iter()
-                        .fold(Attrs::new(), |mut acc, attr| {
-                            acc.insert(
-                                attr.name.local.as_ref().to_string(),
-                                attr.value.to_string(),
-                            );
-                            acc
-                        });
-                    Some(TrunkAssetReference::Script(attrs))
+
+                // Set the node's Trunk ID
+                let id = next_id;
+                next_id += 1;
+                link.set_attr(TRUNK_ID, &id.to_string());
+                attrs.insert(TRUNK_ID.into(), id.to_string());
+                if let Some(element_id) = attrs.get("id") {
+                    element_ids.insert(id, element_id.clone());
                 }
-                _ => None,
-            };
+                let asset_ref = match node_name.as_deref() {
+                    Some("link") => Some(TrunkAssetReference::Link(attrs)),
+                    Some("script") => Some(TrunkAssetReference::Script(attrs)),
+                    Some("img") => Some(TrunkAssetReference::Image(attrs)),
+                    _ => None,
+                };
 
-            if let Some(asset_ref) = asset_ref {
-                let asset = TrunkAsset::from_html(
+                if let Some(asset_ref) = asset_ref {
+                    let asset = TrunkAsset::from_html(
+                        self.cfg.clone(),
+                        target.dir.clone(),
+                        self.ignore_chan.clone(),
+                        &self.registry,
+                        asset_ref,
+                        id,
+                    )
+                    .await?;
+                    asset_keys.insert(key, (id, assets.len()));
+                    assets.push((asset, vec![doc]));
+                    labels.push(label);
+                }
+            }
+
+            // Ensure we have a Rust app pipeline to spawn.
+            let rust_app_nodes = target_html
+                .select(r#"link[data-trunk][rel="rust"][data-type="main"], link[data-trunk][rel="rust"]:not([data-type])"#)
+                .length();
+            ensure!(
+                rust_app_nodes <= 1,
+                r#"only one <link data-trunk rel="rust" data-type="main" .../> may be specified"#
+            );
+            if rust_app_nodes == 0 {
+                if let Some(&idx) = default_apps.get(&target.dir) {
+                    assets[idx].1.push(doc);
+                } else if let Ok(app) = RustApp::new_default(
                     self.cfg.clone(),
-                    self.target_html_dir.clone(),
+                    target.dir.clone(),
                     self.ignore_chan.clone(),
-                    asset_ref,
-                    id,
                 )
-                .await?;
-                assets.push(asset);
+                .await
+                {
+                    default_apps.insert(target.dir.clone(), assets.len());
+                    assets.push((TrunkAsset::RustApp(app), vec![doc]));
+                    labels.push(RustApp::TYPE_RUST_APP.to_owned());
+                } else {
+                    tracing::warn!("no rust project found")
+                };
             }
+            docs.push(target_html);
         }
 
-        // Ensure we have a Rust app pipeline to spawn.
-        let rust_app_nodes = target_html
-            .select(r#"link[data-trunk][rel="rust"][data-type="main"], link[data-trunk][rel="rust"]:not([data-type])"#)
-            .length();
-        ensure!(
-            rust_app_nodes <= 1,
-            r#"only one <link data-trunk rel="rust" data-type="main" .../> may be specified"#
-        );
-        if rust_app_nodes == 0 {
-            if let Ok(app) = RustApp::new_default(
-                self.cfg.clone(),
-                self.target_html_dir.clone(),
-                self.ignore_chan.clone(),
-            )
-            .await
-            {
-                assets.push(TrunkAsset::RustApp(app));
-            } else {
-                tracing::warn!("no rust project found")
-            };
+        // Reuse the outputs of the previous build for the assets not affected by the changes. The
+        // files of all others are removed, as hashed files may be replaced by ones of new names.
+        let mut reused = vec![None; assets.len()];
+        if let (Some(affected), Some(previous)) = (plan, previous) {
+            let same_assets = previous.len() == assets.len();
+            for (idx, ((_, output), affected)) in previous.into_iter().zip(affected).enumerate() {
+                if same_assets && !affected {
+                    reused[idx] = Some(output);
+                } else if self.cfg.filehash {
+                    remove_hashed_files(&self.cfg, &output).await?;
+                }
+            }
+            tracing::info!(
+                "rebuilding {} of {} assets affected by the changes",
+                reused.iter().filter(|output| output.is_none()).count(),
+                assets.len()
+            );
         }
+        let sources: Vec<_> = assets.iter().map(|(asset, _)| asset.sources()).collect();
 
         // Spawn all asset pipelines.
-        let mut pipelines: AssetPipelineHandles = FuturesUnordered::new();
-        pipelines.extend(assets.into_iter().map(|asset| asset.spawn()));
+        let handles = assets.into_iter().zip(reused).zip(labels).enumerate().map(
+            |(idx, (((asset, asset_docs), reused), label))| {
+                let res = match reused {
+                    Some(output) => futures_util::future::ready(Ok(Ok(output))).boxed(),
+                    None => {
+                        progress::send(ProgressEvent::PipelineStarted {
+                            id: idx,
+                            asset: label.clone(),
+                        });
+                        asset
+                            .spawn()
+                            .inspect(move |_| {
+                                progress::send(ProgressEvent::PipelineFinished {
+                                    id: idx,
+                                    asset: label,
+                                })
+                            })
+                            .boxed()
+                    }
+                };
+                res.map(move |res| (idx, asset_docs, res))
+            },
+        );
+        // Reproducible builds finalize the assets in document order rather than in the order
+        // their pipelines finish, so that appended elements are always ordered the same way.
+        let pipelines: AssetPipelineHandles = if self.cfg.reproducible {
+            handles.collect::<FuturesOrdered<_>>().boxed()
+        } else {
+            handles.collect::<FuturesUnordered<_>>().boxed()
+        };
         // Spawn all build hooks.
         let build_hooks = spawn_hooks(self.cfg.clone(), PipelineStage::Build);
 
         // Finalize asset pipelines.
-        self.finalize_asset_pipelines(&mut target_html, pipelines)
+        let mut outputs = vec![None; sources.len()];
+        let logical_names = self
+            .finalize_asset_pipelines(&mut docs, pipelines, &element_ids, &mut outputs)
             .await?;
 
         // Wait for all build hooks to finish.
         wait_hooks(build_hooks).await?;
 
-        // Finalize HTML.
-        self.finalize_html(&mut target_html);
+        for (target, mut target_html) in self.targets.iter().zip(docs) {
+            // Finalize HTML.
+            self.finalize_html(&mut target_html)?;
 
-        // Assemble a new output index.html file.
-        let output_html = target_html.html().to_string(); // TODO: prettify this output.
-        fs::write(self.cfg.staging_dist.join("index.html"), &output_html)
+            // Assemble a new output HTML file.
+            let output_html = target_html.html().to_string(); // TODO: prettify this output.
+            let output_path = self.cfg.staging_dist.join(&target.output);
+            if let Some(dir) = output_path.parent() {
+                fs::create_dir_all(dir)
+                    .await
+                    .with_context(|| format!("error creating dir {:?} for HTML output", dir))?;
+            }
+            common::write_file(&output_path, &output_html)
+                .await
+                .with_context(|| {
+                    format!("error writing finalized HTML output {:?}", output_path)
+                })?;
+        }
+
+        if !self.cfg.transforms.is_empty() {
+            transform::apply_transforms(&self.cfg)
+                .await
+                .context("error applying transforms")?;
+        }
+
+        // Routes are only prerendered by release builds which are not served, as it takes a while.
+        if self.cfg.release && !self.cfg.inject_autoloader && self.cfg.prerender.is_some() {
+            prerender::prerender(&self.cfg)
+                .await
+                .context("error prerendering routes")?;
+        }
+        sitemap::write_sitemap(&self.cfg, &logical_names)
+            .await
+            .context("error writing sitemap")?;
+
+        // Write the asset manifest, once all files of the build are in place.
+        asset_manifest::write_asset_manifest(&self.cfg, &logical_names)
             .await
-            .context("error writing finalized HTML output")?;
+            .context("error writing asset manifest")?;
 
         // Spawn and wait on post-build hooks.
         wait_hooks(spawn_hooks(self.cfg.clone(), PipelineStage::PostBuild)).await?;
 
+        *self.previous.lock().unwrap_or_else(|err| err.into_inner()) = sources
+            .into_iter()
+            .zip(outputs)
+            .map(|(sources, output)| Some((sources, output?)))
+            .collect();
         Ok(())
     }
 
-    /// Finalize asset pipelines & prep the DOM for final output.
+    /// Finalize asset pipelines & prep the DOMs for final output.
+    ///
+    /// Each asset is finalized into all documents using it. `element_ids` maps the Trunk IDs of
+    /// the asset elements to their `id` attrs, if any. The outputs of the assets are stored in
+    /// `outputs` by asset index, while the logical names of all hashed output files are returned,
+    /// by their path in the dist dir.
     async fn finalize_asset_pipelines(
         &self,
-        target_html: &mut Document,
+        docs: &mut [Document],
         mut pipelines: AssetPipelineHandles,
-    ) -> Result<()> {
-        while let Some(asset_res) = pipelines.next().await {
+        element_ids: &HashMap<usize, String>,
+        outputs: &mut [Option<TrunkAssetPipelineOutput>],
+    ) -> Result<HashMap<String, String>> {
+        let mut deferred = vec![];
+        let mut logical_names = HashMap::new();
+        // The output files of the finalized assets which can be preloaded, by element `id`, for
+        // each document.
+        let mut preload_targets = vec![HashMap::new(); docs.len()];
+        while let Some((idx, asset_docs, asset_res)) = pipelines.next().await {
             let asset = asset_res
                 .context("failed to await asset finalization")?
                 .context("error from asset pipeline")?;
-            asset.finalize(target_html).await?;
+            outputs[idx] = Some(asset.clone());
+            if self.cfg.filehash {
+                logical_names.extend(asset.hashed_files());
+            }
+            // Copied files & dirs may rewrite references to hashed files in the output of other
+            // assets, preload hints may reference the output of other assets, import maps map the
+            // output of all JS assets, while service workers precache the output of all other
+            // assets, so these are finalized last, in this order.
+            if matches!(
+                asset,
+                TrunkAssetPipelineOutput::CopyDir(_)
+                    | TrunkAssetPipelineOutput::CopyFile(_)
+                    | TrunkAssetPipelineOutput::Preload(_)
+                    | TrunkAssetPipelineOutput::ImportMap(_)
+                    | TrunkAssetPipelineOutput::ServiceWorker(_)
+            ) {
+                deferred.push((asset_docs, asset));
+                continue;
+            }
+            if let Some((id, file, as_value)) = asset.preload_target() {
+                if let Some(element_id) = element_ids.get(&id) {
+                    for &doc in &asset_docs {
+                        preload_targets[doc]
+                            .insert(element_id.clone(), (file.to_string(), as_value));
+                    }
+                }
+            }
+            asset.finalize_dist().await?;
+            for &doc in &asset_docs {
+                asset.clone().finalize(&mut docs[doc]).await?;
+            }
         }
-        Ok(())
+        deferred.sort_by_key(|(_, asset)| match asset {
+            TrunkAssetPipelineOutput::Preload(_) | TrunkAssetPipelineOutput::ImportMap(_) => 1,
+            TrunkAssetPipelineOutput::ServiceWorker(_) => 2,
+            _ => 0,
+        });
+        for (asset_docs, asset) in deferred {
+            asset.finalize_dist().await?;
+            for &doc in &asset_docs {
+                let mut asset = asset.clone();
+                match &mut asset {
+                    TrunkAssetPipelineOutput::Preload(out) => out.resolve(&preload_targets[doc])?,
+                    TrunkAssetPipelineOutput::ImportMap(out) => out.resolve(&logical_names),
+                    _ => {}
+                }
+                asset.finalize(&mut docs[doc]).await?;
+            }
+        }
+        Ok(logical_names)
     }
 
     /// Prepare the document for final output.
-    fn finalize_html(&self, target_html: &mut Document) {
+    fn finalize_html(&self, target_html: &mut Document) -> Result<()> {
         // Write public_url to base element.
         let mut base_elements =
             target_html.select(&format!("html head base[{}]", PUBLIC_URL_MARKER_ATTR));
@@ -212,5 +499,44 @@ impl HtmlPipeline {
                 .select("body")
                 .append_html(format!("<script>{}</script>", RELOAD_SCRIPT));
         }
+
+        // Generate the Content-Security-Policy, once all inline scripts & styles are in place.
+        csp::finalize_csp(target_html, self.cfg.reproducible)
+            .context("error generating Content-Security-Policy")
+    }
+}
+
+/// Remove the hashed files of the given output of a previous build from the staging dist dir,
+/// along with their precompressed copies.
+async fn remove_hashed_files(cfg: &RtcBuild, output: &TrunkAssetPipelineOutput) -> Result<()> {
+    for (file, _) in output.hashed_files() {
+        for ext in ["", ".gz", ".br"] {
+            let path = cfg.staging_dist.join(format!("{}{}", file, ext));
+            match fs::remove_file(&path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(err)
+                        .with_context(|| format!("error removing previous file {:?}", path));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A description of the asset of the element of the given name & attrs for progress reports,
+/// such as `sass index.scss`.
+fn asset_label(node_name: Option<&str>, attrs: &Attrs) -> String {
+    let (kind, path) = match node_name {
+        Some("script") => ("js", attrs.get("src")),
+        Some("img") => ("image", attrs.get("src")),
+        _ => (
+            attrs.get("rel").map_or("link", String::as_str),
+            attrs.get("href"),
+        ),
+    };
+    match path {
+        Some(path) => format!("{} {}", kind, path),
+        None => kind.to_owned(),
     }
 }
@@ -1,14 +1,26 @@
 //! Icon asset pipeline.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use nipper::Document;
-use tokio::task::JoinHandle;
+use tokio::fs;
 
-use super::{AssetFile, Attrs, TrunkAssetPipelineOutput, ATTR_HREF};
-use crate::config::RtcBuild;
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF};
+use crate::common;
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
+use crate::tools::Application;
+
+/// The attribute used to enable icon generation & to configure the generated sizes.
+const ATTR_SIZES: &str = "data-sizes";
+/// The PNG icon sizes generated if `data-sizes` is empty.
+const DEFAULT_SIZES: &[u32] = &[16, 32, 48, 192, 512];
+/// The sizes embedded into the generated `.ico` file.
+const ICO_SIZES: &str = "16,32,48";
+/// The size of the generated apple touch icon.
+const APPLE_TOUCH_ICON_SIZE: u32 = 180;
 
 /// An Icon asset pipeline.
 pub struct Icon {
@@ -17,7 +29,11 @@ pub struct Icon {
     /// Runtime build config.
     cfg: Arc<RtcBuild>,
     /// The asset file being processed.
-    asset: AssetFile,
+    pub(super) asset: AssetFile,
+    /// The PNG icon sizes to generate from the source image, if icon generation is enabled.
+    sizes: Option<Vec<u32>>,
+    /// The strategy used to hash the output file names, unless hashing is disabled.
+    hash: Option<FileHash>,
 }
 
 impl Icon {
@@ -36,51 +52,260 @@ impl Icon {
         let mut path = PathBuf::new();
         path.extend(href_attr.split('/'));
         let asset = AssetFile::new(&html_dir, path).await?;
-        Ok(Self { id, cfg, asset })
+        let sizes = attrs
+            .get(ATTR_SIZES)
+            .map(|val| parse_sizes(val))
+            .transpose()?;
+        let hash = super::file_hash(&cfg, &attrs);
+        Ok(Self {
+            id,
+            cfg,
+            asset,
+            sizes,
+            hash,
+        })
     }
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
     #[tracing::instrument(level = "trace", skip(self))]
     async fn run(self) -> Result<TrunkAssetPipelineOutput> {
         let rel_path = crate::common::strip_prefix(&self.asset.path);
-        tracing::info!(path = ?rel_path, "copying & hashing icon");
-        let file = self
-            .asset
-            .copy(&self.cfg.staging_dist, self.cfg.filehash)
-            .await?;
-        tracing::info!(path = ?rel_path, "finished copying & hashing icon");
+        let icons = match &self.sizes {
+            Some(sizes) => {
+                tracing::info!(path = ?rel_path, "generating icons");
+                let icons = self.generate(sizes).await?;
+                tracing::info!(path = ?rel_path, "finished generating icons");
+                icons
+            }
+            None => {
+                tracing::info!(path = ?rel_path, "copying & hashing icon");
+                let file = self
+                    .asset
+                    .copy_output(&self.cfg, AssetClass::Other, self.hash.as_ref())
+                    .await?;
+                tracing::info!(path = ?rel_path, "finished copying & hashing icon");
+                vec![IconRef {
+                    rel: "icon",
+                    mime: None,
+                    sizes: None,
+                    file,
+                }]
+            }
+        };
         Ok(TrunkAssetPipelineOutput::Icon(IconOutput {
             cfg: self.cfg.clone(),
             id: self.id,
-            file,
+            icons,
         }))
     }
+
+    /// Generate a favicon, PNG icons of the given sizes & an apple touch icon from the source
+    /// image using ImageMagick.
+    async fn generate(&self, sizes: &[u32]) -> Result<Vec<IconRef>> {
+        let magick = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Magick,
+                self.cfg.tool_version(Application::Magick, None),
+            )
+            .await?;
+        let file_stem = self.asset.file_stem.to_string_lossy();
+
+        let mut icons = Vec::with_capacity(sizes.len() + 2);
+        let file = self
+            .run_magick(
+                &magick,
+                &[
+                    "-background",
+                    "none",
+                    "-define",
+                    &format!("icon:auto-resize={ICO_SIZES}"),
+                ],
+                file_stem.to_string(),
+                "ico",
+            )
+            .await?;
+        icons.push(IconRef {
+            rel: "icon",
+            mime: None,
+            sizes: Some("any".into()),
+            file,
+        });
+        for size in sizes.iter().copied() {
+            let name = format!("{file_stem}-{size}x{size}");
+            let file = self.resize_png(&magick, size, name).await?;
+            icons.push(IconRef {
+                rel: "icon",
+                mime: Some("image/png"),
+                sizes: Some(format!("{size}x{size}")),
+                file,
+            });
+        }
+        let name = format!("{file_stem}-apple-touch-icon");
+        let file = self
+            .resize_png(&magick, APPLE_TOUCH_ICON_SIZE, name)
+            .await?;
+        icons.push(IconRef {
+            rel: "apple-touch-icon",
+            mime: None,
+            sizes: Some(format!("{APPLE_TOUCH_ICON_SIZE}x{APPLE_TOUCH_ICON_SIZE}")),
+            file,
+        });
+        Ok(icons)
+    }
+
+    /// Resize the source image into a square PNG of the given size.
+    async fn resize_png(&self, magick: &Path, size: u32, name: String) -> Result<String> {
+        let geometry = format!("{size}x{size}");
+        let args = [
+            "-background",
+            "none",
+            "-resize",
+            &geometry,
+            "-gravity",
+            "center",
+            "-extent",
+            &geometry,
+        ];
+        self.run_magick(magick, &args, name, "png").await
+    }
+
+    /// Run ImageMagick on the source image with the given arguments, writing the result to the
+    /// assets dir of the staging dist dir using the given file name & extension.
+    ///
+    /// The path of the output relative to the dist dir, hashed if enabled, is returned.
+    async fn run_magick(
+        &self,
+        magick: &Path,
+        args: &[&str],
+        name: String,
+        ext: &str,
+    ) -> Result<String> {
+        let file_name = format!("{}.{}", name, ext);
+        let out_dir = self.cfg.output_dir(AssetClass::Other);
+        let file_path = out_dir.join(&file_name);
+
+        let path_str = dunce::simplified(&self.asset.path).display().to_string();
+        let file_path_str = dunce::simplified(&file_path).display().to_string();
+        let mut magick_args = vec![path_str.as_str()];
+        magick_args.extend(args);
+        magick_args.push(&file_path_str);
+        common::remove_file_if_exists(&file_path).await?;
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Magick.name(), magick, &magick_args).await?;
+
+        let Some(hash) = &self.hash else {
+            return Ok(self.cfg.output_file(AssetClass::Other, &file_name));
+        };
+
+        // Hash the generated icon & move it to its final location.
+        let bytes = fs::read(&file_path)
+            .await
+            .with_context(|| format!("error reading generated icon {:?}", &file_path))?;
+        let hashed_file_name = hash.name(&name, &format!(".{}", ext), &bytes);
+        let hashed_file_path = out_dir.join(hashing::file_path(&hashed_file_name));
+        fs::rename(&file_path, &hashed_file_path)
+            .await
+            .with_context(|| format!("error moving {:?} to {:?}", &file_path, &hashed_file_path))?;
+        Ok(self.cfg.output_file(AssetClass::Other, &hashed_file_name))
+    }
+}
+
+/// Parse the value of a `data-sizes` attribute, a comma or whitespace separated list of sizes in
+/// pixels. An empty value results in the default sizes.
+fn parse_sizes(val: &str) -> Result<Vec<u32>> {
+    let sizes = val
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|size| !size.is_empty())
+        .map(|size| match size.parse::<u32>() {
+            Ok(size) if size > 0 => Ok(size),
+            _ => bail!(
+                r#"invalid size `{}` in `data-sizes` attr for <link data-trunk rel="icon" .../> element; please ensure all sizes are positive numbers"#,
+                size
+            ),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if sizes.is_empty() {
+        return Ok(DEFAULT_SIZES.to_vec());
+    }
+    Ok(sizes)
+}
+
+/// A reference to a finalized icon file.
+#[derive(Clone)]
+pub struct IconRef {
+    /// The value of the `rel` attribute of the icon link.
+    pub rel: &'static str,
+    /// The MIME type of the icon, if it should be specified.
+    pub mime: Option<&'static str>,
+    /// The value of the `sizes` attribute of the icon link, if any.
+    pub sizes: Option<String>,
+    /// Name of the finalized output file.
+    pub file: String,
 }
 
 /// The output of an Icon build pipeline.
+#[derive(Clone)]
 pub struct IconOutput {
     /// The runtime build config.
     pub cfg: Arc<RtcBuild>,
     /// The ID of this pipeline.
     pub id: usize,
-    /// Name of the finalized output file.
-    pub file: String,
+    /// The finalized icons.
+    pub icons: Vec<IconRef>,
 }
 
 impl IconOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let html = self
+            .icons
+            .iter()
+            .map(|icon| {
+                let mime = icon
+                    .mime
+                    .map(|mime| format!(r#" type="{mime}""#))
+                    .unwrap_or_default();
+                let sizes = icon
+                    .sizes
+                    .as_ref()
+                    .map(|sizes| format!(r#" sizes="{sizes}""#))
+                    .unwrap_or_default();
+                format!(
+                    r#"<link rel="{rel}"{mime}{sizes} href="{base}{file}"/>"#,
+                    rel = icon.rel,
+                    base = &self.cfg.public_url,
+                    file = icon.file
+                )
+            })
+            .collect::<String>();
         dom.select(&super::trunk_id_selector(self.id))
-            .replace_with_html(format!(
-                r#"<link rel="icon" href="{base}{file}"/>"#,
-                base = &self.cfg.public_url,
-                file = self.file
-            ));
+            .replace_with_html(html);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sizes_defaults_if_empty() {
+        let sizes = parse_sizes("").expect("expected sizes to parse");
+        assert_eq!(sizes, DEFAULT_SIZES);
+    }
+
+    #[test]
+    fn parse_sizes_accepts_separators() {
+        let sizes = parse_sizes("16, 32 192").expect("expected sizes to parse");
+        assert_eq!(sizes, vec![16, 32, 192]);
+        assert!(parse_sizes("16,0").is_err());
+        assert!(parse_sizes("large").is_err());
+    }
+}
@@ -0,0 +1,417 @@
+//! Image asset pipeline.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use nipper::Document;
+use tokio::fs;
+
+use super::{
+    AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF, ATTR_NO_HASH, ATTR_REL,
+    ATTR_SRC,
+};
+use crate::common;
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
+use crate::tools::Application;
+
+/// The attribute used to configure the quality of lossy image optimization.
+const ATTR_QUALITY: &str = "data-quality";
+/// The attribute used to configure additional image formats to generate.
+const ATTR_FORMATS: &str = "data-formats";
+/// The quality used when converting images to other formats, if none is configured.
+const DEFAULT_CONVERSION_QUALITY: u8 = 80;
+
+/// An image asset pipeline.
+pub struct Image {
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The asset file being processed.
+    pub(super) asset: AssetFile,
+    /// An optional quality setting (`0` to `100`) which enables lossy optimization for image
+    /// formats that support it.
+    quality: Option<u8>,
+    /// Additional formats the image should be converted into, in order of preference.
+    formats: Vec<ImageFormat>,
+    /// The CSS selector of the source HTML element.
+    selector: String,
+    /// The attributes to be placed on the output `img` element.
+    attrs: Attrs,
+    /// The strategy used to hash the output file names, unless hashing is disabled.
+    hash: Option<FileHash>,
+}
+
+impl Image {
+    pub const TYPE_IMAGE: &'static str = "image";
+
+    /// Create a new instance from a `<link data-trunk rel="image" .../>` element.
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        let href_attr = attrs.get(ATTR_HREF).context(
+            r#"required attr `href` missing for <link data-trunk rel="image" .../> element"#,
+        )?;
+        let target = href_attr.clone();
+        let selector = super::trunk_id_selector(id);
+        Self::from_attrs(cfg, html_dir, target, attrs, selector).await
+    }
+
+    /// Create a new instance from an `<img data-trunk .../>` element.
+    pub async fn new_img(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        let src_attr = attrs
+            .get(ATTR_SRC)
+            .context(r#"required attr `src` missing for <img data-trunk .../> element"#)?;
+        let target = src_attr.clone();
+        let selector = super::trunk_img_id_selector(id);
+        Self::from_attrs(cfg, html_dir, target, attrs, selector).await
+    }
+
+    async fn from_attrs(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        target: String,
+        attrs: Attrs,
+        selector: String,
+    ) -> Result<Self> {
+        // Build the path to the target asset.
+        let mut path = PathBuf::new();
+        path.extend(target.split('/'));
+        let asset = AssetFile::new(&html_dir, path).await?;
+        let quality = attrs
+            .get(ATTR_QUALITY)
+            .map(|val| parse_quality(val))
+            .transpose()?;
+        let formats = attrs
+            .get(ATTR_FORMATS)
+            .map(|val| parse_formats(val))
+            .transpose()?
+            .unwrap_or_default();
+        let hash = super::file_hash(&cfg, &attrs);
+        // Remove trunk specific attributes, everything else is passed along to the `img` element.
+        let attrs = attrs
+            .into_iter()
+            .filter(|(x, _)| {
+                *x != ATTR_HREF
+                    && *x != ATTR_SRC
+                    && *x != ATTR_REL
+                    && *x != ATTR_QUALITY
+                    && *x != ATTR_FORMATS
+                    && *x != ATTR_NO_HASH
+                    && !x.starts_with("data-trunk")
+            })
+            .collect();
+        Ok(Self {
+            cfg,
+            asset,
+            quality,
+            formats,
+            selector,
+            attrs,
+            hash,
+        })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let rel_path = crate::common::strip_prefix(&self.asset.path);
+        tracing::info!(path = ?rel_path, "copying & hashing image");
+        let file = self
+            .asset
+            .copy_output(&self.cfg, AssetClass::Other, self.hash.as_ref())
+            .await?;
+
+        // Image optimization can take quite some time, so it is only performed for release builds.
+        let file_path = self.cfg.staging_dist.join(hashing::file_path(&file));
+        let ext = self.asset.ext.as_deref().map(str::to_lowercase);
+        let convertible = matches!(ext.as_deref(), Some("png" | "jpg" | "jpeg"));
+        if self.cfg.release {
+            match ext.as_deref() {
+                Some("png") => self.optimize_png(&file_path).await?,
+                Some("jpg" | "jpeg") => self.optimize_jpeg(&file_path).await?,
+                _ => tracing::debug!(path = ?rel_path, "no optimizer available for image type"),
+            }
+        }
+
+        // Generate the requested variants. Only PNG & JPEG sources can be converted.
+        let mut variants = Vec::with_capacity(self.formats.len());
+        if !self.formats.is_empty() && !convertible {
+            tracing::warn!(path = ?rel_path, "only png & jpeg images can be converted to other formats, skipping");
+        } else {
+            for format in self.formats.iter().copied() {
+                let file = self.convert(format).await?;
+                variants.push(ImageVariant { format, file });
+            }
+        }
+
+        tracing::info!(path = ?rel_path, "finished copying & hashing image");
+        Ok(TrunkAssetPipelineOutput::Image(ImageOutput {
+            cfg: self.cfg.clone(),
+            selector: self.selector,
+            file,
+            variants,
+            attrs: self.attrs,
+        }))
+    }
+
+    /// Optimize the PNG at the given path in place using `oxipng`.
+    ///
+    /// PNG optimization is always lossless, so the quality setting has no effect.
+    async fn optimize_png(&self, file_path: &Path) -> Result<()> {
+        let version = self.cfg.tools.oxipng.as_deref();
+        let oxipng = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Oxipng,
+                self.cfg.tool_version(Application::Oxipng, version),
+            )
+            .await?;
+
+        let path_str = dunce::simplified(file_path).display().to_string();
+        let args = &["--opt", "4", "--strip", "safe", "--quiet", &path_str];
+
+        tracing::info!(path = ?common::strip_prefix(&self.asset.path), "optimizing png");
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Oxipng.name(), &oxipng, args).await
+    }
+
+    /// Optimize the JPEG at the given path in place using `jpegoptim`.
+    ///
+    /// If a quality has been configured, a lossy optimization is performed.
+    async fn optimize_jpeg(&self, file_path: &Path) -> Result<()> {
+        let jpegoptim = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Jpegoptim,
+                self.cfg.tool_version(Application::Jpegoptim, None),
+            )
+            .await?;
+
+        let path_str = dunce::simplified(file_path).display().to_string();
+        let arg_quality = self.quality.map(|quality| format!("--max={}", quality));
+        let mut args = vec!["--strip-all", "--quiet"];
+        if let Some(arg_quality) = &arg_quality {
+            args.push(arg_quality);
+        }
+        args.push(&path_str);
+
+        tracing::info!(path = ?common::strip_prefix(&self.asset.path), "optimizing jpeg");
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Jpegoptim.name(), &jpegoptim, &args).await
+    }
+
+    /// Convert the source image into the given format, writing the result to the assets dir of the
+    /// staging dist dir.
+    ///
+    /// The path of the generated image relative to the dist dir is returned.
+    async fn convert(&self, format: ImageFormat) -> Result<String> {
+        let quality = self
+            .quality
+            .unwrap_or(DEFAULT_CONVERSION_QUALITY)
+            .to_string();
+        let file_stem = self.asset.file_stem.to_string_lossy();
+        let out_dir = self.cfg.output_dir(AssetClass::Other);
+        let file_path = out_dir.join(format!("{}.{}", file_stem, format.ext()));
+
+        let path_str = dunce::simplified(&self.asset.path).display().to_string();
+        let file_path_str = dunce::simplified(&file_path).display().to_string();
+
+        tracing::info!(path = ?common::strip_prefix(&self.asset.path), "converting image to {}", format.ext());
+        common::remove_file_if_exists(&file_path).await?;
+        let _job = self.cfg.acquire_job().await?;
+        match format {
+            ImageFormat::Webp => {
+                let version = self.cfg.tools.cwebp.as_deref();
+                let cwebp = self
+                    .cfg
+                    .tool_context
+                    .get(
+                        Application::Cwebp,
+                        self.cfg.tool_version(Application::Cwebp, version),
+                    )
+                    .await?;
+                let args = &["-quiet", "-q", &quality, &path_str, "-o", &file_path_str];
+                common::run_command(Application::Cwebp.name(), &cwebp, args).await?;
+            }
+            ImageFormat::Avif => {
+                let avifenc = self
+                    .cfg
+                    .tool_context
+                    .get(
+                        Application::Avifenc,
+                        self.cfg.tool_version(Application::Avifenc, None),
+                    )
+                    .await?;
+                let args = &["-q", &quality, &path_str, &file_path_str];
+                common::run_command(Application::Avifenc.name(), &avifenc, args).await?;
+            }
+        }
+
+        let Some(hash) = &self.hash else {
+            let file_name = format!("{}.{}", file_stem, format.ext());
+            return Ok(self.cfg.output_file(AssetClass::Other, &file_name));
+        };
+
+        // Hash the generated image & move it to its final location.
+        let bytes = fs::read(&file_path)
+            .await
+            .with_context(|| format!("error reading converted image {:?}", &file_path))?;
+        let file_name = hash.name(&file_stem, &format!(".{}", format.ext()), &bytes);
+        let hashed_path = out_dir.join(hashing::file_path(&file_name));
+        fs::rename(&file_path, &hashed_path)
+            .await
+            .with_context(|| format!("error moving {:?} to {:?}", &file_path, &hashed_path))?;
+        Ok(self.cfg.output_file(AssetClass::Other, &file_name))
+    }
+}
+
+/// Parse the value of a `data-quality` attribute.
+fn parse_quality(val: &str) -> Result<u8> {
+    match val.parse::<u8>() {
+        Ok(quality) if quality <= 100 => Ok(quality),
+        _ => bail!(
+            r#"invalid `data-quality="{}"` value for image asset; please ensure the value is a number between 0 and 100"#,
+            val
+        ),
+    }
+}
+
+/// Parse the value of a `data-formats` attribute, a comma or whitespace separated list.
+fn parse_formats(val: &str) -> Result<Vec<ImageFormat>> {
+    val.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|format| !format.is_empty())
+        .map(ImageFormat::from_str)
+        .collect()
+}
+
+/// An image format which PNG & JPEG images can be converted into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Webp,
+    Avif,
+}
+
+impl ImageFormat {
+    /// The file extension of this format.
+    fn ext(self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// The MIME type of this format.
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "webp" => Ok(Self::Webp),
+            "avif" => Ok(Self::Avif),
+            _ => bail!(
+                r#"unknown image format `{}` in `data-formats` attr; please ensure the value is lowercase and is one of `webp` or `avif`"#,
+                s
+            ),
+        }
+    }
+}
+
+/// A generated variant of an image in another format.
+#[derive(Clone)]
+pub struct ImageVariant {
+    /// The format of the variant.
+    pub format: ImageFormat,
+    /// Name of the finalized output file.
+    pub file: String,
+}
+
+/// The output of an image build pipeline.
+#[derive(Clone)]
+pub struct ImageOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The CSS selector of the source HTML element.
+    pub selector: String,
+    /// Name of the finalized output file.
+    pub file: String,
+    /// Generated variants of the image, in order of preference.
+    pub variants: Vec<ImageVariant>,
+    /// The attributes to be added to the `img` element.
+    pub attrs: Attrs,
+}
+
+impl ImageOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let base = &self.cfg.public_url;
+        let attrs = self
+            .attrs
+            .into_iter()
+            .map(|(k, v)| format!(r#" {k}="{v}""#))
+            .collect::<String>();
+        let img = format!(r#"<img src="{base}{file}"{attrs}/>"#, file = self.file);
+
+        // Wrap the image in a `<picture>` element so that browsers can pick a supported variant.
+        let html = if self.variants.is_empty() {
+            img
+        } else {
+            let sources = self
+                .variants
+                .iter()
+                .map(|variant| {
+                    format!(
+                        r#"<source type="{mime}" srcset="{base}{file}"/>"#,
+                        mime = variant.format.mime(),
+                        file = variant.file,
+                    )
+                })
+                .collect::<String>();
+            format!("<picture>{sources}{img}</picture>")
+        };
+        dom.select(&self.selector).replace_with_html(html);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats_accepts_separators() {
+        let formats = parse_formats("avif, webp").expect("expected formats to parse");
+        assert_eq!(formats, vec![ImageFormat::Avif, ImageFormat::Webp]);
+        let formats = parse_formats("webp avif").expect("expected formats to parse");
+        assert_eq!(formats, vec![ImageFormat::Webp, ImageFormat::Avif]);
+    }
+
+    #[test]
+    fn parse_formats_rejects_unknown() {
+        assert!(parse_formats("webp,gif").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Import map pipeline.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nipper::Document;
+use serde_json::{Map, Value};
+use tokio::fs;
+
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF};
+use crate::config::RtcBuild;
+
+/// An import map pipeline.
+pub struct ImportMap {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The JSON file of the import map, with the mappings of bare specifiers, if any.
+    pub(super) asset: Option<AssetFile>,
+}
+
+impl ImportMap {
+    pub const TYPE_IMPORT_MAP: &'static str = "importmap";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        let asset = match attrs.get(ATTR_HREF) {
+            Some(href_attr) => {
+                let mut path = PathBuf::new();
+                path.extend(href_attr.split('/'));
+                Some(AssetFile::new(&html_dir, path).await?)
+            }
+            None => None,
+        };
+        Ok(Self { id, cfg, asset })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let map = match &self.asset {
+            Some(asset) => {
+                let rel_path = crate::common::strip_prefix(&asset.path);
+                tracing::info!(path = ?rel_path, "reading import map");
+                let json = fs::read(&asset.path)
+                    .await
+                    .with_context(|| format!("error reading import map {:?}", rel_path))?;
+                let map: Map<String, Value> = serde_json::from_slice(&json)
+                    .with_context(|| format!("error parsing import map {:?}", rel_path))?;
+                if !matches!(map.get("imports"), None | Some(Value::Object(_))) {
+                    anyhow::bail!(
+                        "the `imports` of import map {:?} must be an object",
+                        rel_path
+                    );
+                }
+                map
+            }
+            None => Map::new(),
+        };
+        Ok(TrunkAssetPipelineOutput::ImportMap(ImportMapOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            map,
+        }))
+    }
+}
+
+/// The output of an import map pipeline.
+#[derive(Clone)]
+pub struct ImportMapOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The import map.
+    pub map: Map<String, Value>,
+}
+
+impl ImportMapOutput {
+    /// Map the URLs of the hashed JS files of the build to the URLs of their logical names, so
+    /// that modules can import each other by their unhashed names.
+    ///
+    /// `logical_names` maps the paths of all hashed files in the dist dir to their logical names.
+    /// Mappings of the import map itself take precedence.
+    pub fn resolve(&mut self, logical_names: &HashMap<String, String>) {
+        let base = &self.cfg.public_url;
+        let mut files: Vec<_> = logical_names
+            .iter()
+            .filter(|(_, name)| name.ends_with(".js") || name.ends_with(".mjs"))
+            .collect();
+        files.sort();
+        let imports = self
+            .map
+            .entry("imports")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(imports) = imports {
+            for (file, name) in files {
+                imports
+                    .entry(format!("{base}{name}"))
+                    .or_insert_with(|| format!("{base}{file}").into());
+            }
+        }
+    }
+
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        // The import map only applies to the module scripts after it, so it is moved to the start
+        // of the head, before any scripts or preload hints.
+        let selector = super::trunk_id_selector(self.id);
+        let link = dom.select(&selector);
+        let head = dom.select("html head");
+        let first = head.nodes().first().and_then(|head| head.first_child());
+        if let (Some(link), Some(first)) = (link.nodes().first(), first) {
+            if first.id != link.id {
+                first.append_prev_sibling(&link.id);
+            }
+        }
+        // `</` is escaped, so that no value can end the script element.
+        let json = Value::Object(self.map).to_string().replace("</", r"<\/");
+        dom.select(&selector)
+            .replace_with_html(format!(r#"<script type="importmap">{json}</script>"#));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn import_map_maps_hashed_modules_before_module_scripts() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let cfg = Arc::new(RtcBuild::new_test(tmpdir.path()).await?);
+        let map = serde_json::json!({"imports": {"lit": "https://esm.sh/lit@3"}});
+        let mut output = ImportMapOutput {
+            cfg,
+            id: 0,
+            map: map.as_object().cloned().unwrap_or_default(),
+        };
+        output.resolve(&HashMap::from([
+            ("js/app-123.js".into(), "js/app.js".into()),
+            ("style-456.css".into(), "style.css".into()),
+        ]));
+        let mut dom = Document::from(
+            r#"<html><head><script type="module" src="/a.js"></script></head><body><link data-trunk-id="0"></body></html>"#,
+        );
+        output.finalize(&mut dom).await?;
+        assert_eq!(
+            dom.select("head").html().to_string(),
+            r#"<head><script type="importmap">{"imports":{"/js/app.js":"/js/app-123.js","lit":"https://esm.sh/lit@3"}}</script><script type="module" src="/a.js"></script></head>"#
+        );
+        Ok(())
+    }
+}
@@ -6,16 +6,18 @@ use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use nipper::Document;
-use tokio::task::JoinHandle;
 
-use super::{AssetFile, Attrs, TrunkAssetPipelineOutput, ATTR_HREF, ATTR_TYPE};
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF, ATTR_TYPE};
+
+/// The attribute used to override the content type, taking precedence over `type`.
+const ATTR_DATA_TYPE: &str = "data-type";
 
 /// An Inline asset pipeline.
 pub struct Inline {
     /// The ID of this pipeline's source HTML element.
     id: usize,
     /// The asset file being processed.
-    asset: AssetFile,
+    pub(super) asset: AssetFile,
     /// The type of the asset file that determines how the content of the file
     /// is inserted into `index.html`.
     content_type: ContentType,
@@ -33,8 +35,8 @@ impl Inline {
         path.extend(href_attr.split('/'));
 
         let asset = AssetFile::new(&html_dir, path).await?;
-        let content_type =
-            ContentType::from_attr_or_ext(attrs.get(ATTR_TYPE), asset.ext.as_deref())?;
+        let type_attr = attrs.get(ATTR_DATA_TYPE).or_else(|| attrs.get(ATTR_TYPE));
+        let content_type = ContentType::from_attr_or_ext(type_attr, asset.ext.as_deref())?;
 
         Ok(Self {
             id,
@@ -45,8 +47,8 @@ impl Inline {
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
@@ -66,6 +68,7 @@ impl Inline {
 }
 
 /// The content type of a inlined file.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ContentType {
     /// Html is just pasted into `index.html` as is.
     Html,
@@ -111,6 +114,7 @@ impl FromStr for ContentType {
 }
 
 /// The output of a Inline build pipeline.
+#[derive(Clone)]
 pub struct InlineOutput {
     /// The ID of this pipeline.
     pub id: usize,
@@ -133,3 +137,23 @@ impl InlineOutput {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_type_attr_takes_precedence_over_ext() {
+        let content_type = ContentType::from_attr_or_ext(Some("svg"), Some("html"))
+            .expect("expected content type from attr");
+        assert_eq!(content_type, ContentType::Svg);
+    }
+
+    #[test]
+    fn content_type_inferred_from_ext() {
+        let content_type = ContentType::from_attr_or_ext(None::<&str>, Some("css"))
+            .expect("expected content type from ext");
+        assert_eq!(content_type, ContentType::Css);
+        assert!(ContentType::from_attr_or_ext(None::<&str>, Some("txt")).is_err());
+    }
+}
@@ -3,12 +3,25 @@
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use nipper::Document;
-use tokio::task::JoinHandle;
+use tokio::fs;
 
-use super::{AssetFile, Attrs, TrunkAssetPipelineOutput, ATTR_SRC};
-use crate::config::RtcBuild;
+use super::{
+    AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_INTEGRITY, ATTR_NO_HASH,
+    ATTR_SRC, ATTR_TYPE,
+};
+use crate::common;
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
+use crate::tools::Application;
+
+/// The attribute used to configure the ECMAScript version transpiled TypeScript should target.
+const ATTR_TARGET: &str = "data-target";
+/// The attribute used to enable or disable minification.
+const ATTR_MINIFY: &str = "data-minify";
+/// The attribute used to bundle the imports of the script into its output.
+const ATTR_BUNDLE: &str = "data-bundle";
 
 /// A JS asset pipeline.
 pub struct Js {
@@ -17,9 +30,19 @@ pub struct Js {
     /// Runtime build config.
     cfg: Arc<RtcBuild>,
     /// The asset file being processed.
-    asset: AssetFile,
+    pub(super) asset: AssetFile,
+    /// The ECMAScript version to target when transpiling TypeScript, e.g. `es2020`.
+    target: Option<String>,
+    /// If the JS should be minified.
+    minify: bool,
+    /// If the imports of the JS, including packages of `node_modules`, should be bundled into it.
+    pub(super) bundle: bool,
+    /// If subresource integrity attributes should be added to the script tag.
+    use_integrity: bool,
     /// The attributes to be placed on the output script tag.
     attrs: Attrs,
+    /// The strategy used to hash the output file name, unless hashing is disabled.
+    hash: Option<FileHash>,
 }
 
 impl Js {
@@ -36,44 +59,161 @@ impl Js {
         let mut path = PathBuf::new();
         path.extend(src_attr.split('/'));
         let asset = AssetFile::new(&html_dir, path).await?;
-        // Remove src and data-trunk from attributes.
+        let target = attrs.get(ATTR_TARGET).cloned();
+        // Minification is enabled by default for release builds.
+        let minify = match attrs.get(ATTR_MINIFY).map(String::as_str) {
+            None => cfg.minify,
+            Some("" | "true") => true,
+            Some("false") => false,
+            Some(other) => bail!(
+                r#"invalid `data-minify="{}"` value for <script data-trunk .../> element; please ensure the value is empty, `true` or `false`"#,
+                other
+            ),
+        };
+        let bundle = match attrs.get(ATTR_BUNDLE).map(String::as_str) {
+            None | Some("false") => false,
+            Some("" | "true") => true,
+            Some(other) => bail!(
+                r#"invalid `data-bundle="{}"` value for <script data-trunk .../> element; please ensure the value is empty, `true` or `false`"#,
+                other
+            ),
+        };
+        let use_integrity = super::use_integrity(&cfg, &attrs)?;
+        let hash = super::file_hash(&cfg, &attrs);
+        // Remove src, data-target, data-minify, data-bundle, data-integrity, data-no-hash and
+        // data-trunk from attributes.
         let attrs = attrs
             .into_iter()
-            .filter(|(x, _)| *x != "src" && !x.starts_with("data-trunk"))
+            .filter(|(x, _)| {
+                *x != "src"
+                    && *x != ATTR_TARGET
+                    && *x != ATTR_MINIFY
+                    && *x != ATTR_BUNDLE
+                    && *x != ATTR_INTEGRITY
+                    && *x != ATTR_NO_HASH
+                    && !x.starts_with("data-trunk")
+            })
             .collect();
         Ok(Self {
             id,
             cfg,
             asset,
+            target,
+            minify,
+            bundle,
+            use_integrity,
             attrs,
+            hash,
         })
     }
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
     #[tracing::instrument(level = "trace", skip(self))]
     async fn run(self) -> Result<TrunkAssetPipelineOutput> {
         let rel_path = crate::common::strip_prefix(&self.asset.path);
-        tracing::info!(path = ?rel_path, "copying & hashing js");
-        let file = self
-            .asset
-            .copy(&self.cfg.staging_dist, self.cfg.filehash)
-            .await?;
-        tracing::info!(path = ?rel_path, "finished copying & hashing js");
+        let file = if self.is_typescript() || self.minify || self.bundle {
+            tracing::info!(path = ?rel_path, "processing & hashing js");
+            let file = self.process().await?;
+            tracing::info!(path = ?rel_path, "finished processing & hashing js");
+            self.cfg.output_file(AssetClass::Js, &file)
+        } else {
+            tracing::info!(path = ?rel_path, "copying & hashing js");
+            let file = self
+                .asset
+                .copy_output(&self.cfg, AssetClass::Js, self.hash.as_ref())
+                .await?;
+            tracing::info!(path = ?rel_path, "finished copying & hashing js");
+            file
+        };
+        let integrity = if self.use_integrity {
+            let file_path = self.cfg.staging_dist.join(hashing::file_path(&file));
+            Some(super::file_integrity(&file_path).await?)
+        } else {
+            None
+        };
         let attrs = Self::attrs_to_string(self.attrs);
         Ok(TrunkAssetPipelineOutput::Js(JsOutput {
             cfg: self.cfg.clone(),
             id: self.id,
             file,
+            integrity,
             attrs,
         }))
     }
 
+    /// Check if the asset is a TypeScript file which needs to be transpiled.
+    fn is_typescript(&self) -> bool {
+        matches!(self.asset.ext.as_deref(), Some("ts" | "mts" | "tsx"))
+    }
+
+    /// Check if the asset is loaded as a JS module.
+    fn is_module(&self) -> bool {
+        self.attrs.get(ATTR_TYPE).map(String::as_str) == Some("module")
+    }
+
+    /// Process the asset using `esbuild`, transpiling TypeScript to JS by stripping all types,
+    /// bundling its imports and minifying it if enabled. Module scripts additionally have unused
+    /// code removed.
+    ///
+    /// The resulting JS is written to the JS dir of the staging dist dir, hashed if enabled, and
+    /// its base file name is returned.
+    async fn process(&self) -> Result<String> {
+        let version = self.cfg.tools.esbuild.as_deref();
+        let esbuild = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Esbuild,
+                self.cfg.tool_version(Application::Esbuild, version),
+            )
+            .await?;
+
+        let path_str = dunce::simplified(&self.asset.path).display().to_string();
+        let file_name = format!("{}.js", &self.asset.file_stem.to_string_lossy());
+        let out_dir = self.cfg.output_dir(AssetClass::Js);
+        let file_path = out_dir.join(&file_name);
+        let arg_outfile = format!("--outfile={}", dunce::simplified(&file_path).display());
+        let mut args = vec![path_str, arg_outfile, "--log-level=warning".into()];
+        if let Some(target) = &self.target {
+            args.push(format!("--target={}", target));
+        }
+        if self.minify {
+            args.push("--minify".into());
+        }
+        if self.bundle {
+            // Packages are resolved from the `node_modules` dirs next to the script & above it.
+            args.push("--bundle".into());
+        }
+        if self.is_module() {
+            args.extend(["--format=esm".into(), "--tree-shaking=true".into()]);
+        }
+
+        common::remove_file_if_exists(&file_path).await?;
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Esbuild.name(), &esbuild, &args).await?;
+
+        let Some(hash) = &self.hash else {
+            return Ok(file_name);
+        };
+
+        // Hash the generated JS & move it to its final location.
+        let js = fs::read(&file_path)
+            .await
+            .with_context(|| format!("error reading processed js {:?}", &file_path))?;
+        let hashed_file_name = hash.name(&self.asset.file_stem.to_string_lossy(), ".js", &js);
+        let hashed_file_path = out_dir.join(hashing::file_path(&hashed_file_name));
+        fs::rename(&file_path, &hashed_file_path)
+            .await
+            .with_context(|| format!("error moving {:?} to {:?}", &file_path, &hashed_file_path))?;
+        Ok(hashed_file_name)
+    }
+
     /// Convert attributes to a string, to be used in JsOutput.
     fn attrs_to_string(attrs: Attrs) -> String {
         attrs
@@ -85,6 +225,7 @@ impl Js {
 }
 
 /// The output of a JS build pipeline.
+#[derive(Clone)]
 pub struct JsOutput {
     /// The runtime build config.
     pub cfg: Arc<RtcBuild>,
@@ -92,6 +233,8 @@ pub struct JsOutput {
     pub id: usize,
     /// Name of the finalized output file.
     pub file: String,
+    /// The subresource integrity digest of the output file, if enabled.
+    pub integrity: Option<String>,
     /// The attributes to be added to the script tag.
     pub attrs: String,
 }
@@ -100,10 +243,11 @@ impl JsOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
         dom.select(&super::trunk_script_id_selector(self.id))
             .replace_with_html(format!(
-                r#"<script {attrs} src="{base}{file}"/>"#,
+                r#"<script {attrs} src="{base}{file}"{integrity}/>"#,
                 attrs = self.attrs,
                 base = &self.cfg.public_url,
-                file = self.file
+                file = self.file,
+                integrity = super::integrity_attrs(self.integrity.as_deref()),
             ));
         Ok(())
     }
@@ -0,0 +1,146 @@
+//! Less asset pipeline.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nipper::Document;
+use tokio::fs;
+
+use super::sass::CssRef;
+use super::{
+    AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_CRITICAL, ATTR_HREF,
+    ATTR_INLINE,
+};
+use crate::common;
+use crate::config::RtcBuild;
+use crate::hashing::FileHash;
+use crate::tools::Application;
+
+/// A less asset pipeline.
+pub struct Less {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The asset file being processed.
+    pub(super) asset: AssetFile,
+    /// If the specified less file should be inlined.
+    use_inline: bool,
+    /// If subresource integrity attributes should be added to the stylesheet link.
+    use_integrity: bool,
+    /// If the critical rules should be inlined & the stylesheet deferred.
+    use_critical: bool,
+    /// The strategy used to hash the output file name, unless hashing is disabled.
+    hash: Option<FileHash>,
+}
+
+impl Less {
+    pub const TYPE_LESS: &'static str = "less";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        // Build the path to the target asset.
+        let href_attr = attrs.get(ATTR_HREF).context(
+            r#"required attr `href` missing for <link data-trunk rel="less" .../> element"#,
+        )?;
+        let mut path = PathBuf::new();
+        path.extend(href_attr.split('/'));
+        let asset = AssetFile::new(&html_dir, path).await?;
+        let use_inline = attrs.contains_key(ATTR_INLINE);
+        let use_integrity = super::use_integrity(&cfg, &attrs)?;
+        // Critical CSS is only extracted for release builds.
+        let use_critical = attrs.contains_key(ATTR_CRITICAL) && cfg.release;
+        let hash = super::file_hash(&cfg, &attrs);
+        Ok(Self {
+            id,
+            cfg,
+            asset,
+            use_inline,
+            use_integrity,
+            use_critical,
+            hash,
+        })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let version = self.cfg.tools.lessc.as_deref();
+        let lessc = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Lessc,
+                self.cfg.tool_version(Application::Lessc, version),
+            )
+            .await?;
+
+        // Compile the target less file.
+        let path_str = dunce::simplified(&self.asset.path).display().to_string();
+        let file_name = format!("{}.css", &self.asset.file_stem.to_string_lossy());
+        let file_path = dunce::simplified(&self.cfg.staging_dist.join(&file_name))
+            .display()
+            .to_string();
+        let args = &["--no-color", &path_str, &file_path];
+
+        let rel_path = crate::common::strip_prefix(&self.asset.path);
+        tracing::info!(path = ?rel_path, "compiling less");
+        common::remove_file_if_exists(&file_path).await?;
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Lessc.name(), &lessc, args).await?;
+
+        let css = fs::read_to_string(&file_path).await?;
+        fs::remove_file(&file_path).await?;
+
+        let css_ref = CssRef::new(
+            &self.cfg,
+            &self.asset,
+            css,
+            None,
+            self.hash.as_ref(),
+            self.use_inline,
+            self.use_integrity,
+            self.use_critical,
+        )
+        .await
+        .context("error writing less pipeline output")?;
+
+        tracing::info!(path = ?rel_path, "finished compiling less");
+        Ok(TrunkAssetPipelineOutput::Less(LessOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            css_ref,
+        }))
+    }
+}
+
+/// The output of a less build pipeline.
+#[derive(Clone)]
+pub struct LessOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// Data on the finalized output file.
+    pub css_ref: CssRef,
+}
+
+impl LessOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let html = self.css_ref.to_html(&self.cfg, dom);
+        dom.select(&super::trunk_id_selector(self.id))
+            .replace_with_html(html);
+        Ok(())
+    }
+}
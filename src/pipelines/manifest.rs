@@ -0,0 +1,150 @@
+//! Web app manifest asset pipeline.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nipper::Document;
+use serde_json::Value;
+
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF};
+use crate::common;
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
+
+/// A web app manifest asset pipeline.
+pub struct Manifest {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The asset file being processed.
+    pub(super) asset: AssetFile,
+    /// The strategy used to hash the names of the manifest & its icons, unless hashing is
+    /// disabled.
+    hash: Option<FileHash>,
+}
+
+impl Manifest {
+    pub const TYPE_MANIFEST: &'static str = "manifest";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        // Build the path to the target asset.
+        let href_attr = attrs.get(ATTR_HREF).context(
+            r#"required attr `href` missing for <link data-trunk rel="manifest" .../> element"#,
+        )?;
+        let mut path = PathBuf::new();
+        path.extend(href_attr.split('/'));
+        let asset = AssetFile::new(&html_dir, path).await?;
+        let hash = super::file_hash(&cfg, &attrs);
+        Ok(Self {
+            id,
+            cfg,
+            asset,
+            hash,
+        })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let rel_path = crate::common::strip_prefix(&self.asset.path);
+        tracing::info!(path = ?rel_path, "processing & hashing manifest");
+        let content = self.asset.read_to_string().await?;
+        let mut manifest: Value = serde_json::from_str(&content)
+            .with_context(|| format!("error parsing web app manifest {:?}", &self.asset.path))?;
+
+        // Copy all icons referenced by the manifest & point to their hashed output files.
+        if let Some(icons) = manifest.get_mut("icons").and_then(Value::as_array_mut) {
+            for icon in icons {
+                if let Some(src) = icon.get_mut("src") {
+                    if let Some(file) = self.copy_icon(src).await? {
+                        *src = Value::String(format!("{}{}", &self.cfg.public_url, file));
+                    }
+                }
+            }
+        }
+
+        let content = serde_json::to_string(&manifest).context("error serializing manifest")?;
+        let file_name = match &self.hash {
+            Some(hash) => {
+                super::hashed_file_name(hash, Path::new(&self.asset.file_name), content.as_bytes())
+            }
+            None => self.asset.file_name.to_string_lossy().into_owned(),
+        };
+        let file_name = self.cfg.output_file(AssetClass::Other, &file_name);
+        common::write_file(
+            self.cfg.staging_dist.join(hashing::file_path(&file_name)),
+            content,
+        )
+        .await
+        .context("error writing manifest pipeline output")?;
+        tracing::info!(path = ?rel_path, "finished processing & hashing manifest");
+
+        Ok(TrunkAssetPipelineOutput::Manifest(ManifestOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            file: file_name,
+        }))
+    }
+
+    /// Copy the icon referenced by the given `src` value of a manifest icon to the assets dir of
+    /// the staging dist dir.
+    ///
+    /// Icons which don't reference a local file relative to the manifest, such as remote URLs or
+    /// absolute paths, are left untouched and `None` is returned.
+    async fn copy_icon(&self, src: &Value) -> Result<Option<String>> {
+        let src = match src.as_str() {
+            Some(src) if !src.starts_with('/') && !src.contains(':') => src,
+            _ => return Ok(None),
+        };
+        let manifest_dir = self
+            .asset
+            .path
+            .parent()
+            .context("manifest has no parent directory")?;
+        let mut path = PathBuf::new();
+        path.extend(src.split('/'));
+        let icon = AssetFile::new(manifest_dir, path)
+            .await
+            .with_context(|| format!("error finding icon `{}` of web app manifest", src))?;
+        let file = icon
+            .copy_output(&self.cfg, AssetClass::Other, self.hash.as_ref())
+            .await?;
+        Ok(Some(file))
+    }
+}
+
+/// The output of a web app manifest build pipeline.
+#[derive(Clone)]
+pub struct ManifestOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// Name of the finalized output file.
+    pub file: String,
+}
+
+impl ManifestOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        dom.select(&super::trunk_id_selector(self.id))
+            .replace_with_html(format!(
+                r#"<link rel="manifest" href="{base}{file}"/>"#,
+                base = &self.cfg.public_url,
+                file = self.file
+            ));
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Markdown asset pipeline.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nipper::Document;
+use tokio::fs;
+
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF};
+use crate::common;
+use crate::config::RtcBuild;
+use crate::tools::Application;
+
+/// The attribute used to inject the generated HTML into another element.
+const ATTR_TARGET_SELECTOR: &str = "data-target-selector";
+
+/// A Markdown asset pipeline.
+pub struct Markdown {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The asset file being processed.
+    pub(super) asset: AssetFile,
+    /// An optional CSS selector of the element the generated HTML should be placed in.
+    target_selector: Option<String>,
+}
+
+impl Markdown {
+    pub const TYPE_MARKDOWN: &'static str = "markdown";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        // Build the path to the target asset.
+        let href_attr = attrs.get(ATTR_HREF).context(
+            r#"required attr `href` missing for <link data-trunk rel="markdown" .../> element"#,
+        )?;
+        let mut path = PathBuf::new();
+        path.extend(href_attr.split('/'));
+        let asset = AssetFile::new(&html_dir, path).await?;
+        let target_selector = attrs
+            .get(ATTR_TARGET_SELECTOR)
+            .filter(|selector| !selector.is_empty())
+            .cloned();
+        Ok(Self {
+            id,
+            cfg,
+            asset,
+            target_selector,
+        })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let pandoc = self
+            .cfg
+            .tool_context
+            .get(
+                Application::Pandoc,
+                self.cfg.tool_version(Application::Pandoc, None),
+            )
+            .await?;
+
+        let path_str = dunce::simplified(&self.asset.path).display().to_string();
+        let file_name = format!(
+            "{}-{}.html",
+            &self.asset.file_stem.to_string_lossy(),
+            self.id
+        );
+        let file_path = self.cfg.staging_dist.join(&file_name);
+        let arg_output = format!("--output={}", dunce::simplified(&file_path).display());
+        let args = &["--from=gfm", "--to=html", &arg_output, &path_str];
+
+        let rel_path = crate::common::strip_prefix(&self.asset.path);
+        tracing::info!(path = ?rel_path, "converting markdown");
+        common::remove_file_if_exists(&file_path).await?;
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::Pandoc.name(), &pandoc, args).await?;
+
+        let html = fs::read_to_string(&file_path).await?;
+        fs::remove_file(&file_path).await?;
+        tracing::info!(path = ?rel_path, "finished converting markdown");
+
+        Ok(TrunkAssetPipelineOutput::Markdown(MarkdownOutput {
+            id: self.id,
+            html,
+            target_selector: self.target_selector,
+        }))
+    }
+}
+
+/// The output of a Markdown build pipeline.
+#[derive(Clone)]
+pub struct MarkdownOutput {
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The HTML generated from the Markdown file.
+    pub html: String,
+    /// The CSS selector of the element the HTML should be placed in, if any.
+    pub target_selector: Option<String>,
+}
+
+impl MarkdownOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let mut link = dom.select(&super::trunk_id_selector(self.id));
+        match &self.target_selector {
+            Some(selector) => {
+                let mut target = dom.select(selector);
+                if !target.exists() {
+                    tracing::warn!(
+                        "no element matches `{}={}`, the markdown content is not added",
+                        ATTR_TARGET_SELECTOR,
+                        selector
+                    );
+                }
+                target.set_html(self.html);
+                link.remove();
+            }
+            None => link.replace_with_html(self.html),
+        }
+        Ok(())
+    }
+}
@@ -1,44 +1,93 @@
+mod asset_manifest;
+mod budget;
+mod cargo;
 mod copy_dir;
 #[cfg(test)]
 mod copy_dir_test;
 mod copy_file;
 #[cfg(test)]
 mod copy_file_test;
+mod critical_css;
+mod csp;
 mod css;
+mod font;
+mod hook;
 mod html;
 mod icon;
+mod image;
+mod import_map;
 mod inline;
 mod js;
+mod less;
+mod manifest;
+mod markdown;
+mod plugin;
+mod preload;
+mod prerender;
+mod registry;
 mod rust;
 mod sass;
+mod service_worker;
+mod sitemap;
 mod tailwind_css;
+mod transform;
 
 use std::collections::HashMap;
 use std::ffi::OsString;
-use std::path::{Path, PathBuf};
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
 
 use anyhow::{bail, ensure, Context, Result};
+pub use asset_manifest::{read_asset_manifest, ManifestEntry, MANIFEST_DIR};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+pub use budget::{check_budgets, Budget};
+pub(crate) use copy_dir::url_path;
 pub use html::HtmlPipeline;
 use nipper::Document;
+pub use prerender::Prerender;
+pub use registry::PipelineRegistry;
 use serde::Deserialize;
+use sha2::{Digest, Sha384};
+pub use sitemap::Sitemap;
 use tokio::fs;
 use tokio::sync::mpsc;
-use tokio::task::JoinHandle;
+use tokio::task::{JoinError, JoinHandle};
+pub use transform::Transform;
 
-use crate::common::path_exists;
-use crate::config::RtcBuild;
+use crate::common::{self, path_exists};
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
 use crate::pipelines::copy_dir::{CopyDir, CopyDirOutput};
 use crate::pipelines::copy_file::{CopyFile, CopyFileOutput};
 use crate::pipelines::css::{Css, CssOutput};
+use crate::pipelines::font::{Font, FontOutput};
+use crate::pipelines::hook::{Hook, HookOutput};
 use crate::pipelines::icon::{Icon, IconOutput};
+use crate::pipelines::image::{Image, ImageOutput};
+use crate::pipelines::import_map::{ImportMap, ImportMapOutput};
 use crate::pipelines::inline::{Inline, InlineOutput};
 use crate::pipelines::js::{Js, JsOutput};
-use crate::pipelines::rust::{RustApp, RustAppOutput};
+use crate::pipelines::less::{Less, LessOutput};
+use crate::pipelines::manifest::{Manifest, ManifestOutput};
+use crate::pipelines::markdown::{Markdown, MarkdownOutput};
+use crate::pipelines::plugin::Plugin;
+use crate::pipelines::preload::{Preload, PreloadOutput, PreloadOutputTarget};
+use crate::pipelines::registry::LinkPipeline;
+use crate::pipelines::rust::{RustApp, RustAppOutput, RustAppType};
 use crate::pipelines::sass::{Sass, SassOutput};
+use crate::pipelines::service_worker::{ServiceWorker, ServiceWorkerOutput};
 use crate::pipelines::tailwind_css::{TailwindCss, TailwindCssOutput};
 
 const ATTR_INLINE: &str = "data-inline";
+const ATTR_CRITICAL: &str = "data-critical";
+const ATTR_INTEGRITY: &str = "data-integrity";
+const ATTR_HASH: &str = "data-hash";
+const ATTR_NO_HASH: &str = "data-no-hash";
+const ATTR_TARGET_PATH: &str = "data-target-path";
 const ATTR_HREF: &str = "href";
 const ATTR_SRC: &str = "src";
 const ATTR_TYPE: &str = "type";
@@ -53,6 +102,7 @@ pub type Attrs = HashMap<String, String>;
 pub enum TrunkAssetReference {
     Link(Attrs),
     Script(Attrs),
+    Image(Attrs),
 }
 
 /// A model of all of the supported Trunk asset links expressed in the source HTML as
@@ -65,12 +115,22 @@ pub enum TrunkAssetReference {
 pub enum TrunkAsset {
     Css(Css),
     Sass(Sass),
+    Less(Less),
     TailwindCss(TailwindCss),
     Js(Js),
     Icon(Icon),
+    Image(Image),
+    Font(Font),
+    Manifest(Manifest),
+    ServiceWorker(ServiceWorker),
     Inline(Inline),
+    Markdown(Markdown),
+    Preload(Preload),
+    ImportMap(ImportMap),
     CopyFile(CopyFile),
     CopyDir(CopyDir),
+    Hook(Hook),
+    Plugin(Plugin),
     RustApp(RustApp),
 }
 
@@ -80,6 +140,7 @@ impl TrunkAsset {
         cfg: Arc<RtcBuild>,
         html_dir: Arc<PathBuf>,
         ignore_chan: Option<mpsc::Sender<PathBuf>>,
+        registry: &PipelineRegistry,
         reference: TrunkAssetReference,
         id: usize,
     ) -> Result<Self> {
@@ -89,63 +150,216 @@ impl TrunkAsset {
                     "all <link data-trunk .../> elements must have a `rel` attribute indicating \
                      the asset type",
                 )?;
-                Ok(match rel.as_str() {
-                    Sass::TYPE_SASS | Sass::TYPE_SCSS => {
-                        Self::Sass(Sass::new(cfg, html_dir, attrs, id).await?)
+                let pipeline = registry.get(rel).cloned().with_context(|| {
+                    format!(
+                        r#"unknown <link data-trunk .../> attr value `rel="{}"`; please ensure the value is lowercase and is one of {}"#,
+                        rel,
+                        registry.rels().join(", ")
+                    )
+                })?;
+                Ok(match pipeline {
+                    LinkPipeline::Sass => Self::Sass(Sass::new(cfg, html_dir, attrs, id).await?),
+                    LinkPipeline::Less => Self::Less(Less::new(cfg, html_dir, attrs, id).await?),
+                    LinkPipeline::Icon => Self::Icon(Icon::new(cfg, html_dir, attrs, id).await?),
+                    LinkPipeline::Image => Self::Image(Image::new(cfg, html_dir, attrs, id).await?),
+                    LinkPipeline::Font => Self::Font(Font::new(cfg, html_dir, attrs, id).await?),
+                    LinkPipeline::Manifest => {
+                        Self::Manifest(Manifest::new(cfg, html_dir, attrs, id).await?)
                     }
-                    Icon::TYPE_ICON => Self::Icon(Icon::new(cfg, html_dir, attrs, id).await?),
-                    Inline::TYPE_INLINE => Self::Inline(Inline::new(html_dir, attrs, id).await?),
-                    Css::TYPE_CSS => Self::Css(Css::new(cfg, html_dir, attrs, id).await?),
-                    CopyFile::TYPE_COPY_FILE => {
+                    LinkPipeline::ServiceWorker => {
+                        Self::ServiceWorker(ServiceWorker::new(cfg, html_dir, attrs, id).await?)
+                    }
+                    LinkPipeline::Inline => Self::Inline(Inline::new(html_dir, attrs, id).await?),
+                    LinkPipeline::Markdown => {
+                        Self::Markdown(Markdown::new(cfg, html_dir, attrs, id).await?)
+                    }
+                    LinkPipeline::Preload => {
+                        Self::Preload(Preload::new(cfg, html_dir, attrs, id).await?)
+                    }
+                    LinkPipeline::ImportMap => {
+                        Self::ImportMap(ImportMap::new(cfg, html_dir, attrs, id).await?)
+                    }
+                    LinkPipeline::Css => Self::Css(Css::new(cfg, html_dir, attrs, id).await?),
+                    LinkPipeline::CopyFile => {
                         Self::CopyFile(CopyFile::new(cfg, html_dir, attrs, id).await?)
                     }
-                    CopyDir::TYPE_COPY_DIR => {
+                    LinkPipeline::CopyDir => {
                         Self::CopyDir(CopyDir::new(cfg, html_dir, attrs, id).await?)
                     }
-                    RustApp::TYPE_RUST_APP => {
+                    LinkPipeline::Hook => {
+                        Self::Hook(Hook::new(cfg, html_dir, ignore_chan, attrs, id).await?)
+                    }
+                    LinkPipeline::RustApp => {
                         Self::RustApp(RustApp::new(cfg, html_dir, ignore_chan, attrs, id).await?)
                     }
-                    TailwindCss::TYPE_TAILWIND_CSS => {
+                    LinkPipeline::TailwindCss => {
                         Self::TailwindCss(TailwindCss::new(cfg, html_dir, attrs, id).await?)
                     }
-                    _ => bail!(
-                        r#"unknown <link data-trunk .../> attr value `rel="{}"`; please ensure the value is lowercase and is a supported asset type"#,
-                        rel
-                    ),
+                    LinkPipeline::Plugin(plugin) => {
+                        Self::Plugin(Plugin::new(cfg, html_dir, plugin, attrs, id))
+                    }
                 })
             }
             TrunkAssetReference::Script(attrs) => {
                 Ok(Self::Js(Js::new(cfg, html_dir, attrs, id).await?))
             }
+            TrunkAssetReference::Image(attrs) => {
+                Ok(Self::Image(Image::new_img(cfg, html_dir, attrs, id).await?))
+            }
+        }
+    }
+
+    /// The sources of this asset, determining which changes it is affected by.
+    pub fn sources(&self) -> AssetSources {
+        let file = |asset: &AssetFile| AssetSources::Paths(vec![asset.path.clone()]);
+        // Stylesheets are affected by the files they import, which are usually placed next to
+        // them or in their load paths.
+        let dir = |asset: &AssetFile| asset.path.parent().map(PathBuf::from).into_iter();
+        // Changed paths are canonical, unlike the paths given by attrs.
+        let canonical =
+            |path: &PathBuf| std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        match self {
+            Self::Js(Js {
+                asset,
+                bundle: false,
+                ..
+            })
+            | Self::Icon(Icon { asset, .. })
+            | Self::Image(Image { asset, .. })
+            | Self::Font(Font { asset, .. })
+            | Self::Manifest(Manifest { asset, .. })
+            | Self::Inline(Inline { asset, .. })
+            | Self::Markdown(Markdown { asset, .. })
+            | Self::CopyFile(CopyFile { asset, .. }) => file(asset),
+            // Bundled scripts are affected by the modules they import as well.
+            Self::Less(Less { asset, .. })
+            | Self::Js(Js {
+                asset,
+                bundle: true,
+                ..
+            }) => AssetSources::Paths(dir(asset).collect()),
+            Self::Css(Css {
+                asset,
+                include_paths: load_paths,
+                ..
+            })
+            | Self::Sass(Sass {
+                asset, load_paths, ..
+            }) => AssetSources::Paths(dir(asset).chain(load_paths.iter().map(canonical)).collect()),
+            Self::CopyDir(CopyDir { path, .. }) => AssetSources::Paths(vec![canonical(path)]),
+            Self::RustApp(app) => AssetSources::Paths(app.sources()),
+            // Tailwind scans any content files for classes, service workers list the outputs of
+            // all other assets, preload hints may reference them & the commands of hooks and
+            // plugins may read any file. Import maps map the outputs of all JS assets.
+            Self::TailwindCss(_)
+            | Self::ServiceWorker(_)
+            | Self::Preload(_)
+            | Self::ImportMap(_)
+            | Self::Hook(_)
+            | Self::Plugin(_) => AssetSources::Any,
         }
     }
 
     /// Spawn the build pipeline for this asset.
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
         match self {
             Self::Css(inner) => inner.spawn(),
             Self::Sass(inner) => inner.spawn(),
+            Self::Less(inner) => inner.spawn(),
             Self::TailwindCss(inner) => inner.spawn(),
             Self::Js(inner) => inner.spawn(),
             Self::Icon(inner) => inner.spawn(),
+            Self::Image(inner) => inner.spawn(),
+            Self::Font(inner) => inner.spawn(),
+            Self::Manifest(inner) => inner.spawn(),
+            Self::ServiceWorker(inner) => inner.spawn(),
             Self::Inline(inner) => inner.spawn(),
+            Self::Markdown(inner) => inner.spawn(),
+            Self::Preload(inner) => inner.spawn(),
+            Self::ImportMap(inner) => inner.spawn(),
             Self::CopyFile(inner) => inner.spawn(),
             Self::CopyDir(inner) => inner.spawn(),
+            Self::Hook(inner) => inner.spawn(),
+            Self::Plugin(inner) => inner.spawn(),
             Self::RustApp(inner) => inner.spawn(),
         }
     }
 }
 
+/// The source files & dirs of an asset, determining which changes it is affected by, so that
+/// rebuilds only re-run the pipelines of the assets affected by the changes.
+#[derive(Clone, Debug)]
+pub enum AssetSources {
+    /// The asset is affected by changes of the given files & anything in the given dirs.
+    Paths(Vec<PathBuf>),
+    /// The asset may be affected by any change, so it is rebuilt by every build.
+    Any,
+}
+
+impl AssetSources {
+    /// Whether the asset is affected by a change of the given path.
+    pub fn affected_by(&self, path: &Path) -> bool {
+        match self {
+            Self::Paths(paths) => paths.iter().any(|source| path.starts_with(source)),
+            Self::Any => true,
+        }
+    }
+
+    /// Whether the given path is a known source of the asset, rather than the asset being
+    /// affected by any change.
+    pub fn contains(&self, path: &Path) -> bool {
+        matches!(self, Self::Paths(_)) && self.affected_by(path)
+    }
+}
+
+/// Spawn the given pipeline onto the runtime.
+pub fn spawn<T>(pipeline: impl Future<Output = T> + Send + 'static) -> PipelineHandle<T>
+where
+    T: Send + 'static,
+{
+    PipelineHandle(tokio::spawn(pipeline))
+}
+
+/// The handle of a spawned pipeline, resolving to its output.
+///
+/// The pipeline is aborted when its handle is dropped, so that the pipelines of a cancelled build
+/// do not keep running.
+pub struct PipelineHandle<T>(JoinHandle<T>);
+
+impl<T> Future for PipelineHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for PipelineHandle<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 /// The output of a `<trunk-link/>` asset pipeline.
+#[derive(Clone)]
 pub enum TrunkAssetPipelineOutput {
     Css(CssOutput),
     Sass(SassOutput),
+    Less(LessOutput),
     TailwindCss(TailwindCssOutput),
     Js(JsOutput),
     Icon(IconOutput),
+    Image(ImageOutput),
+    Font(FontOutput),
+    Manifest(ManifestOutput),
+    ServiceWorker(ServiceWorkerOutput),
     Inline(InlineOutput),
+    Markdown(MarkdownOutput),
+    Preload(PreloadOutput),
+    ImportMap(ImportMapOutput),
     CopyFile(CopyFileOutput),
     CopyDir(CopyDirOutput),
+    Hook(HookOutput),
     RustApp(RustAppOutput),
 }
 
@@ -154,15 +368,113 @@ impl TrunkAssetPipelineOutput {
         match self {
             TrunkAssetPipelineOutput::Css(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::Sass(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::Less(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::TailwindCss(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::Js(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::Icon(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::Image(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::Font(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::Manifest(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::ServiceWorker(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::Inline(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::Markdown(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::Preload(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::ImportMap(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::CopyFile(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::CopyDir(out) => out.finalize(dom).await,
+            TrunkAssetPipelineOutput::Hook(out) => out.finalize(dom).await,
             TrunkAssetPipelineOutput::RustApp(out) => out.finalize(dom).await,
         }
     }
+
+    /// Finalize the files of this output in the dist dir.
+    ///
+    /// Unlike `finalize`, which runs for every document using the asset, this runs once per build,
+    /// before the output is finalized into any document.
+    pub async fn finalize_dist(&self) -> Result<()> {
+        match self {
+            TrunkAssetPipelineOutput::CopyDir(CopyDirOutput { cfg, hashed, .. })
+            | TrunkAssetPipelineOutput::CopyFile(CopyFileOutput { cfg, hashed, .. }) => {
+                copy_dir::rewrite_hashed_css(cfg, hashed).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The ID of the source HTML element, the output file & the `as` value of preload hints for
+    /// it, for assets with a single output file which can be referenced by preload hints.
+    pub fn preload_target(&self) -> Option<(usize, &str, &'static str)> {
+        match self {
+            TrunkAssetPipelineOutput::Css(out) => Some((out.id, out.file.as_str(), "style")),
+            TrunkAssetPipelineOutput::Sass(out) => Some((out.id, out.css_ref.file()?, "style")),
+            TrunkAssetPipelineOutput::Less(out) => Some((out.id, out.css_ref.file()?, "style")),
+            TrunkAssetPipelineOutput::TailwindCss(out) => {
+                Some((out.id, out.css_ref.file()?, "style"))
+            }
+            TrunkAssetPipelineOutput::Js(out) => Some((out.id, out.file.as_str(), "script")),
+            TrunkAssetPipelineOutput::Font(out) => Some((out.id, out.file.as_str(), "font")),
+            _ => None,
+        }
+    }
+
+    /// The files written to the dist dir with a hashed name by this output, as pairs of their
+    /// path relative to the dist dir & their logical name, which is the path without the hash.
+    ///
+    /// This must only be called if file hashing is enabled.
+    pub fn hashed_files(&self) -> Vec<(String, String)> {
+        let files = match self {
+            TrunkAssetPipelineOutput::Css(out) => vec![out.file.clone()],
+            TrunkAssetPipelineOutput::Sass(SassOutput { css_ref, .. })
+            | TrunkAssetPipelineOutput::Less(LessOutput { css_ref, .. })
+            | TrunkAssetPipelineOutput::TailwindCss(TailwindCssOutput { css_ref, .. }) => css_ref
+                .file()
+                .map(|file| vec![file.to_string(), format!("{file}.map")])
+                .unwrap_or_default(),
+            TrunkAssetPipelineOutput::Js(out) => vec![out.file.clone()],
+            TrunkAssetPipelineOutput::Font(out) => vec![out.file.clone()],
+            TrunkAssetPipelineOutput::Icon(out) => {
+                out.icons.iter().map(|icon| icon.file.clone()).collect()
+            }
+            TrunkAssetPipelineOutput::Image(out) => std::iter::once(&out.file)
+                .chain(out.variants.iter().map(|variant| &variant.file))
+                .cloned()
+                .collect(),
+            TrunkAssetPipelineOutput::Manifest(out) => vec![out.file.clone()],
+            TrunkAssetPipelineOutput::Hook(out) => {
+                out.files.iter().map(|file| file.file.clone()).collect()
+            }
+            TrunkAssetPipelineOutput::RustApp(out) if out.type_ == RustAppType::Main => {
+                let mut files = vec![out.js_output.clone(), out.wasm_output.clone()];
+                files.extend(out.auxiliary_files().cloned());
+                files
+            }
+            TrunkAssetPipelineOutput::Preload(PreloadOutput {
+                target: PreloadOutputTarget::File { file, .. },
+                ..
+            }) => vec![file.clone()],
+            // Copied files are only hashed if requested, these know their unhashed paths.
+            TrunkAssetPipelineOutput::CopyDir(CopyDirOutput { hashed, .. })
+            | TrunkAssetPipelineOutput::CopyFile(CopyFileOutput { hashed, .. }) => {
+                return hashed
+                    .iter()
+                    .map(|(path, hashed_name)| {
+                        let file = match path.rsplit_once('/') {
+                            Some((dir, _)) => format!("{dir}/{hashed_name}"),
+                            None => hashed_name.clone(),
+                        };
+                        (file, path.clone())
+                    })
+                    .collect();
+            }
+            _ => vec![],
+        };
+        // Files hashed in a query keep their names, so are already listed under these.
+        files
+            .into_iter()
+            .filter(|file| !file.contains('?'))
+            .filter_map(|file| Some((file.clone(), asset_manifest::unhashed_name(&file)?)))
+            .collect()
+    }
 }
 
 /// An asset file to be processed by some build pipeline.
@@ -222,36 +534,44 @@ impl AssetFile {
         })
     }
 
-    /// Copy this asset to the target dir. If hashing is enabled, create a hash from the file
-    /// contents and include it as hex string in the destination file name.
+    /// Copy this asset to the target dir. If a hash strategy is given, create a hash from the
+    /// file contents and include it as hex string in the destination file name.
     ///
     /// The base file name (stripped path, without any parent folders) is returned if the operation
     /// was successful.
-    pub async fn copy(&self, to_dir: &Path, with_hash: bool) -> Result<String> {
+    pub async fn copy(&self, to_dir: &Path, hash: Option<&FileHash>) -> Result<String> {
         let bytes = fs::read(&self.path)
             .await
             .with_context(|| format!("error reading file for copying {:?}", &self.path))?;
 
-        let file_name = if with_hash {
-            format!(
-                "{}-{:x}.{}",
-                &self.file_stem.to_string_lossy(),
-                seahash::hash(bytes.as_ref()),
-                &self.ext.as_deref().unwrap_or_default()
-            )
-        } else {
-            self.file_name.to_string_lossy().into_owned()
+        let file_name = match hash {
+            Some(hash) => hashed_file_name(hash, Path::new(&self.file_name), &bytes),
+            None => self.file_name.to_string_lossy().into_owned(),
         };
 
-        let file_path = to_dir.join(&file_name);
+        let file_path = to_dir.join(hashing::file_path(&file_name));
 
-        fs::write(&file_path, bytes)
+        common::write_file(&file_path, bytes)
             .await
             .with_context(|| format!("error copying file {:?} to {:?}", &self.path, &file_path))?;
 
         Ok(file_name)
     }
 
+    /// Copy this asset to the dir of the given asset class in the staging dist dir, see
+    /// [`AssetFile::copy`].
+    ///
+    /// The path of the copy relative to the dist dir is returned.
+    pub async fn copy_output(
+        &self,
+        cfg: &RtcBuild,
+        class: AssetClass,
+        hash: Option<&FileHash>,
+    ) -> Result<String> {
+        let file_name = self.copy(&cfg.output_dir(class), hash).await?;
+        Ok(cfg.output_file(class, &file_name))
+    }
+
     /// Read the content of this asset to a String.
     pub async fn read_to_string(&self) -> Result<String> {
         fs::read_to_string(&self.path)
@@ -276,11 +596,130 @@ pub enum PipelineStage {
 }
 
 /// Create the CSS selector for selecting a trunk link by ID.
-pub(self) fn trunk_id_selector(id: usize) -> String {
+fn trunk_id_selector(id: usize) -> String {
     format!(r#"link[{}="{}"]"#, TRUNK_ID, id)
 }
 
 /// Create the CSS selector for selecting a trunk script by ID.
-pub(self) fn trunk_script_id_selector(id: usize) -> String {
+fn trunk_script_id_selector(id: usize) -> String {
     format!(r#"script[{}="{}"]"#, TRUNK_ID, id)
 }
+
+/// Create the CSS selector for selecting a trunk image by ID.
+fn trunk_img_id_selector(id: usize) -> String {
+    format!(r#"img[{}="{}"]"#, TRUNK_ID, id)
+}
+
+/// Parse the `data-target-path` attr of a copy asset, which must be a relative path without `..`
+/// so that the output stays inside the dist dir.
+fn target_path(attrs: &Attrs) -> Result<Option<PathBuf>> {
+    let Some(val) = attrs.get(ATTR_TARGET_PATH) else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(val);
+    if path.is_absolute() || path.components().any(|c| matches!(c, Component::ParentDir)) {
+        bail!(
+            "Invalid data-target-path '{}'. Must be a relative path without '..'.",
+            path.display()
+        );
+    }
+    Ok(Some(path))
+}
+
+/// Build the file name of a copied file, including the hash of its content.
+fn hashed_file_name(hash: &FileHash, path: &Path, bytes: &[u8]) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let suffix = match path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => String::new(),
+    };
+    hash.name(&stem, &suffix, bytes)
+}
+
+/// Get the strategy used to hash the output file names of an asset, unless hashing is disabled
+/// globally or for the asset via its `data-no-hash` attr.
+fn file_hash(cfg: &RtcBuild, attrs: &Attrs) -> Option<FileHash> {
+    if attrs.contains_key(ATTR_NO_HASH) {
+        return None;
+    }
+    cfg.file_hash().cloned()
+}
+
+/// Check if subresource integrity attributes should be emitted for an asset, based on its
+/// `data-integrity` attr, falling back to the global config.
+fn use_integrity(cfg: &RtcBuild, attrs: &Attrs) -> Result<bool> {
+    match attrs.get(ATTR_INTEGRITY).map(String::as_str) {
+        None => Ok(cfg.integrity),
+        Some("" | "true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(other) => bail!(
+            r#"invalid `data-integrity="{}"` value; please ensure the value is empty, `true` or `false`"#,
+            other
+        ),
+    }
+}
+
+/// Check whether the given glob matches the file at the given path, relative to the dist dir.
+///
+/// Globs containing a `/` are matched against the relative path, all others against the file name
+/// only.
+pub(crate) fn glob_matches(glob: &glob::Pattern, file: &str) -> bool {
+    if glob.as_str().contains('/') {
+        glob.matches(file)
+    } else {
+        glob.matches(file.rsplit('/').next().unwrap_or_default())
+    }
+}
+
+/// Compute the subresource integrity digest (SHA-384) of the given content.
+fn integrity_digest(content: &[u8]) -> String {
+    format!("sha384-{}", BASE64.encode(Sha384::digest(content)))
+}
+
+/// Compute the subresource integrity digest of the given file.
+async fn file_integrity(path: &Path) -> Result<String> {
+    let content = fs::read(path)
+        .await
+        .with_context(|| format!("error reading file for integrity digest {:?}", path))?;
+    Ok(integrity_digest(&content))
+}
+
+/// Render the `integrity` & `crossorigin` attributes for the given digest, if any.
+fn integrity_attrs(integrity: Option<&str>) -> String {
+    integrity
+        .map(|digest| format!(r#" integrity="{digest}" crossorigin="anonymous""#))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integrity_digest_is_base64_sha384() {
+        assert_eq!(
+            integrity_digest(b""),
+            "sha384-OLBgp1GsljhM2TJ+sbHjaiH9txEUvgdDTAzHv2P24donTt6/529l+9Ua0vFImLlb"
+        );
+    }
+
+    #[test]
+    fn integrity_attrs_empty_without_digest() {
+        assert_eq!(integrity_attrs(None), "");
+        assert_eq!(
+            integrity_attrs(Some("sha384-abc")),
+            r#" integrity="sha384-abc" crossorigin="anonymous""#
+        );
+    }
+
+    #[test]
+    fn asset_sources_cover_files_and_dirs() {
+        let sources = AssetSources::Paths(vec!["/app/index.scss".into(), "/app/styles".into()]);
+        assert!(sources.contains(Path::new("/app/index.scss")));
+        assert!(sources.contains(Path::new("/app/styles/_vars.scss")));
+        assert!(!sources.affected_by(Path::new("/app/styles.scss")));
+        assert!(!sources.affected_by(Path::new("/app/main.js")));
+        assert!(AssetSources::Any.affected_by(Path::new("/app/main.js")));
+        assert!(!AssetSources::Any.contains(Path::new("/app/main.js")));
+    }
+}
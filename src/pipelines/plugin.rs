@@ -0,0 +1,215 @@
+//! Plugin asset pipeline, building the assets of a `rel` value declared by a plugin.
+//!
+//! A plugin is an external command, which is passed the asset as JSON on stdin, writes the output
+//! files of the asset to a dir & reports them as JSON on stdout. See [`PluginRequest`] &
+//! [`PluginResponse`] for the protocol.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use super::hook::{copy_outputs, HookOutput};
+use super::{Attrs, PipelineHandle, TrunkAssetPipelineOutput};
+use crate::common::{self, remove_dir_all};
+use crate::config::{ConfigOptsBuildPlugin, RtcBuild};
+use crate::hashing::FileHash;
+use crate::hooks::trunk_env;
+
+/// The version of the plugin protocol.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// The asset passed to a plugin on stdin.
+#[derive(Debug, Serialize)]
+pub struct PluginRequest<'a> {
+    /// The version of the protocol.
+    pub version: u32,
+    /// The `rel` value of the asset.
+    pub rel: &'a str,
+    /// The attrs of the `<link data-trunk>` element, without the `data-trunk*` ones.
+    pub attrs: &'a Attrs,
+    /// The dir of the source HTML file, which the command runs in.
+    pub html_dir: &'a Path,
+    /// The empty dir to write the output files to.
+    pub output_dir: &'a Path,
+    /// Whether this is a release build.
+    pub release: bool,
+    /// The public URL of the build.
+    pub public_url: &'a str,
+}
+
+/// The outputs of an asset reported by a plugin on stdout.
+#[derive(Debug, Default, Deserialize)]
+pub struct PluginResponse {
+    /// The output files, relative to the output dir.
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// The HTML replacing the element, with `{{file}}` placeholders for the URLs of the output
+    /// files. By default, the stylesheets & scripts among the files are referenced.
+    pub html: Option<String>,
+}
+
+/// A plugin asset pipeline.
+pub struct Plugin {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The dir of the source HTML file.
+    html_dir: Arc<PathBuf>,
+    /// The plugin building the asset.
+    plugin: ConfigOptsBuildPlugin,
+    /// The attrs passed to the plugin.
+    attrs: Attrs,
+    /// The strategy used to hash the output file names, unless hashing is disabled.
+    hash: Option<FileHash>,
+}
+
+impl Plugin {
+    pub fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        plugin: ConfigOptsBuildPlugin,
+        attrs: Attrs,
+        id: usize,
+    ) -> Self {
+        let hash = super::file_hash(&cfg, &attrs);
+        let attrs = attrs
+            .into_iter()
+            .filter(|(name, _)| !name.starts_with("data-trunk"))
+            .collect();
+        Self {
+            id,
+            cfg,
+            html_dir,
+            plugin,
+            attrs,
+            hash,
+        }
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let rel = &self.plugin.rel;
+        tracing::info!(rel, "building plugin asset");
+
+        // Each asset gets its own output dir, so that plugins can not clash with other assets.
+        let output_dir = self.cfg.staging_dist.join(format!(".plugin-{}", self.id));
+        remove_dir_all(output_dir.clone()).await?;
+        fs::create_dir_all(&output_dir)
+            .await
+            .with_context(|| format!("error creating plugin output dir {:?}", output_dir))?;
+
+        let request = PluginRequest {
+            version: PROTOCOL_VERSION,
+            rel,
+            attrs: &self.attrs,
+            html_dir: &self.html_dir,
+            output_dir: &output_dir,
+            release: self.cfg.release,
+            public_url: &self.cfg.public_url,
+        };
+        let request = serde_json::to_vec(&request).context("error serializing plugin request")?;
+        let response = {
+            let _job = self.cfg.acquire_job().await?;
+            self.call(request).await?
+        };
+
+        let files = copy_outputs(&self.cfg, &output_dir, &response.files, self.hash.as_ref())
+            .await
+            .with_context(|| format!("error copying outputs of plugin for `rel=\"{}\"`", rel))?;
+        remove_dir_all(output_dir).await?;
+        tracing::info!(rel, "finished building plugin asset");
+        Ok(TrunkAssetPipelineOutput::Hook(HookOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            files,
+            html: response.html,
+        }))
+    }
+
+    /// Call the plugin command with the given request, returning its response.
+    async fn call(&self, request: Vec<u8>) -> Result<PluginResponse> {
+        let command = &self.plugin.command;
+        let mut child = common::command(command)
+            .args(&self.plugin.command_arguments)
+            .envs(&self.cfg.env)
+            .envs(trunk_env(&self.cfg))
+            .current_dir(self.html_dir.as_ref())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("error spawning plugin call to {}", command))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .with_context(|| format!("error opening stdin of plugin call to {}", command))?;
+        // The request is written concurrently to reading the output, as the command may start
+        // writing before it read all of its input.
+        let write = tokio::spawn(async move {
+            let res = stdin.write_all(&request).await;
+            // Close stdin, signaling the end of the input.
+            drop(stdin);
+            res
+        });
+        let output = child
+            .wait_with_output()
+            .await
+            .with_context(|| format!("error during plugin call to {}", command))?;
+        write
+            .await
+            .context("error awaiting plugin request")?
+            .with_context(|| format!("error writing to stdin of plugin call to {}", command))?;
+        if !output.status.success() {
+            bail!("plugin call to {} returned a bad status", command);
+        }
+        parse_response(&output.stdout)
+            .with_context(|| format!("error reading response of plugin call to {}", command))
+    }
+}
+
+/// Parse the response of a plugin, where an empty response has no output files.
+fn parse_response(stdout: &[u8]) -> Result<PluginResponse> {
+    if stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(PluginResponse::default());
+    }
+    let response: PluginResponse =
+        serde_json::from_slice(stdout).context("invalid JSON response")?;
+    for file in &response.files {
+        ensure!(
+            !file.starts_with('/') && !file.split('/').any(|segment| segment == ".."),
+            "output file {:?} is not within the output dir",
+            file
+        );
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_response_reads_files_and_html() -> Result<()> {
+        let response =
+            parse_response(br#"{"files": ["book.css"], "html": "<a>{{book.css}}</a>"}"#)?;
+        assert_eq!(response.files, ["book.css"]);
+        assert_eq!(response.html.as_deref(), Some("<a>{{book.css}}</a>"));
+        assert!(parse_response(b"\n")?.files.is_empty());
+        assert!(parse_response(br#"{"files": ["../index.html"]}"#).is_err());
+        assert!(parse_response(br#"{"files": ["/etc/hosts"]}"#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+//! Preload & prefetch hint pipeline.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use nipper::Document;
+
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF, ATTR_REL};
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::FileHash;
+
+/// The attribute used to reference the output of another asset, by the `id` of its element.
+const ATTR_REF: &str = "data-ref";
+/// The attribute used to override the `as` value of the hint.
+const ATTR_AS: &str = "data-as";
+
+/// A preload or prefetch hint pipeline.
+pub struct Preload {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The kind of hint, either `preload` or `prefetch`.
+    rel: &'static str,
+    /// The hinted file.
+    target: PreloadTarget,
+    /// The `as` value of the hint, if set explicitly.
+    as_value: Option<String>,
+    /// The strategy used to hash the name of the copied file, unless hashing is disabled.
+    hash: Option<FileHash>,
+}
+
+/// The file hinted by a preload or prefetch pipeline.
+enum PreloadTarget {
+    /// A file which is copied to the dist dir.
+    File(AssetFile),
+    /// The output of another asset, referenced by the `id` of its element.
+    Ref(String),
+}
+
+impl Preload {
+    pub const TYPE_PRELOAD: &'static str = "preload";
+    pub const TYPE_PREFETCH: &'static str = "prefetch";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        let rel = match attrs.get(ATTR_REL).map(String::as_str) {
+            Some(Self::TYPE_PREFETCH) => Self::TYPE_PREFETCH,
+            _ => Self::TYPE_PRELOAD,
+        };
+        let target = match (attrs.get(ATTR_HREF), attrs.get(ATTR_REF)) {
+            (Some(href_attr), None) => {
+                let mut path = PathBuf::new();
+                path.extend(href_attr.split('/'));
+                PreloadTarget::File(AssetFile::new(&html_dir, path).await?)
+            }
+            (None, Some(reference)) => PreloadTarget::Ref(reference.clone()),
+            _ => bail!(
+                r#"exactly one of the attrs `href` or `{}` is required for <link data-trunk rel="{}" .../> elements"#,
+                ATTR_REF,
+                rel
+            ),
+        };
+        let as_value = attrs.get(ATTR_AS).cloned();
+        let hash = super::file_hash(&cfg, &attrs);
+        Ok(Self {
+            id,
+            cfg,
+            rel,
+            target,
+            as_value,
+            hash,
+        })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let target = match self.target {
+            PreloadTarget::File(asset) => {
+                let rel_path = crate::common::strip_prefix(&asset.path);
+                tracing::info!(path = ?rel_path, "copying & hashing {} file", self.rel);
+                let file = asset
+                    .copy_output(&self.cfg, AssetClass::Other, self.hash.as_ref())
+                    .await?;
+                tracing::info!(path = ?rel_path, "finished copying & hashing {} file", self.rel);
+                let as_value = self
+                    .as_value
+                    .unwrap_or_else(|| as_for_extension(asset.ext.as_deref()).into());
+                PreloadOutputTarget::File { file, as_value }
+            }
+            PreloadTarget::Ref(reference) => PreloadOutputTarget::Ref {
+                reference,
+                as_value: self.as_value,
+            },
+        };
+        Ok(TrunkAssetPipelineOutput::Preload(PreloadOutput {
+            cfg: self.cfg.clone(),
+            id: self.id,
+            rel: self.rel,
+            target,
+        }))
+    }
+}
+
+/// Determine the `as` value of a hint for a file with the given extension.
+fn as_for_extension(ext: Option<&str>) -> &'static str {
+    match ext.map(str::to_lowercase).as_deref() {
+        Some("css") => "style",
+        Some("js" | "mjs") => "script",
+        Some("woff" | "woff2" | "ttf" | "otf" | "eot") => "font",
+        Some("png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg" | "ico") => "image",
+        Some("mp3" | "ogg" | "wav" | "flac") => "audio",
+        Some("mp4" | "webm") => "video",
+        _ => "fetch",
+    }
+}
+
+/// The hinted file of a preload or prefetch pipeline's output.
+#[derive(Clone)]
+pub enum PreloadOutputTarget {
+    /// A copied file, with the `as` value of the hint.
+    File { file: String, as_value: String },
+    /// The output of another asset, which still needs to be resolved.
+    Ref {
+        reference: String,
+        as_value: Option<String>,
+    },
+}
+
+/// The output of a preload or prefetch pipeline.
+#[derive(Clone)]
+pub struct PreloadOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The kind of hint, either `preload` or `prefetch`.
+    pub rel: &'static str,
+    /// The hinted file.
+    pub target: PreloadOutputTarget,
+}
+
+impl PreloadOutput {
+    /// Resolve a reference to the output of another asset.
+    ///
+    /// `files` maps the `id`s of the elements of all finalized assets to their output file & the
+    /// `as` value of hints for it.
+    pub fn resolve(&mut self, files: &HashMap<String, (String, &'static str)>) -> Result<()> {
+        if let PreloadOutputTarget::Ref {
+            reference,
+            as_value,
+        } = &self.target
+        {
+            let (file, default_as) = files.get(reference).with_context(|| {
+                format!(
+                    r#"no asset with a single output file found for `{}="{}"` of <link data-trunk rel="{}" .../> element; please ensure it references the `id` of a css, sass, scss, less, tailwind-css, font or script asset which is not inlined"#,
+                    ATTR_REF, reference, self.rel
+                )
+            })?;
+            self.target = PreloadOutputTarget::File {
+                file: file.clone(),
+                as_value: as_value.clone().unwrap_or_else(|| default_as.to_string()),
+            };
+        }
+        Ok(())
+    }
+
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let PreloadOutputTarget::File { file, as_value } = &self.target else {
+            bail!(
+                r#"unresolved `{}` of <link data-trunk rel="{}" .../> element"#,
+                ATTR_REF,
+                self.rel
+            );
+        };
+        // Fonts & fetches are always requested in CORS mode, the hint has to match.
+        let crossorigin = match as_value.as_str() {
+            "font" | "fetch" => " crossorigin",
+            _ => "",
+        };
+        dom.select(&super::trunk_id_selector(self.id))
+            .replace_with_html(format!(
+                r#"<link rel="{rel}" href="{base}{file}" as="{as_value}"{crossorigin}/>"#,
+                rel = self.rel,
+                base = &self.cfg.public_url,
+            ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn as_for_extension_detects_destination() {
+        assert_eq!(as_for_extension(Some("WOFF2")), "font");
+        assert_eq!(as_for_extension(Some("mjs")), "script");
+        assert_eq!(as_for_extension(Some("wasm")), "fetch");
+        assert_eq!(as_for_extension(None), "fetch");
+    }
+}
@@ -0,0 +1,221 @@
+//! Prerendering the routes of the app via a headless browser, writing their HTML to the dist dir.
+
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use axum::http::{StatusCode, Uri};
+use axum::routing::{get_service, Router};
+use axum::Server;
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tower_http::services::{ServeDir, ServeFile};
+
+use crate::common::{self, CommandFailed};
+use crate::config::{ConfigOptsBuildPrerender, RtcBuild};
+
+/// The browsers looked up in `PATH`, unless configured.
+const BROWSERS: [&str; 5] = [
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "chrome",
+];
+
+/// The time the app is given to render a route by default.
+const DEFAULT_WAIT: Duration = Duration::from_secs(5);
+
+/// The routes of the app to prerender, along with the headless browser rendering them.
+#[derive(Clone, Debug)]
+pub struct Prerender {
+    /// The routes to prerender, relative to the public URL.
+    routes: Vec<String>,
+    /// The Chrome or Chromium executable, if configured.
+    browser: Option<PathBuf>,
+    /// Additional arguments of the browser.
+    args: Vec<String>,
+    /// The virtual time the app is given to render each route.
+    wait: Duration,
+}
+
+impl Prerender {
+    /// Create a new instance, validating the routes of the given config.
+    pub fn new(opts: &ConfigOptsBuildPrerender) -> Result<Self> {
+        for route in &opts.routes {
+            check_route(route)?;
+        }
+        Ok(Self {
+            routes: opts.routes.clone(),
+            browser: opts.browser.clone(),
+            args: opts.args.clone().unwrap_or_default(),
+            wait: opts.wait.unwrap_or(DEFAULT_WAIT),
+        })
+    }
+
+    /// The routes to prerender, relative to the public URL.
+    pub fn routes(&self) -> &[String] {
+        &self.routes
+    }
+
+    /// The configured browser, or the first one found in `PATH`.
+    fn browser(&self) -> Result<PathBuf> {
+        if let Some(browser) = &self.browser {
+            return Ok(browser.clone());
+        }
+        BROWSERS
+            .into_iter()
+            .find_map(|name| which::which(name).ok())
+            .context(
+                "no headless browser found for prerendering, please install Chrome or Chromium, \
+                 or set `build.prerender.browser`",
+            )
+    }
+
+    /// Render the page of the given URL, returning its HTML.
+    async fn render(&self, browser: &PathBuf, url: &str) -> Result<String> {
+        let output = common::command(browser)
+            .args(["--headless", "--disable-gpu", "--dump-dom"])
+            .arg(format!("--virtual-time-budget={}", self.wait.as_millis()))
+            .args(&self.args)
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output();
+        // The virtual time of the browser may pass far slower than real time, but not forever.
+        let output = tokio::time::timeout(self.wait + Duration::from_secs(60), output)
+            .await
+            .with_context(|| format!("timed out prerendering {}", url))?
+            .with_context(|| format!("error spawning headless browser {:?}", browser))?;
+        if !output.status.success() {
+            bail!(CommandFailed {
+                name: browser.display().to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).into(),
+            });
+        }
+        let html = String::from_utf8_lossy(&output.stdout);
+        let html = html.trim();
+        // The DOM is dumped without its doctype.
+        Ok(match html.get(..9) {
+            Some(start) if start.eq_ignore_ascii_case("<!doctype") => format!("{}\n", html),
+            _ => format!("<!DOCTYPE html>\n{}\n", html),
+        })
+    }
+}
+
+/// Prerender the configured routes of the build in the staging dist dir, which is served to the
+/// headless browser meanwhile.
+///
+/// All routes are rendered before any is written, so that each one is rendered from the index
+/// HTML file of the build, which is served for all paths without a file.
+pub async fn prerender(cfg: &RtcBuild) -> Result<()> {
+    let Some(prerender) = &cfg.prerender else {
+        return Ok(());
+    };
+    let browser = prerender.browser()?;
+    // Only the path of an absolute public URL is served.
+    let base = match cfg.public_url.parse::<Uri>() {
+        Ok(uri) => uri.path().trim_end_matches('/').to_owned(),
+        Err(_) => String::new(),
+    };
+
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .context("error binding the server of the prerendered build")?;
+    let addr = listener
+        .local_addr()
+        .context("error reading the address of the server of the prerendered build")?;
+    let index = ServeFile::new(cfg.staging_dist.join("index.html"));
+    let serve_dir = get_service(ServeDir::new(&cfg.staging_dist).fallback(index)).handle_error(
+        |error| async move {
+            tracing::error!(?error, "failed serving file for prerendering");
+            StatusCode::INTERNAL_SERVER_ERROR
+        },
+    );
+    let router = match base.as_str() {
+        "" => Router::new().fallback_service(serve_dir),
+        base => Router::new().nest_service(base, serve_dir),
+    };
+    let shutdown = CancellationToken::new();
+    let server = Server::from_tcp(listener)
+        .context("error starting the server of the prerendered build")?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+    let server = tokio::spawn(server);
+
+    let mut pages = Vec::new();
+    for route in &prerender.routes {
+        tracing::info!(route, "prerendering route");
+        let url = format!("http://{}{}{}", addr, base, route);
+        match prerender.render(&browser, &url).await {
+            Ok(html) => pages.push((route_file(route), html)),
+            Err(err) => {
+                shutdown.cancel();
+                return Err(err).with_context(|| format!("error prerendering route {:?}", route));
+            }
+        }
+    }
+    shutdown.cancel();
+    let _ = server.await;
+
+    for (file, html) in pages {
+        let path = cfg.staging_dist.join(&file);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("error creating dir {:?} for prerendered HTML", dir))?;
+        }
+        common::write_file(&path, html)
+            .await
+            .with_context(|| format!("error writing prerendered HTML {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Check that the given route is a path starting with `/`, which stays within the dist dir.
+pub(super) fn check_route(route: &str) -> Result<()> {
+    ensure!(
+        route.starts_with('/')
+            && !route.contains(['?', '#'])
+            && !route.split('/').any(|segment| segment == ".."),
+        "the route {:?} must be a path starting with `/`, without `..`, a query or a fragment",
+        route
+    );
+    Ok(())
+}
+
+/// The path of the HTML file of the given route, relative to the dist dir.
+///
+/// Routes of HTML files are written to those files, all others to the `index.html` file of their
+/// dir.
+pub(super) fn route_file(route: &str) -> String {
+    let path = route.trim_start_matches('/');
+    if path.is_empty() || path.ends_with('/') {
+        format!("{}index.html", path)
+    } else if path.ends_with(".html") {
+        path.to_owned()
+    } else {
+        format!("{}/index.html", path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_are_written_to_index_files() -> Result<()> {
+        assert_eq!(route_file("/"), "index.html");
+        assert_eq!(route_file("/about"), "about/index.html");
+        assert_eq!(route_file("/blog/"), "blog/index.html");
+        assert_eq!(route_file("/404.html"), "404.html");
+        let opts = ConfigOptsBuildPrerender {
+            routes: vec!["/../secret".into()],
+            ..Default::default()
+        };
+        assert!(Prerender::new(&opts).is_err());
+        Ok(())
+    }
+}
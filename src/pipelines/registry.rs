@@ -0,0 +1,147 @@
+//! The registry of the pipelines building `<link data-trunk>` assets, by their `rel` values.
+
+use std::collections::HashMap;
+
+use super::copy_dir::CopyDir;
+use super::copy_file::CopyFile;
+use super::css::Css;
+use super::font::Font;
+use super::hook::Hook;
+use super::icon::Icon;
+use super::image::Image;
+use super::import_map::ImportMap;
+use super::inline::Inline;
+use super::less::Less;
+use super::manifest::Manifest;
+use super::markdown::Markdown;
+use super::preload::Preload;
+use super::rust::RustApp;
+use super::sass::Sass;
+use super::service_worker::ServiceWorker;
+use super::tailwind_css::TailwindCss;
+use crate::config::ConfigOptsBuildPlugin;
+
+/// A pipeline building the `<link data-trunk>` assets of a `rel` value.
+#[derive(Clone, Debug)]
+pub enum LinkPipeline {
+    Sass,
+    Less,
+    Icon,
+    Image,
+    Font,
+    Manifest,
+    ServiceWorker,
+    Inline,
+    Markdown,
+    Preload,
+    ImportMap,
+    Css,
+    CopyFile,
+    CopyDir,
+    Hook,
+    RustApp,
+    TailwindCss,
+    /// A plugin of the config.
+    Plugin(ConfigOptsBuildPlugin),
+}
+
+/// The pipelines of all `rel` values of a build, assembled from the built-in pipelines & the
+/// plugins of the config.
+#[derive(Clone, Debug)]
+pub struct PipelineRegistry {
+    pipelines: HashMap<String, LinkPipeline>,
+}
+
+impl PipelineRegistry {
+    /// Assemble the registry of the given plugins, which replace the built-in pipelines of their
+    /// `rel` values, except for `rust`.
+    ///
+    /// If several plugins declare the same `rel` value, the first one is used.
+    pub fn new(plugins: &[ConfigOptsBuildPlugin]) -> Self {
+        let builtin = [
+            (Sass::TYPE_SASS, LinkPipeline::Sass),
+            (Sass::TYPE_SCSS, LinkPipeline::Sass),
+            (Less::TYPE_LESS, LinkPipeline::Less),
+            (Icon::TYPE_ICON, LinkPipeline::Icon),
+            (Image::TYPE_IMAGE, LinkPipeline::Image),
+            (Font::TYPE_FONT, LinkPipeline::Font),
+            (Manifest::TYPE_MANIFEST, LinkPipeline::Manifest),
+            (
+                ServiceWorker::TYPE_SERVICE_WORKER,
+                LinkPipeline::ServiceWorker,
+            ),
+            (Inline::TYPE_INLINE, LinkPipeline::Inline),
+            (Markdown::TYPE_MARKDOWN, LinkPipeline::Markdown),
+            (Preload::TYPE_PRELOAD, LinkPipeline::Preload),
+            (Preload::TYPE_PREFETCH, LinkPipeline::Preload),
+            (ImportMap::TYPE_IMPORT_MAP, LinkPipeline::ImportMap),
+            (Css::TYPE_CSS, LinkPipeline::Css),
+            (CopyFile::TYPE_COPY_FILE, LinkPipeline::CopyFile),
+            (CopyDir::TYPE_COPY_DIR, LinkPipeline::CopyDir),
+            (Hook::TYPE_HOOK, LinkPipeline::Hook),
+            (RustApp::TYPE_RUST_APP, LinkPipeline::RustApp),
+            (TailwindCss::TYPE_TAILWIND_CSS, LinkPipeline::TailwindCss),
+        ];
+        let mut pipelines: HashMap<_, _> = builtin
+            .into_iter()
+            .map(|(rel, pipeline)| (rel.to_owned(), pipeline))
+            .collect();
+        // Inserted in reverse, so that the first plugin of a `rel` value is kept.
+        for plugin in plugins.iter().rev() {
+            // The HTML pipeline relies on the `rust` links to find the main app.
+            if plugin.rel == RustApp::TYPE_RUST_APP {
+                tracing::warn!(
+                    r#"ignoring plugin for `rel="{}"`, which can not be replaced"#,
+                    plugin.rel
+                );
+                continue;
+            }
+            pipelines.insert(plugin.rel.clone(), LinkPipeline::Plugin(plugin.clone()));
+        }
+        Self { pipelines }
+    }
+
+    /// The pipeline of the given `rel` value, if any.
+    pub fn get(&self, rel: &str) -> Option<&LinkPipeline> {
+        self.pipelines.get(rel)
+    }
+
+    /// All registered `rel` values, sorted.
+    pub fn rels(&self) -> Vec<&str> {
+        let mut rels: Vec<_> = self.pipelines.keys().map(String::as_str).collect();
+        rels.sort_unstable();
+        rels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(rel: &str, command: &str) -> ConfigOptsBuildPlugin {
+        ConfigOptsBuildPlugin {
+            rel: rel.into(),
+            command: command.into(),
+            command_arguments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn plugins_extend_and_replace_builtin_pipelines() {
+        let registry = PipelineRegistry::new(&[
+            plugin("book", "first"),
+            plugin("book", "second"),
+            plugin("css", "lightningcss"),
+            plugin("rust", "cargo"),
+        ]);
+        assert!(matches!(
+            registry.get("book"),
+            Some(LinkPipeline::Plugin(plugin)) if plugin.command == "first"
+        ));
+        assert!(matches!(registry.get("css"), Some(LinkPipeline::Plugin(_))));
+        assert!(matches!(registry.get("scss"), Some(LinkPipeline::Sass)));
+        assert!(matches!(registry.get("rust"), Some(LinkPipeline::RustApp)));
+        assert!(registry.get("unknown").is_none());
+        assert!(registry.rels().contains(&"book"));
+    }
+}
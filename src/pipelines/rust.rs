@@ -1,25 +1,44 @@
 //! Rust application pipeline.
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::iter::Iterator;
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use anyhow::{anyhow, bail, ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use cargo_lock::Lockfile;
 use nipper::Document;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
-use tokio::process::Command;
 use tokio::sync::mpsc;
-use tokio::task::JoinHandle;
 
-use super::{Attrs, TrunkAssetPipelineOutput, ATTR_HREF, SNIPPETS_DIR};
+use super::cargo;
+use super::copy_dir::url_path;
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF, SNIPPETS_DIR};
+use crate::cache::BuildCache;
 use crate::common::{self, copy_dir_recursive, path_exists};
-use crate::config::{CargoMetadata, ConfigOptsTools, Features, RtcBuild};
-use crate::tools::{self, Application};
+use crate::config::{AssetClass, CargoMetadata, ConfigOptsTools, Features, RtcBuild};
+use crate::hashing::{self, FileHash};
+use crate::tools::Application;
+
+/// The loader of main apps of the `web` target, reporting the progress of loading the wasm.
+const LOADER_SCRIPT: &str = include_str!("../loader.js");
+/// The bootstrap script of the threads of apps built with wasm threads, with `{js}` being the file
+/// name of the JS glue.
+const THREAD_BOOTSTRAP_SCRIPT: &str = include_str!("../thread_bootstrap.js");
+/// The loader of the deferred module of apps split by wasm-split, appended to their JS glue, with
+/// `{deferred}` being the URL of the deferred module & `{table}` the name of the table of the primary
+/// module, as JS strings.
+const WASM_SPLIT_SCRIPT: &str = include_str!("../wasm_split.js");
+/// The script recording the profile of apps instrumented by wasm-split, appended to their JS glue,
+/// with `{profile}` being the file name of the profile, as JS string.
+const WASM_SPLIT_PROFILE_SCRIPT: &str = include_str!("../wasm_split_profile.js");
+/// The target features required by wasm threads.
+const WASM_THREADS_RUSTFLAGS: &str = "-C target-feature=+atomics,+bulk-memory,+mutable-globals";
 
 /// A Rust application pipeline.
 pub struct RustApp {
@@ -38,9 +57,15 @@ pub struct RustApp {
     /// An optional binary name which will cause cargo & wasm-bindgen to process only the target
     /// binary.
     bin: Option<String>,
+    /// An optional example name which will cause cargo & wasm-bindgen to process the target
+    /// example instead of a binary.
+    example: Option<String>,
     /// An option to instruct wasm-bindgen to preserve debug info in the final WASM output, even
     /// for `--release` mode.
     keep_debug: bool,
+    /// Whether to keep the DWARF debug info of the app through the whole build, including
+    /// wasm-opt, for debuggers showing its Rust sources.
+    debug_info: bool,
     /// An option to instruct wasm-bindgen to output Typescript bindings. Defaults to false
     typescript: bool,
     /// An option to instruct wasm-bindgen to not demangle Rust symbol names.
@@ -49,6 +74,10 @@ pub struct RustApp {
     reference_types: bool,
     /// An option to instruct wasm-bindgen to enable weak references.
     weak_refs: bool,
+    /// An option to instruct wasm-bindgen to include debug checks in the JS glue.
+    bindgen_debug: bool,
+    /// The target of the JS glue generated by wasm-bindgen.
+    bindgen_target: BindgenTarget,
     /// An optional optimization setting that enables wasm-opt. Can be nothing, `0` (default), `1`,
     /// `2`, `3`, `4`, `s or `z`. Using `0` disables wasm-opt completely.
     wasm_opt: WasmOptLevel,
@@ -57,6 +86,19 @@ pub struct RustApp {
     name: String,
     /// Whether to create a loader shim script
     loader_shim: bool,
+    /// Whether to build the app with shared memory & atomics, for it to spawn threads as workers
+    /// via the generated bootstrap script.
+    wasm_threads: bool,
+    /// The wasm-split profile of the functions used at startup, if the app is split into a primary
+    /// module & a deferred module, which is loaded once the split off functions are called.
+    wasm_split: Option<PathBuf>,
+    /// The JS module of a main app, whose default export returns the callbacks reporting the
+    /// progress of loading the app.
+    initializer: Option<AssetFile>,
+    /// If subresource integrity attributes should be added to the preload links.
+    use_integrity: bool,
+    /// The strategy used to hash the output file names, unless hashing is disabled.
+    hash: Option<FileHash>,
 }
 
 /// Describes how the rust application is used.
@@ -83,6 +125,43 @@ impl FromStr for RustAppType {
     }
 }
 
+/// The targets of the JS glue generated by wasm-bindgen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindgenTarget {
+    /// An ES module exporting an `init` function, which loads the WASM.
+    Web,
+    /// A classic script defining a global `wasm_bindgen` function, which loads the WASM.
+    NoModules,
+    /// An ES module importing the WASM as a module, which requires a bundler.
+    Bundler,
+}
+
+impl BindgenTarget {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Web => "web",
+            Self::NoModules => "no-modules",
+            Self::Bundler => "bundler",
+        }
+    }
+}
+
+impl FromStr for BindgenTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "web" => Ok(Self::Web),
+            "no-modules" => Ok(Self::NoModules),
+            "bundler" => Ok(Self::Bundler),
+            _ => bail!(
+                r#"unknown wasm-bindgen target `{}`; please ensure it is one of `web`, `no-modules` or `bundler`"#,
+                s
+            ),
+        }
+    }
+}
+
 impl RustApp {
     pub const TYPE_RUST_APP: &'static str = "rust";
 
@@ -109,7 +188,15 @@ impl RustApp {
             })
             .unwrap_or_else(|| html_dir.join("Cargo.toml"));
         let bin = attrs.get("data-bin").map(|val| val.to_string());
-        let keep_debug = attrs.contains_key("data-keep-debug");
+        let example = attrs.get("data-example").map(|val| val.to_string());
+        ensure!(
+            bin.is_none() || example.is_none(),
+            "Cannot combine data-bin with data-example"
+        );
+        // The flags of the attributes default to those of the config.
+        let bindgen = &cfg.wasm_bindgen;
+        let debug_info = attrs.contains_key("data-debug-info");
+        let keep_debug = attrs.contains_key("data-keep-debug") || bindgen.keep_debug || debug_info;
         let typescript = attrs.contains_key("data-typescript");
         let no_demangle = attrs.contains_key("data-no-demangle");
         let app_type = attrs
@@ -117,10 +204,18 @@ impl RustApp {
             .map(|s| s.as_str())
             .unwrap_or("main")
             .parse()?;
-        let reference_types = attrs.contains_key("data-reference-types");
-        let weak_refs = attrs.contains_key("data-weak-refs");
+        let reference_types = attrs.contains_key("data-reference-types") || bindgen.reference_types;
+        let weak_refs = attrs.contains_key("data-weak-refs") || bindgen.weak_refs;
+        let bindgen_debug = attrs.contains_key("data-bindgen-debug") || bindgen.debug;
+        // Workers are loaded via `importScripts` by default, so the config applies to main apps.
+        let bindgen_target = match (attrs.get("data-bindgen-target"), app_type) {
+            (Some(target), _) => target.parse()?,
+            (None, RustAppType::Main) => main_bindgen_target(&cfg)?,
+            (None, RustAppType::Worker) => BindgenTarget::NoModules,
+        };
         let wasm_opt = attrs
             .get("data-wasm-opt")
+            .or(cfg.wasm_opt_level.as_ref())
             .map(|val| val.parse())
             .transpose()?
             .unwrap_or_else(|| {
@@ -130,20 +225,94 @@ impl RustApp {
                     WasmOptLevel::Off
                 }
             });
-        let manifest = CargoMetadata::new(&manifest_href).await?;
+        let manifest = CargoMetadata::new(&manifest_href, &cfg.cargo_env).await?;
         let id = Some(id);
-        let name = bin.clone().unwrap_or_else(|| manifest.package.name.clone());
+        let name = bin
+            .clone()
+            .or_else(|| example.clone())
+            .unwrap_or_else(|| manifest.package.name.clone());
 
         let data_features = attrs.get("data-cargo-features").map(|val| val.to_string());
-        let data_all_features = attrs.get("data-cargo-all-features").is_some();
-        let data_no_default_features = attrs.get("data-cargo-no-default-features").is_some();
+        let data_all_features = attrs.contains_key("data-cargo-all-features");
+        let data_no_default_features = attrs.contains_key("data-cargo-no-default-features");
 
-        let loader_shim = attrs.get("data-loader-shim").is_some();
+        let loader_shim = attrs.contains_key("data-loader-shim");
+        let wasm_threads = attrs.contains_key("data-wasm-threads");
+        if wasm_threads {
+            ensure!(
+                app_type == RustAppType::Main,
+                "data-wasm-threads has no effect when data-type is \"worker\"!"
+            );
+            ensure!(
+                bindgen_target == BindgenTarget::Web,
+                r#"data-wasm-threads requires data-bindgen-target="web", as the threads import the JS glue as a module"#
+            );
+        }
+        let initializer = match attrs.get("data-initializer") {
+            Some(path) => {
+                ensure!(
+                    app_type == RustAppType::Main,
+                    "data-initializer has no effect when data-type is \"worker\"!"
+                );
+                let mut rel_path = PathBuf::new();
+                rel_path.extend(path.split('/'));
+                Some(AssetFile::new(&html_dir, rel_path).await?)
+            }
+            None => None,
+        };
+        let wasm_split = match attrs.get("data-wasm-split") {
+            Some(path) => {
+                ensure!(
+                    app_type == RustAppType::Main,
+                    "data-wasm-split has no effect when data-type is \"worker\"!"
+                );
+                ensure!(
+                    bindgen_target == BindgenTarget::Web,
+                    r#"data-wasm-split requires data-bindgen-target="web", as the deferred module is loaded relative to the JS glue"#
+                );
+                ensure!(
+                    !wasm_threads,
+                    "data-wasm-split can not be combined with data-wasm-threads"
+                );
+                let path = match path.is_empty() {
+                    true => "wasm-split.prof",
+                    false => path,
+                };
+                let mut rel_path = PathBuf::new();
+                rel_path.extend(path.split('/'));
+                Some(html_dir.join(rel_path))
+            }
+            None => None,
+        };
+        let use_integrity = super::use_integrity(&cfg, &attrs)?;
+        // Workers are not hashed, as their file name must be known to the app at runtime.
+        let hash = match app_type {
+            RustAppType::Main => super::file_hash(&cfg, &attrs),
+            RustAppType::Worker => None,
+        };
         if loader_shim {
             ensure!(
                 app_type == RustAppType::Worker,
                 "Loader shim has no effect when data-type is \"main\"!"
             );
+            ensure!(
+                bindgen_target == BindgenTarget::NoModules,
+                r#"Loader shim requires data-bindgen-target="no-modules", as it loads the worker via importScripts"#
+            );
+        }
+        if app_type == RustAppType::Main {
+            check_bindgen_target(&cfg, bindgen_target)?;
+        }
+        ensure!(
+            initializer.is_none() || bindgen_target == BindgenTarget::Web,
+            r#"data-initializer requires data-bindgen-target="web", as it imports the JS glue as a module"#
+        );
+        if keep_debug && !debug_info && wasm_opt != WasmOptLevel::Off {
+            tracing::warn!(
+                "keeping the debug info of {} may conflict with wasm-opt; consider setting \
+                 data-wasm-opt=\"0\" or data-debug-info",
+                name
+            );
         }
 
         // Highlander-rule: There can be only one (prohibits contradicting arguments):
@@ -172,15 +341,24 @@ impl RustApp {
             manifest,
             ignore_chan,
             bin,
+            example,
             keep_debug,
+            debug_info,
             typescript,
             no_demangle,
             reference_types,
             weak_refs,
+            bindgen_debug,
+            bindgen_target,
             wasm_opt,
             app_type,
             name,
             loader_shim,
+            wasm_threads,
+            wasm_split,
+            initializer,
+            use_integrity,
+            hash,
         })
     }
 
@@ -190,8 +368,19 @@ impl RustApp {
         ignore_chan: Option<mpsc::Sender<PathBuf>>,
     ) -> Result<Self> {
         let path = html_dir.join("Cargo.toml");
-        let manifest = CargoMetadata::new(&path).await?;
+        let manifest = CargoMetadata::new(&path, &cfg.cargo_env).await?;
         let name = manifest.package.name.clone();
+        let use_integrity = cfg.integrity;
+        let hash = cfg.file_hash().cloned();
+        let wasm_opt = cfg
+            .wasm_opt_level
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(WasmOptLevel::Off);
+        let bindgen = cfg.wasm_bindgen.clone();
+        let bindgen_target = main_bindgen_target(&cfg)?;
+        check_bindgen_target(&cfg, bindgen_target)?;
 
         Ok(Self {
             id: None,
@@ -200,34 +389,103 @@ impl RustApp {
             manifest,
             ignore_chan,
             bin: None,
-            keep_debug: false,
+            example: None,
+            keep_debug: bindgen.keep_debug,
+            debug_info: false,
             typescript: false,
             no_demangle: false,
-            reference_types: false,
-            weak_refs: false,
-            wasm_opt: WasmOptLevel::Off,
+            reference_types: bindgen.reference_types,
+            weak_refs: bindgen.weak_refs,
+            bindgen_debug: bindgen.debug,
+            bindgen_target,
+            wasm_opt,
             app_type: RustAppType::Main,
             name,
             loader_shim: false,
+            wasm_threads: false,
+            wasm_split: None,
+            initializer: None,
+            use_integrity,
+            hash,
         })
     }
 
+    /// The sources of the local packages of the cargo workspace, i.e. its members & path
+    /// dependencies: their manifests & the dirs of their targets, along with the lock file.
+    ///
+    /// Build scripts are only included as files, as they are usually placed in the package root.
+    pub fn sources(&self) -> Vec<PathBuf> {
+        let metadata = &self.manifest.metadata;
+        let mut sources = vec![
+            metadata.workspace_root.join("Cargo.toml").into(),
+            metadata.workspace_root.join("Cargo.lock").into(),
+        ];
+        for package in metadata.packages.iter().filter(|pkg| pkg.source.is_none()) {
+            sources.push(package.manifest_path.clone().into());
+            for target in &package.targets {
+                let path = match target.kind.iter().any(|kind| kind == "custom-build") {
+                    true => Some(target.src_path.as_std_path()),
+                    false => target.src_path.parent().map(|dir| dir.as_std_path()),
+                };
+                if let Some(path) = path.filter(|path| !sources.iter().any(|p| p == path)) {
+                    sources.push(path.to_owned());
+                }
+            }
+        }
+        sources.extend(self.initializer.as_ref().map(|file| file.path.clone()));
+        sources.extend(self.wasm_split.clone());
+        sources
+    }
+
     /// Spawn a new pipeline.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.build())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.build())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     async fn build(mut self) -> Result<TrunkAssetPipelineOutput> {
-        let (wasm, hashed_name) = self.cargo_build().await?;
-        let output = self.wasm_bindgen_build(wasm.as_ref(), &hashed_name).await?;
-        self.wasm_opt_build(&output.wasm_output).await?;
+        if self.wasm_threads {
+            self.cfg
+                .cross_origin_isolated
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        let (wasm, wasm_hash) = self.cargo_build().await?;
+        let mut output = self
+            .wasm_bindgen_build(wasm.as_ref(), wasm_hash.as_deref())
+            .await?;
+        let js_path = hashing::file_path(&output.js_output);
+        let wasm_path = hashing::file_path(&output.wasm_output);
+        self.wasm_opt_build(wasm_path).await?;
+        if let Some(profile) = &self.wasm_split {
+            if !self.wasm_split_build(profile, &output).await? {
+                output.wasm_split_output = None;
+            }
+        }
+        if let Some(initializer) = &self.initializer {
+            output.initializer = Some(
+                initializer
+                    .copy_output(&self.cfg, AssetClass::Js, self.hash.as_ref())
+                    .await?,
+            );
+        }
+        // The total of the loading progress is known upfront, as compressed responses lack it.
+        output.wasm_size = fs::metadata(self.cfg.staging_dist.join(wasm_path))
+            .await
+            .context("error reading metadata of wasm file")?
+            .len();
+        // The digests are computed last, as wasm-opt modifies the WASM file in place.
+        if self.use_integrity {
+            let staging_dist = &self.cfg.staging_dist;
+            output.js_integrity = Some(super::file_integrity(&staging_dist.join(js_path)).await?);
+            output.wasm_integrity =
+                Some(super::file_integrity(&staging_dist.join(wasm_path)).await?);
+        }
         Ok(TrunkAssetPipelineOutput::RustApp(output))
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
-    async fn cargo_build(&mut self) -> Result<(PathBuf, String)> {
+    async fn cargo_build(&mut self) -> Result<(PathBuf, Option<String>)> {
         tracing::info!("building {}", &self.manifest.package.name);
 
         // Spawn the cargo build process.
@@ -240,10 +498,18 @@ impl RustApp {
         if self.cfg.release {
             args.push("--release");
         }
+        // Cargo's stderr is captured for the error overlay, so colors are kept explicitly.
+        if std::io::stderr().is_terminal() {
+            args.push("--color=always");
+        }
         if let Some(bin) = &self.bin {
             args.push("--bin");
             args.push(bin);
         }
+        if let Some(example) = &self.example {
+            args.push("--example");
+            args.push(example);
+        }
 
         match &self.cargo_features {
             Features::All => args.push("--all-features"),
@@ -262,7 +528,29 @@ impl RustApp {
             }
         }
 
-        let build_res = common::run_command("cargo", Path::new("cargo"), &args)
+        let mut env = Cow::Borrowed(&self.cfg.cargo_env);
+        if self.debug_info && self.cfg.release {
+            // Release builds have no debug info by default.
+            env.to_mut()
+                .insert("CARGO_PROFILE_RELEASE_DEBUG".into(), "true".into());
+        }
+        if self.wasm_threads {
+            // The standard library must be rebuilt with atomics, which requires a nightly toolchain.
+            args.extend(["-Z", "build-std=panic_abort,std"]);
+            let rustflags = env
+                .get("RUSTFLAGS")
+                .cloned()
+                .or_else(|| std::env::var("RUSTFLAGS").ok())
+                .unwrap_or_default();
+            env.to_mut().insert(
+                "RUSTFLAGS".into(),
+                format!("{rustflags} {WASM_THREADS_RUSTFLAGS}")
+                    .trim()
+                    .to_owned(),
+            );
+        }
+
+        let build_res = cargo::build(&args, &env)
             .await
             .context("error during cargo build execution");
 
@@ -279,44 +567,20 @@ impl RustApp {
             );
         }
 
-        // Now propagate any errors which came from the cargo build.
-        build_res?;
-
-        // Perform a final cargo invocation on success to get artifact names.
-        tracing::info!("fetching cargo artifacts");
-        args.push("--message-format=json");
-        let artifacts_out = Command::new("cargo")
-            .args(args.as_slice())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .context("error spawning cargo build artifacts task")?
-            .wait_with_output()
-            .await
-            .context("error getting cargo build artifacts info")?;
-        if !artifacts_out.status.success() {
-            eprintln!("{}", String::from_utf8_lossy(&artifacts_out.stderr));
-            bail!("bad status returned from cargo artifacts request");
-        }
-
-        // Stream over cargo messages to find the artifacts we are interested in.
-        let reader = std::io::BufReader::new(artifacts_out.stdout.as_slice());
-        let mut bin_artifacts: Vec<cargo_metadata::Artifact> =
-            cargo_metadata::Message::parse_stream(reader)
-                .filter_map(|msg| msg.ok())
-                .filter_map(|msg| match msg {
-                    cargo_metadata::Message::CompilerArtifact(art)
-                        if art.package_id == self.manifest.package.id
-                            && art.target.kind.iter().any(|k| k == "bin") =>
-                    {
-                        Some(Ok(art))
-                    }
-                    cargo_metadata::Message::BuildFinished(finished) if !finished.success => {
-                        Some(Err(anyhow!("error while fetching cargo artifact info")))
-                    }
-                    _ => None,
-                })
-                .collect::<Result<_>>()?;
+        // Now propagate any errors which came from the cargo build, then find the artifacts we
+        // are interested in.
+        let kind = if self.example.is_some() {
+            "example"
+        } else {
+            "bin"
+        };
+        let mut bin_artifacts: Vec<_> = build_res?
+            .into_iter()
+            .filter(|art| {
+                art.package_id == self.manifest.package.id
+                    && art.target.kind.iter().any(|k| k == kind)
+            })
+            .collect();
         // If there is already a `link data-trunk rel=rust` in index.html
         // then the --bin flag was passed to the cargo command
         // and it has built just a single binary
@@ -341,31 +605,46 @@ impl RustApp {
             .find(|path| path.extension().map(|ext| ext == "wasm").unwrap_or(false))
             .context("could not find WASM output after cargo build")?;
 
-        // Hash the built wasm app, then use that as the out-name param.
+        // Hash the built wasm app, the hash is then used for the out-name param.
         tracing::info!("processing WASM for {}", self.name);
-        let wasm_bytes = fs::read(&wasm)
-            .await
-            .context("error reading wasm file for hash generation")?;
-        let hashed_name = self
-            .cfg
-            .filehash
-            .then(|| format!("{}-{:x}", self.name, seahash::hash(&wasm_bytes)))
-            .unwrap_or_else(|| self.name.clone());
+        let wasm_hash = match &self.hash {
+            Some(hash) => {
+                let wasm_bytes = fs::read(&wasm)
+                    .await
+                    .context("error reading wasm file for hash generation")?;
+                Some(hash.digest(&wasm_bytes))
+            }
+            None => None,
+        };
 
-        Ok((wasm.into_std_path_buf(), hashed_name))
+        Ok((wasm.into_std_path_buf(), wasm_hash))
     }
 
-    #[tracing::instrument(level = "trace", skip(self, wasm, hashed_name))]
-    async fn wasm_bindgen_build(&self, wasm: &Path, hashed_name: &str) -> Result<RustAppOutput> {
-        // Skip the hashed file name for workers as their file name must be named at runtime.
-        // Therefore, workers use the Cargo binary name for file naming.
-        let hashed_name = match self.app_type {
-            RustAppType::Main => hashed_name,
-            RustAppType::Worker => &self.name,
+    #[tracing::instrument(level = "trace", skip(self, wasm, wasm_hash))]
+    async fn wasm_bindgen_build(
+        &self,
+        wasm: &Path,
+        wasm_hash: Option<&str>,
+    ) -> Result<RustAppOutput> {
+        // The names of the output files, along with the references to the JS loader & WASM file,
+        // which differ from the file names if the hash is placed in a query.
+        let hashed = |suffix: &str| match (&self.hash, wasm_hash) {
+            (Some(hash), Some(wasm_hash)) => hash.name_with_hash(&self.name, suffix, wasm_hash),
+            _ => format!("{}{}", self.name, suffix),
         };
+        let hashed_name = hashed("");
+        let hashed_name = hashing::file_path(&hashed_name);
 
         let version = find_wasm_bindgen_version(&self.cfg.tools, &self.manifest);
-        let wasm_bindgen = tools::get(Application::WasmBindgen, version.as_deref()).await?;
+        let wasm_bindgen = self
+            .cfg
+            .tool_context
+            .get(
+                Application::WasmBindgen,
+                self.cfg
+                    .tool_version(Application::WasmBindgen, version.as_deref()),
+            )
+            .await?;
 
         // Ensure our output dir is in place.
         let wasm_bindgen_name = Application::WasmBindgen.name();
@@ -384,15 +663,15 @@ impl RustApp {
         let arg_out_path = format!("--out-dir={}", bindgen_out);
         let arg_out_name = format!("--out-name={}", &hashed_name);
         let target_wasm = wasm.to_string_lossy().to_string();
-        let target_type = match self.app_type {
-            RustAppType::Main => "--target=web",
-            RustAppType::Worker => "--target=no-modules",
-        };
+        let target_type = format!("--target={}", self.bindgen_target.as_str());
 
-        let mut args = vec![target_type, &arg_out_path, &arg_out_name, &target_wasm];
+        let mut args = vec![&*target_type, &arg_out_path, &arg_out_name, &target_wasm];
         if self.keep_debug {
             args.push("--keep-debug");
         }
+        if self.bindgen_debug {
+            args.push("--debug");
+        }
         if self.no_demangle {
             args.push("--no-demangle");
         }
@@ -409,43 +688,48 @@ impl RustApp {
 
         // Invoke wasm-bindgen.
         tracing::info!("calling wasm-bindgen for {}", self.name);
+        let _job = self.cfg.acquire_job().await?;
         common::run_command(wasm_bindgen_name, &wasm_bindgen, &args)
             .await
             .map_err(|err| check_target_not_found_err(err, wasm_bindgen_name))?;
 
         // Copy the generated WASM & JS loader to the dist dir.
         tracing::info!("copying generated wasm-bindgen artifacts");
-        let hashed_js_name = format!("{}.js", &hashed_name);
-        let hashed_wasm_name = format!("{}_bg.wasm", &hashed_name);
+        let out_dir = self.cfg.output_dir(AssetClass::Wasm);
+        let hashed_js_name = hashed(".js");
+        let hashed_wasm_name = hashed("_bg.wasm");
         let hashed_ts_name = format!("{}.d.ts", &hashed_name);
-        let js_loader_path = bindgen_out.join(&hashed_js_name);
-        let js_loader_path_dist = self.cfg.staging_dist.join(&hashed_js_name);
-        let wasm_path = bindgen_out.join(&hashed_wasm_name);
-        let wasm_path_dist = self.cfg.staging_dist.join(&hashed_wasm_name);
+        let js_file_name = hashing::file_path(&hashed_js_name);
+        let wasm_file_name = hashing::file_path(&hashed_wasm_name);
+        let js_loader_path = bindgen_out.join(js_file_name);
+        let js_loader_path_dist = out_dir.join(js_file_name);
+        let wasm_path = bindgen_out.join(wasm_file_name);
+        let wasm_path_dist = out_dir.join(wasm_file_name);
         let hashed_loader_name = self
             .loader_shim
             .then(|| format!("{}_loader.js", &hashed_name));
-        let loader_shim_path = hashed_loader_name
-            .as_ref()
-            .map(|m| self.cfg.staging_dist.join(m));
+        let loader_shim_path = hashed_loader_name.as_ref().map(|m| out_dir.join(m));
 
-        fs::copy(js_loader_path, js_loader_path_dist)
+        common::copy_file(js_loader_path, &js_loader_path_dist)
             .await
             .context("error copying JS loader file to stage dir")?;
-        fs::copy(wasm_path, wasm_path_dist)
+        common::copy_file(wasm_path, wasm_path_dist)
             .await
             .context("error copying wasm file to stage dir")?;
 
         if self.typescript {
             let ts_path = bindgen_out.join(&hashed_ts_name);
-            let ts_path_dist = self.cfg.staging_dist.join(&hashed_ts_name);
+            let ts_path_dist = out_dir.join(&hashed_ts_name);
 
-            fs::copy(ts_path, ts_path_dist)
+            common::copy_file(ts_path, ts_path_dist)
                 .await
                 .context("error copying TS files to stage dir")?;
         }
 
         if let Some(ref m) = loader_shim_path {
+            common::remove_file_if_exists(m)
+                .await
+                .context("error removing loader shim script")?;
             let mut loader_f = fs::File::create(m)
                 .await
                 .context("error creating loader shim script")?;
@@ -466,48 +750,140 @@ impl RustApp {
                 .context("error writing loader shim script")?;
         }
 
+        // Threads are spawned by the app as module workers running the bootstrap script, which
+        // instantiates the app with the module & the shared memory sent by the main thread.
+        let hashed_thread_name = self
+            .wasm_threads
+            .then(|| format!("{}_thread.js", &hashed_name));
+        if let Some(name) = &hashed_thread_name {
+            let bootstrap = THREAD_BOOTSTRAP_SCRIPT.replace("{js}", &hashed_js_name);
+            common::write_file(out_dir.join(name), bootstrap)
+                .await
+                .context("error writing thread bootstrap script")?;
+        }
+
         let ts_output = if self.typescript {
-            Some(hashed_ts_name)
+            Some(self.cfg.output_file(AssetClass::Wasm, &hashed_ts_name))
         } else {
             None
         };
 
         // Check for any snippets, and copy them over.
-        let snippets_dir = bindgen_out.join(SNIPPETS_DIR);
-        if path_exists(&snippets_dir).await? {
-            copy_dir_recursive(
-                bindgen_out.join(SNIPPETS_DIR),
-                self.cfg.staging_dist.join(SNIPPETS_DIR),
-            )
-            .await
-            .context("error copying snippets dir to stage dir")?;
-        }
+        let snippets = self
+            .copy_snippets(bindgen_out.as_std_path(), &out_dir, &js_loader_path_dist)
+            .await?
+            .iter()
+            .map(|name| self.cfg.output_file(AssetClass::Wasm, name))
+            .collect();
 
         Ok(RustAppOutput {
             id: self.id,
             cfg: self.cfg.clone(),
-            js_output: hashed_js_name,
-            wasm_output: hashed_wasm_name,
+            name: self.name.clone(),
+            js_output: self.cfg.output_file(AssetClass::Wasm, &hashed_js_name),
+            wasm_output: self.cfg.output_file(AssetClass::Wasm, &hashed_wasm_name),
             ts_output,
-            loader_shim_output: hashed_loader_name,
+            loader_shim_output: hashed_loader_name
+                .map(|name| self.cfg.output_file(AssetClass::Wasm, &name)),
             type_: self.app_type,
+            target: self.bindgen_target,
+            initializer: None,
+            snippets,
+            thread_bootstrap_output: hashed_thread_name
+                .map(|name| self.cfg.output_file(AssetClass::Wasm, &name)),
+            wasm_split_output: self.wasm_split.as_ref().map(|_| {
+                self.cfg
+                    .output_file(AssetClass::Wasm, &hashed("_bg.deferred.wasm"))
+            }),
+            wasm_size: 0,
+            js_integrity: None,
+            wasm_integrity: None,
         })
     }
 
-    #[tracing::instrument(level = "trace", skip(self, hashed_name))]
-    async fn wasm_opt_build(&self, hashed_name: &str) -> Result<()> {
-        // If not in release mode, we skip calling wasm-opt.
-        if !self.cfg.release {
-            return Ok(());
+    /// Copy the JS snippets generated by wasm-bindgen, i.e. the inline snippets & the local files
+    /// of `#[wasm_bindgen(module = "...")]`, to the output dir.
+    ///
+    /// If hashing is enabled, the snippets are hashed & the imports of the JS loader rewritten to
+    /// their hashed names. The names of the copied files, relative to the output dir, are returned.
+    async fn copy_snippets(
+        &self,
+        bindgen_out: &Path,
+        out_dir: &Path,
+        js_loader: &Path,
+    ) -> Result<Vec<String>> {
+        let snippets_dir = bindgen_out.join(SNIPPETS_DIR);
+        if !path_exists(&snippets_dir).await? {
+            return Ok(vec![]);
         }
+        let files = common::list_files_recursive(&snippets_dir)
+            .await
+            .context("error listing snippets")?;
+        let names = files
+            .iter()
+            .map(|path| url_path(path.strip_prefix(bindgen_out).unwrap_or(path)));
+        let Some(hash) = &self.hash else {
+            copy_dir_recursive(snippets_dir, out_dir.join(SNIPPETS_DIR))
+                .await
+                .context("error copying snippets dir to stage dir")?;
+            return Ok(names.collect());
+        };
 
-        // If opt level is off, we skip calling wasm-opt as it wouldn't have any effect.
+        let mut js = fs::read_to_string(js_loader)
+            .await
+            .context("error reading JS loader file")?;
+        let mut hashed_names = vec![];
+        for (path, name) in files.iter().zip(names) {
+            let content = fs::read(path)
+                .await
+                .with_context(|| format!("error reading snippet {:?}", path))?;
+            // Snippets are always placed in the dir of their crate.
+            let (dir, file_name) = name.rsplit_once('/').unwrap_or(("", &name));
+            let (stem, suffix) = match file_name.rsplit_once('.') {
+                Some((stem, ext)) => (stem, format!(".{ext}")),
+                None => (file_name, String::new()),
+            };
+            let hashed_name = format!("{dir}/{}", hash.name(stem, &suffix, &content));
+            let dest = out_dir.join(hashing::file_path(&hashed_name));
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .await
+                    .context("error creating snippets dir")?;
+            }
+            common::write_file(&dest, &content)
+                .await
+                .with_context(|| format!("error copying snippet to {:?}", dest))?;
+            for quote in ['\'', '"'] {
+                js = js.replace(
+                    &format!("{quote}./{name}{quote}"),
+                    &format!("{quote}./{hashed_name}{quote}"),
+                );
+            }
+            hashed_names.push(hashed_name);
+        }
+        common::write_file(js_loader, js)
+            .await
+            .context("error writing JS loader file")?;
+        Ok(hashed_names)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, hashed_name))]
+    async fn wasm_opt_build(&self, hashed_name: &str) -> Result<()> {
+        // If opt level is off, we skip calling wasm-opt as it wouldn't have any effect. It is off
+        // by default in debug builds.
         if self.wasm_opt == WasmOptLevel::Off {
             return Ok(());
         }
 
         let version = self.cfg.tools.wasm_opt.as_deref();
-        let wasm_opt = tools::get(Application::WasmOpt, version).await?;
+        let (wasm_opt, version) = self
+            .cfg
+            .tool_context
+            .get_with_version(
+                Application::WasmOpt,
+                self.cfg.tool_version(Application::WasmOpt, version),
+            )
+            .await?;
 
         // Ensure our output dir is in place.
         let wasm_opt_name = Application::WasmOpt.name();
@@ -523,7 +899,8 @@ impl RustApp {
             .context("error creating wasm-opt output dir")?;
 
         // Build up args for calling wasm-opt.
-        let output = output.join(hashed_name);
+        let file_name = hashed_name.rsplit('/').next().unwrap_or(hashed_name);
+        let output = output.join(file_name);
         let arg_output = format!("--output={}", output);
         let arg_opt_level = format!("-O{}", self.wasm_opt.as_ref());
         let target_wasm = self
@@ -532,26 +909,359 @@ impl RustApp {
             .join(hashed_name)
             .to_string_lossy()
             .to_string();
-        let mut args: Vec<&str> = vec![&arg_output, &arg_opt_level, &target_wasm];
+        let mut args = vec![arg_output, target_wasm.clone()];
+        args.extend(self.feature_args());
+        // Passes run in the order given, after the optimizations of the level.
+        // The DWARF sections are updated by the passes supporting it, others are skipped.
+        if self.debug_info {
+            args.push("--debuginfo".into());
+        }
+        args.push(arg_opt_level);
+        // Passes may be given with or without the leading dashes of their flags.
+        args.extend(
+            self.cfg
+                .wasm_opt_passes
+                .iter()
+                .map(|pass| format!("--{}", pass.trim_start_matches('-'))),
+        );
 
-        if self.reference_types {
-            args.push("--enable-reference-types");
+        // The output only depends on the input WASM, the version of wasm-opt & the options used.
+        let cache_key = match &self.cfg.cache {
+            Some(_) => {
+                let wasm = fs::read(&target_wasm)
+                    .await
+                    .context("error reading wasm file")?;
+                // Without the output path, which is the same for all builds of the app.
+                let mut key: Vec<&[u8]> = vec![wasm_opt_name.as_bytes(), version.as_bytes()];
+                key.extend(args[2..].iter().map(|arg| arg.as_bytes()));
+                key.push(&wasm);
+                Some(BuildCache::key(&key))
+            }
+            None => None,
+        };
+        if let (Some(cache), Some(key)) = (&self.cfg.cache, &cache_key) {
+            if let Some([wasm]) = cache.get(key).await.as_deref() {
+                tracing::info!("using cached wasm-opt output");
+                common::write_file(&target_wasm, wasm)
+                    .await
+                    .context("error writing wasm file to dist dir")?;
+                return Ok(());
+            }
         }
 
         // Invoke wasm-opt.
         tracing::info!("calling wasm-opt");
+        let _job = self.cfg.acquire_job().await?;
         common::run_command(wasm_opt_name, &wasm_opt, &args)
             .await
             .map_err(|err| check_target_not_found_err(err, wasm_opt_name))?;
 
         // Copy the generated WASM file to the dist dir.
         tracing::info!("copying generated wasm-opt artifacts");
-        fs::copy(output, self.cfg.staging_dist.join(hashed_name))
+        common::copy_file(&output, self.cfg.staging_dist.join(hashed_name))
             .await
             .context("error copying wasm file to dist dir")?;
 
+        if let (Some(cache), Some(key)) = (&self.cfg.cache, &cache_key) {
+            let wasm = fs::read(&output).await.context("error reading wasm file")?;
+            cache.put(key, &[&wasm], &[]).await;
+        }
+
         Ok(())
     }
+
+    /// The flags enabling the WASM features of the app for the tools of binaryen, i.e. wasm-opt &
+    /// wasm-split.
+    fn feature_args(&self) -> Vec<String> {
+        let mut features = self.cfg.wasm_opt_features.clone();
+        if self.reference_types && !features.iter().any(|f| f == "reference-types") {
+            features.push("reference-types".into());
+        }
+        if self.wasm_threads {
+            for feature in ["threads", "bulk-memory"] {
+                if !features.iter().any(|f| f == feature) {
+                    features.push(feature.into());
+                }
+            }
+        }
+        // Features may be given with or without the leading dashes of their flags.
+        features
+            .iter()
+            .map(|feature| format!("--enable-{}", feature.trim_start_matches('-')))
+            .collect()
+    }
+
+    /// Split the WASM file of the app with wasm-split into a primary module & the deferred module
+    /// of the given output, returning whether the app was split.
+    ///
+    /// The functions in the given profile are kept in the primary module, while the JS glue is
+    /// patched to load the deferred module. Without the profile, the app is instrumented to record
+    /// it instead. If splitting fails, e.g. as the profile was recorded for another build of the
+    /// app, the app is not split.
+    #[tracing::instrument(level = "trace", skip(self, output))]
+    async fn wasm_split_build(&self, profile: &Path, output: &RustAppOutput) -> Result<bool> {
+        let version = self.cfg.tools.wasm_opt.as_deref();
+        let wasm_opt = self
+            .cfg
+            .tool_context
+            .get(
+                Application::WasmOpt,
+                self.cfg.tool_version(Application::WasmOpt, version),
+            )
+            .await?;
+        // wasm-split is released along with wasm-opt.
+        let wasm_split_name = "wasm-split";
+        let wasm_split = wasm_opt.with_file_name(format!(
+            "{}{}",
+            wasm_split_name,
+            std::env::consts::EXE_SUFFIX
+        ));
+        ensure!(
+            path_exists(&wasm_split).await?,
+            "{} was not found next to wasm-opt at {:?}; please install binaryen along with it, or \
+             remove wasm-opt via `trunk tools prune` for it to be downloaded again",
+            wasm_split_name,
+            wasm_opt
+        );
+
+        // Ensure our output dir is in place.
+        let mode_segment = if self.cfg.release { "release" } else { "debug" };
+        let out_dir = self
+            .manifest
+            .metadata
+            .target_directory
+            .join(wasm_split_name)
+            .join(mode_segment);
+        fs::create_dir_all(&out_dir)
+            .await
+            .context("error creating wasm-split output dir")?;
+
+        let staging_dist = &self.cfg.staging_dist;
+        let target_wasm = staging_dist.join(hashing::file_path(&output.wasm_output));
+        let js_path = staging_dist.join(hashing::file_path(&output.js_output));
+        let file_name = target_wasm
+            .file_name()
+            .context("missing wasm file name")?
+            .to_string_lossy();
+        let primary = out_dir.join(&*file_name);
+        let mut args = vec![target_wasm.to_string_lossy().to_string()];
+        args.extend(self.feature_args());
+        let mut js = fs::read_to_string(&js_path)
+            .await
+            .context("error reading JS loader file")?;
+
+        if !path_exists(profile).await? {
+            args.extend(["--instrument".into(), format!("--output={}", primary)]);
+            tracing::info!("calling wasm-split to instrument {}", self.name);
+            let _job = self.cfg.acquire_job().await?;
+            common::run_command(wasm_split_name, &wasm_split, &args).await?;
+            common::copy_file(&primary, &target_wasm)
+                .await
+                .context("error copying instrumented wasm file to dist dir")?;
+
+            let profile_name = profile
+                .file_name()
+                .context("missing wasm-split profile file name")?
+                .to_string_lossy();
+            let profile_name = serde_json::Value::from(profile_name.as_ref()).to_string();
+            js.push_str(&WASM_SPLIT_PROFILE_SCRIPT.replace("{profile}", &profile_name));
+            common::write_file(&js_path, js)
+                .await
+                .context("error writing JS loader file")?;
+            tracing::warn!(
+                "{} is instrumented by wasm-split, as its profile {:?} does not exist; please \
+                 call `__trunkWasmSplitProfile()` once the app started & save the downloaded \
+                 profile there",
+                self.name,
+                profile
+            );
+            return Ok(false);
+        }
+
+        let deferred_output = output
+            .wasm_split_output
+            .as_deref()
+            .context("missing deferred wasm output")?;
+        let deferred = out_dir.join(format!("{}.deferred", file_name));
+        args.extend([
+            "--split".into(),
+            format!("--profile={}", profile.display()),
+            format!("--primary-output={}", primary),
+            format!("--secondary-output={}", deferred),
+            // The exports & imports added must not clash with those of wasm-bindgen.
+            "--export-prefix=%".into(),
+            "--placeholder-namespace=__trunk_split".into(),
+        ]);
+        tracing::info!("calling wasm-split for {}", self.name);
+        let _job = self.cfg.acquire_job().await?;
+        if let Err(err) = common::run_command(wasm_split_name, &wasm_split, &args).await {
+            tracing::warn!(
+                "{} is not split, as wasm-split failed; the profile {:?} must be recorded again \
+                 once the app changed: {:#}",
+                self.name,
+                profile,
+                err
+            );
+            return Ok(false);
+        }
+
+        let deferred_wasm = fs::read(&deferred)
+            .await
+            .context("error reading deferred wasm file")?;
+        let table = funcref_table_import(&deferred_wasm)?;
+        common::copy_file(&primary, &target_wasm)
+            .await
+            .context("error copying primary wasm file to dist dir")?;
+        common::write_file(
+            staging_dist.join(hashing::file_path(deferred_output)),
+            deferred_wasm,
+        )
+        .await
+        .context("error writing deferred wasm file to dist dir")?;
+
+        // The deferred module is placed next to the JS glue.
+        let url = format!(
+            "./{}",
+            deferred_output.rsplit('/').next().unwrap_or_default()
+        );
+        common::write_file(&js_path, split_glue(&js, &url, &table)?)
+            .await
+            .context("error writing JS loader file")?;
+        Ok(true)
+    }
+}
+
+/// Patch the JS glue of wasm-bindgen to load the deferred module of an app split by wasm-split
+/// from the given URL, which places its functions in the table of the given name.
+fn split_glue(js: &str, url: &str, table: &str) -> Result<String> {
+    static IMPORTS_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"const imports = (__wbg_get_imports|getImports)\(\);")
+            .expect("error compiling imports regex")
+    });
+    static FINALIZE_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"return (__wbg_finalize_init|finalizeInit)\(instance, module\);")
+            .expect("error compiling finalize regex")
+    });
+    ensure!(
+        IMPORTS_REGEX.is_match(js) && FINALIZE_REGEX.is_match(js),
+        "the JS glue of wasm-bindgen can not be patched to load the deferred module of wasm-split; \
+         please update wasm-bindgen"
+    );
+    let js = IMPORTS_REGEX.replace_all(js, "const imports = __trunkSplitImports(${1}());");
+    let js = FINALIZE_REGEX.replace_all(&js, "return __trunkSplitStart(${1}(instance, module));");
+    let script = WASM_SPLIT_SCRIPT
+        .replace("{deferred}", &serde_json::Value::from(url).to_string())
+        .replace("{table}", &serde_json::Value::from(table).to_string());
+    Ok(format!("{}{}", js, script))
+}
+
+/// The name of the funcref table imported by the given WASM module, which is the table of the
+/// primary module the deferred module of wasm-split places its functions in.
+fn funcref_table_import(wasm: &[u8]) -> Result<String> {
+    let mut module = WasmReader(wasm);
+    ensure!(
+        module.bytes(8)? == b"\0asm\x01\0\0\0",
+        "invalid wasm module"
+    );
+    while !module.0.is_empty() {
+        let id = module.byte()?;
+        let size = module.leb()? as usize;
+        let mut section = WasmReader(module.bytes(size)?);
+        // Only the import section is of interest.
+        if id != 2 {
+            continue;
+        }
+        for _ in 0..section.leb()? {
+            let _module = section.name()?;
+            let name = section.name()?;
+            match section.byte()? {
+                0 => {
+                    section.leb()?;
+                }
+                1 => {
+                    let funcref = section.byte()? == 0x70;
+                    section.limits()?;
+                    if funcref {
+                        return Ok(name);
+                    }
+                }
+                2 => section.limits()?,
+                3 => {
+                    section.bytes(2)?;
+                }
+                4 => {
+                    section.byte()?;
+                    section.leb()?;
+                }
+                kind => bail!("unknown import kind {} in wasm module", kind),
+            }
+        }
+    }
+    bail!("the deferred wasm module of wasm-split does not import a function table")
+}
+
+/// A reader of the binary format of WASM modules.
+struct WasmReader<'a>(&'a [u8]);
+
+impl<'a> WasmReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(self.0.len() >= len, "unexpected end of wasm module");
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// An unsigned LEB128 number.
+    fn leb(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("invalid LEB128 number in wasm module")
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self.leb()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    /// The limits of a table or memory.
+    fn limits(&mut self) -> Result<()> {
+        let flags = self.byte()?;
+        self.leb()?;
+        if flags & 1 != 0 {
+            self.leb()?;
+        }
+        Ok(())
+    }
+}
+
+/// The wasm-bindgen target of main apps without a `data-bindgen-target` attribute.
+fn main_bindgen_target(cfg: &RtcBuild) -> Result<BindgenTarget> {
+    cfg.wasm_bindgen
+        .target
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map(|target| target.unwrap_or(BindgenTarget::Web))
+}
+
+/// Check that a main app of the given wasm-bindgen target can be loaded by the injected script.
+fn check_bindgen_target(cfg: &RtcBuild, target: BindgenTarget) -> Result<()> {
+    ensure!(
+        target != BindgenTarget::Bundler || !cfg.inject_scripts || cfg.pattern_script.is_some(),
+        "the `bundler` target of wasm-bindgen can not be loaded by browsers as is; please set \
+         `build.inject_scripts = false` or a `build.pattern_script` to load the app"
+    );
+    Ok(())
 }
 
 /// Find the appropriate version of `wasm-bindgen` to use. The version can be found in 3 different
@@ -595,6 +1305,7 @@ fn find_wasm_bindgen_version<'a>(
 }
 
 /// The output of a cargo build pipeline.
+#[derive(Clone)]
 pub struct RustAppOutput {
     /// The runtime build config.
     pub cfg: Arc<RtcBuild>,
@@ -608,8 +1319,27 @@ pub struct RustAppOutput {
     pub ts_output: Option<String>,
     /// The filename of the generated loader shim script for web workers written to the dist dir.
     pub loader_shim_output: Option<String>,
+    /// The name of the app, i.e. the name of its binary or cargo project.
+    pub name: String,
     /// Is this module main or a worker.
     pub type_: RustAppType,
+    /// The target of the JS loader file.
+    pub target: BindgenTarget,
+    /// The filename of the initializer module written to the dist dir, if any.
+    pub initializer: Option<String>,
+    /// The filenames of the JS snippets written to the dist dir.
+    pub snippets: Vec<String>,
+    /// The filename of the bootstrap script of the threads of the app written to the dist dir, if
+    /// built with wasm threads.
+    pub thread_bootstrap_output: Option<String>,
+    /// The filename of the deferred WASM module written to the dist dir, if split by wasm-split.
+    pub wasm_split_output: Option<String>,
+    /// The size of the final WASM file.
+    pub wasm_size: u64,
+    /// The subresource integrity digest of the JS loader file, if enabled.
+    pub js_integrity: Option<String>,
+    /// The subresource integrity digest of the WASM file, if enabled.
+    pub wasm_integrity: Option<String>,
 }
 
 pub fn pattern_evaluate(template: &str, params: &HashMap<String, String>) -> String {
@@ -628,12 +1358,30 @@ pub fn pattern_evaluate(template: &str, params: &HashMap<String, String>) -> Str
 }
 
 impl RustAppOutput {
+    /// The filenames of the files written to the dist dir besides the JS loader & the WASM file,
+    /// i.e. the TypeScript declarations, the loader shim, the initializer module, the snippets,
+    /// the thread bootstrap script & the deferred WASM module.
+    pub fn auxiliary_files(&self) -> impl Iterator<Item = &String> {
+        self.ts_output
+            .iter()
+            .chain(&self.loader_shim_output)
+            .chain(&self.initializer)
+            .chain(&self.snippets)
+            .chain(&self.thread_bootstrap_output)
+            .chain(&self.wasm_split_output)
+    }
+
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
         if self.type_ == RustAppType::Worker {
-            // Skip the script tag and preload links for workers, and remove the link tag only.
-            // Workers are initialized and managed by the app itself at runtime.
+            // Skip the script tag and preload links for workers. Workers are initialized and
+            // managed by the app itself at runtime, which finds the URLs of their files in the
+            // `__trunk_workers` global set by the script replacing the link tag.
             if let Some(id) = self.id {
-                dom.select(&super::trunk_id_selector(id)).remove();
+                let mut link = dom.select(&super::trunk_id_selector(id));
+                match self.cfg.inject_scripts {
+                    true => link.replace_with_html(self.worker_script()),
+                    false => link.remove(),
+                }
             }
             return Ok(());
         }
@@ -659,32 +1407,76 @@ impl RustAppOutput {
         params.insert("base".to_owned(), base.clone());
         params.insert("js".to_owned(), js.clone());
         params.insert("wasm".to_owned(), wasm.clone());
+        if let Some(initializer) = &self.initializer {
+            params.insert("initializer".to_owned(), initializer.clone());
+        }
+        if let Some(bootstrap) = &self.thread_bootstrap_output {
+            params.insert("thread_bootstrap".to_owned(), bootstrap.clone());
+        }
 
         let preload = match pattern_preload {
             Some(pattern) => pattern_evaluate(pattern, &params),
             None => {
+                // The WASM preload is already requested in CORS mode, so only the digest is added.
+                let wasm_integrity = self
+                    .wasm_integrity
+                    .as_ref()
+                    .map(|digest| format!(r#" integrity="{digest}""#))
+                    .unwrap_or_default();
+                // The glue of the `no-modules` target is a classic script.
+                let js_preload = match self.target {
+                    BindgenTarget::NoModules => r#"rel="preload" as="script""#,
+                    _ => r#"rel="modulepreload""#,
+                };
                 format!(
                     r#"
-<link rel="preload" href="{base}{wasm}" as="fetch" type="application/wasm" crossorigin>
-<link rel="modulepreload" href="{base}{js}">"#,
+<link rel="preload" href="{base}{wasm}" as="fetch" type="application/wasm" crossorigin{wasm_integrity}>
+<link {js_preload} href="{base}{js}"{js_integrity}>"#,
                     base = base,
                     js = js,
-                    wasm = wasm
+                    wasm = wasm,
+                    js_integrity = super::integrity_attrs(self.js_integrity.as_deref()),
                 )
             }
         };
         dom.select(head).append_html(preload);
+        if let Some(bootstrap) = &self.thread_bootstrap_output {
+            // The app needs the URL of the bootstrap script to spawn its threads.
+            let url = serde_json::Value::from(format!("{base}{bootstrap}")).to_string();
+            dom.select(head).append_html(format!(
+                r#"<script>window.__trunk_thread_bootstrap={};</script>"#,
+                url.replace("</", r"<\/")
+            ));
+        }
 
-        let script = match pattern_script {
-            Some(pattern) => pattern_evaluate(pattern, &params),
-            None => {
-                format!(
-                    r#"<script type="module">import init from '{base}{js}';init('{base}{wasm}');</script>"#,
-                    base = base,
-                    js = js,
-                    wasm = wasm,
-                )
-            }
+        let script = match (pattern_script, &self.initializer) {
+            (Some(pattern), _) => pattern_evaluate(pattern, &params),
+            (None, Some(initializer)) => format!(
+                r#"<script type="module">import init from '{base}{js}';import initializer from '{base}{initializer}';
+{script}__trunkLoad(init, '{base}{wasm}', {size}, initializer);</script>"#,
+                base = base,
+                js = js,
+                wasm = wasm,
+                initializer = initializer,
+                script = LOADER_SCRIPT,
+                size = self.wasm_size,
+            ),
+            (None, None) if self.target == BindgenTarget::NoModules => format!(
+                r#"<script src="{base}{js}"{js_integrity}></script><script>wasm_bindgen('{base}{wasm}');</script>"#,
+                base = base,
+                js = js,
+                wasm = wasm,
+                js_integrity = super::integrity_attrs(self.js_integrity.as_deref()),
+            ),
+            (None, None) => format!(
+                r#"<script type="module">import init from '{base}{js}';
+{script}__trunkLoad(init, '{base}{wasm}', {size});</script>"#,
+                base = base,
+                js = js,
+                wasm = wasm,
+                script = LOADER_SCRIPT,
+                size = self.wasm_size,
+            ),
         };
         match self.id {
             Some(id) => dom
@@ -694,12 +1486,32 @@ impl RustAppOutput {
         }
         Ok(())
     }
+
+    /// The script registering the URLs of the files of a worker in the `__trunk_workers` global.
+    fn worker_script(&self) -> String {
+        let base = &self.cfg.public_url;
+        let mut urls = serde_json::Map::new();
+        urls.insert("js".into(), format!("{base}{}", self.js_output).into());
+        urls.insert("wasm".into(), format!("{base}{}", self.wasm_output).into());
+        if let Some(loader) = &self.loader_shim_output {
+            urls.insert("loader".into(), format!("{base}{}", loader).into());
+        }
+        // `</` is escaped, so that no value can end the script element.
+        let name = serde_json::Value::from(self.name.as_str()).to_string();
+        let urls = serde_json::Value::from(urls).to_string();
+        format!(
+            r#"<script>(window.__trunk_workers=window.__trunk_workers||{{}})[{}]={};</script>"#,
+            name.replace("</", r"<\/"),
+            urls.replace("</", r"<\/")
+        )
+    }
 }
 
 /// Different optimization levels that can be configured with `wasm-opt`.
-#[derive(PartialEq, Eq)]
+#[derive(Default, PartialEq, Eq)]
 enum WasmOptLevel {
     /// Default optimization passes.
+    #[default]
     Default,
     /// No optimization passes, skipping the wasp-opt step.
     Off,
@@ -751,12 +1563,6 @@ impl AsRef<str> for WasmOptLevel {
     }
 }
 
-impl Default for WasmOptLevel {
-    fn default() -> Self {
-        Self::Default
-    }
-}
-
 /// Handle invocation errors indicating that the target binary was not found, simply wrapping the
 /// error in additional context stating more clearly that the target was not found.
 fn check_target_not_found_err(err: anyhow::Error, target: &str) -> anyhow::Error {
@@ -769,3 +1575,74 @@ fn check_target_not_found_err(err: anyhow::Error, target: &str) -> anyhow::Error
         _ => err,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn worker_script_registers_worker_urls() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let mut cfg = RtcBuild::new_test(tmpdir.path()).await?;
+        cfg.public_url = "/app/".into();
+        let output = RustAppOutput {
+            cfg: Arc::new(cfg),
+            id: Some(1),
+            js_output: "worker.js".into(),
+            wasm_output: "worker_bg.wasm".into(),
+            ts_output: None,
+            loader_shim_output: Some("worker_loader.js".into()),
+            name: "worker".into(),
+            type_: RustAppType::Worker,
+            target: BindgenTarget::NoModules,
+            initializer: None,
+            snippets: vec![],
+            thread_bootstrap_output: None,
+            wasm_split_output: None,
+            wasm_size: 0,
+            js_integrity: None,
+            wasm_integrity: None,
+        };
+        assert_eq!(
+            output.worker_script(),
+            r#"<script>(window.__trunk_workers=window.__trunk_workers||{})["worker"]={"js":"/app/worker.js","loader":"/app/worker_loader.js","wasm":"/app/worker_bg.wasm"};</script>"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn split_glue_passes_imports_and_exports_through_loader() -> Result<()> {
+        let glue =
+            "async function __wbg_init(input) {\n    const imports = __wbg_get_imports();\n    \
+                    const { instance, module } = await __wbg_load(await input, imports);\n    \
+                    return __wbg_finalize_init(instance, module);\n}\n";
+        let js = split_glue(glue, "./app_bg.deferred.wasm", "%table")?;
+        assert!(js.contains("const imports = __trunkSplitImports(__wbg_get_imports());"));
+        assert!(js.contains("return __trunkSplitStart(__wbg_finalize_init(instance, module));"));
+        assert!(js.contains(r#"new URL("./app_bg.deferred.wasm", import.meta.url)"#));
+        assert!(js.contains(r#"wasm["%table"].get(Number(index))"#));
+        assert!(split_glue("export default init;", "./app_bg.deferred.wasm", "%table").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn funcref_table_import_skips_other_imports() -> Result<()> {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        // A custom section, preceding the import section.
+        wasm.extend([0, 3, 1, b'x', 0]);
+        let imports: &[u8] = &[
+            4, // The count of imports.
+            7, b'p', b'r', b'i', b'm', b'a', b'r', b'y', 6, b'm', b'e', b'm', b'o', b'r', b'y', 2,
+            0x01, 17, 0x80, 0x01, // A memory of 17 to 128 pages.
+            7, b'p', b'r', b'i', b'm', b'a', b'r', b'y', 2, b'%', b'a', 3, 0x7f,
+            0x01, // A global.
+            7, b'p', b'r', b'i', b'm', b'a', b'r', b'y', 2, b'%', b'b', 1, 0x6f, 0x00, 0x00, 7,
+            b'p', b'r', b'i', b'm', b'a', b'r', b'y', 2, b'%', b'c', 1, 0x70, 0x00, 0x02,
+        ];
+        wasm.extend([2, imports.len() as u8]);
+        wasm.extend(imports);
+        assert_eq!(funcref_table_import(&wasm)?, "%c");
+        assert!(funcref_table_import(&wasm[..wasm.len() - 1]).is_err());
+        Ok(())
+    }
+}
@@ -1,17 +1,33 @@
 //! Sass/Scss asset pipeline.
 
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use nipper::Document;
 use tokio::fs;
-use tokio::task::JoinHandle;
 
-use super::{AssetFile, Attrs, TrunkAssetPipelineOutput, ATTR_HREF, ATTR_INLINE};
-use crate::common;
-use crate::config::RtcBuild;
-use crate::tools::{self, Application};
+use super::{
+    critical_css, AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_CRITICAL,
+    ATTR_HREF, ATTR_INLINE,
+};
+use crate::cache::BuildCache;
+use crate::common::{self, path_exists};
+use crate::config::{AssetClass, RtcBuild};
+use crate::hashing::{self, FileHash};
+use crate::tools::Application;
+
+/// The attribute used to enable source map generation.
+const ATTR_SOURCEMAP: &str = "data-sourcemap";
+/// The attribute used to configure additional load paths.
+const ATTR_LOAD_PATHS: &str = "data-load-paths";
+/// The attribute used to enable the node package importer.
+const ATTR_PKG_IMPORTER: &str = "data-pkg-importer";
+/// The attribute used to inject variables.
+const ATTR_VARS: &str = "data-vars";
+/// The first sass release supporting the `--pkg-importer` flag.
+const PKG_IMPORTER_MIN_VERSION: (u32, u32, u32) = (1, 71, 0);
 
 /// A sass/scss asset pipeline.
 pub struct Sass {
@@ -20,9 +36,23 @@ pub struct Sass {
     /// Runtime build config.
     cfg: Arc<RtcBuild>,
     /// The asset file being processed.
-    asset: AssetFile,
+    pub(super) asset: AssetFile,
     /// If the specified SASS/SCSS file should be inlined.
     use_inline: bool,
+    /// If a source map should be generated for the compiled CSS.
+    use_sourcemap: bool,
+    /// Additional paths to resolve imports against.
+    pub(super) load_paths: Vec<PathBuf>,
+    /// If `pkg:` imports should be resolved from node packages.
+    use_pkg_importer: bool,
+    /// The variables declared in front of the file's content, as name & value.
+    vars: Vec<(String, String)>,
+    /// If subresource integrity attributes should be added to the stylesheet link.
+    use_integrity: bool,
+    /// If the critical rules should be inlined & the stylesheet deferred.
+    use_critical: bool,
+    /// The strategy used to hash the output file name, unless hashing is disabled.
+    hash: Option<FileHash>,
 }
 
 impl Sass {
@@ -42,19 +72,58 @@ impl Sass {
         let mut path = PathBuf::new();
         path.extend(href_attr.split('/'));
         let asset = AssetFile::new(&html_dir, path).await?;
-        let use_inline = attrs.get(ATTR_INLINE).is_some();
+        let use_inline = attrs.contains_key(ATTR_INLINE);
+        let use_sourcemap = attrs.contains_key(ATTR_SOURCEMAP);
+        let mut load_paths: Vec<PathBuf> = attrs
+            .get(ATTR_LOAD_PATHS)
+            .map(|paths| {
+                paths
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .map(|path| {
+                        let mut load_path = PathBuf::new();
+                        load_path.extend(path.split('/'));
+                        html_dir.join(load_path)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let use_pkg_importer = attrs.contains_key(ATTR_PKG_IMPORTER);
+        if use_pkg_importer {
+            // Allows plain imports of package files, e.g. `@import "bootstrap/scss/bootstrap"`.
+            if let Some(node_modules) = find_node_modules(&html_dir).await? {
+                load_paths.push(node_modules);
+            }
+        }
+        let vars = attrs
+            .get(ATTR_VARS)
+            .map(|vars| parse_vars(vars, &cfg))
+            .transpose()?
+            .unwrap_or_default();
+        let use_integrity = super::use_integrity(&cfg, &attrs)?;
+        // Critical CSS is only extracted for release builds.
+        let use_critical = attrs.contains_key(ATTR_CRITICAL) && cfg.release;
+        let hash = super::file_hash(&cfg, &attrs);
         Ok(Self {
             id,
             cfg,
             asset,
             use_inline,
+            use_sourcemap,
+            load_paths,
+            use_pkg_importer,
+            vars,
+            use_integrity,
+            use_critical,
+            hash,
         })
     }
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
@@ -62,7 +131,25 @@ impl Sass {
     async fn run(self) -> Result<TrunkAssetPipelineOutput> {
         // tracing::info!("downloading sass");
         let version = self.cfg.tools.sass.as_deref();
-        let sass = tools::get(Application::Sass, version).await?;
+        let (sass, version) = self
+            .cfg
+            .tool_context
+            .get_with_version(
+                Application::Sass,
+                self.cfg.tool_version(Application::Sass, version),
+            )
+            .await?;
+        if self.use_pkg_importer {
+            ensure!(
+                parse_version(&version) >= Some(PKG_IMPORTER_MIN_VERSION),
+                "`{}` requires sass {}.{}.{} or later, but {} is used; please set `tools.sass` accordingly",
+                ATTR_PKG_IMPORTER,
+                PKG_IMPORTER_MIN_VERSION.0,
+                PKG_IMPORTER_MIN_VERSION.1,
+                PKG_IMPORTER_MIN_VERSION.2,
+                version
+            );
+        }
 
         // Compile the target SASS/SCSS file.
         let style = if self.cfg.release {
@@ -72,41 +159,134 @@ impl Sass {
         };
         let path_str = dunce::simplified(&self.asset.path).display().to_string();
         let file_name = format!("{}.css", &self.asset.file_stem.to_string_lossy());
-        let file_path = dunce::simplified(&self.cfg.staging_dist.join(&file_name))
+        // Sass compiles next to the final CSS file, so that the paths of its source map are
+        // relative to it.
+        let out_dir = self.cfg.output_dir(AssetClass::Css);
+        let file_path = dunce::simplified(&out_dir.join(&file_name))
             .display()
             .to_string();
-        let args = &["--no-source-map", "-s", style, &path_str, &file_path];
+        let map_path = format!("{}.map", file_path);
+        // Outputs are cached along with the files loaded by sass, as listed in the source map, so
+        // a source map is always generated then. Source maps embedded into inlined CSS can't be
+        // read back, so these are not cached.
+        let cache = self
+            .cfg
+            .cache
+            .as_ref()
+            .filter(|_| !(self.use_sourcemap && self.use_inline));
+        // Inlined CSS can't reference a separate file, so its source map is embedded instead.
+        let source_map_args: &[&str] = match (self.use_sourcemap, self.use_inline) {
+            (false, _) if cache.is_none() => &["--no-source-map"],
+            (false, _) => &["--source-map"],
+            (true, false) => &["--source-map", "--embed-sources"],
+            (true, true) => &["--source-map", "--embed-sources", "--embed-source-map"],
+        };
+        let mut import_args = self
+            .load_paths
+            .iter()
+            .map(|path| format!("--load-path={}", dunce::simplified(path).display()))
+            .collect::<Vec<_>>();
+        if self.use_pkg_importer {
+            import_args.push("--pkg-importer=node".into());
+        }
+        let mut args = source_map_args.to_vec();
+        args.extend(import_args.iter().map(String::as_str));
+        args.extend(["-s", style]);
+        // The stderr output is captured for the error overlay, so colors are kept explicitly.
+        if std::io::stderr().is_terminal() {
+            args.push("--color");
+        }
+
+        // The variables are declared in front of the file's content, which is passed via stdin.
+        let input = if self.vars.is_empty() {
+            None
+        } else {
+            let is_indented = self.asset.ext.as_deref() == Some("sass");
+            let mut input = declare_vars(&self.vars, is_indented);
+            input.push_str(&self.asset.read_to_string().await?);
+            Some(input)
+        };
+        let cache_key = match cache {
+            Some(_) => {
+                let content = match &input {
+                    Some(input) => input.as_bytes().to_vec(),
+                    None => fs::read(&self.asset.path)
+                        .await
+                        .with_context(|| format!("error reading file {:?}", self.asset.path))?,
+                };
+                let mut parts = vec![Application::Sass.name().as_bytes(), version.as_bytes()];
+                parts.extend(args.iter().map(|arg| arg.as_bytes()));
+                parts.extend([path_str.as_bytes(), file_path.as_bytes(), &content]);
+                Some(BuildCache::key(&parts))
+            }
+            None => None,
+        };
 
         let rel_path = crate::common::strip_prefix(&self.asset.path);
-        tracing::info!(path = ?rel_path, "compiling sass/scss");
-        common::run_command(Application::Sass.name(), &sass, args).await?;
+        let cached = match (cache, &cache_key) {
+            (Some(cache), Some(key)) => cache.get(key).await,
+            _ => None,
+        };
+        let (mut css, source_map) = match cached.as_deref() {
+            Some([css, source_map]) => {
+                tracing::info!(path = ?rel_path, "using cached sass/scss output");
+                let css = String::from_utf8(css.clone()).context("error reading cached CSS")?;
+                let source_map = String::from_utf8(source_map.clone())
+                    .context("error reading cached source map")?;
+                (css, Some(source_map))
+            }
+            _ => {
+                tracing::info!(path = ?rel_path, "compiling sass/scss");
+                common::remove_file_if_exists(&file_path).await?;
+                common::remove_file_if_exists(&map_path).await?;
+                self.compile(&sass, args, input.as_deref(), &path_str, &file_path)
+                    .await?;
 
-        let css = fs::read_to_string(&file_path).await?;
-        fs::remove_file(&file_path).await?;
+                let css = fs::read_to_string(&file_path).await?;
+                fs::remove_file(&file_path).await?;
+                let source_map = if path_exists(&map_path).await? {
+                    let source_map = fs::read_to_string(&map_path)
+                        .await
+                        .context("error reading SASS source map")?;
+                    fs::remove_file(&map_path).await?;
+                    Some(source_map)
+                } else {
+                    None
+                };
+                if let (Some(cache), Some(key), Some(source_map)) = (cache, &cache_key, &source_map)
+                {
+                    if let Some(deps) = source_map_deps(source_map, &out_dir) {
+                        cache
+                            .put(key, &[css.as_bytes(), source_map.as_bytes()], &deps)
+                            .await;
+                    }
+                }
+                (css, source_map)
+            }
+        };
 
-        // Check if the specified SASS/SCSS file should be inlined.
-        let css_ref = if self.use_inline {
-            // Avoid writing any files, return the CSS as a String.
-            CssRef::Inline(css)
-        } else {
-            // Hash the contents to generate a file name, and then write the contents to the dist
-            // dir.
-            let hash = seahash::hash(css.as_bytes());
-            let file_name = self
-                .cfg
-                .filehash
-                .then(|| format!("{}-{:x}.css", &self.asset.file_stem.to_string_lossy(), hash))
-                .unwrap_or(file_name);
-            let file_path = self.cfg.staging_dist.join(&file_name);
-
-            // Write the generated CSS to the filesystem.
-            fs::write(&file_path, css)
-                .await
-                .context("error writing SASS pipeline output")?;
+        // Take the external source map, if any. The `sourceMappingURL` comment added by sass
+        // points to the unhashed file name, so it is removed here & re-added once the final file
+        // name is known.
+        let source_map = source_map.and_then(|source_map| {
+            if let Some(idx) = css.rfind("/*# sourceMappingURL=") {
+                css.truncate(idx);
+            }
+            self.use_sourcemap.then_some(source_map)
+        });
 
-            // Generate a hashed reference to the new CSS file.
-            CssRef::File(file_name)
-        };
+        let css_ref = CssRef::new(
+            &self.cfg,
+            &self.asset,
+            css,
+            source_map,
+            self.hash.as_ref(),
+            self.use_inline,
+            self.use_integrity,
+            self.use_critical,
+        )
+        .await
+        .context("error writing SASS pipeline output")?;
 
         tracing::info!(path = ?rel_path, "finished compiling sass/scss");
         Ok(TrunkAssetPipelineOutput::Sass(SassOutput {
@@ -115,9 +295,164 @@ impl Sass {
             css_ref,
         }))
     }
+
+    /// Compile the target file, or the given input in its place, to the given output file.
+    async fn compile(
+        &self,
+        sass: &Path,
+        mut args: Vec<&str>,
+        input: Option<&str>,
+        path_str: &str,
+        file_path: &str,
+    ) -> Result<()> {
+        let _job = self.cfg.acquire_job().await?;
+        let Some(input) = input else {
+            args.extend([path_str, file_path]);
+            return common::run_command(Application::Sass.name(), sass, &args).await;
+        };
+
+        // Relative imports still need to be resolved relative to the file.
+        let parent = self.asset.path.parent().unwrap_or(&self.asset.path);
+        let arg_parent = format!("--load-path={}", dunce::simplified(parent).display());
+        args.extend([arg_parent.as_str(), "--stdin"]);
+        if self.asset.ext.as_deref() == Some("sass") {
+            args.push("--indented");
+        }
+        args.push(file_path);
+        common::run_command_with_input(Application::Sass.name(), sass, &args, input.as_bytes())
+            .await
+    }
+}
+
+/// Find the closest `node_modules` dir, starting at the given dir & walking up its ancestors.
+async fn find_node_modules(dir: &Path) -> Result<Option<PathBuf>> {
+    for dir in dir.ancestors() {
+        let node_modules = dir.join("node_modules");
+        if path_exists(&node_modules).await? {
+            return Ok(Some(node_modules));
+        }
+    }
+    Ok(None)
+}
+
+/// Resolve the files loaded by sass, as listed in the sources of the given source map, written to
+/// the given dir. Relative sources are resolved against that dir.
+///
+/// `None` is returned if any stylesheet can not be resolved, as the output can't be cached then.
+fn source_map_deps(source_map: &str, dir: &Path) -> Option<Vec<PathBuf>> {
+    let source_map: serde_json::Value = serde_json::from_str(source_map).ok()?;
+    let mut deps = vec![];
+    for source in source_map.get("sources")?.as_array()? {
+        let source = source.as_str()?;
+        let path = match source.strip_prefix("file://") {
+            Some(path) => PathBuf::from(percent_decode(path)?),
+            None if source.contains(':') => PathBuf::from(source),
+            None => dir.join(percent_decode(source)?),
+        };
+        if path.is_file() {
+            deps.push(path);
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("sass" | "scss" | "css")
+        ) {
+            return None;
+        }
+    }
+    Some(deps)
+}
+
+/// Decode the percent-encoded bytes of a URL path.
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Parse the value of a `data-vars` attribute, a comma separated list of `name=value` pairs.
+///
+/// Values of the form `$NAME` are replaced with the value of the environment variable `NAME`, if
+/// it is set by `build.env` or allowed by `build.env_allowlist`. Unset variables are kept,
+/// referencing a sass variable, while set variables which are not allowed are an error.
+fn parse_vars(val: &str, cfg: &RtcBuild) -> Result<Vec<(String, String)>> {
+    val.split(',')
+        .map(str::trim)
+        .filter(|var| !var.is_empty())
+        .map(|var| {
+            let (name, value) = var
+                .split_once('=')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .filter(|(name, value)| !value.is_empty() && is_identifier(name))
+                .with_context(|| {
+                    format!(
+                        r#"invalid variable `{}` in `{}` attr for <link data-trunk rel="sass|scss" .../> element; please ensure it is formatted as `name=value`"#,
+                        var, ATTR_VARS
+                    )
+                })?;
+            let value = match value.strip_prefix('$') {
+                Some(env) => env_var(env, cfg)?.unwrap_or_else(|| value.to_string()),
+                None => value.to_string(),
+            };
+            Ok((name.to_string(), value))
+        })
+        .collect()
+}
+
+/// The value of the given environment variable of a `data-vars` value, if it is set.
+fn env_var(name: &str, cfg: &RtcBuild) -> Result<Option<String>> {
+    if let Some(val) = cfg.env.get(name) {
+        return Ok(Some(val.clone()));
+    }
+    if std::env::var_os(name).is_none() {
+        return Ok(None);
+    }
+    common::allowed_env_var(name, &cfg.env_allowlist)
+        .map(Some)
+        .with_context(|| format!("error expanding `${}` of `{}` attr", name, ATTR_VARS))
+}
+
+/// Check if the given name is a valid sass identifier.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_' || c == '-')
+        .unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Render the declarations of the given variables, in the indented or SCSS syntax.
+fn declare_vars(vars: &[(String, String)], is_indented: bool) -> String {
+    let terminator = if is_indented { "" } else { ";" };
+    vars.iter()
+        .map(|(name, value)| format!("${}: {}{}\n", name, value, terminator))
+        .collect()
+}
+
+/// Parse a `major.minor.patch` version.
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    // Ignore pre-release & build suffixes, e.g. `1.72.0-dev`.
+    let patch = parts
+        .next()?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((major, minor, patch))
 }
 
 /// The output of a sass/scss build pipeline.
+#[derive(Clone)]
 pub struct SassOutput {
     /// The runtime build config.
     pub cfg: Arc<RtcBuild>,
@@ -128,28 +463,189 @@ pub struct SassOutput {
 }
 
 /// The resulting CSS of the SASS/SCSS compilation.
+#[derive(Clone)]
 pub enum CssRef {
     /// CSS to be inlined (for `data-inline`).
     Inline(String),
     /// A hashed file reference to a CSS file (default).
-    File(String),
+    File {
+        /// Name of the finalized output file.
+        file: String,
+        /// The subresource integrity digest of the output file, if enabled.
+        integrity: Option<String>,
+        /// The CSS to extract the critical rules from, if enabled.
+        critical: Option<String>,
+    },
 }
 
-impl SassOutput {
-    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
-        let html = match self.css_ref {
+impl CssRef {
+    /// The name of the finalized output file, unless the CSS is inlined.
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            Self::File { file, .. } => Some(file),
+            Self::Inline(_) => None,
+        }
+    }
+
+    /// Create a reference to the given compiled CSS of an asset.
+    ///
+    /// Unless the CSS should be inlined, it is written to the staging dir, hashed if a hash
+    /// strategy is given. An
+    /// optional source map is written next to it and referenced from the CSS. If enabled, the
+    /// subresource integrity digest of the written CSS is computed, and the CSS is kept for
+    /// critical CSS extraction.
+    ///
+    /// In both cases, local files referenced via `url(...)` are copied & the references are
+    /// rewritten.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        cfg: &RtcBuild,
+        asset: &AssetFile,
+        css: String,
+        source_map: Option<String>,
+        hash: Option<&FileHash>,
+        use_inline: bool,
+        use_integrity: bool,
+        use_critical: bool,
+    ) -> Result<Self> {
+        // Copy the files referenced by the CSS, relative to the source file.
+        let base_dir = asset.path.parent().unwrap_or(&asset.path);
+        let mut css = super::css::rewrite_urls(cfg, base_dir, &css).await?;
+
+        // Check if the CSS should be inlined.
+        if use_inline {
+            // Avoid writing any files, return the CSS as a String.
+            return Ok(Self::Inline(css));
+        }
+
+        // Hash the contents to generate a file name, and then write the contents to the dist dir.
+        let file_stem = asset.file_stem.to_string_lossy();
+        let file_name = match hash {
+            Some(hash) => hash.name(&file_stem, ".css", css.as_bytes()),
+            None => format!("{}.css", file_stem),
+        };
+        let out_dir = cfg.output_dir(AssetClass::Css);
+        let file_path = out_dir.join(hashing::file_path(&file_name));
+
+        // Write the source map next to the CSS file & reference it.
+        if let Some(source_map) = source_map {
+            let map_name = format!("{}.map", hashing::file_path(&file_name));
+            common::write_file(out_dir.join(&map_name), source_map)
+                .await
+                .context("error writing source map")?;
+            css.push_str(&format!("/*# sourceMappingURL={} */\n", map_name));
+        }
+
+        let integrity = use_integrity.then(|| super::integrity_digest(css.as_bytes()));
+        let critical = use_critical.then(|| css.clone());
+
+        // Write the generated CSS to the filesystem.
+        common::write_file(&file_path, css)
+            .await
+            .with_context(|| format!("error writing CSS to {:?}", &file_path))?;
+
+        // Generate a hashed reference to the new CSS file.
+        Ok(Self::File {
+            file: cfg.output_file(AssetClass::Css, &file_name),
+            integrity,
+            critical,
+        })
+    }
+
+    /// The HTML used to reference the CSS in the given document.
+    pub fn to_html(&self, cfg: &RtcBuild, dom: &Document) -> String {
+        match self {
             // Insert the inlined CSS into a `<style>` tag.
-            CssRef::Inline(css) => format!(r#"<style type="text/css">{}</style>"#, css),
+            Self::Inline(css) => format!(r#"<style type="text/css">{}</style>"#, css),
             // Link to the CSS file.
-            CssRef::File(file) => {
-                format!(
-                    r#"<link rel="stylesheet" href="{base}{file}"/>"#,
-                    base = &self.cfg.public_url,
-                )
+            Self::File {
+                file,
+                integrity,
+                critical,
+            } => {
+                let href = format!("{}{}", &cfg.public_url, file);
+                let integrity = super::integrity_attrs(integrity.as_deref());
+                match critical {
+                    Some(css) => {
+                        critical_css::deferred_stylesheet_html(css, &href, &integrity, dom)
+                    }
+                    None => format!(r#"<link rel="stylesheet" href="{href}"{integrity}/>"#),
+                }
             }
-        };
+        }
+    }
+}
+
+impl SassOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let html = self.css_ref.to_html(&self.cfg, dom);
         dom.select(&super::trunk_id_selector(self.id))
             .replace_with_html(html);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn parse_vars_expands_env_vars() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let mut cfg = RtcBuild::new_test(tmpdir.path()).await?;
+        cfg.env_allowlist = vec!["TRUNK_TEST_SASS_VERSION".into()];
+        std::env::set_var("TRUNK_TEST_SASS_VERSION", "\"1.2.3\"");
+        let vars = parse_vars(
+            "theme=dark, version=$TRUNK_TEST_SASS_VERSION, accent=$primary",
+            &cfg,
+        )
+        .expect("expected vars to parse");
+        assert_eq!(
+            declare_vars(&vars, false),
+            "$theme: dark;\n$version: \"1.2.3\";\n$accent: $primary;\n"
+        );
+        assert_eq!(declare_vars(&vars[..1], true), "$theme: dark\n");
+        assert!(parse_vars("theme", &cfg).is_err());
+        assert!(parse_vars("1theme=dark", &cfg).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parse_vars_denies_env_vars_not_allowed() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let mut cfg = RtcBuild::new_test(tmpdir.path()).await?;
+        std::env::set_var("TRUNK_TEST_SASS_SECRET", "secret");
+        assert!(parse_vars("secret=$TRUNK_TEST_SASS_SECRET", &cfg).is_err());
+
+        cfg.env
+            .insert("TRUNK_TEST_SASS_SECRET".into(), "public".into());
+        let vars = parse_vars("secret=$TRUNK_TEST_SASS_SECRET", &cfg)?;
+        assert_eq!(vars, vec![("secret".into(), "public".into())]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_version_ignores_suffix() {
+        assert_eq!(parse_version("1.71.0"), Some((1, 71, 0)));
+        assert_eq!(parse_version("1.72.0-dev"), Some((1, 72, 0)));
+        assert_eq!(parse_version("1.63"), None);
+        assert!(parse_version("1.63.6") < Some(PKG_IMPORTER_MIN_VERSION));
+    }
+
+    #[test]
+    fn source_map_deps_resolves_sources() -> Result<()> {
+        let tmpdir = tempfile::tempdir().context("error building tempdir for test")?;
+        let dist = tmpdir.path().join("dist");
+        std::fs::create_dir_all(&dist)?;
+        std::fs::write(tmpdir.path().join("my app.scss"), "")?;
+        let source_map = r#"{"sources": ["../my%20app.scss", "data:;charset=utf-8,a", "-"]}"#;
+        assert_eq!(
+            source_map_deps(source_map, &dist),
+            Some(vec![dist.join("../my app.scss")])
+        );
+
+        let source_map = r#"{"sources": ["../my%20app.scss", "../missing.scss"]}"#;
+        assert_eq!(source_map_deps(source_map, &dist), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Service worker asset pipeline.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nipper::Document;
+
+use super::{AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_HREF};
+use crate::common;
+use crate::config::RtcBuild;
+
+/// The placeholder in the service worker which is replaced with the list of files to precache.
+const PRECACHE_PLACEHOLDER: &str = "self.__TRUNK_MANIFEST";
+
+/// A service worker asset pipeline.
+pub struct ServiceWorker {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The asset file being processed.
+    asset: AssetFile,
+}
+
+impl ServiceWorker {
+    pub const TYPE_SERVICE_WORKER: &'static str = "service-worker";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>,
+        html_dir: Arc<PathBuf>,
+        attrs: Attrs,
+        id: usize,
+    ) -> Result<Self> {
+        // Build the path to the target asset.
+        let href_attr = attrs.get(ATTR_HREF).context(
+            r#"required attr `href` missing for <link data-trunk rel="service-worker" .../> element"#,
+        )?;
+        let mut path = PathBuf::new();
+        path.extend(href_attr.split('/'));
+        let asset = AssetFile::new(&html_dir, path).await?;
+        Ok(Self { id, cfg, asset })
+    }
+
+    /// Spawn the pipeline for this asset type.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
+    }
+
+    /// Run this pipeline.
+    #[tracing::instrument(level = "trace", skip(self))]
+    async fn run(self) -> Result<TrunkAssetPipelineOutput> {
+        let rel_path = crate::common::strip_prefix(&self.asset.path);
+        tracing::info!(path = ?rel_path, "reading service worker");
+        let content = self.asset.read_to_string().await?;
+        if !content.contains(PRECACHE_PLACEHOLDER) {
+            tracing::warn!(path = ?rel_path, "service worker does not reference `{PRECACHE_PLACEHOLDER}`, no precache list will be injected");
+        }
+        tracing::info!(path = ?rel_path, "finished reading service worker");
+
+        Ok(TrunkAssetPipelineOutput::ServiceWorker(
+            ServiceWorkerOutput {
+                cfg: self.cfg.clone(),
+                id: self.id,
+                file: self.asset.file_name.to_string_lossy().into_owned(),
+                content,
+            },
+        ))
+    }
+}
+
+/// The output of a service worker build pipeline.
+///
+/// As the precache list includes the output of all other pipelines, this output must be finalized
+/// after all of them.
+#[derive(Clone)]
+pub struct ServiceWorkerOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// Name of the finalized output file.
+    ///
+    /// This is never hashed, as browsers check for service worker updates using its URL.
+    pub file: String,
+    /// The content of the service worker.
+    pub content: String,
+}
+
+impl ServiceWorkerOutput {
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        // Build the precache list from all files in the dist dir, along with the index page.
+        let base = &self.cfg.public_url;
+        let mut files = common::list_files_recursive(&self.cfg.staging_dist)
+            .await?
+            .iter()
+            .map(|path| {
+                // Build a URL path from the path relative to the dist dir.
+                path.strip_prefix(&self.cfg.staging_dist)
+                    .unwrap_or(path)
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect::<Vec<_>>();
+        files.retain(|file| *file != self.file);
+        files.sort();
+        let mut precache = vec![base.clone()];
+        // The other HTML files are only written once all assets are finalized.
+        precache.extend(
+            self.cfg
+                .targets
+                .iter()
+                .skip(1)
+                .map(|target| format!("{base}{}", target.output)),
+        );
+        precache.extend(files.into_iter().map(|file| format!("{base}{file}")));
+        let precache =
+            serde_json::to_string(&precache).context("error serializing precache list")?;
+
+        let content = self.content.replace(PRECACHE_PLACEHOLDER, &precache);
+        let file_path = self.cfg.staging_dist.join(&self.file);
+        common::write_file(&file_path, content)
+            .await
+            .with_context(|| format!("error writing service worker {:?}", &file_path))?;
+
+        dom.select(&super::trunk_id_selector(self.id))
+            .replace_with_html(format!(
+                r#"<script>if ("serviceWorker" in navigator) {{ window.addEventListener("load", () => navigator.serviceWorker.register("{base}{file}")); }}</script>"#,
+                file = self.file
+            ));
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Generating the `sitemap.xml` & `robots.txt` files of the routes of the app.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{ensure, Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tokio::fs;
+
+use super::asset_manifest::MANIFEST_DIR;
+use super::copy_dir::url_path;
+use super::prerender::{check_route, route_file};
+use crate::common::{self, path_exists};
+use crate::config::{ConfigOptsBuildSitemap, RtcBuild};
+
+/// The file name of the sitemap, in the root of the dist dir.
+const SITEMAP_FILE: &str = "sitemap.xml";
+/// The file name of the robots.txt file, in the root of the dist dir.
+const ROBOTS_FILE: &str = "robots.txt";
+/// The robots.txt file written unless configured, allowing all crawlers.
+const DEFAULT_ROBOTS: &str = "User-agent: *\nAllow: /\n\nSitemap: %TRUNK_SITEMAP_URL%\n";
+
+/// Matches the URLs of a sitemap, along with their last modification time.
+static URL_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"<loc>([^<]*)</loc>\s*<lastmod>([^<]*)</lastmod>")
+        .expect("error compiling sitemap URL regex")
+});
+
+/// The sitemap of the routes of the app, along with its robots.txt file.
+#[derive(Clone, Debug)]
+pub struct Sitemap {
+    /// The absolute URL of the public URL, without a trailing slash.
+    base: String,
+    /// The routes listed by the sitemap, if configured.
+    routes: Option<Vec<String>>,
+    /// The template of the robots.txt file, if configured.
+    robots: Option<PathBuf>,
+}
+
+impl Sitemap {
+    /// Create a new instance from the given config, for the given public URL.
+    pub fn new(opts: &ConfigOptsBuildSitemap, public_url: &str) -> Result<Self> {
+        let is_absolute = |url: &str| url.starts_with("http://") || url.starts_with("https://");
+        let base = match &opts.base_url {
+            Some(base_url) => {
+                ensure!(
+                    is_absolute(base_url),
+                    "the sitemap base URL {:?} must be an absolute http(s) URL",
+                    base_url
+                );
+                // The path of the public URL is served below the base URL.
+                let path = match public_url.starts_with('/') {
+                    true => public_url,
+                    false => "",
+                };
+                format!("{}{}", base_url.trim_end_matches('/'), path)
+            }
+            None => {
+                ensure!(
+                    is_absolute(public_url),
+                    "the sitemap needs the absolute URL of the site, please set \
+                     `build.sitemap.base_url` or an absolute public URL"
+                );
+                public_url.to_owned()
+            }
+        };
+        for route in opts.routes.iter().flatten() {
+            check_route(route)?;
+        }
+        Ok(Self {
+            base: base.trim_end_matches('/').to_owned(),
+            routes: opts.routes.clone(),
+            robots: opts.robots.clone(),
+        })
+    }
+}
+
+/// Write the `sitemap.xml` & `robots.txt` files of the build to the staging dist dir.
+///
+/// The routes are the configured ones, the prerendered ones, or else those of all HTML files of
+/// the build, except for hashed files. The lastmod of a route is kept from the sitemap of the
+/// previous build, as long as the HTML file serving the route did not change.
+pub async fn write_sitemap(cfg: &RtcBuild, logical_names: &HashMap<String, String>) -> Result<()> {
+    let Some(sitemap) = &cfg.sitemap else {
+        return Ok(());
+    };
+    let routes = match (&sitemap.routes, &cfg.prerender) {
+        (Some(routes), _) => routes.clone(),
+        (None, Some(prerender)) => prerender.routes().to_vec(),
+        (None, None) => html_routes(&cfg.staging_dist, logical_names).await?,
+    };
+
+    let previous = fs::read_to_string(cfg.final_dist.join(SITEMAP_FILE))
+        .await
+        .unwrap_or_default();
+    let previous: HashMap<_, _> = URL_REGEX
+        .captures_iter(&previous)
+        .map(|caps| (caps[1].to_owned(), caps[2].to_owned()))
+        .collect();
+    let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset \
+         xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for route in routes {
+        let loc = escape(&format!("{}{}", sitemap.base, route));
+        // Routes without an HTML file of their own are served the index HTML file.
+        let mut file = route_file(&route);
+        if !path_exists(cfg.staging_dist.join(&file)).await? {
+            file = "index.html".into();
+        }
+        let lastmod = match previous.get(&loc) {
+            Some(lastmod) if unchanged(cfg, &file).await => lastmod.as_str(),
+            _ => now.as_str(),
+        };
+        xml.push_str(&format!(
+            "  <url>\n    <loc>{}</loc>\n    <lastmod>{}</lastmod>\n  </url>\n",
+            loc, lastmod
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    let path = cfg.staging_dist.join(SITEMAP_FILE);
+    common::write_file(&path, xml)
+        .await
+        .with_context(|| format!("error writing sitemap {:?}", path))?;
+
+    let robots_path = cfg.staging_dist.join(ROBOTS_FILE);
+    let robots = match &sitemap.robots {
+        Some(template) => fs::read_to_string(template)
+            .await
+            .with_context(|| format!("error reading robots.txt template {:?}", template))?,
+        // A robots.txt file copied into the build is kept as it is.
+        None if path_exists(&robots_path).await? => return Ok(()),
+        None => DEFAULT_ROBOTS.to_owned(),
+    };
+    let robots = robots
+        .replace(
+            "%TRUNK_SITEMAP_URL%",
+            &format!("{}/{}", sitemap.base, SITEMAP_FILE),
+        )
+        .replace("%TRUNK_BASE_URL%", &sitemap.base);
+    common::write_file(&robots_path, robots)
+        .await
+        .with_context(|| format!("error writing robots.txt {:?}", robots_path))
+}
+
+/// The routes of the HTML files in the given dist dir, except for hashed files & `404.html` files.
+async fn html_routes(dist: &Path, logical_names: &HashMap<String, String>) -> Result<Vec<String>> {
+    let mut routes: Vec<_> = common::list_files_recursive(dist)
+        .await?
+        .iter()
+        .filter_map(|path| {
+            let file = url_path(path.strip_prefix(dist).ok()?);
+            if file.starts_with(MANIFEST_DIR) || logical_names.contains_key(&file) {
+                return None;
+            }
+            html_route(&file)
+        })
+        .collect();
+    routes.sort();
+    Ok(routes)
+}
+
+/// The route of the given HTML file, relative to the dist dir.
+fn html_route(file: &str) -> Option<String> {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    if !name.ends_with(".html") || name == "404.html" {
+        return None;
+    }
+    match name == "index.html" {
+        true => Some(format!("/{}", &file[..file.len() - name.len()])),
+        false => Some(format!("/{}", file)),
+    }
+}
+
+/// Whether the given file of the build is the same as in the previous build.
+async fn unchanged(cfg: &RtcBuild, file: &str) -> bool {
+    match fs::read(cfg.staging_dist.join(file)).await {
+        Ok(bytes) => fs::read(cfg.final_dist.join(file)).await.ok() == Some(bytes),
+        Err(_) => false,
+    }
+}
+
+/// Escape the given text for XML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sitemap_keeps_lastmod_of_unchanged_routes() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        let mut cfg = RtcBuild::new_test(tmpdir.path()).await?;
+        cfg.sitemap = Some(Sitemap::new(
+            &ConfigOptsBuildSitemap {
+                base_url: Some("https://example.com/".into()),
+                ..Default::default()
+            },
+            "/app/",
+        )?);
+        for (dir, html) in [(&cfg.final_dist, "old"), (&cfg.staging_dist, "new")] {
+            fs::create_dir_all(dir.join("about")).await?;
+            fs::write(dir.join("index.html"), "index").await?;
+            fs::write(dir.join("about").join("index.html"), html).await?;
+            fs::write(dir.join("404.html"), "").await?;
+            fs::write(dir.join("app-0123456789abcdef.html"), "").await?;
+        }
+        fs::write(
+            cfg.final_dist.join(SITEMAP_FILE),
+            "<url><loc>https://example.com/app/</loc><lastmod>2020-01-01T00:00:00Z</lastmod></url>\
+             <url><loc>https://example.com/app/about/</loc><lastmod>2020-01-01T00:00:00Z</lastmod></url>",
+        )
+        .await?;
+        let logical_names = HashMap::from([(
+            "app-0123456789abcdef.html".to_owned(),
+            "app.html".to_owned(),
+        )]);
+        write_sitemap(&cfg, &logical_names).await?;
+
+        let xml = fs::read_to_string(cfg.staging_dist.join(SITEMAP_FILE)).await?;
+        let urls: Vec<_> = URL_REGEX
+            .captures_iter(&xml)
+            .map(|caps| (caps[1].to_owned(), caps[2].starts_with("2020")))
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                ("https://example.com/app/".to_owned(), true),
+                ("https://example.com/app/about/".to_owned(), false),
+            ]
+        );
+        let robots = fs::read_to_string(cfg.staging_dist.join(ROBOTS_FILE)).await?;
+        assert!(robots.contains("Sitemap: https://example.com/app/sitemap.xml"));
+        Ok(())
+    }
+}
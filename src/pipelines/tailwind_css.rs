@@ -1,17 +1,31 @@
 //! Tailwind CSS asset pipeline.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use nipper::Document;
 use tokio::fs;
-use tokio::task::JoinHandle;
 
-use super::{AssetFile, Attrs, TrunkAssetPipelineOutput, ATTR_HREF, ATTR_INLINE};
-use crate::common;
-use crate::config::RtcBuild;
-use crate::tools::{self, Application};
+use super::sass::CssRef;
+use super::{
+    AssetFile, Attrs, PipelineHandle, TrunkAssetPipelineOutput, ATTR_CRITICAL, ATTR_HREF,
+    ATTR_INLINE,
+};
+use crate::common::{self, path_exists};
+use crate::config::{CargoMetadata, RtcBuild};
+use crate::hashing::FileHash;
+use crate::tools::Application;
+
+/// The attribute used to set the path to the tailwind config.
+const ATTR_CONFIG: &str = "data-config";
+/// The names of the tailwind config files which are detected next to the source HTML.
+const CONFIG_FILES: &[&str] = &[
+    "tailwind.config.js",
+    "tailwind.config.cjs",
+    "tailwind.config.mjs",
+    "tailwind.config.ts",
+];
 
 /// A tailwind css asset pipeline.
 pub struct TailwindCss {
@@ -21,8 +35,18 @@ pub struct TailwindCss {
     cfg: Arc<RtcBuild>,
     /// The asset file being processed.
     asset: AssetFile,
+    /// The parent directory of the source HTML file.
+    html_dir: Arc<PathBuf>,
+    /// The path to the tailwind config, if any.
+    config: Option<PathBuf>,
     /// If the specified tailwind css file should be inlined.
     use_inline: bool,
+    /// If subresource integrity attributes should be added to the stylesheet link.
+    use_integrity: bool,
+    /// If the critical rules should be inlined & the stylesheet deferred.
+    use_critical: bool,
+    /// The strategy used to hash the output file name, unless hashing is disabled.
+    hash: Option<FileHash>,
 }
 
 impl TailwindCss {
@@ -41,66 +65,116 @@ impl TailwindCss {
         let mut path = PathBuf::new();
         path.extend(href_attr.split('/'));
         let asset = AssetFile::new(&html_dir, path).await?;
-        let use_inline = attrs.get(ATTR_INLINE).is_some();
+        let config = match attrs.get(ATTR_CONFIG) {
+            Some(config) => {
+                let mut path = PathBuf::new();
+                path.extend(config.split('/'));
+                let path = html_dir.join(path);
+                ensure!(
+                    path_exists(&path).await?,
+                    "tailwind config set via `{}` does not appear to exist on disk {:?}",
+                    ATTR_CONFIG,
+                    &path
+                );
+                Some(path)
+            }
+            None => find_config(&html_dir).await?,
+        };
+        let use_inline = attrs.contains_key(ATTR_INLINE);
+        let use_integrity = super::use_integrity(&cfg, &attrs)?;
+        // Critical CSS is only extracted for release builds.
+        let use_critical = attrs.contains_key(ATTR_CRITICAL) && cfg.release;
+        let hash = super::file_hash(&cfg, &attrs);
         Ok(Self {
             id,
             cfg,
             asset,
+            html_dir,
+            config,
             use_inline,
+            use_integrity,
+            use_critical,
+            hash,
         })
     }
 
     /// Spawn the pipeline for this asset type.
     #[tracing::instrument(level = "trace", skip(self))]
-    pub fn spawn(self) -> JoinHandle<Result<TrunkAssetPipelineOutput>> {
-        tokio::spawn(self.run())
+    pub fn spawn(self) -> PipelineHandle<Result<TrunkAssetPipelineOutput>> {
+        super::spawn(self.run())
     }
 
     /// Run this pipeline.
     #[tracing::instrument(level = "trace", skip(self))]
     async fn run(self) -> Result<TrunkAssetPipelineOutput> {
         let version = self.cfg.tools.tailwindcss.as_deref();
-        let tailwind = tools::get(Application::TailwindCss, version).await?;
+        let (tailwind, version) = self
+            .cfg
+            .tool_context
+            .get_with_version(
+                Application::TailwindCss,
+                self.cfg.tool_version(Application::TailwindCss, version),
+            )
+            .await?;
 
         // Compile the target tailwind css file.
-        let style = if self.cfg.release { "--minify" } else { "" };
         let path_str = dunce::simplified(&self.asset.path).display().to_string();
         let file_name = format!("{}.css", &self.asset.file_stem.to_string_lossy());
         let file_path = dunce::simplified(&self.cfg.staging_dist.join(&file_name))
             .display()
             .to_string();
-        let args = &["--input", &path_str, "--output", &file_path, style];
+        let mut args = vec![
+            "--input".into(),
+            path_str,
+            "--output".into(),
+            file_path.clone(),
+        ];
+        if self.cfg.minify {
+            args.push("--minify".into());
+        }
+        // Tailwind v4 is configured in CSS only, & detects the files to scan on its own.
+        if is_v4_or_later(&version) {
+            if self.config.is_some() {
+                tracing::warn!(
+                    "tailwind v{} does not support a config file via the CLI, please reference it using `@config` from the CSS instead",
+                    version
+                );
+            }
+        } else {
+            match &self.config {
+                Some(config) => {
+                    args.push("--config".into());
+                    args.push(dunce::simplified(config).display().to_string());
+                }
+                // Without a config, tailwind would not know which files to scan for classes.
+                None => {
+                    args.push("--content".into());
+                    args.push(self.content_globs().await?.join(","));
+                }
+            }
+        }
 
         let rel_path = crate::common::strip_prefix(&self.asset.path);
         tracing::info!(path = ?rel_path, "compiling tailwind css");
-        common::run_command(Application::TailwindCss.name(), &tailwind, args).await?;
+        common::remove_file_if_exists(&file_path).await?;
+        let _job = self.cfg.acquire_job().await?;
+        common::run_command(Application::TailwindCss.name(), &tailwind, &args).await?;
 
         let css = fs::read_to_string(&file_path).await?;
         fs::remove_file(&file_path).await?;
 
-        // Check if the specified tailwind css file should be inlined.
-        let css_ref = if self.use_inline {
-            // Avoid writing any files, return the CSS as a String.
-            CssRef::Inline(css)
-        } else {
-            // Hash the contents to generate a file name, and then write the contents to the dist
-            // dir.
-            let hash = seahash::hash(css.as_bytes());
-            let file_name = self
-                .cfg
-                .filehash
-                .then(|| format!("{}-{:x}.css", &self.asset.file_stem.to_string_lossy(), hash))
-                .unwrap_or(file_name);
-            let file_path = self.cfg.staging_dist.join(&file_name);
-
-            // Write the generated CSS to the filesystem.
-            fs::write(&file_path, css)
-                .await
-                .context("error writing tailwind css pipeline output")?;
-
-            // Generate a hashed reference to the new CSS file.
-            CssRef::File(file_name)
-        };
+        let css_ref = CssRef::new(
+            &self.cfg,
+            &self.asset,
+            css,
+            None,
+            self.hash.as_ref(),
+            self.use_inline,
+            self.use_integrity,
+            self.use_critical,
+        )
+        .await
+        .context("error writing tailwind css pipeline output")?;
 
         tracing::info!(path = ?rel_path, "finished compiling tailwind css");
         Ok(TrunkAssetPipelineOutput::TailwindCss(TailwindCssOutput {
@@ -109,9 +183,61 @@ impl TailwindCss {
             css_ref,
         }))
     }
+
+    /// The globs of the files tailwind scans for classes, if no config is used: the source HTML
+    /// files & the Rust sources of all packages of the cargo workspace next to the HTML file, if
+    /// any.
+    async fn content_globs(&self) -> Result<Vec<String>> {
+        let mut globs = self
+            .cfg
+            .targets
+            .iter()
+            .map(|target| glob_path(&target.path))
+            .collect::<Vec<_>>();
+        let manifest = self.html_dir.join("Cargo.toml");
+        if path_exists(&manifest).await? {
+            let manifest = CargoMetadata::new(&manifest, &self.cfg.cargo_env).await?;
+            for package in manifest.metadata.workspace_packages() {
+                if let Some(dir) = package.manifest_path.parent() {
+                    globs.push(format!("{}/src/**/*.rs", glob_path(dir.as_std_path())));
+                }
+            }
+        }
+        Ok(globs)
+    }
+}
+
+/// Find a tailwind config file in the given directory.
+async fn find_config(dir: &Path) -> Result<Option<PathBuf>> {
+    for name in CONFIG_FILES {
+        let path = dir.join(name);
+        if path_exists(&path).await? {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Check if the given tailwind version is v4 or later, which has a different set of CLI flags.
+fn is_v4_or_later(version: &str) -> bool {
+    version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse::<u32>().ok())
+        .map(|major| major >= 4)
+        .unwrap_or(false)
+}
+
+/// Format a path for use in a glob, which always uses forward slashes.
+fn glob_path(path: &Path) -> String {
+    dunce::simplified(path)
+        .display()
+        .to_string()
+        .replace('\\', "/")
 }
 
 /// The output of a Tailwind CSS build pipeline.
+#[derive(Clone)]
 pub struct TailwindCssOutput {
     /// The runtime build config.
     pub cfg: Arc<RtcBuild>,
@@ -121,29 +247,24 @@ pub struct TailwindCssOutput {
     pub css_ref: CssRef,
 }
 
-/// The resulting CSS of the Tailwind CSS compilation.
-pub enum CssRef {
-    /// CSS to be inlined (for `data-inline`).
-    Inline(String),
-    /// A hashed file reference to a CSS file (default).
-    File(String),
-}
-
 impl TailwindCssOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
-        let html = match self.css_ref {
-            // Insert the inlined CSS into a `<style>` tag.
-            CssRef::Inline(css) => format!(r#"<style type="text/css">{}</style>"#, css),
-            // Link to the CSS file.
-            CssRef::File(file) => {
-                format!(
-                    r#"<link rel="stylesheet" href="{base}{file}"/>"#,
-                    base = &self.cfg.public_url,
-                )
-            }
-        };
+        let html = self.css_ref.to_html(&self.cfg, dom);
         dom.select(&super::trunk_id_selector(self.id))
             .replace_with_html(html);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_v4_or_later_checks_major_version() {
+        assert!(is_v4_or_later("4.0.0"));
+        assert!(is_v4_or_later("4.1.3"));
+        assert!(!is_v4_or_later("3.3.2"));
+        assert!(!is_v4_or_later("latest"));
+    }
+}
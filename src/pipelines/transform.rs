@@ -0,0 +1,219 @@
+//! Transforms of the built files via external commands.
+
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{bail, Context, Result};
+use glob::Pattern;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use super::asset_manifest::MANIFEST_DIR;
+use super::copy_dir::url_path;
+use crate::common;
+use crate::config::{ConfigOptsBuildTransform, RtcBuild};
+
+/// A command transforming the built files matching a glob.
+#[derive(Clone, Debug)]
+pub struct Transform {
+    /// The glob of the files the command transforms.
+    files: Pattern,
+    /// The command to run.
+    command: String,
+    /// Any arguments to pass to the command.
+    command_arguments: Vec<String>,
+}
+
+impl Transform {
+    /// Create a new instance, parsing the glob of the given config.
+    pub fn new(opts: &ConfigOptsBuildTransform) -> Result<Self> {
+        let files = Pattern::new(&opts.files)
+            .with_context(|| format!("invalid glob `{}` of transform", opts.files))?;
+        Ok(Self {
+            files,
+            command: opts.command.clone(),
+            command_arguments: opts.command_arguments.clone(),
+        })
+    }
+
+    /// Run the command on the given content of the file at `file`, relative to the dist dir,
+    /// returning the transformed content.
+    async fn run(&self, cfg: &RtcBuild, file: &str, content: Vec<u8>) -> Result<Vec<u8>> {
+        let mut child = common::command(&self.command)
+            .args(&self.command_arguments)
+            .envs(&cfg.env)
+            .env("TRUNK_TRANSFORM_FILE", file)
+            .env("TRUNK_STAGING_DIR", &cfg.staging_dist)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("error spawning transform call to {}", self.command))?;
+        let mut stdin = child.stdin.take().with_context(|| {
+            format!("error opening stdin of transform call to {}", self.command)
+        })?;
+        // The content is written concurrently to reading the output, as the command may start
+        // writing before it read all of its input.
+        let write = tokio::spawn(async move {
+            let res = stdin.write_all(&content).await;
+            // Close stdin, signaling the end of the input.
+            drop(stdin);
+            res
+        });
+        let output = child
+            .wait_with_output()
+            .await
+            .with_context(|| format!("error during transform call to {}", self.command))?;
+        write
+            .await
+            .context("error awaiting transform input")?
+            .with_context(|| {
+                format!(
+                    "error writing to stdin of transform call to {}",
+                    self.command
+                )
+            })?;
+        if !output.status.success() {
+            bail!("transform call to {} returned a bad status", self.command);
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// Transform all built files in the staging dist dir with the transforms matching them, in the
+/// order they are declared, replacing the files with the output of the commands.
+///
+/// The HTML files are transformed last, after the integrity digests of all other transformed
+/// files in them have been updated, so that subresource integrity attributes stay valid. The names
+/// of hashed files keep reflecting their untransformed content.
+pub async fn apply_transforms(cfg: &RtcBuild) -> Result<()> {
+    let html_files: Vec<_> = cfg
+        .targets
+        .iter()
+        .map(|target| url_path(Path::new(&target.output)))
+        .collect();
+    let mut files = Vec::new();
+    for path in common::list_files_recursive(&cfg.staging_dist).await? {
+        let rel_path = path.strip_prefix(&cfg.staging_dist).unwrap_or(&path);
+        // The files of the other apps of the workspace are transformed by their own builds.
+        if rel_path.starts_with(MANIFEST_DIR)
+            || cfg.apps.iter().any(|app| rel_path.starts_with(&app.path))
+        {
+            continue;
+        }
+        files.push(url_path(rel_path));
+    }
+    files.sort();
+    let (html, others): (Vec<_>, Vec<_>) = files
+        .into_iter()
+        .partition(|file| html_files.contains(file));
+
+    // The integrity digests of the transformed files, before & after transforming them.
+    let mut digests = Vec::new();
+    for file in &others {
+        if let Some((prev, content)) = transform_file(cfg, file).await? {
+            digests.push((
+                super::integrity_digest(&prev),
+                super::integrity_digest(&content),
+            ));
+        }
+    }
+    for file in &html {
+        if !digests.is_empty() {
+            let path = cfg.staging_dist.join(file);
+            let mut content = fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("error reading HTML file {:?}", &path))?;
+            for (prev, digest) in &digests {
+                content = content.replace(prev.as_str(), digest);
+            }
+            common::write_file(&path, content)
+                .await
+                .with_context(|| format!("error writing HTML file {:?}", &path))?;
+        }
+        transform_file(cfg, file).await?;
+    }
+    Ok(())
+}
+
+/// Transform the file at `file`, relative to the staging dist dir, with all transforms matching
+/// it, returning its previous & transformed content if any did.
+async fn transform_file(cfg: &RtcBuild, file: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let transforms: Vec<_> = cfg
+        .transforms
+        .iter()
+        .filter(|transform| super::glob_matches(&transform.files, file))
+        .collect();
+    if transforms.is_empty() {
+        return Ok(None);
+    }
+    let path = cfg.staging_dist.join(file);
+    let prev = fs::read(&path)
+        .await
+        .with_context(|| format!("error reading file to transform {:?}", &path))?;
+    let mut content = prev.clone();
+    for transform in transforms {
+        tracing::info!(file, "transforming file with {}", transform.command);
+        content = transform
+            .run(cfg, file, content)
+            .await
+            .with_context(|| format!("error transforming file {:?}", file))?;
+    }
+    common::write_file(&path, &content)
+        .await
+        .with_context(|| format!("error writing transformed file {:?}", &path))?;
+    Ok(Some((prev, content)))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::super::copy_dir::CopyStrategy;
+    use super::*;
+
+    #[tokio::test]
+    async fn apply_transforms_updates_integrity_digests() -> Result<()> {
+        let tmpdir = tempfile::tempdir().context("error building tempdir for test")?;
+        let mut cfg = RtcBuild::new_test(tmpdir.path()).await?;
+        cfg.transforms = vec![Transform::new(&ConfigOptsBuildTransform {
+            files: "*.js".into(),
+            command: "tr".into(),
+            command_arguments: vec!["a-z".into(), "A-Z".into()],
+        })?];
+        let digest = super::super::integrity_digest(b"let a;");
+        fs::write(cfg.staging_dist.join("app.js"), b"let a;").await?;
+        fs::write(
+            cfg.staging_dist.join("index.html"),
+            format!(r#"<script src="/app.js" integrity="{digest}"></script>"#),
+        )
+        .await?;
+
+        apply_transforms(&cfg).await?;
+
+        assert_eq!(fs::read(cfg.staging_dist.join("app.js")).await?, b"LET A;");
+        let html = fs::read_to_string(cfg.staging_dist.join("index.html")).await?;
+        assert!(html.contains(&super::super::integrity_digest(b"LET A;")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn apply_transforms_keeps_sources_of_linked_files() -> Result<()> {
+        let tmpdir = tempfile::tempdir().context("error building tempdir for test")?;
+        let mut cfg = RtcBuild::new_test(tmpdir.path()).await?;
+        cfg.transforms = vec![Transform::new(&ConfigOptsBuildTransform {
+            files: "*.js".into(),
+            command: "tr".into(),
+            command_arguments: vec!["a-z".into(), "A-Z".into()],
+        })?];
+        let source = tmpdir.path().join("app.js");
+        fs::write(&source, b"let a;").await?;
+        let path = cfg.staging_dist.join("app.js");
+        CopyStrategy::Symlink.apply(&source, &path, None).await?;
+
+        apply_transforms(&cfg).await?;
+
+        assert_eq!(fs::read(&source).await?, b"let a;");
+        assert!(!fs::symlink_metadata(&path).await?.is_symlink());
+        assert_eq!(fs::read(&path).await?, b"LET A;");
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+#!/usr/bin/env node
+// Process a CSS file with the postcss package downloaded by trunk, supporting the subset of the
+// postcss-cli arguments used by trunk: `postcss <input> --output <output> [--no-map]
+// [--config <file or dir>]`, or `postcss --version`.
+//
+// Without `--config`, the config is looked up from the dir of the input file upwards, as
+// `package.json` (its `postcss` key), `.postcssrc`, `.postcssrc.json` or `postcss.config.js`,
+// `.postcssrc.js` (& their `.cjs` & `.mjs` variants). Plugins, parsers, syntaxes & stringifiers
+// given by name are resolved relative to the config file.
+'use strict';
+
+const fs = require('fs');
+const path = require('path');
+const { createRequire } = require('module');
+const { pathToFileURL } = require('url');
+
+const postcss = require('postcss');
+
+const CONFIG_FILES = [
+    'package.json',
+    '.postcssrc',
+    '.postcssrc.json',
+    '.postcssrc.js',
+    '.postcssrc.cjs',
+    '.postcssrc.mjs',
+    'postcss.config.js',
+    'postcss.config.cjs',
+    'postcss.config.mjs',
+];
+
+function parseArgs(argv) {
+    const args = { map: true };
+    for (let i = 0; i < argv.length; i++) {
+        switch (argv[i]) {
+            case '--version':
+                args.version = true;
+                break;
+            case '-o':
+            case '--output':
+                args.output = argv[++i];
+                break;
+            case '--config':
+                args.config = argv[++i];
+                break;
+            case '--no-map':
+                args.map = false;
+                break;
+            default:
+                if (argv[i].startsWith('-') || args.input) {
+                    throw new Error(`unsupported argument ${argv[i]}`);
+                }
+                args.input = argv[i];
+        }
+    }
+    return args;
+}
+
+// The config file below the given dir or its parents, which has a `postcss` key if a package.json.
+function findConfig(dir) {
+    for (;;) {
+        for (const name of CONFIG_FILES) {
+            const file = path.join(dir, name);
+            if (!fs.existsSync(file)) {
+                continue;
+            }
+            if (name !== 'package.json' || JSON.parse(fs.readFileSync(file, 'utf8')).postcss) {
+                return file;
+            }
+        }
+        const parent = path.dirname(dir);
+        if (parent === dir) {
+            return null;
+        }
+        dir = parent;
+    }
+}
+
+async function loadConfig(file, ctx) {
+    let config;
+    if (path.basename(file) === 'package.json') {
+        config = JSON.parse(fs.readFileSync(file, 'utf8')).postcss;
+    } else if (['.postcssrc', '.postcssrc.json'].includes(path.basename(file))) {
+        config = JSON.parse(fs.readFileSync(file, 'utf8'));
+    } else {
+        const module = await import(pathToFileURL(file).href);
+        config = module.default === undefined ? module : module.default;
+    }
+    if (typeof config === 'function') {
+        config = await config(ctx);
+    }
+    return config || {};
+}
+
+// The plugins of the given config, either an array of plugins or an object of plugin options by
+// plugin name, where `false` options disable a plugin.
+function loadPlugins(config, req) {
+    const plugins = config.plugins || [];
+    if (Array.isArray(plugins)) {
+        return plugins.filter(Boolean).map((plugin) => typeof plugin === 'string' ? req(plugin) : plugin);
+    }
+    return Object.entries(plugins)
+        .filter(([, options]) => options !== false)
+        .map(([name, options]) => {
+            const plugin = req(name);
+            const empty = !options || Object.keys(options).length === 0;
+            return empty ? plugin() : plugin(options);
+        });
+}
+
+async function main() {
+    const args = parseArgs(process.argv.slice(2));
+    if (args.version) {
+        console.log(require('postcss/package.json').version);
+        return;
+    }
+    if (!args.input || !args.output) {
+        throw new Error('usage: postcss <input> --output <output> [--no-map] [--config <path>]');
+    }
+
+    const input = path.resolve(args.input);
+    const output = path.resolve(args.output);
+    const ctx = {
+        env: process.env.NODE_ENV || 'development',
+        file: {
+            dirname: path.dirname(input),
+            basename: path.basename(input),
+            extname: path.extname(input),
+        },
+        options: { map: args.map },
+    };
+    let file = args.config ? path.resolve(args.config) : path.dirname(input);
+    if (fs.statSync(file).isDirectory()) {
+        file = findConfig(file);
+    }
+    const config = file ? await loadConfig(file, ctx) : {};
+    const req = createRequire(file || input);
+    const resolve = (option) => typeof option === 'string' ? req(option) : option;
+
+    const css = fs.readFileSync(input, 'utf8');
+    const result = await postcss(loadPlugins(config, req)).process(css, {
+        from: input,
+        to: output,
+        map: args.map ? config.map : false,
+        parser: resolve(config.parser),
+        syntax: resolve(config.syntax),
+        stringifier: resolve(config.stringifier),
+    });
+    for (const warning of result.warnings()) {
+        console.warn(warning.toString());
+    }
+    fs.mkdirSync(path.dirname(output), { recursive: true });
+    fs.writeFileSync(output, result.css);
+}
+
+main().catch((error) => {
+    console.error(error.name === 'CssSyntaxError' ? error.toString() : error.stack || error);
+    process.exit(1);
+});
@@ -0,0 +1,94 @@
+//! Structured progress of builds, as rendered by the CLI & available to library consumers.
+//!
+//! All builds of the process report their progress to a single channel, which any number of
+//! receivers may subscribe to via [`subscribe`]. Events are dropped if there are no receivers,
+//! and receivers lagging behind skip the oldest events.
+//!
+//! The output of the commands run by builds is passed through to stderr, unless redirected via
+//! [`set_output`], such as to keep the output of a progress UI intact.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+use crate::pipelines::ManifestEntry;
+
+/// The number of events buffered for each receiver.
+const CAPACITY: usize = 256;
+
+/// The channel of all progress events.
+static EVENTS: Lazy<broadcast::Sender<ProgressEvent>> =
+    Lazy::new(|| broadcast::channel(CAPACITY).0);
+
+/// A writer of the output of commands.
+type Output = Box<dyn Fn(&[u8]) + Send + Sync>;
+
+/// The writer of the output of commands, if redirected.
+static OUTPUT: RwLock<Option<Output>> = RwLock::new(None);
+
+/// A progress event of a build.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// A build started.
+    BuildStarted,
+    /// A build reached the stage described by the message.
+    Stage(String),
+    /// The pipeline of an asset started, such as `sass index.scss`.
+    PipelineStarted { id: usize, asset: String },
+    /// The pipeline of an asset finished, successfully or not.
+    PipelineFinished { id: usize, asset: String },
+    /// Bytes of a tool were downloaded, out of the total size if known.
+    Download {
+        tool: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// A diagnostic of the compiler, such as a warning of rustc, along with its rendered text &
+    /// its primary location, if any.
+    Diagnostic {
+        level: String,
+        message: String,
+        rendered: String,
+        file: Option<String>,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    /// A build finished, writing the files of the given asset manifest to the dist dir if it
+    /// succeeded.
+    BuildFinished {
+        success: bool,
+        duration: Duration,
+        artifacts: BTreeMap<String, ManifestEntry>,
+    },
+}
+
+/// Subscribe to the progress events of all builds of the process.
+pub fn subscribe() -> broadcast::Receiver<ProgressEvent> {
+    EVENTS.subscribe()
+}
+
+/// Send the given progress event to all subscribers.
+pub(crate) fn send(event: ProgressEvent) {
+    // An error only means there are no subscribers.
+    let _ = EVENTS.send(event);
+}
+
+/// Redirect the output of the commands run by builds, which is written to stderr by default.
+pub fn set_output(output: impl Fn(&[u8]) + Send + Sync + 'static) {
+    *OUTPUT.write().unwrap_or_else(|err| err.into_inner()) = Some(Box::new(output));
+}
+
+/// Write the given output of a command.
+pub(crate) fn write_output(bytes: &[u8]) {
+    match &*OUTPUT.read().unwrap_or_else(|err| err.into_inner()) {
+        Some(output) => output(bytes),
+        // Errors writing to the terminal are ignored, as they are of no concern to the command.
+        None => {
+            let _ = std::io::stderr().write_all(bytes);
+        }
+    }
+}
@@ -1,31 +1,258 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
 use axum::body::Body;
-use axum::extract::ws::{Message as MsgAxm, WebSocket, WebSocketUpgrade};
-use axum::extract::State;
-use axum::http::{Request, Response, Uri};
+use axum::extract::ws::{close_code, Message as MsgAxm, WebSocket, WebSocketUpgrade};
+use axum::extract::{OriginalUri, State};
+use axum::http::header::{
+    HeaderName, CONNECTION, HOST, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY,
+    SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use axum::http::{HeaderMap, Request, Response, StatusCode, Uri};
+use axum::middleware;
+use axum::response::IntoResponse;
 use axum::routing::{any, get, Router};
 use axum::RequestExt;
+use bytes::BytesMut;
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
+use http_body::Body as _;
+use regex::Regex;
 use reqwest::header::HeaderValue;
-use tokio_tungstenite::connect_async;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request as ClientRequest;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use tokio_tungstenite::tungstenite::protocol::CloseFrame;
-use tokio_tungstenite::tungstenite::Message as MsgTng;
+use tokio_tungstenite::tungstenite::{Error as TngError, Message as MsgTng};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use tower_http::trace::TraceLayer;
 
+use crate::config::ConfigOptsProxy;
+use crate::request_log::Proxied;
 use crate::serve::ServerResult;
 
+/// A proxy of the dev server, as declared via CLI or a `[[proxy]]` section of the config.
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    /// An optional name of the proxy, used in its logs & errors.
+    pub name: Option<String>,
+    /// The URL of the backend to which requests are to be proxied.
+    pub backend: Uri,
+    /// An optional rewrite of the paths of the requests.
+    pub rewrite: Option<Rewrite>,
+    /// Whether the proxy is for WebSockets.
+    pub ws: bool,
+    /// Whether to accept insecure certificates of the backend.
+    pub insecure: bool,
+    /// The edits of the headers of the requests to the backend.
+    pub request_headers: HeaderEdits,
+    /// The edits of the headers of the responses of the backend.
+    pub response_headers: HeaderEdits,
+    /// The time to wait for a connection to the backend.
+    pub connect_timeout: Option<Duration>,
+    /// The time to wait for the backend to respond to an HTTP request.
+    pub read_timeout: Option<Duration>,
+    /// The number of times to retry requests which failed to connect to the backend.
+    pub retries: u32,
+    /// The time to wait before retrying a request.
+    pub retry_delay: Duration,
+}
+
+/// The default time to wait before retrying a proxy request.
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+impl Proxy {
+    /// Create a new instance, parsing the rewrite & headers of the given config.
+    pub fn new(opts: &ConfigOptsProxy) -> anyhow::Result<Self> {
+        Self::from_opts(opts).with_context(|| match &opts.name {
+            Some(name) => format!("invalid config of proxy {:?}", name),
+            None => format!("invalid config of proxy to {}", opts.backend),
+        })
+    }
+
+    fn from_opts(opts: &ConfigOptsProxy) -> anyhow::Result<Self> {
+        let rewrite = opts.rewrite.as_deref().map(Rewrite::parse).transpose()?;
+        let request_headers = HeaderEdits::new(&opts.request_headers, &opts.remove_request_headers)
+            .context("invalid request headers")?;
+        let response_headers =
+            HeaderEdits::new(&opts.response_headers, &opts.remove_response_headers)
+                .context("invalid response headers")?;
+        Ok(Self {
+            name: opts.name.clone(),
+            backend: opts.backend.clone(),
+            rewrite,
+            ws: opts.ws,
+            insecure: opts.insecure,
+            request_headers,
+            response_headers,
+            connect_timeout: opts.connect_timeout,
+            read_timeout: opts.read_timeout,
+            retries: opts.retries,
+            retry_delay: opts.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY),
+        })
+    }
+
+    /// The path which this proxy listens at.
+    pub fn path(&self) -> &str {
+        match &self.rewrite {
+            Some(Rewrite::Prefix(path)) | Some(Rewrite::Regex { path, .. }) => path,
+            None => self.backend.path(),
+        }
+    }
+
+    /// Build the URI of the backend for the given request, where `request` is the URI of the
+    /// request with the path of the proxy stripped & `original` is its full URI.
+    fn outbound_uri(&self, request: &Uri, original: &Uri) -> anyhow::Result<Uri> {
+        let Some(Rewrite::Regex {
+            regex, replacement, ..
+        }) = &self.rewrite
+        else {
+            return make_outbound_uri(&self.backend, request);
+        };
+        let path = regex.replace(original.path(), replacement.as_str());
+        let path_and_query = match original.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.into_owned(),
+        };
+        let request = Uri::try_from(path_and_query.as_str()).with_context(|| {
+            format!(
+                "error building rewritten proxy request path {:?}",
+                path_and_query
+            )
+        })?;
+        make_outbound_uri(&self.backend, &request)
+    }
+}
+
+impl fmt::Display for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "proxy {:?} to {}", name, self.backend),
+            None => write!(f, "proxy to {}", self.backend),
+        }
+    }
+}
+
+/// A rewrite of the paths of the requests of a proxy.
+#[derive(Clone, Debug)]
+pub enum Rewrite {
+    /// The proxy listens at the given path prefix, which is replaced with the path of the backend.
+    Prefix(String),
+    /// The part of the request paths matching the regex is replaced with the replacement, which
+    /// may refer to its capture groups, before being sent to the backend.
+    Regex {
+        /// The path prefix which the proxy listens at, being the literal start of the regex.
+        path: String,
+        /// The regex matching the request paths.
+        regex: Regex,
+        /// The replacement of the matches.
+        replacement: String,
+    },
+}
+
+/// The separator of the regex & the replacement of a regex path rewrite.
+const REGEX_REWRITE_SEPARATOR: &str = "->";
+
+impl Rewrite {
+    /// Parse the given rewrite, which is a regex path rewrite if of the form
+    /// `regex -> replacement` & a path prefix otherwise.
+    pub fn parse(rewrite: &str) -> anyhow::Result<Self> {
+        let Some((pattern, replacement)) = rewrite.split_once(REGEX_REWRITE_SEPARATOR) else {
+            return Ok(Self::Prefix(rewrite.to_owned()));
+        };
+        let (pattern, replacement) = (pattern.trim(), replacement.trim());
+        let regex = Regex::new(pattern)
+            .with_context(|| format!("invalid regex `{}` of proxy rewrite", pattern))?;
+        let path = literal_prefix(pattern).with_context(|| {
+            format!(
+                "the regex `{}` of proxy rewrite must match from the start of the path, as in `^/api`",
+                pattern
+            )
+        })?;
+        Ok(Self::Regex {
+            path,
+            regex,
+            replacement: replacement.to_owned(),
+        })
+    }
+}
+
+/// The longest path prefix which all paths matched by the given regex start with, ending at a
+/// complete path segment, or `None` if the regex is not anchored at the start of the path.
+fn literal_prefix(pattern: &str) -> Option<String> {
+    let pattern = pattern.strip_prefix("^/")?;
+    let end = pattern
+        .find(|c| "\\.+*?()|[]{}^$".contains(c))
+        .unwrap_or(pattern.len());
+    let (literal, rest) = pattern.split_at(end);
+    if rest.is_empty() || rest == "$" {
+        return Some(format!("/{}", literal));
+    }
+    // The last char of the literal may be optional.
+    let literal = match rest.starts_with(['?', '*', '{']) {
+        true => &literal[..literal.len() - literal.chars().last().map_or(0, char::len_utf8)],
+        false => literal,
+    };
+    Some(match literal.rfind('/') {
+        Some(idx) => format!("/{}", &literal[..=idx]),
+        None => "/".to_owned(),
+    })
+}
+
+/// Headers to set on & remove from the requests or the responses of a proxy.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderEdits {
+    /// The headers to set, replacing any of the same name.
+    set: HeaderMap,
+    /// The headers to remove.
+    remove: Vec<HeaderName>,
+}
+
+impl HeaderEdits {
+    /// Create a new instance, parsing the given header names & values.
+    fn new(set: &HashMap<String, String>, remove: &[String]) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        for (name, val) in set {
+            let name = HeaderName::try_from(name.as_str())
+                .with_context(|| format!("invalid header name {:?}", name))?;
+            let val = HeaderValue::try_from(val.as_str())
+                .with_context(|| format!("invalid value of header {:?}", name))?;
+            headers.insert(name, val);
+        }
+        let remove = remove
+            .iter()
+            .map(|name| {
+                HeaderName::try_from(name.as_str())
+                    .with_context(|| format!("invalid header name {:?}", name))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            set: headers,
+            remove,
+        })
+    }
+
+    /// Apply the edits to the given headers.
+    fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, val) in &self.set {
+            headers.insert(name.clone(), val.clone());
+        }
+    }
+}
+
 /// A handler used for proxying HTTP requests to a backend.
 pub(crate) struct ProxyHandlerHttp {
-    /// The client to use for proxy logic.
+    /// The client to use for proxy logic, configured for the proxy.
     client: reqwest::Client,
-    /// The URL of the backend to which requests are to be proxied.
-    backend: Uri,
-    /// An optional rewrite path to be used as the listening URI prefix, but which will be
-    /// stripped before being sent to the proxy backend.
-    rewrite: Option<String>,
+    /// The proxy to handle requests for.
+    proxy: Proxy,
 }
 
 fn make_outbound_uri(backend: &Uri, request: &Uri) -> anyhow::Result<Uri> {
@@ -66,13 +293,53 @@ fn make_outbound_uri(backend: &Uri, request: &Uri) -> anyhow::Result<Uri> {
 }
 
 impl ProxyHandlerHttp {
-    /// Construct a new instance.
-    pub fn new(client: reqwest::Client, backend: Uri, rewrite: Option<String>) -> Arc<Self> {
-        Arc::new(Self {
-            client,
-            backend,
-            rewrite,
-        })
+    /// Construct a new instance, building a client for the given proxy.
+    pub fn new(proxy: Proxy) -> anyhow::Result<Arc<Self>> {
+        let mut client = reqwest::ClientBuilder::new()
+            .http1_only()
+            .danger_accept_invalid_certs(proxy.insecure);
+        if let Some(timeout) = proxy.connect_timeout {
+            client = client.connect_timeout(timeout);
+        }
+        let client = client
+            .build()
+            .with_context(|| format!("error building client of {}", proxy))?;
+        Ok(Arc::new(Self { client, proxy }))
+    }
+
+    /// Send the given request to the backend, retrying it as configured if connecting to the
+    /// backend fails, or return `None` if the backend did not respond in time.
+    async fn send(&self, mut req: reqwest::Request) -> anyhow::Result<Option<reqwest::Response>> {
+        let mut attempt = 0;
+        loop {
+            // Only requests with a buffered body can be cloned for retries.
+            let retry = match attempt < self.proxy.retries {
+                true => req.try_clone(),
+                false => None,
+            };
+            let res = match self.proxy.read_timeout {
+                Some(timeout) => {
+                    match tokio::time::timeout(timeout, self.client.execute(req)).await {
+                        Ok(res) => res,
+                        Err(_) => return Ok(None),
+                    }
+                }
+                None => self.client.execute(req).await,
+            };
+            match (res, retry) {
+                (Err(err), Some(retry)) if err.is_connect() => {
+                    attempt += 1;
+                    tracing::warn!(error = ?err, "error connecting to backend of {}, retrying ({}/{})", self.proxy, attempt, self.proxy.retries);
+                    tokio::time::sleep(self.proxy.retry_delay).await;
+                    req = retry;
+                }
+                (res, _) => {
+                    return res
+                        .map(Some)
+                        .with_context(|| format!("error proxying request with {}", self.proxy))
+                }
+            }
+        }
     }
 
     /// Build the sub-router for this proxy.
@@ -81,15 +348,17 @@ impl ProxyHandlerHttp {
             self.path(),
             any(Self::proxy_http_request)
                 .layer(TraceLayer::new_for_http())
+                .layer(middleware::map_response_with_state(
+                    self.proxy.backend.clone(),
+                    mark_proxied,
+                ))
                 .with_state(self.clone()),
         )
     }
 
     /// The path which this proxy backend listens at.
     pub fn path(&self) -> &str {
-        self.rewrite
-            .as_deref()
-            .unwrap_or_else(|| self.backend.path())
+        self.proxy.path()
     }
 
     /// Proxy the given request to the target backend.
@@ -99,30 +368,58 @@ impl ProxyHandlerHttp {
         req: Request<Body>,
     ) -> ServerResult<Response<Body>> {
         // Construct the outbound URI & build a new request to be sent to the proxy backend.
-        let outbound_uri = make_outbound_uri(&state.backend, req.uri())?;
-        let mut outbound_req = state
+        let outbound_uri = state.proxy.outbound_uri(req.uri(), &original_uri(&req))?;
+        let outbound_req = state
             .client
             .request(req.method().clone(), outbound_uri.to_string())
-            .headers(req.headers().clone())
-            .body(req.into_body())
+            .headers(req.headers().clone());
+        // The body is buffered if the request may be retried, streaming it otherwise.
+        let outbound_req = match state.proxy.retries {
+            0 => outbound_req.body(req.into_body()),
+            _ => {
+                let mut body = req.into_body();
+                let mut buf = BytesMut::new();
+                while let Some(chunk) = body.data().await {
+                    buf.extend_from_slice(&chunk.context("error reading request body")?);
+                }
+                outbound_req.body(buf.freeze())
+            }
+        };
+        let mut outbound_req = outbound_req
             .build()
             .context("error building outbound request to proxy backend")?;
 
         // Ensure the host header is set to target the backend.
-        if let Some(host) = state.backend.authority().map(|authority| authority.host()) {
+        if let Some(host) = state
+            .proxy
+            .backend
+            .authority()
+            .map(|authority| authority.host())
+        {
             if let Ok(host) = HeaderValue::from_str(host) {
                 outbound_req.headers_mut().insert("host", host);
             }
         }
+        state
+            .proxy
+            .request_headers
+            .apply(outbound_req.headers_mut());
 
         // Send the request & unpack the response.
-        let backend_res = state
-            .client
-            .execute(outbound_req)
-            .await
-            .context("error proxying request to proxy backend")?;
+        let Some(backend_res) = state.send(outbound_req).await? else {
+            tracing::error!(
+                "{} timed out waiting for the backend to respond",
+                state.proxy
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::GATEWAY_TIMEOUT)
+                .body(Body::empty())
+                .context("error building proxy response")?);
+        };
         let mut res = Response::builder().status(backend_res.status());
-        for (key, val) in backend_res.headers() {
+        let mut headers = backend_res.headers().clone();
+        state.proxy.response_headers.apply(&mut headers);
+        for (key, val) in &headers {
             res = res.header(key, val);
         }
 
@@ -132,19 +429,25 @@ impl ProxyHandlerHttp {
     }
 }
 
+/// Mark the response as proxied from the given backend, for the request log.
+async fn mark_proxied(
+    State(backend): State<Uri>,
+    mut res: axum::response::Response,
+) -> axum::response::Response {
+    res.extensions_mut().insert(Proxied(backend));
+    res
+}
+
 /// A handler used for proxying WebSockets to a backend.
 pub struct ProxyHandlerWebSocket {
-    /// The URL of the backend to which requests are to be proxied.
-    backend: Uri,
-    /// An optional rewrite path to be used as the listening URI prefix, but which will be
-    /// stripped before being sent to the proxy backend.
-    rewrite: Option<String>,
+    /// The proxy to handle connections for.
+    proxy: Proxy,
 }
 
 impl ProxyHandlerWebSocket {
     /// Construct a new instance.
-    pub fn new(backend: Uri, rewrite: Option<String>) -> Arc<Self> {
-        Arc::new(Self { backend, rewrite })
+    pub fn new(proxy: Proxy) -> Arc<Self> {
+        Arc::new(Self { proxy })
     }
 
     /// Build the sub-router for this proxy.
@@ -154,108 +457,263 @@ impl ProxyHandlerWebSocket {
             self.path(),
             get(|req: Request<Body>| async move {
                 let uri = req.uri().clone();
-                let ws = req.extract::<WebSocketUpgrade, _>().await;
-                ws.map(|e| {
-                    e.on_upgrade(|socket| async move {
-                        proxy.clone().proxy_ws_request(socket, uri).await
-                    })
-                })
-            }),
+                let original_uri = original_uri(&req);
+                let headers = req.headers().clone();
+                let ws = match req.extract::<WebSocketUpgrade, _>().await {
+                    Ok(ws) => ws,
+                    Err(rejection) => return rejection.into_response(),
+                };
+                // The backend connection is established before accepting the upgrade, so that the
+                // subprotocol chosen by the backend can be passed on & failures are reported.
+                let (backend, protocol) = match proxy.connect_backend(&uri, &original_uri, &headers).await {
+                    Ok(backend) => backend,
+                    Err(err) => {
+                        tracing::error!(error = ?err, "error establishing WebSocket connection to backend for proxy");
+                        return (StatusCode::BAD_GATEWAY, format!("{:#}", err)).into_response();
+                    }
+                };
+                let ws = match protocol {
+                    Some(protocol) => ws.protocols([protocol]),
+                    None => ws,
+                };
+                let mut res = ws
+                    .on_upgrade(|socket| async move { proxy_ws_request(socket, backend).await })
+                    .into_response();
+                proxy.proxy.response_headers.apply(res.headers_mut());
+                res
+            })
+            .layer(middleware::map_response_with_state(
+                self.proxy.backend.clone(),
+                mark_proxied,
+            )),
         )
     }
 
     /// The path which this proxy backend listens at.
     pub fn path(&self) -> &str {
-        self.rewrite
-            .as_deref()
-            .unwrap_or_else(|| self.backend.path())
+        self.proxy.path()
     }
 
-    /// Proxy the given WebSocket request to the target backend.
-    #[tracing::instrument(level = "debug", skip(self, ws))]
-    async fn proxy_ws_request(self: Arc<Self>, ws: WebSocket, request_uri: Uri) {
-        tracing::debug!("new websocket connection");
-
-        // Build where request will be forwarded
-        let outbound_uri = match make_outbound_uri(&self.backend, &request_uri) {
-            Ok(outbound_uri) => outbound_uri,
-            Err(err) => {
-                tracing::error!(error = ?err, "failed to build proxy uri from {:?}", &request_uri);
-                return;
+    /// Establish a WebSocket connection to the backend for the given request, returning it along
+    /// with the subprotocol chosen by the backend, if any.
+    async fn connect_backend(
+        &self,
+        request_uri: &Uri,
+        original_uri: &Uri,
+        headers: &HeaderMap,
+    ) -> anyhow::Result<(BackendSocket, Option<String>)> {
+        let outbound_uri = self.proxy.outbound_uri(request_uri, original_uri)?;
+        let mut attempt = 0;
+        let (backend, res) = loop {
+            let outbound_req =
+                backend_request(outbound_uri.clone(), headers, &self.proxy.request_headers)?;
+            let connect = connect_async(outbound_req);
+            let res = match self.proxy.connect_timeout {
+                Some(timeout) => {
+                    tokio::time::timeout(timeout, connect)
+                        .await
+                        .with_context(|| {
+                            format!(
+                                "timed out connecting to WebSocket backend {:?} of {}",
+                                &outbound_uri, self.proxy
+                            )
+                        })?
+                }
+                None => connect.await,
+            };
+            match res {
+                // Only failures to connect are retried, rather than rejected handshakes.
+                Err(TngError::Io(err)) if attempt < self.proxy.retries => {
+                    attempt += 1;
+                    tracing::warn!(error = ?err, "error connecting to backend of {}, retrying ({}/{})", self.proxy, attempt, self.proxy.retries);
+                    tokio::time::sleep(self.proxy.retry_delay).await;
+                }
+                res => {
+                    break res.with_context(|| {
+                        format!(
+                            "error establishing WebSocket connection to backend {:?} of {}",
+                            &outbound_uri, self.proxy
+                        )
+                    })?
+                }
             }
         };
+        let protocol = res
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|protocol| protocol.to_str().ok())
+            .map(ToOwned::to_owned);
+        Ok((backend, protocol))
+    }
+}
 
-        // Establish WS connection to backend.
-        let (backend, _res) = match connect_async(outbound_uri.clone()).await {
-            Ok(backend) => backend,
-            Err(err) => {
-                tracing::error!(error = ?err, "error establishing WebSocket connection to backend {:?} for proxy", &outbound_uri);
-                return;
-            }
-        };
-        let (mut backend_sink, mut backend_stream) = backend.split();
-        let (mut frontend_sink, mut frontend_stream) = ws.split();
-
-        // Stream frontend messages to backend.
-        let stream_to_backend = async move {
-            while let Some(Ok(msg_axm)) = frontend_stream.next().await {
-                let msg_tng = match msg_axm {
-                    MsgAxm::Text(msg) => MsgTng::Text(msg),
-                    MsgAxm::Binary(msg) => MsgTng::Binary(msg),
-                    MsgAxm::Ping(msg) => MsgTng::Ping(msg),
-                    MsgAxm::Pong(msg) => MsgTng::Pong(msg),
-                    MsgAxm::Close(Some(close_frame)) => MsgTng::Close(Some(CloseFrame {
-                        code: close_frame.code.into(),
-                        reason: close_frame.reason,
-                    })),
-                    MsgAxm::Close(None) => MsgTng::Close(None),
-                };
+/// A WebSocket connection to a proxy backend.
+type BackendSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// The headers of the WebSocket handshake, which are generated for the backend connection rather
+/// than passed on from the frontend.
+const HANDSHAKE_HEADERS: &[HeaderName] = &[
+    HOST,
+    CONNECTION,
+    UPGRADE,
+    SEC_WEBSOCKET_KEY,
+    SEC_WEBSOCKET_VERSION,
+    // Extensions, such as compression, are negotiated by each connection on its own.
+    SEC_WEBSOCKET_EXTENSIONS,
+];
+
+/// Build the handshake request for the backend connection, passing on all headers of the frontend
+/// request except for those of its own handshake, then applying the given edits.
+///
+/// Along with headers such as cookies, this passes on the subprotocols requested by the frontend.
+fn backend_request(
+    outbound_uri: Uri,
+    headers: &HeaderMap,
+    edits: &HeaderEdits,
+) -> anyhow::Result<ClientRequest> {
+    let mut req = outbound_uri
+        .into_client_request()
+        .context("error building WebSocket request to backend")?;
+    for (name, val) in headers {
+        if !HANDSHAKE_HEADERS.contains(name) {
+            req.headers_mut().append(name.clone(), val.clone());
+        }
+    }
+    edits.apply(req.headers_mut());
+    Ok(req)
+}
 
-                if let Err(err) = backend_sink.send(msg_tng).await {
+/// The full URI of the given request, before the path of the proxy was stripped by the router.
+fn original_uri(req: &Request<Body>) -> Uri {
+    req.extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| req.uri().clone())
+}
+
+/// Proxy the messages of the given WebSocket connections in both directions.
+///
+/// Close frames are passed on in both directions, so that the closing handshake of the frontend
+/// and the backend is completed. If either connection ends without one, the other one is closed.
+#[tracing::instrument(level = "debug", skip(ws, backend))]
+async fn proxy_ws_request(ws: WebSocket, backend: BackendSocket) {
+    tracing::debug!("new websocket connection");
+
+    let (mut backend_sink, mut backend_stream) = backend.split();
+    let (mut frontend_sink, mut frontend_stream) = ws.split();
+
+    // Stream frontend messages to backend.
+    let stream_to_backend = async move {
+        while let Some(Ok(msg_axm)) = frontend_stream.next().await {
+            let msg_tng = match msg_axm {
+                MsgAxm::Text(msg) => MsgTng::Text(msg),
+                MsgAxm::Binary(msg) => MsgTng::Binary(msg),
+                MsgAxm::Ping(msg) => MsgTng::Ping(msg),
+                MsgAxm::Pong(msg) => MsgTng::Pong(msg),
+                MsgAxm::Close(Some(close_frame)) => MsgTng::Close(Some(CloseFrame {
+                    code: close_frame.code.into(),
+                    reason: close_frame.reason,
+                })),
+                MsgAxm::Close(None) => MsgTng::Close(None),
+            };
+            let is_close = matches!(msg_tng, MsgTng::Close(_));
+
+            if let Err(err) = backend_sink.send(msg_tng).await {
+                // The connection may already be closed by an automatic reply to a close frame.
+                if !is_close {
                     tracing::error!(error = ?err, "error forwarding frontend WebSocket message to backend");
-                    return;
                 }
+                return;
             }
-        };
-
-        // Stream backend messages to frontend.
-        let stream_to_frontend = async move {
-            while let Some(Ok(msg)) = backend_stream.next().await {
-                let msg_axm = match msg {
-                    MsgTng::Binary(val) => MsgAxm::Binary(val),
-                    MsgTng::Text(val) => MsgAxm::Text(val),
-                    MsgTng::Ping(val) => MsgAxm::Ping(val),
-                    MsgTng::Pong(val) => MsgAxm::Pong(val),
-                    MsgTng::Close(Some(frame)) => {
-                        MsgAxm::Close(Some(axum::extract::ws::CloseFrame {
-                            code: frame.code.into(),
-                            reason: frame.reason,
-                        }))
-                    }
-                    MsgTng::Close(None) => MsgAxm::Close(None),
-                    MsgTng::Frame(_) => continue,
-                };
-                if let Err(err) = frontend_sink.send(msg_axm).await {
+            if is_close {
+                return;
+            }
+        }
+        // The frontend went away without closing the connection.
+        let _ = backend_sink
+            .send(MsgTng::Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: "frontend went away".into(),
+            })))
+            .await;
+    };
+
+    // Stream backend messages to frontend.
+    let stream_to_frontend = async move {
+        while let Some(Ok(msg)) = backend_stream.next().await {
+            let msg_axm = match msg {
+                MsgTng::Binary(val) => MsgAxm::Binary(val),
+                MsgTng::Text(val) => MsgAxm::Text(val),
+                MsgTng::Ping(val) => MsgAxm::Ping(val),
+                MsgTng::Pong(val) => MsgAxm::Pong(val),
+                MsgTng::Close(Some(frame)) => MsgAxm::Close(Some(axum::extract::ws::CloseFrame {
+                    code: frame.code.into(),
+                    reason: frame.reason,
+                })),
+                MsgTng::Close(None) => MsgAxm::Close(None),
+                MsgTng::Frame(_) => continue,
+            };
+            let is_close = matches!(msg_axm, MsgAxm::Close(_));
+            if let Err(err) = frontend_sink.send(msg_axm).await {
+                // The connection may already be closed by an automatic reply to a close frame.
+                if !is_close {
                     tracing::error!(error = ?err, "error forwarding backend WebSocket message to frontend");
-                    return;
                 }
+                return;
             }
-        };
-
-        tokio::select! {
-            _ = stream_to_backend => (),
-            _ = stream_to_frontend => ()
-        };
-
-        tracing::debug!("websocket connection closed");
-    }
+            if is_close {
+                return;
+            }
+        }
+        // The backend went away without closing the connection.
+        let _ = frontend_sink
+            .send(MsgAxm::Close(Some(axum::extract::ws::CloseFrame {
+                code: close_code::ERROR,
+                reason: "backend went away".into(),
+            })))
+            .await;
+    };
+
+    // Both directions run until their close frames were passed on, completing the closing
+    // handshake.
+    tokio::join!(stream_to_backend, stream_to_frontend);
+
+    tracing::debug!("websocket connection closed");
 }
 
 #[cfg(test)]
 mod tests {
-    use axum::http::Uri;
+    use std::time::Duration;
+
+    use axum::http::{header, HeaderMap, HeaderValue, Uri};
 
-    use crate::proxy::make_outbound_uri;
+    use crate::proxy::{backend_request, make_outbound_uri, HeaderEdits, Proxy, Rewrite};
+
+    #[test]
+    fn backend_request_forwards_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("localhost:8080"));
+        headers.insert(
+            header::SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static("permessage-deflate"),
+        );
+        headers.insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("graphql-ws"),
+        );
+        headers.insert(header::COOKIE, HeaderValue::from_static("session=1"));
+        let req = backend_request(
+            Uri::from_static("ws://backend:9000/ws"),
+            &headers,
+            &HeaderEdits::default(),
+        )
+        .expect("Unexpected error");
+        let headers = req.headers();
+        assert_eq!(headers[header::HOST], "backend:9000");
+        assert!(!headers.contains_key(header::SEC_WEBSOCKET_EXTENSIONS));
+        assert_eq!(headers[header::SEC_WEBSOCKET_PROTOCOL], "graphql-ws");
+        assert_eq!(headers[header::COOKIE], "session=1");
+    }
 
     #[test]
     fn make_outbound_uri_two_base_paths() {
@@ -316,4 +774,73 @@ mod tests {
             Uri::from_static("https://backend/sub/auth")
         )
     }
+
+    #[test]
+    fn rewrite_regex_listens_at_literal_prefix() {
+        for (rewrite, path) in [
+            ("^/api/v1 -> /v1", "/api/v1"),
+            ("^/api/v(\\d+)/ -> /$1/", "/api/"),
+            ("^/api/?(.*) -> /$1", "/"),
+            ("^/health$ -> /status", "/health"),
+        ] {
+            match Rewrite::parse(rewrite).expect("Unexpected error") {
+                Rewrite::Regex { path: prefix, .. } => assert_eq!(prefix, path, "{}", rewrite),
+                rewrite => panic!("unexpected rewrite {:?}", rewrite),
+            }
+        }
+        assert!(Rewrite::parse("/api/v1 -> /v1").is_err());
+        assert!(matches!(Rewrite::parse("/api/"), Ok(Rewrite::Prefix(path)) if path == "/api/"));
+    }
+
+    #[test]
+    fn outbound_uri_regex_rewrite() {
+        let proxy = Proxy {
+            name: None,
+            backend: Uri::from_static("http://backend:9000/"),
+            rewrite: Some(Rewrite::parse("^/api/v(\\d+) -> /v$1").expect("Unexpected error")),
+            ws: false,
+            insecure: false,
+            request_headers: HeaderEdits::default(),
+            response_headers: HeaderEdits::default(),
+            connect_timeout: None,
+            read_timeout: None,
+            retries: 0,
+            retry_delay: Duration::ZERO,
+        };
+        assert_eq!(proxy.path(), "/api/");
+        assert_eq!(
+            proxy
+                .outbound_uri(
+                    &Uri::from_static("/v2/users"),
+                    &Uri::from_static("http://localhost/api/v2/users?page=2"),
+                )
+                .expect("Unexpected error"),
+            Uri::from_static("http://backend:9000/v2/users?page=2")
+        )
+    }
+
+    #[test]
+    fn header_edits_remove_then_set() {
+        let edits = HeaderEdits::new(
+            &[("Authorization".to_owned(), "Bearer dev".to_owned())].into(),
+            &[
+                "Access-Control-Allow-Origin".to_owned(),
+                "Authorization".to_owned(),
+            ],
+        )
+        .expect("Unexpected error");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer prod"),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        );
+        edits.apply(&mut headers);
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer dev");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(HeaderEdits::new(&Default::default(), &["bad header".to_owned()]).is_err());
+    }
 }
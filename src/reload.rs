@@ -0,0 +1,439 @@
+//! Detection of rebuilds whose changes can be applied to the served pages without reloading them.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::common;
+use crate::pipelines::{url_path, MANIFEST_DIR};
+
+/// Matches the integrity attributes of HTML elements.
+static INTEGRITY_ATTR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"integrity="[^"]*""#).expect("valid integrity regex"));
+
+/// Matches the script initializing the main wasm module, capturing the URLs of its JS & wasm
+/// files.
+static MODULE_INIT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"import init from '([^']*)';[^<]*__trunkLoad\(init, '([^']*)'")
+        .expect("valid module init regex")
+});
+
+/// Matches the size of the main wasm module passed to its loader, capturing all but the size.
+static MODULE_SIZE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(__trunkLoad\(init, '[^']*', )\d+").expect("valid module size regex")
+});
+
+/// How the served pages apply the changes of a successful build.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reload {
+    /// The pages are reloaded.
+    Full,
+    /// Only stylesheets changed, which are swapped in place.
+    Css(Vec<StylesheetSwap>),
+    /// Only the main wasm module & stylesheets changed, which may be hot reloaded.
+    Module {
+        /// The new main wasm module of the pages.
+        module: ModuleSwap,
+        /// The changed stylesheets.
+        css: Vec<StylesheetSwap>,
+    },
+}
+
+/// A stylesheet of the served pages replaced by a rebuild.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StylesheetSwap {
+    /// The URL of the previous stylesheet, without any query.
+    pub old: String,
+    /// The URL of the new stylesheet.
+    pub new: String,
+}
+
+/// The main wasm module of the pages replacing the previous one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ModuleSwap {
+    /// The URL of the JS file of the module.
+    pub js: String,
+    /// The URL of the wasm file of the module.
+    pub wasm: String,
+}
+
+/// The digests of the files of a dist dir, along with the content of its HTML files.
+#[derive(Debug, Default)]
+pub struct DistSnapshot {
+    /// The digest of each file, by its path relative to the dist dir.
+    files: HashMap<String, u64>,
+    /// The content of each HTML file, by its path relative to the dist dir.
+    html: HashMap<String, String>,
+    /// The size & mtime of each file, by its path relative to the dist dir.
+    stats: HashMap<String, (u64, SystemTime)>,
+}
+
+impl DistSnapshot {
+    /// Read the snapshot of the given dist dir.
+    ///
+    /// The digests of the files of the given previous snapshot are reused for the files of the same
+    /// size & mtime, which rebuilds leave in place, rather than reading & hashing these again.
+    /// Files with the mtime of the unix epoch, as set by reproducible builds, are always hashed.
+    pub async fn read(dist: &Path, prev: Option<&Self>) -> Result<Self> {
+        let mut snapshot = Self::default();
+        for path in common::list_files_recursive(dist).await? {
+            let rel_path = path.strip_prefix(dist).unwrap_or(&path);
+            // The asset manifest is not loaded by the pages, and only changes along with the
+            // assets listed in it.
+            if rel_path.starts_with(MANIFEST_DIR) {
+                continue;
+            }
+            let rel_path = url_path(rel_path);
+            let meta = fs::metadata(&path)
+                .await
+                .with_context(|| format!("error reading metadata of dist file {:?}", path))?;
+            let stat = meta
+                .modified()
+                .ok()
+                .filter(|mtime| *mtime != SystemTime::UNIX_EPOCH)
+                .map(|mtime| (meta.len(), mtime));
+            let unchanged = prev.and_then(|prev| {
+                let stat = stat?;
+                (prev.stats.get(&rel_path) == Some(&stat)).then_some(prev)
+            });
+            match unchanged {
+                Some(prev) if prev.files.contains_key(&rel_path) => {
+                    snapshot
+                        .files
+                        .insert(rel_path.clone(), prev.files[&rel_path]);
+                    if let Some(html) = prev.html.get(&rel_path) {
+                        snapshot.html.insert(rel_path.clone(), html.clone());
+                    }
+                }
+                _ => {
+                    let content = fs::read(&path)
+                        .await
+                        .with_context(|| format!("error reading dist file {:?}", path))?;
+                    snapshot
+                        .files
+                        .insert(rel_path.clone(), seahash::hash(&content));
+                    if rel_path.ends_with(".html") {
+                        snapshot.html.insert(
+                            rel_path.clone(),
+                            String::from_utf8_lossy(&content).into_owned(),
+                        );
+                    }
+                }
+            }
+            if let Some(stat) = stat {
+                snapshot.stats.insert(rel_path, stat);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// The URLs of the JS & wasm files of the main wasm module initialized by all HTML files,
+    /// along with their paths relative to the dist dir.
+    fn main_module(&self, public_url: &str) -> Option<(ModuleSwap, [&str; 2])> {
+        let mut modules = self.html.values().map(|html| {
+            let caps = MODULE_INIT.captures(html)?;
+            let (js, wasm) = (caps.get(1)?.as_str(), caps.get(2)?.as_str());
+            Some((js, wasm))
+        });
+        let (js, wasm) = modules.next()??;
+        if !modules.all(|module| module == Some((js, wasm))) {
+            return None;
+        }
+        let path = |url: &str| {
+            let path = url.strip_prefix(public_url)?.split('?').next()?;
+            self.files
+                .get_key_value(path)
+                .map(|(path, _)| path.as_str())
+        };
+        let paths = [path(js)?, path(wasm)?];
+        let module = ModuleSwap {
+            js: js.to_owned(),
+            wasm: wasm.to_owned(),
+        };
+        Some((module, paths))
+    }
+
+    /// The paths of the stylesheets & the given files of the main module, whose references in the
+    /// HTML files are swapped rather than compared.
+    fn swappable<'a>(&'a self, module: &[&'a str]) -> Vec<&'a str> {
+        let mut paths: Vec<_> = self
+            .files
+            .keys()
+            .map(String::as_str)
+            .filter(|path| path.ends_with(".css"))
+            .chain(module.iter().copied())
+            .collect();
+        // Longer paths first, so that no path matches as part of another.
+        paths.sort_by_key(|path| std::cmp::Reverse(path.len()));
+        paths
+    }
+}
+
+/// Determine how the served pages apply the changes from the `prev` to the `next` dist dir.
+///
+/// Stylesheets are swapped in place if all changed files are stylesheets (or their precompressed
+/// copies & source maps), and the HTML files only differ in the URLs & integrity digests of them.
+/// If the files of the main wasm module changed as well, it may be hot reloaded.
+pub fn changes(prev: &DistSnapshot, next: &DistSnapshot, public_url: &str) -> Reload {
+    let changed = changed_paths(prev, next);
+    if changed.is_empty() {
+        return Reload::Full;
+    }
+
+    let (prev_module, next_module) = (prev.main_module(public_url), next.main_module(public_url));
+    let module_paths: Vec<&str> = prev_module
+        .iter()
+        .chain(&next_module)
+        .flat_map(|(_, paths)| *paths)
+        .collect();
+    let is_module_output = |path: &str| {
+        module_paths.iter().any(|module| {
+            path.strip_prefix(module)
+                .is_some_and(|ext| ["", ".gz", ".br"].contains(&ext))
+        }) || path.ends_with(".d.ts")
+            || path.split('/').any(|segment| segment == "snippets")
+    };
+    if changed.iter().any(|path| {
+        !path.ends_with(".html") && !is_stylesheet_output(path) && !is_module_output(path)
+    }) {
+        return Reload::Full;
+    }
+
+    let prev_swappable = prev.swappable(prev_module.as_ref().map_or(&[], |(_, paths)| paths));
+    let next_swappable = next.swappable(next_module.as_ref().map_or(&[], |(_, paths)| paths));
+    let mut swaps = Vec::new();
+    for (path, prev_html) in &prev.html {
+        let Some(next_html) = next.html.get(path) else {
+            return Reload::Full;
+        };
+        let (prev_norm, prev_refs) = swappable_refs(prev_html, &prev_swappable);
+        let (next_norm, next_refs) = swappable_refs(next_html, &next_swappable);
+        if prev_norm != next_norm || prev_refs.len() != next_refs.len() {
+            return Reload::Full;
+        }
+        for ((old, _), (new, query)) in prev_refs.into_iter().zip(next_refs) {
+            let is_stylesheet = old.ends_with(".css") && new.ends_with(".css");
+            if !is_stylesheet || (!changed.contains(old) && !changed.contains(new)) {
+                continue;
+            }
+            let swap = StylesheetSwap {
+                old: format!("{}{}", public_url, old),
+                new: format!("{}{}{}", public_url, new, query),
+            };
+            if !swaps.contains(&swap) {
+                swaps.push(swap);
+            }
+        }
+    }
+    if next.html.keys().any(|path| !prev.html.contains_key(path)) {
+        return Reload::Full;
+    }
+    match (
+        changed.iter().any(|path| is_module_output(path)),
+        next_module,
+    ) {
+        (true, Some((module, _))) => Reload::Module { module, css: swaps },
+        (false, _) if !swaps.is_empty() => Reload::Css(swaps),
+        _ => Reload::Full,
+    }
+}
+
+/// The URLs of the files which were added, changed or removed from the `prev` to the `next` dist
+/// dir, sorted.
+pub fn changed_files(prev: &DistSnapshot, next: &DistSnapshot, public_url: &str) -> Vec<String> {
+    let mut urls: Vec<_> = changed_paths(prev, next)
+        .into_iter()
+        .map(|path| format!("{}{}", public_url, path))
+        .collect();
+    urls.sort();
+    urls
+}
+
+/// The paths of the files which were added, changed or removed from the `prev` to the `next`
+/// dist dir.
+fn changed_paths<'a>(prev: &'a DistSnapshot, next: &'a DistSnapshot) -> HashSet<&'a str> {
+    prev.files
+        .iter()
+        .filter(|(path, digest)| next.files.get(*path) != Some(digest))
+        .chain(
+            next.files
+                .iter()
+                .filter(|(path, _)| !prev.files.contains_key(*path)),
+        )
+        .map(|(path, _)| path.as_str())
+        .collect()
+}
+
+/// Whether the file at the given path is a stylesheet, or a precompressed copy or source map of
+/// one.
+fn is_stylesheet_output(path: &str) -> bool {
+    [".css", ".css.gz", ".css.br", ".css.map"]
+        .iter()
+        .any(|ext| path.ends_with(ext))
+}
+
+/// Find the references of the given files in the given HTML, returning the HTML without them &
+/// any integrity digests, along with the path & query of each reference in document order.
+fn swappable_refs<'a>(html: &str, files: &[&'a str]) -> (String, Vec<(&'a str, String)>) {
+    // Integrity digests change along with the files they are of, which are compared on their own.
+    let html = INTEGRITY_ATTR.replace_all(html, "");
+    // Likewise the size of the main module, which is only passed to its loader.
+    let html = MODULE_SIZE.replace_all(&html, "$1");
+    if files.is_empty() {
+        return (html.into_owned(), Vec::new());
+    }
+
+    let pattern = files
+        .iter()
+        .map(|file| regex::escape(file))
+        .collect::<Vec<_>>()
+        .join("|");
+    let refs =
+        Regex::new(&format!(r#"({})(\?[^"'\s>]*)?"#, pattern)).expect("valid file reference regex");
+    let mut found = Vec::new();
+    let normalized = refs.replace_all(&html, |caps: &regex::Captures| {
+        let path = caps.get(1).map_or("", |path| path.as_str());
+        let query = caps.get(2).map_or("", |query| query.as_str());
+        if let Some(file) = files.iter().find(|file| **file == path) {
+            found.push((*file, query.to_owned()));
+        }
+        ""
+    });
+    (normalized.into_owned(), found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(files: &[(&str, &str)]) -> DistSnapshot {
+        let mut snapshot = DistSnapshot::default();
+        for (path, content) in files {
+            snapshot
+                .files
+                .insert(path.to_string(), seahash::hash(content.as_bytes()));
+            if path.ends_with(".html") {
+                snapshot.html.insert(path.to_string(), content.to_string());
+            }
+        }
+        snapshot
+    }
+
+    #[tokio::test]
+    async fn read_rehashes_changed_files_only() -> Result<()> {
+        let dist = tempfile::tempdir()?;
+        fs::write(dist.path().join("index.html"), "index").await?;
+        fs::write(dist.path().join("app.js"), "app").await?;
+        let mut prev = DistSnapshot::read(dist.path(), None).await?;
+        // A digest only kept from the previous snapshot, as the file is unchanged.
+        prev.files.insert("index.html".into(), 0);
+        fs::remove_file(dist.path().join("app.js")).await?;
+        fs::write(dist.path().join("app.js"), "app2").await?;
+
+        let next = DistSnapshot::read(dist.path(), Some(&prev)).await?;
+
+        assert_eq!(next.files["index.html"], 0);
+        assert_eq!(next.html["index.html"], "index");
+        assert_eq!(next.files["app.js"], seahash::hash(b"app2"));
+        Ok(())
+    }
+
+    #[test]
+    fn changes_swaps_changed_stylesheets() {
+        let prev = snapshot(&[
+            (
+                "index.html",
+                r#"<link rel="stylesheet" href="/css/a-1.css" integrity="sha384-1"/><link rel="stylesheet" href="/b.css?v=1"/><script src="/app-1.js"></script>"#,
+            ),
+            ("css/a-1.css", "a { color: red; }"),
+            ("b.css", "b {}"),
+            ("app-1.js", "let a;"),
+        ]);
+        let next = snapshot(&[
+            (
+                "index.html",
+                r#"<link rel="stylesheet" href="/css/a-2.css" integrity="sha384-2"/><link rel="stylesheet" href="/b.css?v=1"/><script src="/app-1.js"></script>"#,
+            ),
+            ("css/a-2.css", "a { color: blue; }"),
+            ("b.css", "b {}"),
+            ("app-1.js", "let a;"),
+        ]);
+        assert_eq!(
+            changes(&prev, &next, "/"),
+            Reload::Css(vec![StylesheetSwap {
+                old: "/css/a-1.css".into(),
+                new: "/css/a-2.css".into(),
+            }])
+        );
+    }
+
+    #[test]
+    fn changes_reloads_on_other_changes() {
+        let prev = snapshot(&[
+            (
+                "index.html",
+                r#"<link rel="stylesheet" href="/a.css"/><h1>a</h1>"#,
+            ),
+            ("a.css", "a {}"),
+            ("app.js", "let a;"),
+        ]);
+        for next in [
+            snapshot(&[
+                (
+                    "index.html",
+                    r#"<link rel="stylesheet" href="/a.css"/><h1>a</h1>"#,
+                ),
+                ("a.css", "a {}"),
+                ("app.js", "let b;"),
+            ]),
+            snapshot(&[
+                (
+                    "index.html",
+                    r#"<link rel="stylesheet" href="/a.css"/><h1>b</h1>"#,
+                ),
+                ("a.css", "b {}"),
+                ("app.js", "let a;"),
+            ]),
+        ] {
+            assert_eq!(changes(&prev, &next, "/"), Reload::Full);
+        }
+        assert_eq!(changes(&prev, &prev, "/"), Reload::Full);
+    }
+
+    #[test]
+    fn changes_hot_reloads_main_module() {
+        let html = |hash: &str| {
+            format!(
+                r#"<link rel="modulepreload" href="/app-{hash}.js"/><script type="module">import init from '/app-{hash}.js';
+function __trunkLoad() {{}}__trunkLoad(init, '/app-{hash}_bg.wasm', {hash}0);</script>"#
+            )
+        };
+        let (prev_html, next_html) = (html("1"), html("2"));
+        let prev = snapshot(&[
+            ("index.html", &prev_html),
+            ("app-1.js", "export default init;"),
+            ("app-1_bg.wasm", "a"),
+        ]);
+        let next = snapshot(&[
+            ("index.html", &next_html),
+            ("app-2.js", "export default init;"),
+            ("app-2_bg.wasm", "b"),
+        ]);
+        assert_eq!(
+            changes(&prev, &next, "/"),
+            Reload::Module {
+                module: ModuleSwap {
+                    js: "/app-2.js".into(),
+                    wasm: "/app-2_bg.wasm".into(),
+                },
+                css: Vec::new(),
+            }
+        );
+    }
+}
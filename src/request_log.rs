@@ -0,0 +1,254 @@
+//! The request log of the dev server, logging each request & recording them as HAR entries.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Request, Uri, Version};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde_json::{json, Value};
+use tokio::fs;
+
+use crate::common::SERVER;
+use crate::config::RtcServe;
+
+/// The backend a response was proxied from, set as an extension of the response by the proxies.
+#[derive(Clone, Debug)]
+pub struct Proxied(pub Uri);
+
+/// The log of the requests of the server.
+pub struct RequestLog {
+    /// Whether to log each request.
+    log: bool,
+    /// The path to write the HAR file to, if any.
+    har: Option<PathBuf>,
+    /// The scheme of the server.
+    scheme: &'static str,
+    /// The scheme & authority of the server, used for the URLs of requests without a host.
+    origin: String,
+    /// The HAR entries of all requests so far, if recorded.
+    entries: Mutex<Vec<Value>>,
+}
+
+impl RequestLog {
+    /// Construct a new instance, if logging or recording requests is enabled.
+    pub fn new(cfg: &RtcServe, scheme: &'static str) -> Option<Arc<Self>> {
+        if !cfg.log_requests && cfg.har.is_none() {
+            return None;
+        }
+        let origin = match &cfg.unix_socket {
+            Some(_) => format!("{}://localhost", scheme),
+            None => format!("{}://{}:{}", scheme, cfg.address, cfg.port),
+        };
+        Some(Arc::new(Self {
+            log: cfg.log_requests,
+            har: cfg.har.clone(),
+            scheme,
+            origin,
+            entries: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Write the recorded requests to the HAR file, if enabled.
+    pub async fn write_har(&self) -> Result<()> {
+        let Some(path) = &self.har else {
+            return Ok(());
+        };
+        let entries =
+            std::mem::take(&mut *self.entries.lock().unwrap_or_else(|err| err.into_inner()));
+        let count = entries.len();
+        let har = json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "pages": [],
+                "entries": entries,
+            }
+        });
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("error creating dir of HAR file {:?}", path))?;
+        }
+        fs::write(path, format!("{:#}\n", har))
+            .await
+            .with_context(|| format!("error writing HAR file {:?}", path))?;
+        tracing::info!("{} wrote {} requests to {:?}", SERVER, count, path);
+        Ok(())
+    }
+}
+
+/// Log the request & its response, and record it as a HAR entry if enabled.
+///
+/// The latency is the time until the response headers are ready, excluding streaming the body.
+pub async fn log_requests<B>(
+    State(log): State<Arc<RequestLog>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (started, start) = (SystemTime::now(), Instant::now());
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let version = req.version();
+    let req_headers = req.headers().clone();
+
+    let res = next.run(req).await;
+    let latency = start.elapsed();
+    let status = res.status();
+    let proxied = res.extensions().get::<Proxied>().map(|proxied| &proxied.0);
+    let source = match proxied {
+        Some(_) => "proxy",
+        None => "static",
+    };
+
+    if log.log {
+        match proxied {
+            Some(backend) => tracing::info!(
+                method = %method,
+                path = %uri,
+                status = status.as_u16(),
+                ?latency,
+                source,
+                %backend,
+                "{} request",
+                SERVER
+            ),
+            None => tracing::info!(
+                method = %method,
+                path = %uri,
+                status = status.as_u16(),
+                ?latency,
+                source,
+                "{} request",
+                SERVER
+            ),
+        }
+    }
+
+    if log.har.is_some() {
+        let url = match req_headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+        {
+            Some(host) => format!("{}://{}{}", log.scheme, host, uri),
+            None => format!("{}{}", log.origin, uri),
+        };
+        let size = |headers: &HeaderMap| -> i64 {
+            headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok()?.parse().ok())
+                .unwrap_or(-1)
+        };
+        let millis = latency.as_secs_f64() * 1000.0;
+        let entry = json!({
+            "startedDateTime": humantime::format_rfc3339_millis(started).to_string(),
+            "time": millis,
+            "request": {
+                "method": method.as_str(),
+                "url": url,
+                "httpVersion": http_version(version),
+                "cookies": [],
+                "headers": har_headers(&req_headers),
+                "queryString": query_string(&uri),
+                "headersSize": -1,
+                "bodySize": size(&req_headers),
+            },
+            "response": {
+                "status": status.as_u16(),
+                "statusText": status.canonical_reason().unwrap_or_default(),
+                "httpVersion": http_version(res.version()),
+                "cookies": [],
+                "headers": har_headers(res.headers()),
+                "content": {
+                    "size": size(res.headers()).max(0),
+                    "mimeType": res
+                        .headers()
+                        .get(header::CONTENT_TYPE)
+                        .and_then(|val| val.to_str().ok())
+                        .unwrap_or_default(),
+                },
+                "redirectURL": res
+                    .headers()
+                    .get(header::LOCATION)
+                    .and_then(|val| val.to_str().ok())
+                    .unwrap_or_default(),
+                "headersSize": -1,
+                "bodySize": size(res.headers()),
+            },
+            "cache": {},
+            "timings": {
+                "send": 0,
+                "wait": millis,
+                "receive": 0,
+            },
+            "_source": source,
+            "_backend": proxied.map(|backend| backend.to_string()),
+        });
+        log.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(entry);
+    }
+    res
+}
+
+/// The HTTP version as named in HAR files.
+fn http_version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+}
+
+/// The given headers as HAR name/value pairs.
+fn har_headers(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, val)| {
+            json!({
+                "name": name.as_str(),
+                "value": String::from_utf8_lossy(val.as_bytes()),
+            })
+        })
+        .collect()
+}
+
+/// The query parameters of the given URI as HAR name/value pairs.
+fn query_string(uri: &Uri) -> Vec<Value> {
+    uri.query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, val) = param.split_once('=').unwrap_or((param, ""));
+            json!({ "name": name, "value": val })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_string_splits_params() {
+        let uri: Uri = "/index.html?a=1&b&c=x=y".parse().expect("valid URI");
+        assert_eq!(
+            query_string(&uri),
+            vec![
+                json!({ "name": "a", "value": "1" }),
+                json!({ "name": "b", "value": "" }),
+                json!({ "name": "c", "value": "x=y" }),
+            ]
+        );
+    }
+}
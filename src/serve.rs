@@ -1,57 +1,93 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
 use std::net::{IpAddr, Ipv4Addr};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use axum::body::{self, Body};
 use axum::extract::ws::{WebSocket, WebSocketUpgrade};
-use axum::http::StatusCode;
-use axum::response::Response;
-use axum::routing::{get, get_service, Router};
+use axum::extract::OriginalUri;
+use axum::http::{
+    header, Extensions, HeaderMap, HeaderName, HeaderValue, Request, StatusCode, Version,
+};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, get_service, MethodRouter, Router};
 use axum::Server;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures_util::future::BoxFuture;
+use futures_util::{FutureExt, TryFutureExt};
+use glob::Pattern;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
+#[cfg(unix)]
+use tokio_stream::wrappers::UnixListenerStream;
+use tower::{service_fn, ServiceExt};
+use tower_http::compression::predicate::{DefaultPredicate, Predicate};
+use tower_http::compression::CompressionLayer;
 use tower_http::services::{ServeDir, ServeFile};
+use tower_http::set_header::SetResponseHeaderLayer;
 use tower_http::trace::TraceLayer;
 
+use crate::build::BuildEvent;
+use crate::cache::BuildCache;
 use crate::common::{LOCAL, NETWORK, SERVER};
-use crate::config::RtcServe;
+use crate::config::{RtcServe, ServeAuth, ServeTls};
+use crate::http3;
+use crate::overlay;
+use crate::pipelines::{glob_matches, MANIFEST_DIR};
 use crate::proxy::{ProxyHandlerHttp, ProxyHandlerWebSocket};
+use crate::reload::Reload;
+use crate::request_log::{self, RequestLog};
+use crate::tools::cache_dir;
 use crate::watch::WatchSystem;
 
 const INDEX_HTML: &str = "index.html";
+/// The file the address of the server is announced in, within the Trunk dir of the dist dir.
+const ANNOUNCEMENT_FILE: &str = "serve.json";
+/// The version of the protocol of the autoreload websocket, announced in its `hello` message.
+const AUTORELOAD_PROTOCOL_VERSION: u32 = 1;
 
 /// A system encapsulating a build & watch system, responsible for serving generated content.
 pub struct ServeSystem {
     cfg: Arc<RtcServe>,
     watch: WatchSystem,
-    http_addr: String,
     shutdown_tx: broadcast::Sender<()>,
     //  N.B. we use a broadcast channel here because a watch channel triggers a
     //  false positive on the first read of channel
-    build_done_chan: broadcast::Sender<()>,
+    build_events: broadcast::Sender<BuildEvent>,
 }
 
 impl ServeSystem {
     /// Construct a new instance.
     pub async fn new(cfg: Arc<RtcServe>, shutdown: broadcast::Sender<()>) -> Result<Self> {
-        let (build_done_chan, _) = broadcast::channel(8);
+        let cfg = if cfg.port == 0 && cfg.unix_socket.is_none() {
+            let mut selected = (*cfg).clone();
+            selected.port = select_free_port(&cfg).await?;
+            Arc::new(selected)
+        } else {
+            cfg
+        };
+        let (build_events, _) = broadcast::channel(64);
         let watch = WatchSystem::new(
             cfg.watch.clone(),
             shutdown.clone(),
-            Some(build_done_chan.clone()),
+            Some(build_events.clone()),
         )
         .await?;
-        let http_addr = format!(
-            "http://{}:{}{}",
-            cfg.address, cfg.port, &cfg.watch.build.public_url
-        );
         Ok(Self {
             cfg,
             watch,
-            http_addr,
             shutdown_tx: shutdown,
-            build_done_chan,
+            build_events,
         })
     }
 
@@ -59,18 +95,46 @@ impl ServeSystem {
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn run(mut self) -> Result<()> {
         // Spawn the watcher & the server.
-        let _build_res = self.watch.build().await; // TODO: only open after a successful build.
+        let build_res = self.watch.build().await;
+        let mut build_events_rx = self.build_events.subscribe();
+        // The dist dir is replaced by each build, so the announcement is written after each one.
+        if build_res.is_ok() {
+            announce(&self.cfg).await;
+        }
+        let (cfg, mut announce_rx) = (self.cfg.clone(), self.build_events.subscribe());
+        tokio::spawn(async move {
+            loop {
+                match announce_rx.recv().await {
+                    Ok(BuildEvent::Done(Ok(_))) => announce(&cfg).await,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
         let watch_handle = tokio::spawn(self.watch.run());
         let server_handle = Self::spawn_server(
             self.cfg.clone(),
             self.shutdown_tx.subscribe(),
-            self.build_done_chan,
-        )?;
+            self.build_events,
+            build_res.as_ref().err().map(overlay::report),
+        )
+        .await?;
 
-        // Open the browser.
-        if self.cfg.open {
-            if let Err(err) = open::that(self.http_addr) {
-                tracing::error!(error = ?err, "error opening browser");
+        // Open the browser once a build succeeded.
+        if self.cfg.open.is_some() {
+            let cfg = self.cfg.clone();
+            if build_res.is_ok() {
+                open_browser(&cfg);
+            } else {
+                tracing::info!("{} opening the browser once a build succeeds", SERVER);
+                tokio::spawn(async move {
+                    while let Ok(event) = build_events_rx.recv().await {
+                        if matches!(event, BuildEvent::Done(Ok(_))) {
+                            open_browser(&cfg);
+                            break;
+                        }
+                    }
+                });
             }
         }
         drop(self.shutdown_tx); // Drop the broadcast channel to ensure it does not keep the system alive.
@@ -83,70 +147,101 @@ impl ServeSystem {
         Ok(())
     }
 
-    #[tracing::instrument(level = "trace", skip(cfg, shutdown_rx))]
-    fn spawn_server(
+    #[tracing::instrument(level = "trace", skip(cfg, shutdown_rx, build_error))]
+    async fn spawn_server(
         cfg: Arc<RtcServe>,
         mut shutdown_rx: broadcast::Receiver<()>,
-        build_done_chan: broadcast::Sender<()>,
+        build_events: broadcast::Sender<BuildEvent>,
+        build_error: Option<String>,
     ) -> Result<JoinHandle<()>> {
         // Build a shutdown signal for the warp server.
+        let mut h3_shutdown_rx = shutdown_rx.resubscribe();
         let shutdown_fut = async move {
             // Any event on this channel, even a drop, should trigger shutdown.
             let _res = shutdown_rx.recv().await;
             tracing::debug!("server is shutting down");
         };
 
-        // Build the proxy client.
-        let client = reqwest::ClientBuilder::new()
-            .http1_only()
-            .build()
-            .context("error building proxy client")?;
-
-        let insecure_client = reqwest::ClientBuilder::new()
-            .http1_only()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .context("error building insecure proxy client")?;
-
         // Build the server.
         let state = Arc::new(State::new(
             cfg.watch.build.final_dist.clone(),
             cfg.watch.build.public_url.clone(),
-            client,
-            insecure_client,
             &cfg,
-            build_done_chan,
+            build_events,
+            build_error,
         ));
-        let router = router(state, cfg.clone());
+        let scheme = scheme(&cfg);
+        let request_log = RequestLog::new(&cfg, scheme);
+        let mut router = router(state, cfg.clone(), request_log.clone())?;
         let addr = (cfg.address, cfg.port).into();
-        let server = Server::bind(&addr)
-            .serve(router.into_make_service())
-            .with_graceful_shutdown(shutdown_fut);
-
-        if addr.ip().is_unspecified() {
-            let addresses = local_ip_address::list_afinet_netifas()
-                .map(|addrs| {
-                    addrs
-                        .into_iter()
-                        .filter_map(|(_, ipaddr)| match ipaddr {
-                            IpAddr::V4(ip) if ip.is_private() || ip.is_loopback() => Some(ip),
-                            _ => None,
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .unwrap_or_else(|_| vec![Ipv4Addr::LOCALHOST]);
+        let server = match (&cfg.tls, &cfg.unix_socket) {
+            (None, Some(path)) => serve_unix_socket(path, router, shutdown_fut)?,
+            (None, None) => Server::bind(&addr)
+                .serve(router.into_make_service())
+                .with_graceful_shutdown(shutdown_fut)
+                .map_err(anyhow::Error::from)
+                .boxed(),
+            // Rejected when validating the config already, but never served without TLS.
+            (Some(_), Some(path)) => bail!(
+                "TLS is not supported when serving on the Unix domain socket {:?}",
+                path
+            ),
+            (Some(tls), None) => {
+                let tls_config = match tls {
+                    ServeTls::Files { cert, key } => RustlsConfig::from_pem_file(cert, key)
+                        .await
+                        .with_context(|| {
+                        format!("error reading TLS certificate {:?} & key {:?}", cert, key)
+                    })?,
+                    ServeTls::SelfSigned => {
+                        let (cert, key) = self_signed_cert(&cfg).await?;
+                        RustlsConfig::from_pem(cert, key)
+                            .await
+                            .context("error loading self-signed TLS certificate")?
+                    }
+                };
+                if cfg.http3 {
+                    http3::spawn(addr, &tls_config, router.clone(), async move {
+                        let _res = h3_shutdown_rx.recv().await;
+                    })?;
+                    tracing::info!("{} serving HTTP/3 on UDP port {}", SERVER, cfg.port);
+                    // Browsers switch to HTTP/3 for subsequent requests once it is advertised.
+                    let alt_svc = format!(r#"h3=":{}"; ma=86400"#, cfg.port);
+                    router = router.layer(SetResponseHeaderLayer::if_not_present(
+                        header::ALT_SVC,
+                        HeaderValue::from_str(&alt_svc).context("error building Alt-Svc header")?,
+                    ));
+                }
+                let handle = Handle::new();
+                let shutdown_handle = handle.clone();
+                tokio::spawn(async move {
+                    shutdown_fut.await;
+                    shutdown_handle.graceful_shutdown(None);
+                });
+                axum_server::bind_rustls(addr, tls_config)
+                    .handle(handle)
+                    .serve(router.into_make_service())
+                    .map_err(anyhow::Error::from)
+                    .boxed()
+            }
+        };
+
+        if let Some(path) = &cfg.unix_socket {
+            tracing::info!("{} server listening at unix:{}", SERVER, path.display());
+        } else if addr.ip().is_unspecified() {
             tracing::info!(
                 "{} server listening at:\n{}",
                 SERVER,
-                addresses
+                local_addresses()
                     .iter()
                     .map(|address| format!(
-                        "    {} http://{}:{}",
+                        "    {} {}://{}:{}",
                         if address.is_loopback() {
                             LOCAL
                         } else {
                             NETWORK
                         },
+                        scheme,
                         address,
                         cfg.port
                     ))
@@ -154,31 +249,265 @@ impl ServeSystem {
                     .join("\n")
             );
         } else {
-            tracing::info!("{} server listening at http://{}", SERVER, addr);
+            tracing::info!("{} server listening at {}://{}", SERVER, scheme, addr);
+        }
+        if let Some(ServeAuth::Token(token)) = &cfg.auth {
+            tracing::info!(
+                "{} access requires the token, e.g. by opening {}?{}={}",
+                SERVER,
+                cfg.watch.build.public_url,
+                TOKEN_PARAM,
+                token
+            );
         }
         // Block this routine on the server's completion.
         Ok(tokio::spawn(async move {
             if let Err(err) = server.await {
                 tracing::error!(error = ?err, "error from server task");
             }
+            if let Some(request_log) = request_log {
+                if let Err(err) = request_log.write_har().await {
+                    tracing::error!(error = ?err, "error writing HAR file");
+                }
+            }
         }))
     }
 }
 
+/// Select a free port to serve on, preferring the port announced by a previous server for the
+/// same dist dir, so that it stays stable across restarts.
+async fn select_free_port(cfg: &RtcServe) -> Result<u16> {
+    let previous = fs::read(announcement_path(cfg))
+        .await
+        .ok()
+        .and_then(|json| serde_json::from_slice::<serde_json::Value>(&json).ok())
+        .and_then(|json| json.get("port")?.as_u64())
+        .and_then(|port| u16::try_from(port).ok())
+        .filter(|port| *port != 0);
+    if let Some(port) = previous {
+        if std::net::TcpListener::bind((cfg.address, port)).is_ok() {
+            return Ok(port);
+        }
+    }
+    let listener = std::net::TcpListener::bind((cfg.address, 0))
+        .with_context(|| format!("error selecting a free port on {}", cfg.address))?;
+    let port = listener
+        .local_addr()
+        .context("error reading the address of the selected port")?
+        .port();
+    tracing::info!("{} selected free port {}", SERVER, port);
+    Ok(port)
+}
+
+/// The path of the file the address of the server is announced in.
+fn announcement_path(cfg: &RtcServe) -> PathBuf {
+    cfg.watch
+        .build
+        .final_dist
+        .join(MANIFEST_DIR)
+        .join(ANNOUNCEMENT_FILE)
+}
+
+/// Announce the address of the server in the dist dir, e.g. for tools to find servers with
+/// automatically selected ports.
+async fn announce(cfg: &RtcServe) {
+    let json = match &cfg.unix_socket {
+        Some(path) => serde_json::json!({ "address": format!("unix:{}", path.display()) }),
+        None => serde_json::json!({
+            "address": cfg.address.to_string(),
+            "port": cfg.port,
+            "url": format!(
+                "{}://{}:{}{}",
+                scheme(cfg),
+                cfg.address,
+                cfg.port,
+                cfg.watch.build.public_url
+            ),
+        }),
+    };
+    let path = announcement_path(cfg);
+    let res = async {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(&path, format!("{:#}\n", json)).await
+    };
+    if let Err(err) = res.await {
+        tracing::warn!(error = ?err, "error writing server announcement {:?}", path);
+    }
+}
+
+/// Open the configured page in the configured or the default browser.
+fn open_browser(cfg: &RtcServe) {
+    let Some(path) = &cfg.open else {
+        return;
+    };
+    if cfg.unix_socket.is_some() {
+        tracing::warn!("{} can not open a browser for a Unix domain socket", SERVER);
+        return;
+    }
+    let mut url = format!("{}://{}:{}{}", scheme(cfg), cfg.address, cfg.port, path);
+    // The opened page passes the access token, which is then stored in a cookie.
+    if let Some(ServeAuth::Token(token)) = &cfg.auth {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        url.push_str(&format!("{}{}={}", separator, TOKEN_PARAM, token));
+    }
+    let res = match &cfg.open_browser {
+        Some(browser) => open::with(&url, browser),
+        None => open::that(&url),
+    };
+    if let Err(err) = res {
+        tracing::error!(error = ?err, "error opening browser");
+    }
+}
+
+/// Serve the given router on the Unix domain socket at the given path until the shutdown future
+/// completes, removing the socket afterwards.
+#[cfg(unix)]
+fn serve_unix_socket(
+    path: &Path,
+    router: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<BoxFuture<'static, Result<()>>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // The socket of a previous server is replaced, but no other file.
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        ensure!(
+            metadata.file_type().is_socket(),
+            "can not serve on {:?}, which exists & is not a Unix domain socket",
+            path
+        );
+        std::fs::remove_file(path)
+            .with_context(|| format!("error removing previous Unix domain socket {:?}", path))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("error binding Unix domain socket {:?}", path))?;
+    let accept = hyper::server::accept::from_stream(UnixListenerStream::new(listener));
+    let path = path.to_owned();
+    Ok(Server::builder(accept)
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .map_err(anyhow::Error::from)
+        .map(move |res| {
+            if let Err(err) = std::fs::remove_file(&path) {
+                tracing::debug!(error = ?err, "error removing Unix domain socket {:?}", path);
+            }
+            res
+        })
+        .boxed())
+}
+
+/// Serve the given router on the Unix domain socket at the given path, which is only supported on
+/// Unix.
+#[cfg(not(unix))]
+fn serve_unix_socket(
+    _path: &Path,
+    _router: Router,
+    _shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<BoxFuture<'static, Result<()>>> {
+    anyhow::bail!("serving on a Unix domain socket is only supported on Unix")
+}
+
+/// The URL scheme of the server.
+fn scheme(cfg: &RtcServe) -> &'static str {
+    match cfg.tls {
+        Some(_) => "https",
+        None => "http",
+    }
+}
+
+/// The private & loopback IPv4 addresses of the local network interfaces.
+fn local_addresses() -> Vec<Ipv4Addr> {
+    local_ip_address::list_afinet_netifas()
+        .map(|addrs| {
+            addrs
+                .into_iter()
+                .filter_map(|(_, ipaddr)| match ipaddr {
+                    IpAddr::V4(ip) if ip.is_private() || ip.is_loopback() => Some(ip),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_else(|_| vec![Ipv4Addr::LOCALHOST])
+}
+
+/// Get the PEM encoded self-signed certificate & private key for the addresses of the server.
+///
+/// The certificate is stored in the cache dir & reused as long as the addresses stay the same, so
+/// that an exception for it only needs to be added to the browser once.
+async fn self_signed_cert(cfg: &RtcServe) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".into(), "::1".into()];
+    if cfg.address.is_unspecified() {
+        names.extend(local_addresses().iter().map(ToString::to_string));
+    } else {
+        names.push(cfg.address.to_string());
+    }
+    names.sort();
+    names.dedup();
+
+    let dir = cache_dir().await?.join("tls");
+    let name = &BuildCache::key(&names)[..16];
+    let cert_path = dir.join(format!("{name}.crt.pem"));
+    let key_path = dir.join(format!("{name}.key.pem"));
+    if let (Ok(cert), Ok(key)) = (fs::read(&cert_path).await, fs::read(&key_path).await) {
+        // Keys written by earlier versions may be readable by all users of the system.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o600))
+                .await
+                .with_context(|| format!("error restricting TLS private key {:?}", key_path))?;
+        }
+        return Ok((cert, key));
+    }
+
+    tracing::info!(?names, "generating self-signed TLS certificate");
+    let cert = rcgen::generate_simple_self_signed(names)
+        .context("error generating self-signed TLS certificate")?;
+    let cert_pem = cert
+        .serialize_pem()
+        .context("error serializing self-signed TLS certificate")?;
+    let key_pem = cert.serialize_private_key_pem();
+    fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("error creating TLS certificate dir {:?}", dir))?;
+    fs::write(&cert_path, &cert_pem)
+        .await
+        .with_context(|| format!("error writing TLS certificate {:?}", cert_path))?;
+    // The private key is only readable by its owner, rather than by all users of the system. The
+    // mode only applies to new files, so a key left without its certificate is replaced.
+    let _ = fs::remove_file(&key_path).await;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut key_file = options
+        .open(&key_path)
+        .await
+        .with_context(|| format!("error creating TLS private key {:?}", key_path))?;
+    key_file
+        .write_all(key_pem.as_bytes())
+        .await
+        .with_context(|| format!("error writing TLS private key {:?}", key_path))?;
+    Ok((cert_pem.into_bytes(), key_pem.into_bytes()))
+}
+
 /// Server state.
 pub struct State {
-    /// A client instance used by proxies.
-    pub client: reqwest::Client,
-    /// A client instance used by proxies to make insecure requests.
-    pub insecure_client: reqwest::Client,
     /// The location of the dist dir.
     pub dist_dir: PathBuf,
     /// The public URL from which assets are being served.
     pub public_url: String,
-    /// The channel to receive build_done notifications on.
-    pub build_done_chan: broadcast::Sender<()>,
+    /// The channel to receive build lifecycle events on.
+    pub build_events: broadcast::Sender<BuildEvent>,
+    /// The HTML error report of the last build, if it failed.
+    pub build_error: Arc<Mutex<Option<String>>>,
     /// Whether to disable autoreload
+    #[allow(dead_code)]
     pub no_autoreload: bool,
+    /// Whether to hot reload the wasm module of the web page when possible.
+    pub hmr: bool,
 }
 
 impl State {
@@ -186,25 +515,117 @@ impl State {
     pub fn new(
         dist_dir: PathBuf,
         public_url: String,
-        client: reqwest::Client,
-        insecure_client: reqwest::Client,
         cfg: &RtcServe,
-        build_done_chan: broadcast::Sender<()>,
+        build_events: broadcast::Sender<BuildEvent>,
+        build_error: Option<String>,
     ) -> Self {
+        // Keep track of the error of the last build, for the pages loaded after it.
+        let build_error = Arc::new(Mutex::new(build_error));
+        let mut rx = build_events.subscribe();
+        let last_error = build_error.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(BuildEvent::Done(build_done)) => {
+                        *last_error.lock().unwrap_or_else(|err| err.into_inner()) = build_done.err()
+                    }
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
         Self {
-            client,
-            insecure_client,
             dist_dir,
             public_url,
-            build_done_chan,
+            build_events,
+            build_error,
             no_autoreload: cfg.no_autoreload,
+            hmr: cfg.hmr,
         }
     }
 }
 
+/// Serve the static files of the given dir, falling back to its index HTML file or its not found
+/// page as configured.
+fn serve_dir(dir: &Path, cfg: &Arc<RtcServe>) -> MethodRouter {
+    let (dir_buf, cfg) = (dir.to_path_buf(), cfg.clone());
+    let fallback = service_fn(move |req: Request<Body>| {
+        let (dir, cfg) = (dir_buf.clone(), cfg.clone());
+        async move { Ok::<_, Infallible>(serve_fallback(&dir, &cfg, req).await) }
+    });
+    // The precompressed copies of release builds are served to clients accepting their encoding.
+    let serve_dir = ServeDir::new(dir)
+        .precompressed_br()
+        .precompressed_gzip()
+        .fallback(fallback);
+    get_service(serve_dir)
+        .handle_error(|error| async move {
+            tracing::error!(?error, "failed serving static file");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+        .layer(TraceLayer::new_for_http())
+}
+
+/// The content types of compressible static files, besides `text/*`.
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "application/javascript",
+    "application/json",
+    "application/manifest+json",
+    "application/wasm",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// Check whether a response with the given headers is of a compressible content type.
+fn compressible(_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.starts_with("text/") || COMPRESSIBLE_TYPES.contains(&mime)
+}
+
+/// Serve the fallback of the given dir for a request of a file it does not contain.
+///
+/// Paths matching the fallback globs are served the index HTML file, as expected by apps using the
+/// history API, while all others are served the not found page with a 404 status, if configured.
+async fn serve_fallback(dir: &Path, cfg: &RtcServe, req: Request<Body>) -> Response {
+    let uri = match req.extensions().get::<OriginalUri>() {
+        Some(uri) => uri.0.clone(),
+        None => req.uri().clone(),
+    };
+    let path = uri.path().trim_start_matches('/');
+    let falls_back = cfg.fallback.iter().any(|glob| glob_matches(glob, path))
+        && !cfg
+            .fallback_exclude
+            .iter()
+            .any(|glob| glob_matches(glob, path));
+    let page = match (falls_back, &cfg.not_found) {
+        (true, _) => INDEX_HTML,
+        (false, Some(not_found)) => not_found.as_str(),
+        (false, None) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let mut res = match ServeFile::new(dir.join(page)).oneshot(req).await {
+        Ok(res) => res,
+        Err(err) => match err {},
+    };
+    if !falls_back {
+        *res.status_mut() = StatusCode::NOT_FOUND;
+    }
+    res.map(body::boxed)
+}
+
 /// Build the Trunk router, this includes that static file server, the WebSocket server,
 /// (for autoreload & HMR in the future), as well as any user-defined proxies.
-fn router(state: Arc<State>, cfg: Arc<RtcServe>) -> Router {
+fn router(
+    state: Arc<State>,
+    cfg: Arc<RtcServe>,
+    request_log: Option<Arc<RequestLog>>,
+) -> Result<Router> {
     // Build static file server, middleware, error handler & WS route for reloads.
     let public_route = if state.public_url == "/" {
         &state.public_url
@@ -215,21 +636,25 @@ fn router(state: Arc<State>, cfg: Arc<RtcServe>) -> Router {
             .unwrap_or(&state.public_url)
     };
 
+    // The other apps of the workspace are served from their subpaths, falling back to their own
+    // index HTML file.
+    let mut static_files = Router::new();
+    for app in &cfg.watch.build.apps {
+        let route = format!("{}/{}", public_route.trim_end_matches('/'), app.path);
+        static_files =
+            static_files.nest_service(&route, serve_dir(&state.dist_dir.join(&app.path), &cfg));
+    }
+    let mut static_files =
+        static_files.nest_service(public_route, serve_dir(&state.dist_dir, &cfg));
+    if cfg.compress {
+        // Responses which are already compressed, such as precompressed copies, are passed on.
+        static_files = static_files.layer(
+            CompressionLayer::new().compress_when(DefaultPredicate::new().and(compressible)),
+        );
+    }
+
     let mut router = Router::new()
-        .fallback_service(
-            Router::new().nest_service(
-                public_route,
-                get_service(
-                    ServeDir::new(&state.dist_dir)
-                        .fallback(ServeFile::new(state.dist_dir.join(INDEX_HTML))),
-                )
-                .handle_error(|error| async move {
-                    tracing::error!(?error, "failed serving static file");
-                    StatusCode::INTERNAL_SERVER_ERROR
-                })
-                .layer(TraceLayer::new_for_http()),
-            ),
-        )
+        .fallback_service(static_files)
         .route(
             "/_trunk/ws",
             get(
@@ -247,81 +672,284 @@ fn router(state: Arc<State>, cfg: Arc<RtcServe>) -> Router {
     );
 
     // Build proxies.
-    if let Some(backend) = &cfg.proxy_backend {
-        if cfg.proxy_ws {
-            let handler = ProxyHandlerWebSocket::new(backend.clone(), cfg.proxy_rewrite.clone());
+    for proxy in cfg.proxies.iter() {
+        let name = match &proxy.name {
+            Some(name) => format!("{:?} ", name),
+            None => String::new(),
+        };
+        if proxy.ws {
+            let handler = ProxyHandlerWebSocket::new(proxy.clone());
             router = handler.clone().register(router);
             tracing::info!(
-                "{} proxying websocket {} -> {}",
+                "{} proxying websocket {}{} -> {}",
                 SERVER,
+                name,
                 handler.path(),
-                &backend
+                &proxy.backend
             );
         } else {
-            let client = if cfg.proxy_insecure {
-                state.insecure_client.clone()
-            } else {
-                state.client.clone()
-            };
-
-            let handler = ProxyHandlerHttp::new(client, backend.clone(), cfg.proxy_rewrite.clone());
+            let handler = ProxyHandlerHttp::new(proxy.clone())?;
             router = handler.clone().register(router);
-            tracing::info!("{} proxying {} -> {}", SERVER, handler.path(), &backend);
+            tracing::info!(
+                "{} proxying {}{} -> {}",
+                SERVER,
+                name,
+                handler.path(),
+                &proxy.backend
+            );
+        };
+    }
+
+    // Set the configured headers on all responses, including those of the proxies.
+    if !cfg.headers.is_empty() {
+        router = router.layer(middleware::from_fn_with_state(cfg.clone(), set_headers));
+    }
+    router = router.layer(middleware::from_fn_with_state(
+        cfg.clone(),
+        set_isolation_headers,
+    ));
+
+    // Check the credentials of all requests before handling them.
+    if cfg.auth.is_some() {
+        router = router.layer(middleware::from_fn_with_state(cfg.clone(), check_auth));
+    }
+
+    // Log all requests, including those rejected for missing credentials.
+    if let Some(request_log) = request_log {
+        router = router.layer(middleware::from_fn_with_state(
+            request_log,
+            request_log::log_requests,
+        ));
+    }
+
+    Ok(router)
+}
+
+/// The headers of the responses for the paths matching a glob.
+#[derive(Clone, Debug)]
+pub struct ServeHeaders {
+    /// The glob of the paths, relative to the root of the server.
+    pub glob: Pattern,
+    /// The headers to set, replacing any of the same name.
+    pub headers: HeaderMap,
+}
+
+impl ServeHeaders {
+    /// Create a new instance, parsing the given glob & headers.
+    pub fn new(glob: &str, headers: &HashMap<String, String>) -> Result<Self> {
+        let pattern = Pattern::new(glob)
+            .with_context(|| format!("invalid glob `{}` of `serve.headers`", glob))?;
+        let headers = headers
+            .iter()
+            .map(|(name, val)| {
+                let name = HeaderName::try_from(name.as_str()).with_context(|| {
+                    format!("invalid header name {:?} of `serve.headers`", name)
+                })?;
+                let val = HeaderValue::try_from(val.as_str()).with_context(|| {
+                    format!("invalid value of header {:?} of `serve.headers`", name)
+                })?;
+                Ok((name, val))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            glob: pattern,
+            headers,
+        })
+    }
+}
+
+/// Set the headers of the globs matching the path of the request on its response.
+async fn set_headers<B>(
+    axum::extract::State(cfg): axum::extract::State<Arc<RtcServe>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = req.uri().path().trim_start_matches('/').to_owned();
+    let mut res = next.run(req).await;
+    for headers in &cfg.headers {
+        if glob_matches(&headers.glob, &path) {
+            for (name, val) in &headers.headers {
+                res.headers_mut().insert(name.clone(), val.clone());
+            }
         }
-    } else if let Some(proxies) = &cfg.proxies {
-        for proxy in proxies.iter() {
-            if proxy.ws {
-                let handler =
-                    ProxyHandlerWebSocket::new(proxy.backend.clone(), proxy.rewrite.clone());
-                router = handler.clone().register(router);
-                tracing::info!(
-                    "{} proxying websocket {} -> {}",
-                    SERVER,
-                    handler.path(),
-                    &proxy.backend
-                );
-            } else {
-                let client = if proxy.insecure {
-                    state.insecure_client.clone()
-                } else {
-                    state.client.clone()
-                };
+    }
+    res
+}
+
+/// Set the headers enabling cross-origin isolation on all responses once a Rust app of the build
+/// uses wasm threads, which require `SharedArrayBuffer`, unless set via `serve.headers`.
+async fn set_isolation_headers<B>(
+    axum::extract::State(cfg): axum::extract::State<Arc<RtcServe>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut res = next.run(req).await;
+    if cfg
+        .watch
+        .build
+        .cross_origin_isolated
+        .load(Ordering::Relaxed)
+    {
+        let headers = res.headers_mut();
+        headers
+            .entry(HeaderName::from_static("cross-origin-opener-policy"))
+            .or_insert(HeaderValue::from_static("same-origin"));
+        headers
+            .entry(HeaderName::from_static("cross-origin-embedder-policy"))
+            .or_insert(HeaderValue::from_static("require-corp"));
+    }
+    res
+}
+
+/// The name of the cookie & the query parameter carrying the access token of the server.
+const TOKEN_PARAM: &str = "trunk_token";
 
-                let handler =
-                    ProxyHandlerHttp::new(client, proxy.backend.clone(), proxy.rewrite.clone());
-                router = handler.clone().register(router);
-                tracing::info!(
-                    "{} proxying {} -> {}",
-                    SERVER,
-                    handler.path(),
-                    &proxy.backend
-                );
-            };
+/// Check the credentials of the request, answering with a 401 if they are missing or invalid.
+///
+/// Credentials sent via the `Authorization` header are removed, so that they are not passed on to
+/// proxy backends. An access token given as a query parameter is stored in a cookie, so that it
+/// is sent along with all subsequent requests of the page.
+async fn check_auth<B>(
+    axum::extract::State(cfg): axum::extract::State<Arc<RtcServe>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let header_matches = |expected: &str| {
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .is_some_and(|val| constant_time_eq(val.as_bytes(), expected.as_bytes()))
+    };
+    // Whether the credentials were sent via the header or otherwise.
+    let (in_header, otherwise, set_cookie) = match &cfg.auth {
+        None => (false, true, None),
+        Some(ServeAuth::Basic { user, password }) => {
+            let credentials = BASE64.encode(format!("{}:{}", user, password));
+            (
+                header_matches(&format!("Basic {}", credentials)),
+                false,
+                None,
+            )
+        }
+        Some(ServeAuth::Token(token)) => {
+            let token_matches = |val: &str| constant_time_eq(val.as_bytes(), token.as_bytes());
+            let in_cookie = req
+                .headers()
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|val| val.to_str().ok())
+                .flat_map(|val| val.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .any(|(name, val)| name == TOKEN_PARAM && token_matches(val));
+            let in_query = req
+                .uri()
+                .query()
+                .into_iter()
+                .flat_map(|query| query.split('&'))
+                .filter_map(|param| param.split_once('='))
+                .any(|(name, val)| name == TOKEN_PARAM && token_matches(val));
+            let set_cookie = (in_query && !in_cookie).then(|| {
+                format!(
+                    "{}={}; Path=/; HttpOnly; SameSite=Strict",
+                    TOKEN_PARAM, token
+                )
+            });
+            (
+                header_matches(&format!("Bearer {}", token)),
+                in_cookie || in_query,
+                set_cookie,
+            )
+        }
+    };
+    if !in_header && !otherwise {
+        let mut res = (StatusCode::UNAUTHORIZED, "unauthorized").into_response();
+        if let Some(ServeAuth::Basic { .. }) = &cfg.auth {
+            res.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Basic realm="trunk", charset="UTF-8""#),
+            );
         }
+        return res;
     }
 
-    router
+    if in_header {
+        req.headers_mut().remove(header::AUTHORIZATION);
+    }
+    let mut res = next.run(req).await;
+    if let Some(cookie) = set_cookie.and_then(|cookie| HeaderValue::try_from(cookie).ok()) {
+        res.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    res
+}
+
+/// Compare the given values in constant time for equal lengths, not leaking their common prefix
+/// via the timing of the comparison.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
 }
 
 async fn handle_ws(mut ws: WebSocket, state: Arc<State>) {
-    let mut rx = state.build_done_chan.subscribe();
+    let mut rx = state.build_events.subscribe();
     tracing::debug!("autoreload websocket opened");
-    while tokio::select! {
-        _ = ws.recv() => {
-            tracing::debug!("autoreload websocket closed");
-            return
-        }
-        build_done = rx.recv() => build_done.is_ok(),
-    } {
-        let ws_send = ws.send(axum::extract::ws::Message::Text(
-            r#"{"reload": true}"#.to_owned(),
-        ));
+    let mut pending = vec![serde_json::json!({
+        "type": "hello",
+        "version": AUTORELOAD_PROTOCOL_VERSION,
+    })];
+    // Show the error of the last build on pages loaded after it failed.
+    let build_error = state
+        .build_error
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone();
+    if let Some(html) = build_error {
+        pending.push(event_message(BuildEvent::Done(Err(html)), &state));
+    }
+    let mut pending = pending.into_iter();
+    loop {
+        let msg = match pending.next() {
+            Some(msg) => msg,
+            None => tokio::select! {
+                _ = ws.recv() => {
+                    tracing::debug!("autoreload websocket closed");
+                    return
+                }
+                event = rx.recv() => match event {
+                    Ok(event) => event_message(event, &state),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            },
+        };
+        let ws_send = ws.send(axum::extract::ws::Message::Text(msg.to_string()));
         if ws_send.await.is_err() {
             break;
         }
     }
 }
 
+/// The message of the autoreload protocol for the given build event.
+///
+/// After a successful build, the page is reloaded, or its stylesheets are swapped & its wasm module
+/// is hot reloaded. After a failed one, an error overlay is shown.
+fn event_message(event: BuildEvent, state: &State) -> serde_json::Value {
+    match event {
+        BuildEvent::Started => serde_json::json!({ "type": "build-started" }),
+        BuildEvent::Progress(message) => {
+            serde_json::json!({ "type": "progress", "message": message })
+        }
+        BuildEvent::AssetsChanged(paths) => {
+            serde_json::json!({ "type": "asset-changed", "paths": paths })
+        }
+        BuildEvent::Done(Ok(Reload::Css(css))) => {
+            serde_json::json!({ "type": "success", "action": "swap-css", "css": css })
+        }
+        BuildEvent::Done(Ok(Reload::Module { module, css })) if state.hmr => {
+            serde_json::json!({ "type": "success", "action": "hmr", "hmr": module, "css": css })
+        }
+        BuildEvent::Done(Ok(_)) => serde_json::json!({ "type": "success", "action": "reload" }),
+        BuildEvent::Done(Err(html)) => serde_json::json!({ "type": "failure", "error": html }),
+    }
+}
+
 /// A result type used to work seamlessly with axum.
 pub(crate) type ServerResult<T> = std::result::Result<T, ServerError>;
 
@@ -0,0 +1,254 @@
+//! The status line of the CLI, rendering the progress of builds below the logs.
+//!
+//! Log lines are written via [`Writer`] & the output of commands via the output of [`progress`],
+//! which clear the status line before each line & draw it again afterwards, so that it always stays
+//! at the bottom of the terminal.
+
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, MutexGuard};
+
+use console::{style, Term};
+use tokio::sync::broadcast;
+use tracing_subscriber::fmt::MakeWriter;
+use trunk_pipelines::progress::{self, ProgressEvent};
+
+/// The state of the status line.
+static STATUS: Mutex<Status> = Mutex::new(Status::new());
+
+/// Render the progress of all builds in a status line, if stdout is a terminal.
+pub fn spawn() {
+    if !io::stdout().is_terminal() {
+        return;
+    }
+    progress::set_output(|bytes| {
+        let mut status = status();
+        let mut out = io::stdout().lock();
+        status.clear(&mut out);
+        let _ = out.flush();
+        let _ = io::stderr().write_all(bytes);
+        // The status line is only drawn below complete lines.
+        if bytes.ends_with(b"\n") {
+            status.draw(&mut out);
+            let _ = out.flush();
+        }
+    });
+    // Subscribed right away, so that no events of the first build are missed.
+    let mut events = progress::subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let mut status = status();
+                    let mut out = io::stdout().lock();
+                    status.clear(&mut out);
+                    status.update(event);
+                    status.draw(&mut out);
+                    let _ = out.flush();
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Lock the state of the status line, even if a thread panicked while holding it.
+fn status() -> MutexGuard<'static, Status> {
+    STATUS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// The progress of the builds, as shown by the status line.
+struct Status {
+    /// Whether the status line is currently drawn.
+    drawn: bool,
+    /// Whether a build is running.
+    building: bool,
+    /// The current stage of the build.
+    stage: Option<String>,
+    /// The running asset pipelines, by ID.
+    running: BTreeMap<usize, String>,
+    /// The number of asset pipelines started & finished by the build.
+    started: usize,
+    finished: usize,
+    /// The running downloads of tools, with the bytes downloaded & the total size if known.
+    downloads: BTreeMap<String, (u64, Option<u64>)>,
+}
+
+impl Status {
+    const fn new() -> Self {
+        Self {
+            drawn: false,
+            building: false,
+            stage: None,
+            running: BTreeMap::new(),
+            started: 0,
+            finished: 0,
+            downloads: BTreeMap::new(),
+        }
+    }
+
+    /// Update the progress with the given event.
+    fn update(&mut self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::BuildStarted => {
+                *self = Self {
+                    building: true,
+                    ..Self::new()
+                };
+            }
+            ProgressEvent::Stage(stage) => self.stage = Some(stage),
+            ProgressEvent::PipelineStarted { id, asset } => {
+                self.running.insert(id, asset);
+                self.started += 1;
+            }
+            ProgressEvent::PipelineFinished { id, .. } => {
+                self.running.remove(&id);
+                self.finished += 1;
+            }
+            ProgressEvent::Download {
+                tool,
+                downloaded,
+                total,
+            } => match total {
+                Some(total) if downloaded >= total => {
+                    self.downloads.remove(&tool);
+                }
+                _ => {
+                    self.downloads.insert(tool, (downloaded, total));
+                }
+            },
+            // Diagnostics are written as output.
+            ProgressEvent::Diagnostic { .. } => {}
+            ProgressEvent::BuildFinished { .. } => *self = Self::new(),
+        }
+    }
+
+    /// The text of the status line, if there is any progress to show.
+    fn line(&self) -> Option<String> {
+        if !self.building && self.downloads.is_empty() {
+            return None;
+        }
+        let mut parts = Vec::new();
+        parts.extend(self.stage.clone());
+        if self.started > 0 {
+            parts.push(format!("{}/{} assets", self.finished, self.started));
+        }
+        if !self.running.is_empty() {
+            parts.push(
+                self.running
+                    .values()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+        for (tool, (downloaded, total)) in &self.downloads {
+            parts.push(match total {
+                Some(total) => format!("downloading {} {}%", tool, downloaded * 100 / total),
+                None => format!(
+                    "downloading {} {:.1}MB",
+                    tool,
+                    *downloaded as f64 / 1_000_000.0
+                ),
+            });
+        }
+        (!parts.is_empty()).then(|| parts.join(" · "))
+    }
+
+    /// Clear the status line, if drawn.
+    fn clear(&mut self, out: &mut impl Write) {
+        if self.drawn {
+            let _ = write!(out, "\r\x1b[2K");
+            self.drawn = false;
+        }
+    }
+
+    /// Draw the status line, if there is any progress to show.
+    fn draw(&mut self, out: &mut impl Write) {
+        let Some(line) = self.line() else {
+            return;
+        };
+        // The line must not wrap, as only the last line of the terminal is cleared. The cursor is
+        // moved back to its start, so that the output of commands writing to the terminal directly
+        // overwrites it.
+        let width = Term::stdout().size().1 as usize;
+        let line = console::truncate_str(&line, width.saturating_sub(1), "…");
+        let _ = write!(out, "{}\r", style(line).dim());
+        self.drawn = true;
+    }
+}
+
+/// A writer of log lines, keeping the status line below them.
+pub struct Writer;
+
+impl<'a> MakeWriter<'a> for Writer {
+    type Writer = LineWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LineWriter(Vec::new())
+    }
+}
+
+/// A buffered log line, which is written out when dropped.
+pub struct LineWriter(Vec<u8>);
+
+impl Write for LineWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LineWriter {
+    fn drop(&mut self) {
+        let mut status = status();
+        let mut out = io::stdout().lock();
+        status.clear(&mut out);
+        let _ = out.write_all(&self.0);
+        status.draw(&mut out);
+        let _ = out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_shows_stage_pipelines_and_downloads() {
+        let mut status = Status::new();
+        assert_eq!(status.line(), None);
+        status.update(ProgressEvent::BuildStarted);
+        status.update(ProgressEvent::Stage("building assets".into()));
+        for (id, asset) in ["css a.css", "scss s.scss"].into_iter().enumerate() {
+            status.update(ProgressEvent::PipelineStarted {
+                id,
+                asset: asset.into(),
+            });
+        }
+        status.update(ProgressEvent::PipelineFinished {
+            id: 0,
+            asset: "css a.css".into(),
+        });
+        status.update(ProgressEvent::Download {
+            tool: "sass".into(),
+            downloaded: 250,
+            total: Some(1000),
+        });
+        assert_eq!(
+            status.line().as_deref(),
+            Some("building assets · 1/2 assets · scss s.scss · downloading sass 25%")
+        );
+        status.update(ProgressEvent::BuildFinished {
+            success: true,
+            duration: Default::default(),
+            artifacts: Default::default(),
+        });
+        assert_eq!(status.line(), None);
+    }
+}
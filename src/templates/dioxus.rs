@@ -0,0 +1,16 @@
+use dioxus::prelude::*;
+
+#[component]
+fn App() -> Element {
+    let mut count = use_signal(|| 0);
+    rsx! {
+        main {
+            h1 { "Hello from {{project-name}}!" }
+            button { onclick: move |_| count += 1, "Clicked {count} times" }
+        }
+    }
+}
+
+fn main() {
+    launch(App);
+}
@@ -0,0 +1,19 @@
+use leptos::*;
+
+#[component]
+fn App() -> impl IntoView {
+    let (count, set_count) = create_signal(0);
+    view! {
+        <main>
+            <h1>"Hello from {{project-name}}!"</h1>
+            <button on:click=move |_| set_count.update(|count| *count += 1)>
+                "Clicked " {count} " times"
+            </button>
+        </main>
+    }
+}
+
+fn main() {
+    console_error_panic_hook::set_once();
+    mount_to_body(|| view! { <App/> });
+}
@@ -0,0 +1,15 @@
+use web_sys::window;
+
+fn main() {
+    console_error_panic_hook::set_once();
+    let document = window()
+        .and_then(|window| window.document())
+        .expect("error accessing the document");
+    let body = document.body().expect("error accessing the body");
+    let heading = document
+        .create_element("h1")
+        .expect("error creating the heading");
+    heading.set_text_content(Some("Hello from {{project-name}}!"));
+    body.append_child(&heading)
+        .expect("error appending the heading");
+}
@@ -0,0 +1,13 @@
+// Bootstrap a thread of a Rust app built with wasm threads, run as a module worker.
+//
+// The first message sent to the worker must be an object with the compiled `module` & the shared
+// `memory` of the app, which are used to instantiate it in the worker. If it names an `entry`
+// export of the app, this is called with the `args` of the message afterwards.
+import init, * as bindings from './{js}';
+
+self.addEventListener('message', async ({ data: { module, memory, entry, args = [] } }) => {
+    await init(module, memory);
+    if (entry) {
+        bindings[entry](...args);
+    }
+}, { once: true });
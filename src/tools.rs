@@ -1,23 +1,26 @@
 //! Download management for external tools and applications. Locate and automatically download
 //! applications (if needed) to use them in the build pipeline.
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, ensure, Context, Result};
+use cargo_metadata::semver::{Version, VersionReq};
 use directories::ProjectDirs;
 use futures_util::stream::StreamExt;
-use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 use tokio::sync::{Mutex, OnceCell};
 
 use self::archive::Archive;
-use crate::common::is_executable;
+use crate::common::{is_executable, remove_dir_all};
+use crate::progress::{self, ProgressEvent};
 
-/// The application to locate and eventually download when calling [`get`].
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// The application to locate and eventually download when calling [`ToolContext::get`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Application {
     /// sass for generating css
     Sass,
@@ -27,6 +30,33 @@ pub enum Application {
     WasmBindgen,
     /// wasm-opt to improve performance and size of the output file further.
     WasmOpt,
+    /// oxipng for lossless optimization of PNG images.
+    Oxipng,
+    /// jpegoptim for optimizing JPEG images. This is never downloaded and must be installed on the
+    /// system.
+    Jpegoptim,
+    /// cwebp for converting images to WebP.
+    Cwebp,
+    /// avifenc for converting images to AVIF. This is never downloaded and must be installed on the
+    /// system.
+    Avifenc,
+    /// postcss-cli for transforming css. Unless installed on the system, the postcss package is
+    /// downloaded from npm, and run by a script of trunk on the node installed on the system.
+    Postcss,
+    /// lessc for generating css from less. This is downloaded from npm, and runs on the node
+    /// installed on the system.
+    Lessc,
+    /// esbuild for transforming & minifying JS and TypeScript.
+    Esbuild,
+    /// ImageMagick for generating icons. This is never downloaded and must be installed on the
+    /// system.
+    Magick,
+    /// pyftsubset (part of fonttools) for subsetting fonts. This is never downloaded and must be
+    /// installed on the system.
+    Pyftsubset,
+    /// pandoc for converting markdown to html. This is never downloaded and must be installed on
+    /// the system.
+    Pandoc,
 }
 
 impl Application {
@@ -37,17 +67,82 @@ impl Application {
             Self::TailwindCss => "tailwindcss",
             Self::WasmBindgen => "wasm-bindgen",
             Self::WasmOpt => "wasm-opt",
+            Self::Oxipng => "oxipng",
+            Self::Jpegoptim => "jpegoptim",
+            Self::Cwebp => "cwebp",
+            Self::Avifenc => "avifenc",
+            Self::Postcss => "postcss",
+            Self::Lessc => "lessc",
+            Self::Esbuild => "esbuild",
+            Self::Magick => "magick",
+            Self::Pyftsubset => "pyftsubset",
+            Self::Pandoc => "pandoc",
+        }
+    }
+
+    /// The applications which can be downloaded automatically.
+    pub const DOWNLOADABLE: [Self; 9] = [
+        Self::Sass,
+        Self::TailwindCss,
+        Self::WasmBindgen,
+        Self::WasmOpt,
+        Self::Oxipng,
+        Self::Cwebp,
+        Self::Esbuild,
+        Self::Lessc,
+        Self::Postcss,
+    ];
+
+    /// The downloadable application of the given name, such as `wasm-bindgen` or `wasm_bindgen`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.replace('_', "-");
+        Self::DOWNLOADABLE
+            .into_iter()
+            .find(|app| app.name() == name)
+    }
+
+    /// Whether the application can be downloaded automatically, rather than having to be installed
+    /// on the system.
+    fn is_downloadable(&self) -> bool {
+        Self::DOWNLOADABLE.contains(self)
+    }
+
+    /// The base URL of the releases of a downloadable application, which a mirror replaces.
+    fn release_url(&self) -> Option<&'static str> {
+        match self {
+            Self::Sass => Some("https://github.com/sass/dart-sass/releases/download"),
+            Self::TailwindCss => {
+                Some("https://github.com/tailwindlabs/tailwindcss/releases/download")
+            }
+            Self::WasmBindgen => Some("https://github.com/rustwasm/wasm-bindgen/releases/download"),
+            Self::WasmOpt => Some("https://github.com/WebAssembly/binaryen/releases/download"),
+            Self::Oxipng => Some("https://github.com/shssoichiro/oxipng/releases/download"),
+            Self::Cwebp => {
+                Some("https://storage.googleapis.com/downloads.webmproject.org/releases/webp")
+            }
+            Self::Esbuild | Self::Lessc | Self::Postcss => Some("https://registry.npmjs.org"),
+            _ => None,
         }
     }
 
     /// Path of the executable within the downloaded archive.
-    fn path(&self) -> &str {
+    pub fn path(&self) -> &str {
         if cfg!(target_os = "windows") {
             match self {
                 Self::Sass => "sass.bat",
                 Self::TailwindCss => "tailwindcss.exe",
                 Self::WasmBindgen => "wasm-bindgen.exe",
                 Self::WasmOpt => "bin/wasm-opt.exe",
+                Self::Oxipng => "oxipng.exe",
+                Self::Jpegoptim => "jpegoptim.exe",
+                Self::Cwebp => "bin/cwebp.exe",
+                Self::Avifenc => "avifenc.exe",
+                Self::Postcss => "postcss.cmd",
+                Self::Lessc => "lessc.cmd",
+                Self::Esbuild => "esbuild.exe",
+                Self::Magick => "magick.exe",
+                Self::Pyftsubset => "pyftsubset.exe",
+                Self::Pandoc => "pandoc.exe",
             }
         } else {
             match self {
@@ -55,6 +150,16 @@ impl Application {
                 Self::TailwindCss => "tailwindcss",
                 Self::WasmBindgen => "wasm-bindgen",
                 Self::WasmOpt => "bin/wasm-opt",
+                Self::Oxipng => "oxipng",
+                Self::Jpegoptim => "jpegoptim",
+                Self::Cwebp => "bin/cwebp",
+                Self::Avifenc => "avifenc",
+                Self::Postcss => "bin/postcss",
+                Self::Lessc => "node_modules/less/bin/lessc",
+                Self::Esbuild => "bin/esbuild",
+                Self::Magick => "magick",
+                Self::Pyftsubset => "pyftsubset",
+                Self::Pandoc => "pandoc",
             }
         }
     }
@@ -71,77 +176,234 @@ impl Application {
             }
             Self::TailwindCss => &[],
             Self::WasmBindgen => &[],
+            // wasm-split is released along with wasm-opt.
             Self::WasmOpt => {
                 if cfg!(target_os = "macos") {
-                    &["lib/libbinaryen.dylib"]
+                    &["bin/wasm-split", "lib/libbinaryen.dylib"]
+                } else if cfg!(target_os = "windows") {
+                    &["bin/wasm-split.exe"]
                 } else {
-                    &[]
+                    &["bin/wasm-split"]
                 }
             }
+            Self::Oxipng => &[],
+            Self::Jpegoptim => &[],
+            Self::Cwebp => &[],
+            Self::Avifenc => &[],
+            Self::Postcss => &[],
+            Self::Lessc => &[],
+            Self::Esbuild => &[],
+            Self::Magick => &[],
+            Self::Pyftsubset => &[],
+            Self::Pandoc => &[],
+        }
+    }
+
+    /// The npm package of an application released as JS, which runs on the node installed on the
+    /// system, along with the names & versions of the packages it depends on.
+    ///
+    /// The packages are installed into the `node_modules` dir of the application dir, where the
+    /// dependencies are pinned to versions supported by all releases of the same major version.
+    /// postcss is a library, which is run by the [`POSTCSS_SCRIPT`] of trunk.
+    fn npm_package(&self) -> Option<(&'static str, &'static [(&'static str, &'static str)])> {
+        match self {
+            Self::Lessc => Some((
+                "less",
+                &[
+                    ("copy-anything", "2.0.6"),
+                    ("is-what", "3.14.1"),
+                    ("parse-node-version", "1.0.1"),
+                    ("tslib", "2.6.2"),
+                ],
+            )),
+            Self::Postcss => Some((
+                "postcss",
+                &[
+                    ("nanoid", "3.3.7"),
+                    ("picocolors", "1.0.0"),
+                    ("source-map-js", "1.0.2"),
+                ],
+            )),
+            _ => None,
         }
     }
 
     /// Default version to use if not set by the user.
-    fn default_version(&self) -> &str {
+    pub fn default_version(&self) -> &'static str {
         match self {
             Self::Sass => "1.63.6",
             Self::TailwindCss => "3.3.2",
             Self::WasmBindgen => "0.2.87",
             Self::WasmOpt => "version_113",
+            Self::Oxipng => "8.0.0",
+            Self::Jpegoptim => "1.5.5",
+            Self::Cwebp => "1.3.1",
+            Self::Avifenc => "1.0.1",
+            Self::Postcss => "8.4.31",
+            Self::Lessc => "4.1.3",
+            Self::Esbuild => "0.18.11",
+            Self::Magick => "7.1.1-15",
+            Self::Pyftsubset => "4.42.1",
+            Self::Pandoc => "3.1.8",
         }
     }
 
-    /// Direct URL to the release of an application for download.
-    fn url(&self, version: &str) -> Result<String> {
-        let target_os = if cfg!(target_os = "windows") {
-            "windows"
-        } else if cfg!(target_os = "macos") {
-            "macos"
-        } else if cfg!(target_os = "linux") {
-            "linux"
-        } else {
-            bail!("unsupported OS")
-        };
+    /// Direct URL to the release of an application for download, from the given mirror of its
+    /// releases, if any.
+    fn url(&self, version: &str, mirror: Option<&str>) -> Result<String> {
+        self.url_for(Platform::current()?, version, mirror)
+    }
 
-        let target_arch = if cfg!(target_arch = "x86_64") {
-            "x86_64"
-        } else if cfg!(target_arch = "aarch64") {
-            "aarch64"
-        } else {
-            bail!("unsupported target architecture")
-        };
+    /// Direct URL to the release of an application for the given platform.
+    fn url_for(&self, platform: Platform, version: &str, mirror: Option<&str>) -> Result<String> {
+        let base = mirror
+            .or(self.release_url())
+            .unwrap_or_default()
+            .trim_end_matches('/');
+        let Platform {
+            os: target_os,
+            arch: target_arch,
+            musl,
+        } = platform;
 
         Ok(match self {
             Self::Sass => match (target_os, target_arch) {
-              ("windows", "x86_64") => format!("https://github.com/sass/dart-sass/releases/download/{version}/dart-sass-{version}-windows-x64.zip"),
-              ("macos" | "linux", "x86_64") => format!("https://github.com/sass/dart-sass/releases/download/{version}/dart-sass-{version}-{target_os}-x64.tar.gz"),
-              ("macos" | "linux", "aarch64") => format!("https://github.com/sass/dart-sass/releases/download/{version}/dart-sass-{version}-{target_os}-arm64.tar.gz"),
-              _ => bail!("Unable to download Sass for {target_os} {target_arch}")
+              ("windows", "x86_64") => format!("{base}/{version}/dart-sass-{version}-windows-x64.zip"),
+              ("linux", "x86_64") if musl => format!("{base}/{version}/dart-sass-{version}-linux-x64-musl.tar.gz"),
+              ("linux", "aarch64") if musl => format!("{base}/{version}/dart-sass-{version}-linux-arm64-musl.tar.gz"),
+              ("linux", "armv7") if musl => format!("{base}/{version}/dart-sass-{version}-linux-arm-musl.tar.gz"),
+              ("macos" | "linux", "x86_64") => format!("{base}/{version}/dart-sass-{version}-{target_os}-x64.tar.gz"),
+              ("macos" | "linux", "aarch64") => format!("{base}/{version}/dart-sass-{version}-{target_os}-arm64.tar.gz"),
+              ("linux", "armv7") => format!("{base}/{version}/dart-sass-{version}-linux-arm.tar.gz"),
+              _ => return Err(self.unsupported(platform, version)),
             },
 
-            Self::TailwindCss => match (target_os, target_arch) {
-                ("windows", "x86_64") => format!("https://github.com/tailwindlabs/tailwindcss/releases/download/v{version}/tailwindcss-windows-x64.exe"),
-                ("macos" | "linux", "x86_64") => format!("https://github.com/tailwindlabs/tailwindcss/releases/download/v{version}/tailwindcss-{target_os}-x64"),
-                ("macos" | "linux", "aarch64") => format!("https://github.com/tailwindlabs/tailwindcss/releases/download/v{version}/tailwindcss-{target_os}-arm64"),
-                _ => bail!("Unable to download tailwindcss for {target_os} {target_arch}")
-            },
+            Self::TailwindCss => {
+                // Only the standalone CLI of v4 is built for musl.
+                let v4 = parse_version(version).is_some_and(|version| version.major >= 4);
+                match (target_os, target_arch) {
+                    ("windows", "x86_64") => format!("{base}/v{version}/tailwindcss-windows-x64.exe"),
+                    ("windows", "aarch64") => format!("{base}/v{version}/tailwindcss-windows-arm64.exe"),
+                    ("linux", "x86_64") if musl && v4 => format!("{base}/v{version}/tailwindcss-linux-x64-musl"),
+                    ("linux", "aarch64") if musl && v4 => format!("{base}/v{version}/tailwindcss-linux-arm64-musl"),
+                    ("linux", _) if musl => return Err(self.unsupported(platform, version)),
+                    ("macos" | "linux", "x86_64") => format!("{base}/v{version}/tailwindcss-{target_os}-x64"),
+                    ("macos" | "linux", "aarch64") => format!("{base}/v{version}/tailwindcss-{target_os}-arm64"),
+                    ("linux", "armv7") if !v4 => format!("{base}/v{version}/tailwindcss-linux-armv7"),
+                    _ => return Err(self.unsupported(platform, version)),
+                }
+            }
 
             Self::WasmBindgen => match (target_os, target_arch) {
-              ("windows", "x86_64") => format!("https://github.com/rustwasm/wasm-bindgen/releases/download/{version}/wasm-bindgen-{version}-x86_64-pc-windows-msvc.tar.gz"),
-              ("macos", "x86_64") => format!("https://github.com/rustwasm/wasm-bindgen/releases/download/{version}/wasm-bindgen-{version}-x86_64-apple-darwin.tar.gz"),
-              ("macos", "aarch64") => format!("https://github.com/rustwasm/wasm-bindgen/releases/download/{version}/wasm-bindgen-{version}-aarch64-apple-darwin.tar.gz"),
-              ("linux", "x86_64") => format!("https://github.com/rustwasm/wasm-bindgen/releases/download/{version}/wasm-bindgen-{version}-x86_64-unknown-linux-musl.tar.gz"),
-              ("linux", "aarch64") => format!("https://github.com/rustwasm/wasm-bindgen/releases/download/{version}/wasm-bindgen-{version}-aarch64-unknown-linux-gnu.tar.gz"),
-              _ => bail!("Unable to download wasm-bindgen for {target_os} {target_arch}")
+              ("windows", "x86_64") => format!("{base}/{version}/wasm-bindgen-{version}-x86_64-pc-windows-msvc.tar.gz"),
+              ("macos", "x86_64") => format!("{base}/{version}/wasm-bindgen-{version}-x86_64-apple-darwin.tar.gz"),
+              ("macos", "aarch64") => format!("{base}/{version}/wasm-bindgen-{version}-aarch64-apple-darwin.tar.gz"),
+              ("linux", "x86_64") => format!("{base}/{version}/wasm-bindgen-{version}-x86_64-unknown-linux-musl.tar.gz"),
+              ("linux", "aarch64") if !musl => format!("{base}/{version}/wasm-bindgen-{version}-aarch64-unknown-linux-gnu.tar.gz"),
+              _ => return Err(self.unsupported(platform, version)),
             },
 
+            // The Linux builds of binaryen are statically linked, so they run on musl as well.
             Self::WasmOpt => match (target_os, target_arch) {
-              ("macos", "aarch64") => format!("https://github.com/WebAssembly/binaryen/releases/download/{version}/binaryen-{version}-arm64-macos.tar.gz"),
-              _ => format!("https://github.com/WebAssembly/binaryen/releases/download/{version}/binaryen-{version}-{target_arch}-{target_os}.tar.gz")
-            }
+              ("macos", "aarch64") => format!("{base}/{version}/binaryen-{version}-arm64-macos.tar.gz"),
+              ("windows" | "macos" | "linux", "x86_64") | ("linux", "aarch64") => format!("{base}/{version}/binaryen-{version}-{target_arch}-{target_os}.tar.gz"),
+              _ => return Err(self.unsupported(platform, version)),
+            },
+
+            Self::Oxipng => match (target_os, target_arch) {
+              ("windows", "x86_64") => format!("{base}/v{version}/oxipng-{version}-x86_64-pc-windows-msvc.zip"),
+              ("macos", "x86_64") => format!("{base}/v{version}/oxipng-{version}-x86_64-apple-darwin.tar.gz"),
+              ("macos", "aarch64") => format!("{base}/v{version}/oxipng-{version}-aarch64-apple-darwin.tar.gz"),
+              ("linux", "x86_64") => format!("{base}/v{version}/oxipng-{version}-x86_64-unknown-linux-musl.tar.gz"),
+              ("linux", "aarch64") if !musl => format!("{base}/v{version}/oxipng-{version}-aarch64-unknown-linux-gnu.tar.gz"),
+              _ => return Err(self.unsupported(platform, version)),
+            },
+
+            Self::Jpegoptim => bail!("jpegoptim can not be downloaded automatically, please install it on your system"),
+
+            Self::Cwebp => match (target_os, target_arch) {
+              ("windows", "x86_64") => format!("{base}/libwebp-{version}-windows-x64.zip"),
+              ("macos", "x86_64") => format!("{base}/libwebp-{version}-mac-x86-64.tar.gz"),
+              ("macos", "aarch64") => format!("{base}/libwebp-{version}-mac-arm64.tar.gz"),
+              ("linux", "x86_64") if !musl => format!("{base}/libwebp-{version}-linux-x86-64.tar.gz"),
+              _ => return Err(self.unsupported(platform, version)),
+            },
+
+            Self::Avifenc => bail!("avifenc can not be downloaded automatically, please install it on your system"),
+
+            // less & postcss are plain JS, run by the node installed on the system.
+            Self::Postcss => format!("{base}/postcss/-/postcss-{version}.tgz"),
+
+            Self::Lessc => format!("{base}/less/-/less-{version}.tgz"),
+
+            // esbuild is statically linked, so it runs on musl as well.
+            Self::Esbuild => match (target_os, target_arch) {
+              ("windows", "x86_64") => format!("{base}/@esbuild/win32-x64/-/win32-x64-{version}.tgz"),
+              ("windows", "aarch64") => format!("{base}/@esbuild/win32-arm64/-/win32-arm64-{version}.tgz"),
+              ("macos", "x86_64") => format!("{base}/@esbuild/darwin-x64/-/darwin-x64-{version}.tgz"),
+              ("macos", "aarch64") => format!("{base}/@esbuild/darwin-arm64/-/darwin-arm64-{version}.tgz"),
+              ("linux", "x86_64") => format!("{base}/@esbuild/linux-x64/-/linux-x64-{version}.tgz"),
+              ("linux", "aarch64") => format!("{base}/@esbuild/linux-arm64/-/linux-arm64-{version}.tgz"),
+              ("linux", "armv7") => format!("{base}/@esbuild/linux-arm/-/linux-arm-{version}.tgz"),
+              ("freebsd", "x86_64") => format!("{base}/@esbuild/freebsd-x64/-/freebsd-x64-{version}.tgz"),
+              ("freebsd", "aarch64") => format!("{base}/@esbuild/freebsd-arm64/-/freebsd-arm64-{version}.tgz"),
+              _ => return Err(self.unsupported(platform, version)),
+            },
+
+            Self::Magick => bail!("magick can not be downloaded automatically, please install ImageMagick 7 on your system"),
+
+            Self::Pyftsubset => bail!("pyftsubset can not be downloaded automatically, please install fonttools on your system"),
+
+            Self::Pandoc => bail!("pandoc can not be downloaded automatically, please install it on your system"),
         })
     }
 
+    /// Direct URLs to all release archives of an application, from the given mirror of its
+    /// releases, if any. These are the URL of the release itself, followed by the URLs of the npm
+    /// packages it depends on, if any.
+    fn urls(&self, version: &str, mirror: Option<&str>) -> Result<Vec<String>> {
+        let mut urls = vec![self.url(version, mirror)?];
+        if let Some((_, dependencies)) = self.npm_package() {
+            let base = mirror
+                .or(self.release_url())
+                .unwrap_or_default()
+                .trim_end_matches('/');
+            urls.extend(
+                dependencies
+                    .iter()
+                    .map(|(name, version)| format!("{base}/{name}/-/{name}-{version}.tgz")),
+            );
+        }
+        Ok(urls)
+    }
+
+    /// The error of an application which is not released for the given platform, with a hint on
+    /// how to install it on the system instead.
+    fn unsupported(&self, platform: Platform, version: &str) -> anyhow::Error {
+        let hint = match self {
+            Self::Sass => format!("`npm install -g sass@{version}`"),
+            Self::TailwindCss if parse_version(version).is_some_and(|v| v.major >= 4) => {
+                format!("`npm install -g @tailwindcss/cli@{version}`")
+            }
+            Self::TailwindCss => format!("`npm install -g tailwindcss@{version}`"),
+            Self::WasmBindgen => format!("`cargo install wasm-bindgen-cli --version {version}`"),
+            Self::Oxipng => format!("`cargo install oxipng --version {version}`"),
+            Self::Esbuild => format!("`npm install -g esbuild@{version}`"),
+            Self::Lessc => format!("`npm install -g less@{version}`"),
+            Self::Postcss => format!("`npm install -g postcss@{version} postcss-cli`"),
+            Self::WasmOpt => "the binaryen package of your system".into(),
+            _ => "the package manager of your system".into(),
+        };
+        anyhow::anyhow!(
+            "{} {} is not released for {}, please install it via {} & configure `tools.{} = \"system\"`",
+            self.name(),
+            version,
+            platform.name(),
+            hint,
+            self.name().replace('-', "_")
+        )
+    }
+
     /// The CLI subcommand, flag or option used to check the application's version.
     fn version_test(&self) -> &'static str {
         match self {
@@ -149,6 +411,16 @@ impl Application {
             Application::TailwindCss => "--help",
             Application::WasmBindgen => "--version",
             Application::WasmOpt => "--version",
+            Application::Oxipng => "--version",
+            Application::Jpegoptim => "--version",
+            Application::Cwebp => "-version",
+            Application::Avifenc => "--version",
+            Application::Postcss => "--version",
+            Application::Lessc => "--version",
+            Application::Esbuild => "--version",
+            Application::Magick => "--version",
+            Application::Pyftsubset => "--version",
+            Application::Pandoc => "--version",
         }
     }
 
@@ -172,6 +444,61 @@ impl Application {
                 .nth(1)
                 .with_context(|| format!("missing or malformed version output: {}", text))?
                 .to_owned(),
+            Application::Oxipng => text
+                .split(' ')
+                .nth(1)
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Jpegoptim => text
+                .split_whitespace()
+                .nth(1)
+                .map(|s| s.trim_start_matches('v'))
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Cwebp => text
+                .lines()
+                .next()
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Avifenc => text
+                .lines()
+                .next()
+                .and_then(|s| s.strip_prefix("Version: "))
+                .and_then(|s| s.split(' ').next())
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Postcss => text
+                .lines()
+                .next()
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Esbuild => text
+                .lines()
+                .next()
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Magick => text
+                .lines()
+                .next()
+                .and_then(|s| s.strip_prefix("Version: ImageMagick "))
+                .and_then(|s| s.split(' ').next())
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Pyftsubset => text
+                .lines()
+                .next()
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Pandoc => text
+                .split_whitespace()
+                .nth(1)
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
+            Application::Lessc => text
+                .split(' ')
+                .nth(1)
+                .with_context(|| format!("missing or malformed version output: {}", text))?
+                .to_owned(),
             Application::WasmOpt => format!(
                 "version_{}",
                 text.split(' ')
@@ -183,80 +510,519 @@ impl Application {
     }
 }
 
-/// Global, application wide app cache that keeps track of what tools have already been
-/// downloaded and installed to avoid duplicate installation runs.
-static GLOBAL_APP_CACHE: Lazy<Mutex<AppCache>> = Lazy::new(|| Mutex::new(AppCache::new()));
+/// A platform of the release archives of tools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Platform {
+    /// The OS, one of `windows`, `macos`, `linux` & `freebsd`.
+    os: &'static str,
+    /// The CPU architecture, one of `x86_64`, `aarch64` & `armv7`.
+    arch: &'static str,
+    /// Whether the C library is musl rather than glibc, so that only statically linked or musl
+    /// builds can be run.
+    musl: bool,
+}
+
+impl Platform {
+    /// The platform trunk runs on.
+    fn current() -> Result<Self> {
+        let os = if cfg!(target_os = "windows") {
+            "windows"
+        } else if cfg!(target_os = "macos") {
+            "macos"
+        } else if cfg!(target_os = "linux") {
+            "linux"
+        } else if cfg!(target_os = "freebsd") {
+            "freebsd"
+        } else {
+            bail!(
+                "tools can not be downloaded for the OS {}, please install them on your system & \
+                 configure their versions as `system`",
+                std::env::consts::OS
+            )
+        };
+
+        let arch = if cfg!(target_arch = "x86_64") {
+            "x86_64"
+        } else if cfg!(target_arch = "aarch64") {
+            "aarch64"
+        } else if cfg!(target_arch = "arm") {
+            "armv7"
+        } else {
+            bail!(
+                "tools can not be downloaded for the architecture {}, please install them on your \
+                 system & configure their versions as `system`",
+                std::env::consts::ARCH
+            )
+        };
+
+        Ok(Self {
+            os,
+            arch,
+            musl: cfg!(target_env = "musl"),
+        })
+    }
+
+    /// The name of the platform, such as `linux-x86_64` or `linux-musl-aarch64`.
+    fn name(&self) -> String {
+        match self.musl {
+            true => format!("{}-musl-{}", self.os, self.arch),
+            false => format!("{}-{}", self.os, self.arch),
+        }
+    }
+}
+
+/// The state of the tools used by a build: the configuration of their downloads, the lockfile
+/// pinning them & the tools installed so far.
+///
+/// Each build owns its instance, so that builds of different configs can run in the same process.
+#[derive(Debug, Default)]
+pub struct ToolContext {
+    /// The configuration of the downloads of tools.
+    downloads: Downloads,
+    /// The lockfile pinning the versions & checksums of downloaded tools, if any.
+    lockfile: Mutex<Option<Lockfile>>,
+    /// The tools installed so far.
+    installs: Mutex<AppCache>,
+}
 
 /// An app cache that does the actual download and installation of tools while keeping track of
-/// what has already been installed in the current trunk execution.
+/// what has already been installed by the build.
 ///
 /// This cache doesn't keep track of any system-installed tools or the one's that have been
 /// installed in previous runs of trunk. It only helps in avoiding a download of the same tool
-/// concurrently during a single run of trunk.
-struct AppCache(HashMap<(Application, String), OnceCell<()>>);
+/// concurrently during a single build.
+#[derive(Debug, Default)]
+struct AppCache(HashMap<(Application, String), OnceCell<String>>);
 
 impl AppCache {
-    /// Create a new app cache.
-    fn new() -> Self {
-        Self(HashMap::new())
-    }
-
     /// Install the desired application of given version to the provided application directory. Or
     /// don't if it's already been installed.
+    ///
+    /// The downloaded archive must match the given SHA-256 checksum, if any. The checksum of the
+    /// archive is returned.
     async fn install_once(
         &mut self,
         app: Application,
         version: &str,
+        mirror: Option<&str>,
         app_dir: PathBuf,
-    ) -> Result<()> {
-        let cached = self
-            .0
-            .entry((app, version.to_owned()))
-            .or_insert_with(OnceCell::new);
+        checksum: Option<String>,
+    ) -> Result<String> {
+        let cached = self.0.entry((app, version.to_owned())).or_default();
 
         cached
             .get_or_try_init(|| async move {
-                let path = download(app, version)
+                if let Some((package, _)) = app.npm_package() {
+                    ensure!(
+                        which::which("node").is_ok(),
+                        "{} is downloaded as the npm package {}, which requires node, but node \
+                         was not found in PATH",
+                        app.name(),
+                        package
+                    );
+                }
+                let (paths, sha256) = download(app, version, mirror)
                     .await
                     .context("failed downloading release archive")?;
+                if let Some(checksum) = checksum.filter(|checksum| *checksum != sha256) {
+                    for path in &paths {
+                        tokio::fs::remove_file(path)
+                            .await
+                            .context("failed deleting temporary archive")?;
+                    }
+                    bail!(
+                        "the SHA-256 checksum {} of the downloaded {} {} archive does not match \
+                         the checksum {} pinned by the lockfile",
+                        sha256,
+                        app.name(),
+                        version,
+                        checksum
+                    );
+                }
 
-                let file = File::open(&path)
-                    .await
-                    .context("failed opening downloaded file")?;
-                install(app, file, app_dir).await?;
-                tokio::fs::remove_file(path)
-                    .await
-                    .context("failed deleting temporary archive")?;
+                let mut files = Vec::new();
+                for path in &paths {
+                    let file = File::open(path)
+                        .await
+                        .context("failed opening downloaded file")?;
+                    files.push(file);
+                }
+                // The tool is installed aside & moved into place, as other builds may install it
+                // at once.
+                let temp_dir = paths[0].with_extension("dir");
+                install(app, files, temp_dir.clone()).await?;
+                for path in &paths {
+                    tokio::fs::remove_file(path)
+                        .await
+                        .context("failed deleting temporary archive")?;
+                }
+                if tokio::fs::rename(&temp_dir, &app_dir).await.is_err() {
+                    // Unless installed by another build, a partial install is left behind.
+                    if !is_executable(&app_dir.join(app.path())).await? {
+                        remove_dir_all(app_dir.clone()).await?;
+                        tokio::fs::rename(&temp_dir, &app_dir)
+                            .await
+                            .context("failed moving installed tool into place")?;
+                    }
+                }
+                remove_dir_all(temp_dir).await?;
 
-                Ok(())
+                Ok(sha256)
             })
             .await
-            .map(|_| ())
+            .cloned()
     }
 }
 
-/// Locate the given application and download it if missing.
-#[tracing::instrument(level = "trace")]
-pub async fn get(app: Application, version: Option<&str>) -> Result<PathBuf> {
-    if let Some((path, version)) = find_system(app, version).await {
-        tracing::info!(app = %app.name(), %version, "using system installed binary");
-        return Ok(path);
+/// The configuration of the downloads of tools.
+#[derive(Debug, Default)]
+struct Downloads {
+    /// Whether tools must not be downloaded.
+    offline: bool,
+    /// The mirrors of the releases of tools, replacing their base URLs.
+    mirrors: HashMap<Application, String>,
+}
+
+/// A lockfile, pinning the version of each downloadable tool & the checksums of its release
+/// archives for each platform.
+#[derive(Debug)]
+struct Lockfile {
+    /// The path of the lockfile.
+    path: PathBuf,
+    /// Whether the lockfile must not be changed.
+    frozen: bool,
+    /// The contents of the lockfile.
+    contents: LockfileContents,
+}
+
+/// The contents of a lockfile.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+struct LockfileContents {
+    /// The pinned tools, by name.
+    #[serde(default)]
+    tools: BTreeMap<String, LockedTool>,
+}
+
+/// A tool pinned by a lockfile.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct LockedTool {
+    /// The version of the tool.
+    version: String,
+    /// The SHA-256 checksums of the release archives of the tool, by platform.
+    #[serde(default)]
+    sha256: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// The header written to the lockfile.
+    const HEADER: &'static str =
+        "# This file is generated by trunk, pinning the versions & checksums of downloaded tools.\n";
+
+    /// Read the lockfile at the given path, if it exists.
+    async fn load(path: PathBuf, frozen: bool) -> Result<Self> {
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("error parsing lockfile {:?}", path))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !frozen => {
+                LockfileContents::default()
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("error reading lockfile {:?}", path))
+            }
+        };
+        Ok(Self {
+            path,
+            frozen,
+            contents,
+        })
     }
 
-    let cache_dir = cache_dir().await?;
-    let version = version.unwrap_or_else(|| app.default_version());
-    let app_dir = cache_dir.join(format!("{}-{}", app.name(), version));
-    let bin_path = app_dir.join(app.path());
+    /// The name of the platform of the release archives of tools.
+    fn platform() -> String {
+        match Platform::current() {
+            Ok(platform) => platform.name(),
+            Err(_) => format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        }
+    }
+
+    /// The version of the given app to use, given the version configured for it, if any.
+    ///
+    /// The pinned version is used, unless another version is configured, which is pinned instead.
+    /// Frozen lockfiles must pin the app in its configured version.
+    fn version(&self, app: Application, version: Option<&str>) -> Result<Option<String>> {
+        if !app.is_downloadable() {
+            return Ok(version.map(str::to_owned));
+        }
+        match (self.contents.tools.get(app.name()), version) {
+            (Some(locked), None) => Ok(Some(locked.version.clone())),
+            (Some(locked), Some(version)) if locked.version == version => {
+                Ok(Some(version.to_owned()))
+            }
+            (Some(locked), Some(version)) if self.frozen => bail!(
+                "{} {} is configured, but {} {} is pinned by the frozen lockfile {:?}",
+                app.name(),
+                version,
+                app.name(),
+                locked.version,
+                self.path
+            ),
+            (None, _) if self.frozen => bail!(
+                "{} is not pinned by the frozen lockfile {:?}, please build without `--frozen` \
+                 to pin it",
+                app.name(),
+                self.path
+            ),
+            (_, version) => Ok(version.map(str::to_owned)),
+        }
+    }
+
+    /// The pinned checksum of the release archive of the given app for this platform, if any.
+    ///
+    /// Frozen lockfiles must pin the checksum.
+    fn checksum(&self, app: Application, version: &str) -> Result<Option<String>> {
+        let checksum = self
+            .contents
+            .tools
+            .get(app.name())
+            .filter(|locked| locked.version == version)
+            .and_then(|locked| locked.sha256.get(&Self::platform()))
+            .cloned();
+        if checksum.is_none() && self.frozen && app.is_downloadable() {
+            bail!(
+                "the checksum of {} {} for {} is not pinned by the frozen lockfile {:?}, please \
+                 build without `--frozen` to pin it",
+                app.name(),
+                version,
+                Self::platform(),
+                self.path
+            );
+        }
+        Ok(checksum)
+    }
+
+    /// Pin the given app in the given version, along with the checksum of its release archive for
+    /// this platform, if known, and write the lockfile if it changed.
+    async fn pin(
+        &mut self,
+        app: Application,
+        version: &str,
+        checksum: Option<String>,
+    ) -> Result<()> {
+        if self.frozen || !app.is_downloadable() {
+            return Ok(());
+        }
+        let mut changed = !self.contents.tools.contains_key(app.name());
+        let locked = self
+            .contents
+            .tools
+            .entry(app.name().to_owned())
+            .or_insert_with(|| LockedTool {
+                version: version.to_owned(),
+                sha256: BTreeMap::new(),
+            });
+        if locked.version != version {
+            // The checksums of other versions no longer apply.
+            locked.version = version.to_owned();
+            locked.sha256.clear();
+            changed = true;
+        }
+        if let Some(checksum) = checksum {
+            changed |= locked.sha256.insert(Self::platform(), checksum.clone()) != Some(checksum);
+        }
+        if changed {
+            let text = toml::to_string(&self.contents).context("error serializing lockfile")?;
+            tokio::fs::write(&self.path, format!("{}\n{}", Self::HEADER, text))
+                .await
+                .with_context(|| format!("error writing lockfile {:?}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+impl ToolContext {
+    /// Create a new instance.
+    ///
+    /// If `offline`, tools which are neither installed on the system nor cached are not downloaded,
+    /// but fail the build. `mirrors` maps the names of tools to the base URLs of the mirrors of
+    /// their releases.
+    pub fn new(offline: bool, mirrors: &HashMap<String, String>) -> Result<Self> {
+        let mirrors = mirrors
+            .iter()
+            .map(|(name, url)| {
+                let app = Application::from_name(name).with_context(|| {
+                    format!("can not configure a mirror for unknown tool {:?}", name)
+                })?;
+                Ok((app, url.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            downloads: Downloads { offline, mirrors },
+            ..Default::default()
+        })
+    }
+
+    /// Pin the versions & checksums of the downloaded tools in the lockfile at the given path,
+    /// which is created once a tool is used.
+    ///
+    /// If `frozen`, the lockfile must exist & is never changed, so that only the tools pinned by it
+    /// can be used.
+    pub async fn set_lockfile(&self, path: PathBuf, frozen: bool) -> Result<()> {
+        let lockfile = Lockfile::load(path, frozen).await?;
+        *self.lockfile.lock().await = Some(lockfile);
+        Ok(())
+    }
 
-    if !is_executable(&bin_path).await? {
-        GLOBAL_APP_CACHE
-            .lock()
+    /// The version of the given app pinned by the lockfile, if any.
+    pub async fn pinned_version(&self, app: Application) -> Option<String> {
+        let lockfile = self.lockfile.lock().await;
+        let locked = lockfile.as_ref()?.contents.tools.get(app.name())?;
+        Some(locked.version.clone())
+    }
+
+    /// Pin the given app in the lockfile, if any.
+    async fn pin(&self, app: Application, version: &str, checksum: Option<String>) -> Result<()> {
+        match self.lockfile.lock().await.as_mut() {
+            Some(lockfile) => lockfile.pin(app, version, checksum).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Locate the given application and download it if missing.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get(&self, app: Application, version: Option<&str>) -> Result<PathBuf> {
+        self.get_with_version(app, version)
             .await
-            .install_once(app, version, app_dir)
-            .await?;
+            .map(|(path, _)| path)
+    }
+
+    /// Locate the given application and download it if missing, also returning the version of the
+    /// located application.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_with_version(
+        &self,
+        app: Application,
+        version: Option<&str>,
+    ) -> Result<(PathBuf, String)> {
+        if let Some(requirement) = version.and_then(|version| version.strip_prefix(SYSTEM_VERSION))
+        {
+            return require_system(app, requirement.trim()).await;
+        }
+        let version = match self.lockfile.lock().await.as_ref() {
+            Some(lockfile) => lockfile.version(app, version)?,
+            None => version.map(str::to_owned),
+        };
+        if let Some((path, version)) = find_system(app, version.as_deref()).await {
+            tracing::info!(app = %app.name(), %version, "using system installed binary");
+            self.pin(app, &version, None).await?;
+            return Ok((path, version));
+        }
+
+        let cache_dir = cache_dir().await?;
+        let version = version.as_deref().unwrap_or_else(|| app.default_version());
+        let app_dir = cache_dir.join(format!("{}-{}", app.name(), version));
+        let bin_path = app_dir.join(app.path());
+
+        // Checksums are verified on download, so tools installed already are trusted.
+        let mut checksum = None;
+        if !is_executable(&bin_path).await? {
+            if self.downloads.offline {
+                bail!(
+                    "{} {} is neither installed on the system nor cached, and can not be \
+                     downloaded in offline mode, please build without `--offline` to download it",
+                    app.name(),
+                    version
+                );
+            }
+            let pinned = match self.lockfile.lock().await.as_ref() {
+                Some(lockfile) => lockfile.checksum(app, version)?,
+                None => None,
+            };
+            let mirror = self.downloads.mirrors.get(&app).map(String::as_str);
+            let sha256 = self
+                .installs
+                .lock()
+                .await
+                .install_once(app, version, mirror, app_dir, pinned)
+                .await?;
+            checksum = Some(sha256);
+        }
+        self.pin(app, version, checksum).await?;
+
+        Ok((bin_path, version.to_owned()))
     }
+}
+
+/// The version of tools which must be installed on the system, optionally followed by a requirement
+/// of their version, such as `system >=1.63`.
+const SYSTEM_VERSION: &str = "system";
+
+/// Locate the system installed application, which is never downloaded, and ensure its version
+/// meets the given requirement, if any.
+#[tracing::instrument(level = "trace")]
+async fn require_system(app: Application, requirement: &str) -> Result<(PathBuf, String)> {
+    let path = which::which(app.name()).with_context(|| {
+        format!(
+            "{} is configured to be installed on the system, but it was not found in PATH",
+            app.name()
+        )
+    })?;
+    let version = system_version(app, &path).await?;
+    check_requirement(app, requirement, &version)?;
+    tracing::info!(app = %app.name(), %version, "using system installed binary");
+    Ok((path, version))
+}
+
+/// Ensure the given version of the application meets the given requirement, if any, such as
+/// `>=1.63, <2`.
+fn check_requirement(app: Application, requirement: &str, version: &str) -> Result<()> {
+    if requirement.is_empty() {
+        return Ok(());
+    }
+    let requirement = VersionReq::parse(requirement).with_context(|| {
+        format!(
+            "error parsing the version requirement {:?} of {}",
+            requirement,
+            app.name()
+        )
+    })?;
+    let parsed = parse_version(version)
+        .with_context(|| format!("error parsing the version {:?} of {}", version, app.name()))?;
+    ensure!(
+        requirement.matches(&parsed),
+        "{} {} is installed on the system, but {} is required",
+        app.name(),
+        version,
+        requirement
+    );
+    Ok(())
+}
 
-    Ok(bin_path)
+/// Parse the version of a tool leniently, ignoring any pre-release & padding missing components,
+/// e.g. wasm-opt's `version_113` as `113.0.0`.
+fn parse_version(version: &str) -> Option<Version> {
+    let version = version
+        .trim_start_matches("version_")
+        .trim_start_matches('v');
+    let core = version.split(['-', ' ', '+']).next()?;
+    let mut parts = core.split('.').map(str::parse::<u64>);
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().transpose().ok()?.unwrap_or(0);
+    let patch = parts.next().transpose().ok()?.unwrap_or(0);
+    Some(Version::new(major, minor, patch))
+}
+
+/// The version of the application at the given path.
+async fn system_version(app: Application, path: &Path) -> Result<String> {
+    let output = Command::new(path).arg(app.version_test()).output().await?;
+    ensure!(
+        output.status.success(),
+        "running command `{} {}` failed",
+        path.display(),
+        app.version_test()
+    );
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    app.format_version_output(&text)
 }
 
 /// Try to find a globally system installed version of the application and ensure it is the needed
@@ -265,18 +1031,9 @@ pub async fn get(app: Application, version: Option<&str>) -> Result<PathBuf> {
 async fn find_system(app: Application, version: Option<&str>) -> Option<(PathBuf, String)> {
     let result = || async {
         let path = which::which(app.name())?;
-        let output = Command::new(&path).arg(app.version_test()).output().await?;
-        ensure!(
-            output.status.success(),
-            "running command `{} {}` failed",
-            path.display(),
-            app.version_test()
-        );
+        let system_version = system_version(app, &path).await?;
 
-        let text = String::from_utf8_lossy(&output.stdout);
-        let system_version = app.format_version_output(&text)?;
-
-        Ok((path, system_version))
+        Ok::<_, anyhow::Error>((path, system_version))
     };
 
     match result().await {
@@ -291,48 +1048,116 @@ async fn find_system(app: Application, version: Option<&str>) -> Option<(PathBuf
     }
 }
 
-/// Download a file from its remote location in the given version, extract it and make it ready for
-/// execution at the given location.
+/// Download the release archives of an application in the given version, returning the paths of
+/// the downloaded files & their SHA-256 checksum, which is that of all archives in order.
+///
+/// The names of the downloaded files are unique, as other builds may download the same tool at
+/// once.
 #[tracing::instrument(level = "trace")]
-async fn download(app: Application, version: &str) -> Result<PathBuf> {
+async fn download(
+    app: Application,
+    version: &str,
+    mirror: Option<&str>,
+) -> Result<(Vec<PathBuf>, String)> {
     tracing::info!(version = version, "downloading {}", app.name());
 
     let cache_dir = cache_dir()
         .await
         .context("failed getting the cache directory")?;
-    let temp_out = cache_dir.join(format!("{}-{}.tmp", app.name(), version));
-    let mut file = File::create(&temp_out)
+    let id = rand::random::<u64>();
+    let mut sha256 = Sha256::new();
+    let mut paths = Vec::new();
+    for (idx, url) in app.urls(version, mirror)?.iter().enumerate() {
+        let temp_out = cache_dir.join(format!(
+            "{}-{}-{:016x}-{}.tmp",
+            app.name(),
+            version,
+            id,
+            idx
+        ));
+        download_file(app, url, &temp_out, &mut sha256).await?;
+        paths.push(temp_out);
+    }
+    Ok((paths, format!("{:x}", sha256.finalize())))
+}
+
+/// Download the file at the given URL to the given path, updating the given checksum with it.
+async fn download_file(
+    app: Application,
+    url: &str,
+    temp_out: &Path,
+    sha256: &mut Sha256,
+) -> Result<()> {
+    let mut file = File::create(temp_out)
         .await
         .context("failed creating temporary output file")?;
 
-    let resp = reqwest::get(app.url(version)?)
+    // Proxies are configured via the `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` & `NO_PROXY` env
+    // vars.
+    let resp = reqwest::get(url)
         .await
         .context("error sending HTTP request")?;
     ensure!(
         resp.status().is_success(),
         "error downloading archive file: {:?}\n{}",
         resp.status(),
-        app.url(version)?
+        url
     );
+    let total = resp.content_length();
+    let (mut downloaded, mut reported) = (0, 0);
     let mut res_bytes = resp.bytes_stream();
     while let Some(chunk_res) = res_bytes.next().await {
         let chunk = chunk_res.context("error reading chunk from download")?;
         let _res = file.write(chunk.as_ref()).await;
+        sha256.update(&chunk);
+        // Progress is reported for every percent of the download, or every 100kB without a size.
+        downloaded += chunk.len() as u64;
+        let step = total.map_or(100_000, |total| (total / 100).max(1));
+        if downloaded - reported >= step || Some(downloaded) == total {
+            reported = downloaded;
+            progress::send(ProgressEvent::Download {
+                tool: app.name().to_owned(),
+                downloaded,
+                total,
+            });
+        }
     }
 
-    Ok(temp_out)
+    Ok(())
 }
 
-/// Install an application from a downloaded archive locating and copying it to the given target
+/// Install an application from its downloaded archives locating and copying it to the given target
 /// location.
 #[tracing::instrument(level = "trace")]
-async fn install(app: Application, archive_file: File, target: PathBuf) -> Result<()> {
+async fn install(app: Application, archive_files: Vec<File>, target: PathBuf) -> Result<()> {
     tracing::info!("installing {}", app.name());
 
-    let archive_file = archive_file.into_std().await;
+    let mut files = Vec::new();
+    for file in archive_files {
+        files.push(file.into_std().await);
+    }
+    if let Some((package, dependencies)) = app.npm_package() {
+        return tokio::task::spawn_blocking(move || {
+            let modules = target.join("node_modules");
+            let names = std::iter::once(package).chain(dependencies.iter().map(|(name, _)| *name));
+            for (name, file) in names.zip(files) {
+                Archive::new_tar_gz(file).extract_all(&modules.join(name))?;
+            }
+            install_npm_bin(app, package, &target)
+        })
+        .await?;
+    }
+    let archive_file = files
+        .into_iter()
+        .next()
+        .context("missing release archive")?;
 
     tokio::task::spawn_blocking(move || {
-        let mut archive = if app == Application::Sass && cfg!(target_os = "windows") {
+        let mut archive = if matches!(
+            app,
+            Application::Sass | Application::Oxipng | Application::Cwebp
+        ) && cfg!(target_os = "windows")
+        {
             Archive::new_zip(archive_file)?
         } else if app == Application::TailwindCss {
             Archive::new_none(archive_file)
@@ -359,6 +1184,66 @@ async fn install(app: Application, archive_file: File, target: PathBuf) -> Resul
     .await?
 }
 
+/// The script running the postcss package like postcss-cli, as far as trunk uses it.
+const POSTCSS_SCRIPT: &str = include_str!("postcss.js");
+
+/// Make the executable of the given npm package in the given app dir runnable by the node installed
+/// on the system, which a shim script runs it with on Windows.
+///
+/// The executable of postcss is the [`POSTCSS_SCRIPT`], written to the app dir.
+fn install_npm_bin(app: Application, package: &str, target: &Path) -> Result<()> {
+    let bin = match app {
+        Application::Postcss => {
+            let bin = "bin/postcss".to_owned();
+            std::fs::create_dir_all(target.join("bin")).context("failed creating bin dir")?;
+            std::fs::write(target.join(&bin), POSTCSS_SCRIPT)
+                .context("failed writing postcss script")?;
+            bin
+        }
+        _ => format!("node_modules/{}/bin/{}", package, app.name()),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(target.join(&bin), std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed setting permissions of {}", bin))?;
+    }
+    #[cfg(windows)]
+    std::fs::write(
+        target.join(app.path()),
+        format!("@node \"%~dp0{}\" %*\r\n", bin.replace('/', "\\")),
+    )
+    .context("failed writing shim script")?;
+    Ok(())
+}
+
+/// The tools installed in the cache dir, along with their versions & dirs.
+pub async fn cached() -> Result<Vec<(Application, String, PathBuf)>> {
+    let cache_dir = cache_dir().await?;
+    let mut entries = tokio::fs::read_dir(&cache_dir)
+        .await
+        .context("failed reading cache directory")?;
+    let mut tools = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("failed reading cache directory")?
+    {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let tool = Application::DOWNLOADABLE.into_iter().find_map(|app| {
+            let version = name.strip_prefix(app.name())?.strip_prefix('-')?;
+            Some((app, version.to_owned(), entry.path()))
+        });
+        tools.extend(tool);
+    }
+    tools.sort();
+    Ok(tools)
+}
+
 /// Locate the cache dir for trunk and make sure it exists.
 pub async fn cache_dir() -> Result<PathBuf> {
     let path = ProjectDirs::from("dev", "trunkrs", "trunk")
@@ -374,9 +1259,9 @@ pub async fn cache_dir() -> Result<PathBuf> {
 mod archive {
     use std::fs::{self, File};
     use std::io::{self, BufReader, BufWriter, Read, Seek};
-    use std::path::Path;
+    use std::path::{Component, Path, PathBuf};
 
-    use anyhow::{Context, Result};
+    use anyhow::{bail, Context, Result};
     use flate2::read::GzDecoder;
     use tar::{Archive as TarArchive, Entry as TarEntry};
     use zip::ZipArchive;
@@ -449,6 +1334,37 @@ mod archive {
             Ok(())
         }
 
+        /// Extract all files of a tar archive into the given dir, dropping the first part of their
+        /// paths, as for single files. Links & entries outside of the dir are skipped.
+        pub fn extract_all(self, target: &Path) -> Result<()> {
+            let Self::TarGz(mut archive) = self else {
+                bail!("only tar archives can be extracted as a whole");
+            };
+            let entries = archive
+                .entries()
+                .context("failed getting archive entries")?;
+            for entry in entries {
+                let mut entry = entry.context("error while getting archive entry")?;
+                let path: PathBuf = entry
+                    .path()
+                    .context("invalid entry path")?
+                    .components()
+                    .skip(1)
+                    .collect();
+                let inside = path
+                    .components()
+                    .all(|part| matches!(part, Component::Normal(_)));
+                if !entry.header().entry_type().is_file() || !inside {
+                    continue;
+                }
+                let mut out_file = extract_file(&mut entry, &path.to_string_lossy(), target)?;
+                if let Ok(mode) = entry.header().mode() {
+                    set_file_permissions(&mut out_file, mode)?;
+                }
+            }
+            Ok(())
+        }
+
         pub fn reset(self) -> Result<Self> {
             match self {
                 Self::TarGz(archive) => {
@@ -471,7 +1387,7 @@ mod archive {
     fn find_tar_entry(
         archive: &mut TarArchive<impl Read>,
         path: impl AsRef<Path>,
-    ) -> Result<Option<TarEntry<impl Read>>> {
+    ) -> Result<Option<TarEntry<'_, impl Read>>> {
         let entries = archive
             .entries()
             .context("failed getting archive entries")?;
@@ -558,16 +1474,199 @@ mod tests {
             Application::WasmBindgen,
             Application::WasmOpt,
             Application::TailwindCss,
+            Application::Oxipng,
+            Application::Cwebp,
+            Application::Esbuild,
+            Application::Lessc,
+            Application::Postcss,
         ] {
-            let path = download(app, app.default_version())
+            let (paths, _) = download(app, app.default_version(), None)
                 .await
                 .context("error downloading app")?;
-            let file = File::open(&path).await.context("error opening file")?;
-            install(app, file, dir.path().to_owned())
+            let mut files = Vec::new();
+            for path in &paths {
+                files.push(File::open(path).await.context("error opening file")?);
+            }
+            install(app, files, dir.path().to_owned())
                 .await
                 .context("error installing app")?;
-            std::fs::remove_file(path).context("error during cleanup")?;
+            for path in paths {
+                std::fs::remove_file(path).context("error during cleanup")?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn mirrors_replace_the_release_url() -> Result<()> {
+        let url = Application::WasmOpt
+            .url("version_113", Some("https://mirror.example.com/binaryen/"))?;
+        ensure!(url.starts_with("https://mirror.example.com/binaryen/version_113/binaryen-"));
+        Ok(())
+    }
+
+    #[test]
+    fn urls_match_the_platform() -> Result<()> {
+        let platform = |os, arch, musl| Platform { os, arch, musl };
+        let url = Application::Sass.url_for(platform("linux", "aarch64", true), "1.69.5", None)?;
+        ensure!(url.ends_with("/dart-sass-1.69.5-linux-arm64-musl.tar.gz"));
+        let url =
+            Application::Esbuild.url_for(platform("freebsd", "x86_64", false), "0.18.11", None)?;
+        ensure!(url.ends_with("/@esbuild/freebsd-x64/-/freebsd-x64-0.18.11.tgz"));
+        let url =
+            Application::TailwindCss.url_for(platform("linux", "x86_64", true), "4.0.0", None)?;
+        ensure!(url.ends_with("/v4.0.0/tailwindcss-linux-x64-musl"));
+        let urls = Application::Lessc.urls("4.1.3", None)?;
+        ensure!(urls[0] == "https://registry.npmjs.org/less/-/less-4.1.3.tgz");
+        ensure!(urls.contains(&"https://registry.npmjs.org/tslib/-/tslib-2.6.2.tgz".to_owned()));
+        let err =
+            Application::WasmBindgen.url_for(platform("linux", "armv7", false), "0.2.87", None);
+        ensure!(format!("{:?}", err).contains("cargo install wasm-bindgen-cli --version 0.2.87"));
+        ensure!(Application::TailwindCss
+            .url_for(platform("linux", "x86_64", true), "3.3.2", None)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn system_versions_must_meet_requirements() -> Result<()> {
+        check_requirement(Application::Sass, "", "1.32.0")?;
+        check_requirement(Application::Sass, ">=1.63, <2", "1.69.5")?;
+        ensure!(check_requirement(Application::Sass, ">=1.63", "1.32.0").is_err());
+        check_requirement(Application::WasmOpt, ">=110", "version_113")?;
+        check_requirement(Application::Magick, "^7.1", "7.1.1-15")?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn frozen_lockfile_requires_pinned_tools() -> Result<()> {
+        let dir = tempfile::tempdir().context("error creating temporary dir")?;
+        let path = dir.path().join("Trunk.lock");
+        ensure!(Lockfile::load(path.clone(), true).await.is_err());
+
+        let mut lockfile = Lockfile::load(path.clone(), false).await?;
+        ensure!(lockfile.version(Application::Sass, None)? == None);
+        lockfile
+            .pin(Application::Sass, "1.63.6", Some("abc".into()))
+            .await?;
+
+        let frozen = Lockfile::load(path, true).await?;
+        ensure!(frozen.version(Application::Sass, None)?.as_deref() == Some("1.63.6"));
+        ensure!(frozen.checksum(Application::Sass, "1.63.6")?.as_deref() == Some("abc"));
+        ensure!(frozen.version(Application::Sass, Some("1.69.0")).is_err());
+        ensure!(frozen.version(Application::Esbuild, None).is_err());
+        // Tools which are never downloaded are not pinned.
+        ensure!(frozen.version(Application::Pandoc, None)? == None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn npm_packages_are_installed_into_node_modules() -> Result<()> {
+        let dir = tempfile::tempdir().context("error creating temporary dir")?;
+        let (_, dependencies) = Application::Lessc
+            .npm_package()
+            .context("missing package")?;
+        let mut files = Vec::new();
+        for (idx, name) in std::iter::once("less")
+            .chain(dependencies.iter().map(|(name, _)| *name))
+            .enumerate()
+        {
+            // npm packages are archived below a `package` dir.
+            let path = dir.path().join(format!("{}.tgz", idx));
+            let gz = flate2::write::GzEncoder::new(
+                std::fs::File::create(&path)?,
+                flate2::Compression::fast(),
+            );
+            let mut tar = tar::Builder::new(gz);
+            let file = format!(
+                "package/{}",
+                if idx == 0 { "bin/lessc" } else { "index.js" }
+            );
+            let mut header = tar::Header::new_gnu();
+            header.set_size(name.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, file, name.as_bytes())?;
+            tar.into_inner()?.finish()?;
+            files.push(File::open(&path).await?);
         }
+        let target = dir.path().join("lessc");
+
+        install(Application::Lessc, files, target.clone()).await?;
+
+        ensure!(is_executable(&target.join(Application::Lessc.path())).await?);
+        let tslib = std::fs::read_to_string(target.join("node_modules/tslib/index.js"))?;
+        ensure!(tslib == "tslib");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn postcss_script_transforms_css() -> Result<()> {
+        if which::which("node").is_err() {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir().context("error creating temporary dir")?;
+        let target = dir.path().join("postcss");
+        // A stub of the postcss package, applying plugins which transform the CSS text.
+        let package = target.join("node_modules/postcss");
+        std::fs::create_dir_all(&package)?;
+        std::fs::write(package.join("package.json"), r#"{"version": "8.4.31"}"#)?;
+        std::fs::write(
+            package.join("index.js"),
+            "module.exports = (plugins) => ({ process: async (css, opts) => ({ css: \
+             plugins.reduce((css, plugin) => plugin(css, opts), css), warnings: () => [] }) });",
+        )?;
+        install_npm_bin(Application::Postcss, "postcss", &target)?;
+
+        // The plugin is resolved relative to the config.
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join("styles"))?;
+        std::fs::write(
+            project.join("postcss.config.js"),
+            "module.exports = { plugins: { './upper': {}, './unused': false } };",
+        )?;
+        std::fs::write(
+            project.join("upper.js"),
+            "module.exports = () => (css, opts) => css.toUpperCase() + `/* ${opts.map} */`;",
+        )?;
+        std::fs::write(project.join("styles/app.css"), "a { color: red; }")?;
+        let output = dir.path().join("out.css");
+
+        let postcss = target.join(Application::Postcss.path());
+        ensure!(system_version(Application::Postcss, &postcss).await? == "8.4.31");
+        let status = Command::new(&postcss)
+            .arg(project.join("styles/app.css"))
+            .arg("--output")
+            .arg(&output)
+            .arg("--no-map")
+            .status()
+            .await?;
+        ensure!(status.success());
+        let css = std::fs::read_to_string(&output)?;
+        ensure!(
+            css == "A { COLOR: RED; }/* false */",
+            "unexpected output {:?}",
+            css
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tool_contexts_are_independent() -> Result<()> {
+        let dir = tempfile::tempdir().context("error creating temporary dir")?;
+        let path = dir.path().join("Trunk.lock");
+        let mut lockfile = Lockfile::load(path.clone(), false).await?;
+        lockfile.pin(Application::Sass, "1.63.6", None).await?;
+
+        let pinned = ToolContext::default();
+        pinned.set_lockfile(path, true).await?;
+        ensure!(pinned.pinned_version(Application::Sass).await.as_deref() == Some("1.63.6"));
+        ensure!(ToolContext::default()
+            .pinned_version(Application::Sass)
+            .await
+            .is_none());
+        let mirrors = HashMap::from([("unknown".to_owned(), "https://example.com".to_owned())]);
+        ensure!(ToolContext::new(false, &mirrors).is_err());
         Ok(())
     }
 
@@ -619,10 +1718,71 @@ mod tests {
     );
 
     table_test_format_version!(sass_pre_compiled, Application::Sass, "1.37.5", "1.37.5");
+    table_test_format_version!(
+        oxipng_pre_compiled,
+        Application::Oxipng,
+        "oxipng 8.0.0",
+        "8.0.0"
+    );
+    table_test_format_version!(
+        cwebp_pre_compiled,
+        Application::Cwebp,
+        "1.3.1\nlibsharpyuv: 0.2.1",
+        "1.3.1"
+    );
+    table_test_format_version!(
+        avifenc_system,
+        Application::Avifenc,
+        "Version: 1.0.1 (dav1d [dec]:1.2.1, aom [enc/dec]:3.6.1)",
+        "1.0.1"
+    );
+    table_test_format_version!(
+        esbuild_pre_compiled,
+        Application::Esbuild,
+        "0.18.11",
+        "0.18.11"
+    );
+    table_test_format_version!(
+        magick_system,
+        Application::Magick,
+        "Version: ImageMagick 7.1.1-15 Q16-HDRI x86_64 21298 https://imagemagick.org\nCopyright: (C) 1999 ImageMagick Studio LLC",
+        "7.1.1-15"
+    );
+    table_test_format_version!(
+        pyftsubset_system,
+        Application::Pyftsubset,
+        "4.42.1",
+        "4.42.1"
+    );
+    table_test_format_version!(
+        pandoc_system,
+        Application::Pandoc,
+        "pandoc 3.1.8\nFeatures: +server +lua\nScripting engine: Lua 5.4",
+        "3.1.8"
+    );
+    table_test_format_version!(
+        lessc_system,
+        Application::Lessc,
+        "lessc 4.1.3 (Less Compiler) [JavaScript]",
+        "4.1.3"
+    );
+    table_test_format_version!(postcss_system, Application::Postcss, "10.1.0", "10.1.0");
+    table_test_format_version!(
+        jpegoptim_system,
+        Application::Jpegoptim,
+        "jpegoptim v1.5.5  x86_64-pc-linux-gnu",
+        "1.5.5"
+    );
     table_test_format_version!(
         tailwindcss_pre_compiled,
         Application::TailwindCss,
         "tailwindcss v3.3.2",
         "3.3.2"
     );
+    table_test_format_version!(
+        tailwindcss_v4_pre_compiled,
+        Application::TailwindCss,
+        "≈ tailwindcss v4.0.0\n\nUsage:\n  tailwindcss [--input input.css] [--output output.css] [--watch] [options…]",
+        "4.0.0"
+    );
 }
@@ -0,0 +1,57 @@
+
+// Load the deferred module of a Rust app split by wasm-split, appended to the JS glue of
+// wasm-bindgen, which passes its imports through `__trunkSplitImports` & its exports through
+// `__trunkSplitStart`.
+//
+// The primary module imports placeholders for the functions split off from `__trunk_split`. Once
+// the app is initialized, the deferred module is fetched & instantiated in the background, which
+// replaces the placeholders in the table of the primary module. A placeholder called before
+// instantiates the deferred module synchronously, which browsers only allow for small modules on
+// the main thread, and calls the function in its place.
+//
+// The declarations are hoisted, as the glue may be initialized before this script is evaluated.
+var __trunkSplitInstance;
+
+function __trunkSplitUrl() {
+    return new URL({deferred}, import.meta.url);
+}
+
+function __trunkSplitImports(imports) {
+    imports.__trunk_split = new Proxy({}, {
+        get: (_, index) => (...args) => {
+            if (!__trunkSplitInstance) {
+                const request = new XMLHttpRequest();
+                request.open('GET', __trunkSplitUrl(), false);
+                // Synchronous requests only return text, whose char codes are the bytes.
+                request.overrideMimeType('text/plain; charset=x-user-defined');
+                request.send();
+                if (request.status !== 200) {
+                    throw new Error(`error fetching ${__trunkSplitUrl()}: ${request.status}`);
+                }
+                const bytes = Uint8Array.from(request.responseText, (c) => c.charCodeAt(0) & 0xff);
+                const module = new WebAssembly.Module(bytes);
+                __trunkSplitInstance = new WebAssembly.Instance(module, { primary: wasm });
+            }
+            return wasm[{table}].get(Number(index))(...args);
+        },
+    });
+    return imports;
+}
+
+function __trunkSplitStart(exports) {
+    // Instantiating the module once more is harmless, as all instances place the same functions,
+    // which only use the memory & globals of the primary module.
+    fetch(__trunkSplitUrl())
+        .then((response) => {
+            if (!response.ok) {
+                throw new Error(`error fetching ${response.url}: ${response.status}`);
+            }
+            return response.arrayBuffer();
+        })
+        .then((bytes) => WebAssembly.instantiate(bytes, { primary: exports }))
+        .then(({ instance }) => {
+            __trunkSplitInstance ??= instance;
+        })
+        .catch((error) => console.warn('error loading the deferred wasm module', error));
+    return exports;
+}
@@ -0,0 +1,18 @@
+
+// Record the profile of a Rust app instrumented by wasm-split, appended to the JS glue of
+// wasm-bindgen.
+//
+// Calling `__trunkWasmSplitProfile()` downloads the profile of the functions called so far, which
+// are kept in the primary module when splitting the app.
+window.__trunkWasmSplitProfile = () => {
+    // The profile is written to new pages of the memory, which the app does not use yet.
+    const size = wasm.__write_profile(0, 0);
+    const address = wasm.memory.grow(Math.ceil(size / 65536)) * 65536;
+    wasm.__write_profile(address, size);
+    const profile = new Uint8Array(wasm.memory.buffer, address, size).slice();
+    const link = document.createElement('a');
+    link.href = URL.createObjectURL(new Blob([profile]));
+    link.download = {profile};
+    link.click();
+    setTimeout(() => URL.revokeObjectURL(link.href));
+};
@@ -1,28 +1,36 @@
-use std::path::PathBuf;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result};
 use futures_util::stream::StreamExt;
-use notify::event::ModifyKind;
-use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use glob::{MatchOptions, Pattern};
+use notify::event::{MetadataKind, ModifyKind};
+use notify::{EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
 use notify_debouncer_full::{
-    new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, FileIdMap,
+    new_debouncer_opt, DebounceEventResult, DebouncedEvent, Debouncer, FileIdMap,
 };
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::Instant;
 use tokio_stream::wrappers::BroadcastStream;
 
-use crate::build::BuildSystem;
+use crate::build::{BuildEvent, BuildSystem};
 use crate::config::RtcWatch;
+use crate::overlay;
+use crate::reload::{self, DistSnapshot, Reload};
 
-/// The debouncer type used in this module.
-type FsDebouncer = Debouncer<RecommendedWatcher, FileIdMap>;
+/// The debouncer used in this module, of either the native or the polling watcher.
+///
+/// It is only kept alive, as the watcher stops watching when it is dropped.
+#[allow(dead_code)]
+enum FsDebouncer {
+    Native(Debouncer<RecommendedWatcher, FileIdMap>),
+    Poll(Debouncer<PollWatcher, FileIdMap>),
+}
 
 /// Blacklisted path segments which are ignored by the watcher by default.
 const BLACKLIST: [&str; 1] = [".git"];
-/// The duration of time to debounce FS events.
-const DEBOUNCE_DURATION: Duration = Duration::from_millis(25);
 /// The duration of time during which watcher events will be ignored following a build.
 const WATCHER_COOLDOWN: Duration = Duration::from_secs(1);
 
@@ -30,18 +38,26 @@ const WATCHER_COOLDOWN: Duration = Duration::from_secs(1);
 pub struct WatchSystem {
     /// The build system.
     build: BuildSystem,
-    /// The current vector of paths to be ignored.
-    ignored_paths: Vec<PathBuf>,
-    /// A channel of FS watch events.
-    watch_rx: mpsc::Receiver<DebouncedEvent>,
+    /// The paths & globs to be ignored.
+    ignored: IgnoreList,
+    /// A channel of batches of FS watch events.
+    watch_rx: mpsc::Receiver<Vec<DebouncedEvent>>,
     /// A channel of new paths to ignore from the build system.
     build_rx: mpsc::Receiver<PathBuf>,
     /// The watch system used for watching the filesystem.
     _debouncer: FsDebouncer,
     /// The application shutdown channel.
     shutdown: BroadcastStream<()>,
-    /// Channel that is sent on whenever a build completes.
-    build_done_tx: Option<broadcast::Sender<()>>,
+    /// Channel that is sent on whenever a build starts, progresses or completes.
+    build_events_tx: Option<broadcast::Sender<BuildEvent>>,
+    /// The snapshot of the dist dir after the last successful build, to determine how the
+    /// served pages apply the changes of the next one.
+    dist_snapshot: Option<DistSnapshot>,
+    /// The dist dir & public URL of the build.
+    dist: PathBuf,
+    public_url: String,
+    /// Whether to rebuild all assets on each change, rather than only the affected ones.
+    full_rebuilds: bool,
     /// An instant used to track the last build time, used to implement the watcher cooldown
     /// to avoid infinite build loops.
     ///
@@ -57,25 +73,49 @@ impl WatchSystem {
     pub async fn new(
         cfg: Arc<RtcWatch>,
         shutdown: broadcast::Sender<()>,
-        build_done_tx: Option<broadcast::Sender<()>>,
+        build_events_tx: Option<broadcast::Sender<BuildEvent>>,
     ) -> Result<Self> {
         // Create a channel for being able to listen for new paths to ignore while running.
         let (watch_tx, watch_rx) = mpsc::channel(1);
         let (build_tx, build_rx) = mpsc::channel(1);
 
         // Build the watcher.
-        let _debouncer = build_watcher(watch_tx, cfg.paths.clone())?;
+        let _debouncer = match cfg.poll {
+            None => FsDebouncer::Native(build_watcher(
+                watch_tx,
+                &cfg.paths,
+                cfg.debounce,
+                notify::Config::default(),
+            )?),
+            Some(interval) => {
+                tracing::info!("polling for file system changes every {:?}", interval);
+                FsDebouncer::Poll(build_watcher(
+                    watch_tx,
+                    &cfg.paths,
+                    cfg.debounce,
+                    notify::Config::default().with_poll_interval(interval),
+                )?)
+            }
+        };
 
         // Build dependencies.
-        let build = BuildSystem::new(cfg.build.clone(), Some(build_tx)).await?;
+        let build =
+            BuildSystem::new(cfg.build.clone(), Some(build_tx), build_events_tx.clone()).await?;
         Ok(Self {
             build,
-            ignored_paths: cfg.ignored_paths.clone(),
+            ignored: IgnoreList {
+                paths: cfg.ignored_paths.clone(),
+                globs: cfg.ignored_globs.clone(),
+            },
             watch_rx,
             build_rx,
             _debouncer,
             shutdown: BroadcastStream::new(shutdown.subscribe()),
-            build_done_tx,
+            build_events_tx,
+            dist_snapshot: None,
+            dist: cfg.build.final_dist.clone(),
+            public_url: cfg.build.public_url.clone(),
+            full_rebuilds: cfg.full_rebuilds,
             last_build_finished: Instant::now(),
         })
     }
@@ -83,7 +123,22 @@ impl WatchSystem {
     /// Run a build.
     #[tracing::instrument(level = "trace", skip(self))]
     pub async fn build(&mut self) -> Result<()> {
-        self.build.build().await
+        let res = self.build.build().await;
+        if res.is_ok() && self.build_events_tx.is_some() {
+            self.dist_snapshot = self.read_dist_snapshot(None).await;
+        }
+        res
+    }
+
+    /// Read the snapshot of the dist dir, if possible.
+    async fn read_dist_snapshot(&self, prev: Option<&DistSnapshot>) -> Option<DistSnapshot> {
+        match DistSnapshot::read(&self.dist, prev).await {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) => {
+                tracing::debug!(error = ?err, "error reading dist snapshot");
+                None
+            }
+        }
     }
 
     /// Run the watch system, responding to events and triggering builds.
@@ -91,8 +146,12 @@ impl WatchSystem {
     pub async fn run(mut self) {
         loop {
             tokio::select! {
-                Some(ign) = self.build_rx.recv() => self.update_ignore_list(ign),
-                Some(ev) = self.watch_rx.recv() => self.handle_watch_event(ev).await,
+                Some(ign) = self.build_rx.recv() => self.ignored.update(ign),
+                Some(evs) = self.watch_rx.recv() => {
+                    if self.handle_watch_events(evs).await.is_break() {
+                        break;
+                    }
+                }
                 _ = self.shutdown.next() => break, // Any event, even a drop, will trigger shutdown.
             }
         }
@@ -100,8 +159,9 @@ impl WatchSystem {
         tracing::debug!("watcher system has shut down");
     }
 
-    #[tracing::instrument(level = "trace", skip(self, event))]
-    async fn handle_watch_event(&mut self, event: DebouncedEvent) {
+    /// Handle the given events, breaking if the watch system got shut down during a build.
+    #[tracing::instrument(level = "trace", skip(self, events))]
+    async fn handle_watch_events(&mut self, events: Vec<DebouncedEvent>) -> ControlFlow<()> {
         // There are various OS syscalls which can trigger FS changes, even though semantically no
         // changes were made. A notorious example which has plagued the trunk watcher
         // implementation is `std::fs::copy`, which will trigger watcher changes indicating
@@ -112,93 +172,207 @@ impl WatchSystem {
         if Instant::now().duration_since(self.last_build_finished) <= WATCHER_COOLDOWN {
             // Purge any other events in the queue.
             while let Ok(_event) = self.watch_rx.try_recv() {}
-            return;
+            return ControlFlow::Continue(());
         }
 
-        // Check each path in the event for a match.
-        match event.event.kind {
-            EventKind::Modify(ModifyKind::Name(_) | ModifyKind::Data(_))
-            | EventKind::Create(_)
-            | EventKind::Remove(_) => (),
-            _ => return,
-        };
-        let mut found_matching_path = false;
-        for ev_path in &event.paths {
-            let ev_path = match tokio::fs::canonicalize(&ev_path).await {
-                Ok(ev_path) => ev_path,
-                // Ignore errors here, as this would only take place for a resource which has
-                // been removed, which will happen for each of our dist/.stage entries.
-                Err(_) => continue,
-            };
-
-            // Check ignored paths.
-            if ev_path.ancestors().any(|path| {
-                self.ignored_paths
-                    .iter()
-                    .any(|ignored_path| ignored_path == path)
-            }) {
-                continue; // Don't emit a notification if path is ignored.
-            }
-
-            // Check blacklisted paths.
-            if ev_path
-                .components()
-                .filter_map(|segment| segment.as_os_str().to_str())
-                .any(|segment| BLACKLIST.contains(&segment))
-            {
-                continue; // Don't emit a notification as path is on the blacklist.
-            }
-
-            // If all of the above checks have passed, then we need to trigger a build.
-            tracing::debug!("change detected in {:?} of type {:?}", ev_path, event.kind);
-            found_matching_path = true;
-        }
+        let (mut changed, mut full_rebuild) =
+            self.ignored.changes(&events, self.full_rebuilds).await;
 
         // If a build is not needed, then return.
-        if !found_matching_path {
-            return;
+        if changed.is_empty() {
+            return ControlFlow::Continue(());
         }
 
-        // Else, time to trigger a build.
-        let _res = self.build.build().await;
+        // Else, time to trigger a build. Changes arriving while it runs supersede it, cancelling
+        // it for a build of all changes so far.
+        let res = 'build: loop {
+            let started = SystemTime::now();
+            let (paths, full) = {
+                let build_system = &mut self.build;
+                let paths = &changed;
+                let build = async move {
+                    match full_rebuild {
+                        true => build_system.build().await,
+                        false => build_system.rebuild(paths).await,
+                    }
+                };
+                tokio::pin!(build);
+                loop {
+                    tokio::select! {
+                        res = &mut build => break 'build res,
+                        Some(ign) = self.build_rx.recv() => self.ignored.update(ign),
+                        // Dropping the build cancels it, killing any commands it runs.
+                        _ = self.shutdown.next() => {
+                            tracing::info!("cancelling build for shutdown");
+                            return ControlFlow::Break(());
+                        }
+                        Some(evs) = self.watch_rx.recv() => {
+                            let (paths, full) = self.ignored.changes(&evs, false).await;
+                            // Some watchers report files as changed when they are only read by
+                            // the build, which must not cancel it.
+                            let paths: Vec<_> = paths
+                                .into_iter()
+                                .filter(|path| modified_since(path, started))
+                                .collect();
+                            if !paths.is_empty() {
+                                break (paths, full);
+                            }
+                        }
+                    }
+                }
+            };
+            tracing::info!("changes detected during build, restarting it");
+            for path in paths {
+                if !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+            full_rebuild |= full;
+        };
         self.last_build_finished = tokio::time::Instant::now();
 
-        // TODO/NOTE: in the future, we will want to be able to pass along error info and other
-        // diagnostics info over the socket for use in an error overlay or console logging.
-        if let Some(tx) = self.build_done_tx.as_mut() {
-            let _ = tx.send(());
-        }
+        let Some(tx) = self.build_events_tx.clone() else {
+            return ControlFlow::Continue(());
+        };
+        let build_done = match res {
+            Ok(()) => {
+                let prev = self.dist_snapshot.take();
+                self.dist_snapshot = self.read_dist_snapshot(prev.as_ref()).await;
+                // Pages only swap their stylesheets if nothing else changed.
+                Ok(match (&prev, &self.dist_snapshot) {
+                    (Some(prev), Some(next)) => {
+                        let _ = tx.send(BuildEvent::AssetsChanged(reload::changed_files(
+                            prev,
+                            next,
+                            &self.public_url,
+                        )));
+                        reload::changes(prev, next, &self.public_url)
+                    }
+                    _ => Reload::Full,
+                })
+            }
+            Err(err) => Err(overlay::report(&err)),
+        };
+        let _ = tx.send(BuildEvent::Done(build_done));
+        ControlFlow::Continue(())
     }
+}
+
+/// The paths & globs ignored by the watcher, where a glob matching a dir ignores its contents.
+struct IgnoreList {
+    /// The paths ignored by the watcher.
+    paths: Vec<PathBuf>,
+    /// The globs of the paths ignored by the watcher.
+    globs: Vec<Pattern>,
+}
 
-    fn update_ignore_list(&mut self, arg_path: PathBuf) {
+impl IgnoreList {
+    fn update(&mut self, arg_path: PathBuf) {
         let path = match arg_path.canonicalize() {
             Ok(canon_path) => canon_path,
             Err(_) => arg_path,
         };
 
-        if !self.ignored_paths.contains(&path) {
-            self.ignored_paths.push(path);
+        if !self.paths.contains(&path) {
+            self.paths.push(path);
+        }
+    }
+
+    /// The paths changed by the given events which are not ignored, along with whether they
+    /// require a rebuild of all assets.
+    async fn changes(
+        &self,
+        events: &[DebouncedEvent],
+        full_rebuilds: bool,
+    ) -> (Vec<PathBuf>, bool) {
+        // Check each path of the events for a match.
+        let mut changed = Vec::new();
+        // Renames trigger a rebuild of all assets, as their previous paths are gone & so can not
+        // be attributed to any asset.
+        let mut full_rebuild = full_rebuilds;
+        for event in events {
+            // The polling watcher reports changed files via their write time.
+            match event.event.kind {
+                EventKind::Modify(
+                    ModifyKind::Name(_)
+                    | ModifyKind::Data(_)
+                    | ModifyKind::Metadata(MetadataKind::WriteTime),
+                )
+                | EventKind::Create(_)
+                | EventKind::Remove(_) => (),
+                _ => continue,
+            };
+            for ev_path in &event.paths {
+                let ev_path = match tokio::fs::canonicalize(&ev_path).await {
+                    Ok(ev_path) => ev_path,
+                    // Ignore errors here, as this would only take place for a resource which has
+                    // been removed, which will happen for each entry of the staging dist dir.
+                    Err(_) => continue,
+                };
+
+                // Check ignored paths & globs, where a glob matching a dir ignores its contents.
+                let options = MatchOptions {
+                    require_literal_separator: true,
+                    ..Default::default()
+                };
+                if ev_path.ancestors().any(|path| {
+                    self.paths.iter().any(|ignored_path| ignored_path == path)
+                        || self
+                            .globs
+                            .iter()
+                            .any(|glob| glob.matches_path_with(path, options))
+                }) {
+                    continue; // Don't emit a notification if path is ignored.
+                }
+
+                // Check blacklisted paths.
+                if ev_path
+                    .components()
+                    .filter_map(|segment| segment.as_os_str().to_str())
+                    .any(|segment| BLACKLIST.contains(&segment))
+                {
+                    continue; // Don't emit a notification as path is on the blacklist.
+                }
+
+                // If all of the above checks have passed, then we need to trigger a build.
+                tracing::debug!("change detected in {:?} of type {:?}", ev_path, event.kind);
+                full_rebuild |= matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)));
+                changed.push(ev_path);
+            }
         }
+
+        (changed, full_rebuild)
     }
 }
 
+/// Whether the file at the given path was modified since the given time, or is gone.
+fn modified_since(path: &Path, time: SystemTime) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_or(true, |modified| modified >= time)
+}
+
 /// Build a FS watcher, when the watcher is dropped, it will stop watching for events.
-fn build_watcher(
-    watch_tx: mpsc::Sender<DebouncedEvent>,
-    paths: Vec<PathBuf>,
-) -> Result<FsDebouncer> {
+fn build_watcher<T: Watcher>(
+    watch_tx: mpsc::Sender<Vec<DebouncedEvent>>,
+    paths: &[PathBuf],
+    debounce: Duration,
+    config: notify::Config,
+) -> Result<Debouncer<T, FileIdMap>> {
     // Build the filesystem watcher & debouncer.
-    let mut debouncer = new_debouncer(
-        DEBOUNCE_DURATION,
+    let mut debouncer = new_debouncer_opt::<_, T, _>(
+        debounce,
         None,
         move |result: DebounceEventResult| match result {
-            Ok(events) => events.into_iter().for_each(|event| {
-                let _ = watch_tx.blocking_send(event);
-            }),
+            Ok(events) => {
+                let _ = watch_tx.blocking_send(events);
+            }
             Err(errors) => errors
                 .into_iter()
                 .for_each(|err| tracing::warn!(error=?err, "error from filesystem watcher")),
         },
+        FileIdMap::new(),
+        config,
     )
     .context("failed to build file system watcher")?;
 
@@ -209,7 +383,7 @@ fn build_watcher(
     for path in paths {
         debouncer
             .watcher()
-            .watch(&path, RecursiveMode::Recursive)
+            .watch(path, RecursiveMode::Recursive)
             .context(format!(
                 "failed to watch {:?} for file system changes",
                 path